@@ -0,0 +1,190 @@
+//! Memory used by the compact encodings, compared to storing integers as
+//! plain strings and every hash, list, set and sorted set in its
+//! non-compact encoding.
+//!
+//! The heap is measured with a counting allocator, so the numbers include
+//! the keys and the overhead of the database:
+//!
+//! ```text
+//! cargo bench --bench memory
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use mini_redis_rs::{
+    db::{Db, Entry, ListpackLimits, Value},
+    hash::Hash,
+    list::{List, ListpackSize},
+    set::{Set, SetLimits},
+    zset::ZSet,
+};
+
+/// Bytes currently allocated.
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Bytes per key of a database filled with `keys` values from `value`.
+fn per_key(keys: usize, value: impl Fn(usize) -> Value) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let db = Db::default();
+    for i in 0..keys {
+        db.insert(format!("key:{i:08}"), Entry::new(value(i), None));
+    }
+    let used = ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(db);
+    used / keys
+}
+
+/// A hash of `fields` fields, with the given limits.
+fn hash(limits: ListpackLimits, fields: usize, len: usize) -> Value {
+    let mut h = Hash::new(limits);
+    for j in 0..fields {
+        let field = format!("{j:0len$}");
+        h.insert(field.as_bytes(), field.as_bytes());
+    }
+    Value::Hash(h)
+}
+
+/// A list of `elements` elements, with the given limit.
+fn list(limit: ListpackSize, elements: usize, len: usize) -> Value {
+    let mut l = List::new(limit);
+    for j in 0..elements {
+        l.push_back(format!("{j:0len$}").as_bytes());
+    }
+    Value::List(l)
+}
+
+/// A set of `members` members, with the given limits. `len` of 0 makes
+/// them integers.
+fn set(limits: SetLimits, members: usize, len: usize) -> Value {
+    let mut s = Set::new(limits);
+    for j in 0..members {
+        let member = match len {
+            0 => j.to_string(),
+            _ => format!("m{j:0len$}"),
+        };
+        s.insert(member.as_bytes());
+    }
+    Value::Set(s)
+}
+
+/// A sorted set of `members` members, with the given limits.
+fn zset(limits: ListpackLimits, members: usize, len: usize) -> Value {
+    let mut z = ZSet::new(limits);
+    for j in 0..members {
+        z.insert(format!("{j:0len$}").as_bytes(), j as f64);
+    }
+    Value::ZSet(z)
+}
+
+/// Print the bytes per key of `keys` values built by `naive` and
+/// `compact`.
+fn report(
+    name: &str,
+    keys: usize,
+    naive: impl Fn(usize) -> Value,
+    compact: impl Fn(usize) -> Value,
+) {
+    let (naive, compact) = (per_key(keys, naive), per_key(keys, compact));
+    let ratio = compact as f64 / naive as f64;
+    println!("{name:<36} {naive:>10} {compact:>10} {ratio:>7.2}");
+}
+
+fn main() {
+    let skiplist = ListpackLimits {
+        entries: 0,
+        value: 0,
+    };
+    let listpack = ListpackLimits::default();
+    let quicklist = ListpackSize(0);
+    let hashtable = SetLimits {
+        intset_entries: 0,
+        listpack: skiplist,
+    };
+
+    println!("bytes per key\n");
+    println!(
+        "{:<36} {:>10} {:>10} {:>7}",
+        "", "naive", "compact", "ratio"
+    );
+    let int = |i: usize| (i * 7919).to_string().into_bytes();
+    report(
+        "integers",
+        100_000,
+        |i| Value::String(int(i)),
+        |i| Value::string(int(i)),
+    );
+    for (fields, len) in [(8, 8), (32, 16)] {
+        report(
+            &format!("hashes, {fields} fields of {len} bytes"),
+            10_000,
+            |_| hash(skiplist, fields, len),
+            |_| hash(listpack, fields, len),
+        );
+    }
+    for (elements, len) in [(8, 8), (128, 16)] {
+        report(
+            &format!("lists, {elements} elements of {len} bytes"),
+            10_000,
+            |_| list(quicklist, elements, len),
+            |_| list(ListpackSize::default(), elements, len),
+        );
+    }
+    report(
+        "sets, 64 integers",
+        10_000,
+        |_| set(hashtable, 64, 0),
+        |_| set(SetLimits::default(), 64, 0),
+    );
+    report(
+        "sets, 32 members of 16 bytes",
+        10_000,
+        |_| set(hashtable, 32, 16),
+        |_| set(SetLimits::default(), 32, 16),
+    );
+    for (members, len) in [(8, 8), (32, 16), (128, 16)] {
+        report(
+            &format!("sorted sets, {members} members of {len} bytes"),
+            10_000,
+            |_| zset(skiplist, members, len),
+            |_| zset(listpack, members, len),
+        );
+    }
+    // over the limits, both are skiplists
+    report(
+        "sorted sets, 256 members of 16 bytes",
+        1_000,
+        |_| zset(skiplist, 256, 16),
+        |_| zset(listpack, 256, 16),
+    );
+}
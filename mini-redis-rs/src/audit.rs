@@ -0,0 +1,104 @@
+//! Audit log: write commands appended to a file, one per line, in the
+//! format of `MONITOR` (see [`crate::monitor`]).
+//!
+//! Only commands that succeeded are logged, including the ones called by
+//! scripts. When the file would grow over `audit-log-max-size`, it is
+//! renamed to `<file>.1`, the previous `<file>.1` to `<file>.2`, and so
+//! on up to `audit-log-max-files`, and a new file is started.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+
+/// The audit log, opened on first use.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Mutex<Option<LogFile>>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening audit log {path:?}"))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+        })
+    }
+}
+
+/// Path of the `i`-th rotated file of `path`.
+fn rotated(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{i}"));
+    PathBuf::from(name)
+}
+
+/// Shift the rotated files of `path` by one, dropping the oldest one, and
+/// rename `path` to `<path>.1`.
+fn rotate(path: &Path, max_files: usize) -> Result<()> {
+    let ignore_missing = |res: io::Result<()>| match res {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+    if max_files == 0 {
+        return Ok(ignore_missing(std::fs::remove_file(path))?);
+    }
+    for i in (1..max_files).rev() {
+        let res = std::fs::rename(rotated(path, i), rotated(path, i + 1));
+        ignore_missing(res)?;
+    }
+    ignore_missing(std::fs::rename(path, rotated(path, 1)))?;
+    Ok(())
+}
+
+impl AuditLog {
+    /// Append `line` to the file configured with `audit-log`, if any,
+    /// rotating it first if needed.
+    pub fn write(&self, config: &Config, line: &str) -> Result<()> {
+        let mut guard = self.file.lock().unwrap();
+        if config.audit_log.is_empty() {
+            *guard = None;
+            return Ok(());
+        }
+        let path = Path::new(&config.audit_log);
+        let log = match &mut *guard {
+            Some(log) if log.path == path => log,
+            // not opened yet, or `CONFIG SET` changed the file
+            log => log.insert(LogFile::open(path)?),
+        };
+
+        let len = line.len() as u64 + 1;
+        let max_size = config.audit_log_max_size;
+        if max_size > 0 && log.size > 0 && log.size + len > max_size {
+            rotate(path, config.audit_log_max_files)
+                .with_context(|| format!("rotating audit log {path:?}"))?;
+            *log = LogFile::open(path)?;
+        }
+        let mut buf = Vec::with_capacity(line.len() + 1);
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+        log.file.write_all(&buf)?;
+        log.size += len;
+        Ok(())
+    }
+}
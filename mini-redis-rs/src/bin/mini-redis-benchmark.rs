@@ -0,0 +1,485 @@
+//! Load generator for mini-redis (or redis), like `redis-benchmark`.
+//!
+//! Usage: `mini-redis-benchmark [options] [cmd [arg ...]]`
+
+use std::{
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use hdrhistogram::Histogram;
+use mini_redis_rs::{
+    client::{Connection, Options},
+    wire::Value,
+};
+
+const USAGE: &str = "\
+usage: mini-redis-benchmark [options] [cmd [arg ...]]
+
+options:
+    -h <hostname>     server hostname (default: 127.0.0.1)
+    -p <port>         server port (default: 6379)
+    -c <clients>      number of parallel connections (default: 50)
+    -n <requests>     total number of requests per test (default: 100000)
+    -P <pipeline>     number of requests in flight per connection
+                      (default: 1, no pipelining)
+    -d <size>         size of the values of SET, in bytes (default: 3)
+    -r <keyspace>     use random keys in [0, keyspace) for the tests,
+                      replacing __rand_int__ in keys and commands
+    -t <tests>        comma separated list of tests to run, among
+                      ping, set, get, exists, del, expire, ttl
+                      (default: ping,set,get)
+    --mix <weights>   a single test picking commands at random, e.g.
+                      get=80,set=20
+    --csv             output in CSV
+    --json            output in JSON
+    -q                quiet, only show the throughput and median latency
+    --help            print this help
+
+A command given after the options is run as its own test, with
+__rand_int__ replaced by a random key number.
+
+examples:
+    mini-redis-benchmark -c 100 -n 1000000 -P 16 -r 100000 -t set,get
+    mini-redis-benchmark --mix get=90,set=10 -r 1000 --csv
+    mini-redis-benchmark -r 1000 expire key:__rand_int__ 100
+";
+
+/// Built-in tests.
+const TESTS: &[(&str, &[&str])] = &[
+    ("ping", &["ping"]),
+    ("set", &["set", "key:__rand_int__", "__data__"]),
+    ("get", &["get", "key:__rand_int__"]),
+    ("exists", &["exists", "key:__rand_int__"]),
+    ("del", &["del", "key:__rand_int__"]),
+    ("expire", &["expire", "key:__rand_int__", "100"]),
+    ("ttl", &["ttl", "key:__rand_int__"]),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Output {
+    Text,
+    Quiet,
+    Csv,
+    Json,
+}
+
+/// A test: commands, picked at random according to their weight.
+struct Test {
+    name: String,
+    cmds: Vec<(u32, Vec<String>)>,
+}
+
+struct Args {
+    addr: String,
+    clients: usize,
+    requests: usize,
+    pipeline: usize,
+    data_size: usize,
+    keyspace: Option<u64>,
+    output: Output,
+    tests: Vec<Test>,
+}
+
+fn builtin(name: &str) -> Result<Vec<String>> {
+    let (_, cmd) = TESTS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .with_context(|| format!("unknown test {name:?}"))?;
+    Ok(cmd.iter().map(|a| a.to_string()).collect())
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args> {
+    let mut parsed = Args {
+        addr: String::new(),
+        clients: 50,
+        requests: 100_000,
+        pipeline: 1,
+        data_size: 3,
+        keyspace: None,
+        output: Output::Text,
+        tests: vec![],
+    };
+    let (mut host, mut port) = ("127.0.0.1".to_string(), 6379u16);
+    let mut tests = None;
+    let mut mix = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .with_context(|| format!("missing value for {name}"))
+        };
+        match arg.as_str() {
+            "-h" => host = value("-h")?,
+            "-p" => port = value("-p")?.parse().context("invalid port")?,
+            "-c" => {
+                parsed.clients =
+                    value("-c")?.parse().context("invalid number of clients")?
+            }
+            "-n" => {
+                parsed.requests = value("-n")?
+                    .parse()
+                    .context("invalid number of requests")?
+            }
+            "-P" => {
+                parsed.pipeline =
+                    value("-P")?.parse().context("invalid pipeline")?
+            }
+            "-d" => {
+                parsed.data_size =
+                    value("-d")?.parse().context("invalid data size")?
+            }
+            "-r" => {
+                parsed.keyspace =
+                    Some(value("-r")?.parse().context("invalid keyspace")?)
+            }
+            "-t" => tests = Some(value("-t")?),
+            "--mix" => mix = Some(value("--mix")?),
+            "--csv" => parsed.output = Output::Csv,
+            "--json" => parsed.output = Output::Json,
+            "-q" => parsed.output = Output::Quiet,
+            a if a.starts_with('-') && a.len() > 1 => {
+                anyhow::bail!("unknown option {a}\n\n{USAGE}")
+            }
+            _ => {
+                // a user-supplied command, as its own test
+                let cmd: Vec<String> =
+                    std::iter::once(arg).chain(args).collect();
+                parsed.tests.push(Test {
+                    name: cmd.join(" "),
+                    cmds: vec![(1, cmd)],
+                });
+                break;
+            }
+        }
+    }
+    parsed.addr = format!("{host}:{port}");
+    anyhow::ensure!(parsed.clients > 0, "need at least one client");
+    anyhow::ensure!(parsed.pipeline > 0, "pipeline must be at least 1");
+    anyhow::ensure!(parsed.keyspace != Some(0), "keyspace can't be empty");
+
+    if let Some(mix) = mix {
+        let mut cmds = vec![];
+        for item in mix.split(',') {
+            let (name, weight) = item
+                .split_once('=')
+                .with_context(|| format!("expected test=weight: {item:?}"))?;
+            let weight = weight.parse().context("invalid weight")?;
+            cmds.push((weight, builtin(name)?));
+        }
+        anyhow::ensure!(
+            cmds.iter().any(|(w, _)| *w > 0),
+            "the weights of --mix can't all be zero"
+        );
+        parsed.tests.insert(0, Test { name: mix, cmds });
+    }
+    if tests.is_some() || parsed.tests.is_empty() {
+        let tests = tests.as_deref().unwrap_or("ping,set,get");
+        let builtins = tests
+            .split(',')
+            .map(|name| {
+                Ok(Test {
+                    name: name.to_uppercase(),
+                    cmds: vec![(1, builtin(name)?)],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        parsed.tests.splice(0..0, builtins);
+    }
+    Ok(parsed)
+}
+
+/// Everything a connection needs to generate requests.
+struct Gen {
+    cmds: Vec<(u32, Vec<String>)>,
+    total_weight: u32,
+    data: String,
+    keyspace: Option<u64>,
+}
+
+impl Gen {
+    fn new(test: &Test, args: &Args) -> Self {
+        Self {
+            cmds: test.cmds.clone(),
+            total_weight: test.cmds.iter().map(|(w, _)| w).sum(),
+            data: "x".repeat(args.data_size),
+            keyspace: args.keyspace,
+        }
+    }
+
+    /// A random command, with its placeholders replaced.
+    fn next(&self, rng: &mut fastrand::Rng) -> Vec<String> {
+        let mut pick = rng.u32(..self.total_weight);
+        let cmd = self
+            .cmds
+            .iter()
+            .find(|(w, _)| {
+                let found = pick < *w;
+                pick = pick.saturating_sub(*w);
+                found
+            })
+            .map_or(&self.cmds[0].1, |(_, cmd)| cmd);
+
+        // like redis-benchmark, keys have 12 digits
+        let key = format!("{:012}", self.keyspace.map_or(0, |n| rng.u64(..n)));
+        cmd.iter()
+            .map(|a| match a.as_str() {
+                "__data__" => self.data.clone(),
+                a => a.replace("__rand_int__", &key),
+            })
+            .collect()
+    }
+}
+
+/// Results of a connection, or of a whole test.
+struct Stats {
+    /// Latencies in microseconds.
+    hist: Histogram<u64>,
+    errors: u64,
+}
+
+impl Stats {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            hist: Histogram::new(3)?,
+            errors: 0,
+        })
+    }
+}
+
+/// Send requests until `remaining` runs out, `pipeline` at a time.
+async fn run_client(
+    addr: String,
+    gen: Arc<Gen>,
+    remaining: Arc<AtomicUsize>,
+    pipeline: usize,
+) -> Result<Stats> {
+    let opts = Options::default();
+    let mut conn = Connection::connect(&addr, &opts).await?;
+    let mut rng = fastrand::Rng::new();
+    let mut stats = Stats::new()?;
+    let mut batch = Vec::with_capacity(pipeline);
+
+    loop {
+        // claim up to `pipeline` requests
+        let claimed = remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |r| {
+                Some(r - r.min(pipeline))
+            })
+            .unwrap_or(0);
+        let n = claimed.min(pipeline);
+        if n == 0 {
+            return Ok(stats);
+        }
+
+        batch.clear();
+        batch.extend((0..n).map(|_| gen.next(&mut rng)));
+        let start = Instant::now();
+        conn.send_all(&batch).await?;
+        for _ in 0..n {
+            if let Value::Error(e) = conn.recv().await? {
+                log::debug!("error reply: {e}");
+                stats.errors += 1;
+            }
+            let us = start.elapsed().as_micros() as u64;
+            stats.hist.record(us.max(1))?;
+        }
+    }
+}
+
+/// Run a test to completion, and merge the stats of all connections.
+async fn run_test(test: &Test, args: &Args) -> Result<(Stats, Duration)> {
+    let gen = Arc::new(Gen::new(test, args));
+    let remaining = Arc::new(AtomicUsize::new(args.requests));
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..args.clients)
+        .map(|_| {
+            tokio::spawn(run_client(
+                args.addr.clone(),
+                gen.clone(),
+                remaining.clone(),
+                args.pipeline,
+            ))
+        })
+        .collect();
+
+    let mut stats = Stats::new()?;
+    for t in tasks {
+        let s = t.await??;
+        stats.hist.add(&s.hist)?;
+        stats.errors += s.errors;
+    }
+    Ok((stats, start.elapsed()))
+}
+
+/// Summary of a test, in milliseconds.
+struct Report {
+    name: String,
+    requests: u64,
+    errors: u64,
+    seconds: f64,
+    rps: f64,
+    avg: f64,
+    min: f64,
+    p50: f64,
+    p95: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Report {
+    fn new(name: &str, stats: &Stats, elapsed: Duration) -> Self {
+        let h = &stats.hist;
+        let ms = |us: u64| us as f64 / 1000.;
+        let seconds = elapsed.as_secs_f64();
+        Self {
+            name: name.to_string(),
+            requests: h.len(),
+            errors: stats.errors,
+            seconds,
+            rps: h.len() as f64 / seconds,
+            avg: h.mean() / 1000.,
+            min: ms(h.min()),
+            p50: ms(h.value_at_quantile(0.5)),
+            p95: ms(h.value_at_quantile(0.95)),
+            p99: ms(h.value_at_quantile(0.99)),
+            max: ms(h.max()),
+        }
+    }
+}
+
+fn print_text(r: &Report, stats: &Stats, args: &Args) {
+    println!("====== {} ======", r.name);
+    println!(
+        "  {} requests completed in {:.2} seconds",
+        r.requests, r.seconds
+    );
+    println!("  {} parallel clients", args.clients);
+    println!("  {} bytes payload", args.data_size);
+    if args.pipeline > 1 {
+        println!("  pipeline of {} requests", args.pipeline);
+    }
+    if r.errors > 0 {
+        println!("  {} error replies", r.errors);
+    }
+    println!();
+    println!("Latency by percentile distribution:");
+    for p in [0., 50., 75., 87.5, 93.75, 96.875, 99., 99.9, 100.] {
+        let v = stats.hist.value_at_percentile(p) as f64 / 1000.;
+        println!("{p:>7.3}% <= {v:.3} milliseconds");
+    }
+    println!();
+    println!("Summary:");
+    println!("  throughput summary: {:.2} requests per second", r.rps);
+    println!("  latency summary (msec):");
+    println!(
+        "  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "avg", "min", "p50", "p95", "p99", "max"
+    );
+    println!(
+        "  {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+        r.avg, r.min, r.p50, r.p95, r.p99, r.max
+    );
+    println!();
+}
+
+/// Quote a CSV field.
+fn csv_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Quote a JSON string.
+fn json_quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[tokio::main]
+pub async fn main() -> Result<()> {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help") {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    let args = parse_args(args)?;
+
+    if args.output == Output::Csv {
+        println!(
+            "\"test\",\"rps\",\"avg_latency_ms\",\"min_latency_ms\",\
+            \"p50_latency_ms\",\"p95_latency_ms\",\"p99_latency_ms\",\
+            \"max_latency_ms\""
+        );
+    }
+    let mut reports = vec![];
+    for test in &args.tests {
+        let (stats, elapsed) = run_test(test, &args).await?;
+        let r = Report::new(&test.name, &stats, elapsed);
+        match args.output {
+            Output::Text => print_text(&r, &stats, &args),
+            Output::Quiet => println!(
+                "{}: {:.2} requests per second, p50={:.3} msec",
+                r.name, r.rps, r.p50
+            ),
+            Output::Csv => println!(
+                "{},\"{:.2}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\
+                \"{:.3}\",\"{:.3}\"",
+                csv_quote(&r.name),
+                r.rps,
+                r.avg,
+                r.min,
+                r.p50,
+                r.p95,
+                r.p99,
+                r.max
+            ),
+            Output::Json => reports.push(r),
+        }
+    }
+
+    if args.output == Output::Json {
+        let tests: Vec<String> = reports
+            .iter()
+            .map(|r| {
+                format!(
+                    "  {{\"test\": {}, \"requests\": {}, \"errors\": {}, \
+                    \"seconds\": {:.3}, \"rps\": {:.2}, \"avg_latency_ms\": \
+                    {:.3}, \"min_latency_ms\": {:.3}, \"p50_latency_ms\": \
+                    {:.3}, \"p95_latency_ms\": {:.3}, \"p99_latency_ms\": \
+                    {:.3}, \"max_latency_ms\": {:.3}}}",
+                    json_quote(&r.name),
+                    r.requests,
+                    r.errors,
+                    r.seconds,
+                    r.rps,
+                    r.avg,
+                    r.min,
+                    r.p50,
+                    r.p95,
+                    r.p99,
+                    r.max
+                )
+            })
+            .collect();
+        println!("[\n{}\n]", tests.join(",\n"));
+    }
+    Ok(())
+}
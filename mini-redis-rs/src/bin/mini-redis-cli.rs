@@ -0,0 +1,752 @@
+//! Command line interface to mini-redis (or redis), like `redis-cli`.
+//!
+//! Usage: `mini-redis-cli [options] [cmd [arg ...]]`
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Write as _,
+    io::{IsTerminal, Read, Write as _},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use mini_redis_rs::{
+    client::{Client, Options},
+    wire::{self, Value},
+};
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Editor,
+};
+
+const USAGE: &str = "\
+usage: mini-redis-cli [options] [cmd [arg ...]]
+
+options:
+    -h <hostname>     server hostname (default: 127.0.0.1)
+    -p <port>         server port (default: 6379)
+    -n <db>           database number
+    -i <interval>     interval between samples for --stat and --latency,
+                      in seconds (default: 1 for --stat, 0.01 otherwise)
+    --raw             raw output, the default when stdout isn't a tty
+    --no-raw          formatted output even when stdout isn't a tty
+    --pipe            send the commands read from stdin, as RESP or
+                      inline commands, and report the number of errors
+    --scan            list the keys with SCAN
+    --pattern <pat>   keys pattern for --scan
+    --count <n>       COUNT hint for --scan
+    --latency         measure the latency of PING until interrupted
+    --stat            print general statistics every interval
+    --help            print this help
+    --version         print the version
+
+Without a command nor a mode, start an interactive session.
+
+examples:
+    mini-redis-cli -p 7777 set a b
+    cat data.txt | mini-redis-cli --pipe
+    mini-redis-cli --scan --pattern 'user:*'
+";
+
+/// What to do, from the command line.
+enum Mode {
+    Repl,
+    OneShot(Vec<String>),
+    Pipe,
+    Scan {
+        pattern: Option<String>,
+        count: Option<usize>,
+    },
+    Latency,
+    Stat,
+}
+
+struct Args {
+    host: String,
+    port: u16,
+    db: usize,
+    interval: Option<Duration>,
+    raw: bool,
+    mode: Mode,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args> {
+    let mut parsed = Args {
+        host: "127.0.0.1".to_string(),
+        port: 6379,
+        db: 0,
+        interval: None,
+        raw: !std::io::stdout().is_terminal(),
+        mode: Mode::Repl,
+    };
+    let (mut pipe, mut scan, mut latency, mut stat) =
+        (false, false, false, false);
+    let (mut pattern, mut count) = (None, None);
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .with_context(|| format!("missing value for {name}"))
+        };
+        match arg.as_str() {
+            "-h" => parsed.host = value("-h")?,
+            "-p" => {
+                parsed.port = value("-p")?.parse().context("invalid port")?
+            }
+            "-n" => parsed.db = value("-n")?.parse().context("invalid db")?,
+            "-i" => {
+                let secs: f64 =
+                    value("-i")?.parse().context("invalid interval")?;
+                parsed.interval = Some(Duration::from_secs_f64(secs));
+            }
+            "--raw" => parsed.raw = true,
+            "--no-raw" => parsed.raw = false,
+            "--pipe" => pipe = true,
+            "--scan" => scan = true,
+            "--pattern" => pattern = Some(value("--pattern")?),
+            "--count" => {
+                count =
+                    Some(value("--count")?.parse().context("invalid count")?)
+            }
+            "--latency" => latency = true,
+            "--stat" => stat = true,
+            a if a.starts_with('-') && a.len() > 1 => {
+                anyhow::bail!("unknown option {a}\n\n{USAGE}")
+            }
+            _ => {
+                // the command, and its arguments
+                let cmd: Vec<String> =
+                    std::iter::once(arg).chain(args).collect();
+                parsed.mode = Mode::OneShot(cmd);
+                break;
+            }
+        }
+    }
+
+    if pipe {
+        parsed.mode = Mode::Pipe;
+    } else if scan {
+        parsed.mode = Mode::Scan { pattern, count };
+    } else if latency {
+        parsed.mode = Mode::Latency;
+    } else if stat {
+        parsed.mode = Mode::Stat;
+    }
+    Ok(parsed)
+}
+
+/// Split a line into arguments, with quoting like in `redis-cli`:
+/// `"..."` with escapes (`\n`, `\xHH`, …) and `'...'` verbatim. Arguments
+/// are bytes, since `\xHH` can be any byte.
+fn split_args(line: &str) -> Result<Vec<Vec<u8>>> {
+    let line = line.as_bytes();
+    let space = |i: usize| line.get(i).is_some_and(u8::is_ascii_whitespace);
+    // `\x` is only an escape if two hex digits follow
+    let hex = |i: usize| {
+        let h = line.get(i..i + 2)?;
+        h.iter().all(u8::is_ascii_hexdigit).then(|| {
+            let digit = |c: u8| (c as char).to_digit(16).unwrap() as u8;
+            digit(h[0]) << 4 | digit(h[1])
+        })
+    };
+
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        while space(i) {
+            i += 1;
+        }
+        let Some(&c) = line.get(i) else {
+            return Ok(args);
+        };
+        let mut arg = vec![];
+        i += 1;
+        match c {
+            b'"' => loop {
+                match line.get(i) {
+                    None => anyhow::bail!("unbalanced quotes"),
+                    Some(b'"') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(b'\\') => {
+                        i += 1;
+                        match (line.get(i), hex(i + 1)) {
+                            (Some(b'x'), Some(b)) => {
+                                arg.push(b);
+                                i += 2;
+                            }
+                            (Some(b'n'), _) => arg.push(b'\n'),
+                            (Some(b'r'), _) => arg.push(b'\r'),
+                            (Some(b't'), _) => arg.push(b'\t'),
+                            (Some(b'b'), _) => arg.push(b'\x08'),
+                            (Some(b'a'), _) => arg.push(b'\x07'),
+                            (Some(&c), _) => arg.push(c),
+                            (None, _) => anyhow::bail!("unbalanced quotes"),
+                        }
+                    }
+                    Some(&c) => arg.push(c),
+                }
+                i += 1;
+            },
+            b'\'' => loop {
+                match line.get(i) {
+                    None => anyhow::bail!("unbalanced quotes"),
+                    Some(b'\'') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(b'\\') if line.get(i + 1) == Some(&b'\'') => {
+                        arg.push(b'\'');
+                        i += 1;
+                    }
+                    Some(&c) => arg.push(c),
+                }
+                i += 1;
+            },
+            c => {
+                arg.push(c);
+                while let Some(&c) = line.get(i).filter(|_| !space(i)) {
+                    arg.push(c);
+                    i += 1;
+                }
+            }
+        }
+        if i < line.len() && !space(i) {
+            anyhow::bail!("closing quote must be followed by a space");
+        }
+        args.push(arg);
+    }
+}
+
+/// Quote a string like `redis-cli` does. Bytes that are not valid UTF-8
+/// are escaped.
+fn quote(s: &[u8], out: &mut String) {
+    out.push('"');
+    for chunk in s.utf8_chunks() {
+        quote_chars(chunk.valid(), out);
+        for b in chunk.invalid() {
+            let _ = write!(out, "\\x{b:02x}");
+        }
+    }
+    out.push('"');
+}
+
+fn quote_chars(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x07' => out.push_str("\\a"),
+            '\x08' => out.push_str("\\b"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+/// Format a reply like `redis-cli` does on a terminal. Nested arrays are
+/// indented by `prefix`.
+fn format_tty(v: &Value, prefix: &str, out: &mut String) {
+    match v {
+        Value::String(s) => {
+            quote(s.as_bytes(), out);
+            out.push('\n');
+        }
+        Value::Bytes(b) => {
+            quote(b, out);
+            out.push('\n');
+        }
+        Value::Int(i) => {
+            let _ = writeln!(out, "(integer) {i}");
+        }
+        Value::Error(e) => {
+            let _ = writeln!(out, "(error) {e}");
+        }
+        Value::Null => out.push_str("(nil)\n"),
+        Value::Bulk(items) if items.is_empty() => {
+            out.push_str("(empty array)\n")
+        }
+        Value::Bulk(items) => {
+            let width = items.len().to_string().len();
+            let nested = format!("{prefix}{}", " ".repeat(width + 2));
+            for (i, item) in items.iter().enumerate() {
+                // the first element goes right after the parent's index
+                if i > 0 {
+                    out.push_str(prefix);
+                }
+                let _ = write!(out, "{:>width$}) ", i + 1);
+                format_tty(item, &nested, out);
+            }
+        }
+    }
+}
+
+/// Format a reply for scripts: no quoting, no type annotations.
+fn format_raw(v: &Value, out: &mut String) {
+    match v {
+        Value::String(s) => out.push_str(s),
+        Value::Bytes(b) => out.push_str(&String::from_utf8_lossy(b)),
+        Value::Int(i) => {
+            let _ = write!(out, "{i}");
+        }
+        Value::Error(e) => out.push_str(e),
+        Value::Null => (),
+        Value::Bulk(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                format_raw(item, out);
+            }
+        }
+    }
+}
+
+fn format_reply(v: &Value, raw: bool) -> String {
+    let mut out = String::new();
+    if raw {
+        format_raw(v, &mut out);
+        out.push('\n');
+    } else {
+        format_tty(v, "", &mut out);
+    }
+    out
+}
+
+/// Run a command, and turn error replies back into values to print them.
+async fn query<S: AsRef<[u8]>>(client: &Client, args: &[S]) -> Result<Value> {
+    match client.cmd(args).await {
+        Ok(v) => Ok(v),
+        Err(e) => match e.downcast::<mini_redis_rs::client::ServerError>() {
+            Ok(e) => Ok(Value::Error(e.0)),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Hints and completion from `COMMAND DOCS`.
+#[derive(Default)]
+struct Helper {
+    /// Lowercase command name → syntax of its arguments.
+    syntax: HashMap<String, String>,
+}
+
+impl Helper {
+    /// Fetch the documentation of the commands. Servers that don't
+    /// support `COMMAND DOCS` just get no hints.
+    async fn fetch(client: &Client) -> Self {
+        let Ok(Value::Bulk(docs)) = client.cmd(&["command", "docs"]).await
+        else {
+            return Self::default();
+        };
+        let mut syntax = HashMap::new();
+        for pair in docs.chunks(2) {
+            if let [Value::String(name), Value::Bulk(fields)] = pair {
+                syntax.insert(name.to_lowercase(), doc_syntax(fields));
+            }
+        }
+        Self { syntax }
+    }
+}
+
+/// The syntax of a command, from its `COMMAND DOCS` fields: mini-redis
+/// has a `syntax` field; for redis, build it from the argument names.
+fn doc_syntax(fields: &[Value]) -> String {
+    let field = |name: &str| {
+        fields.chunks(2).find_map(|f| match f {
+            [Value::String(k), v] if k == name => Some(v),
+            _ => None,
+        })
+    };
+    if let Some(Value::String(syntax)) = field("syntax") {
+        return syntax.clone();
+    }
+    let Some(Value::Bulk(args)) = field("arguments") else {
+        return String::new();
+    };
+    let mut words = vec![];
+    for arg in args {
+        let Value::Bulk(arg) = arg else { continue };
+        let get = |name: &str| {
+            arg.chunks(2).find_map(|f| match f {
+                [Value::String(k), v] if k == name => Some(v.clone()),
+                _ => None,
+            })
+        };
+        let Some(Value::String(name)) = get("display_text").or(get("name"))
+        else {
+            continue;
+        };
+        let optional = matches!(
+            get("flags"),
+            Some(Value::Bulk(flags))
+                if flags.contains(&Value::String("optional".into()))
+        );
+        words.push(if optional { format!("[{name}]") } else { name });
+    }
+    words.join(" ")
+}
+
+/// Split a syntax string into top-level words, keeping bracketed groups
+/// together.
+fn syntax_words(syntax: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let (mut depth, mut start) = (0, None);
+    for (i, c) in syntax.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(s) = start.take() {
+                    words.push(&syntax[s..i]);
+                }
+                continue;
+            }
+            _ => (),
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        words.push(&syntax[s..]);
+    }
+    words
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+
+    fn hint(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        let args = split_args(line).ok()?;
+        let name = String::from_utf8_lossy(args.first()?).to_lowercase();
+        let syntax = self.syntax.get(&name)?;
+        // skip the arguments already typed, including the one being typed
+        let n = args.len() - 1;
+        let words = syntax_words(syntax);
+        // past a repeated argument, we don't know where we are
+        if words.iter().take(n).any(|w| w.contains("...")) {
+            return None;
+        }
+        let rest = words.get(n..).filter(|r| !r.is_empty())?;
+        let sep = if line.ends_with(' ') { "" } else { " " };
+        Some(format!("{sep}{}", rest.join(" ")))
+    }
+}
+
+impl Highlighter for Helper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[90m{hint}\x1b[0m"))
+    }
+}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // only complete the command name
+        let word = &line[..pos];
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        let upper = word.chars().next().is_some_and(|c| c.is_uppercase());
+        let mut names: Vec<String> = self
+            .syntax
+            .keys()
+            .filter(|n| n.starts_with(&word.to_lowercase()))
+            .map(|n| if upper { n.to_uppercase() } else { n.clone() })
+            .collect();
+        names.sort();
+        Ok((0, names))
+    }
+}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+fn history_path() -> Option<std::path::PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(std::path::Path::new(&home).join(".mini_redis_cli_history"))
+}
+
+/// Interactive session.
+async fn repl(args: &Args, opts: Options) -> Result<()> {
+    let addr = format!("{}:{}", args.host, args.port);
+    let mut opts = opts;
+    let mut client = Client::with_options(addr.clone(), opts.clone());
+
+    let mut editor: Editor<Helper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(Helper::fetch(&client).await));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let prompt = match opts.db {
+            0 => format!("{addr}> "),
+            db => format!("{addr}[{db}]> "),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(rustyline::error::ReadlineError::Interrupted) => continue,
+            Err(rustyline::error::ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let cmd = match split_args(&line) {
+            Ok(cmd) if cmd.is_empty() => continue,
+            Ok(cmd) => cmd,
+            Err(e) => {
+                println!("Invalid argument(s): {e}");
+                continue;
+            }
+        };
+        let _ = editor.add_history_entry(line.trim());
+
+        match String::from_utf8_lossy(&cmd[0]).to_lowercase().as_str() {
+            "quit" | "exit" => break,
+            "clear" => {
+                print!("\x1b[H\x1b[2J");
+                continue;
+            }
+            _ => (),
+        }
+        match query(&client, &cmd).await {
+            Ok(reply) => {
+                // connections of the pool must select the same database,
+                // including after reconnecting
+                if cmd[0].eq_ignore_ascii_case(b"select")
+                    && !matches!(reply, Value::Error(_))
+                {
+                    opts.db = std::str::from_utf8(&cmd[1])?.parse()?;
+                    client = Client::with_options(addr.clone(), opts.clone());
+                }
+                print!("{}", format_reply(&reply, args.raw));
+            }
+            Err(e) => println!("Could not connect to {addr}: {e:#}"),
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    Ok(())
+}
+
+/// Parse the commands of `--pipe`: RESP if the input starts with `*`,
+/// inline commands otherwise.
+fn parse_pipe_input(input: &[u8]) -> Result<Vec<Vec<Vec<u8>>>> {
+    let mut cmds = vec![];
+    if input.first() == Some(&b'*') {
+        let mut pos = 0;
+        while pos < input.len() {
+            let Some((v, n)) = wire::decode(&input[pos..])? else {
+                anyhow::bail!("truncated command at byte {pos}");
+            };
+            let Value::Bulk(args) = v else {
+                anyhow::bail!("expected an array at byte {pos}");
+            };
+            let args = args
+                .into_iter()
+                .map(Value::into_bytes)
+                .collect::<Result<_>>()?;
+            cmds.push(args);
+            pos += n;
+        }
+    } else {
+        let input = std::str::from_utf8(input).context("decoding input")?;
+        for line in input.lines() {
+            let args = split_args(line)?;
+            if !args.is_empty() {
+                cmds.push(args);
+            }
+        }
+    }
+    Ok(cmds)
+}
+
+/// Mass insertion: send every command from stdin, in batches, and count
+/// the errors.
+async fn pipe(client: &Client) -> Result<()> {
+    const BATCH: usize = 1_000;
+    let mut input = vec![];
+    std::io::stdin().read_to_end(&mut input)?;
+    let cmds = parse_pipe_input(&input)?;
+
+    let (mut replies, mut errors) = (0, 0);
+    for batch in cmds.chunks(BATCH) {
+        for reply in client.pipeline(batch).await? {
+            replies += 1;
+            if let Value::Error(e) = reply {
+                errors += 1;
+                eprintln!("{e}");
+            }
+        }
+    }
+    println!("All data transferred. Waiting for the last reply...");
+    println!("errors: {errors}, replies: {replies}");
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn scan(
+    client: &Client,
+    pattern: Option<&str>,
+    count: Option<usize>,
+) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    let mut cursor = 0;
+    loop {
+        let (next, keys) = client.scan(cursor, pattern, count, None).await?;
+        for k in keys {
+            writeln!(stdout, "{k}")?;
+        }
+        if next == 0 {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
+
+/// Measure the round-trip time of `PING`, forever.
+async fn latency(client: &Client, interval: Duration) -> Result<()> {
+    let (mut min, mut max, mut total, mut n) = (u128::MAX, 0, 0, 0);
+    let tty = std::io::stdout().is_terminal();
+    let mut last_print = Instant::now();
+    loop {
+        let start = Instant::now();
+        client.cmd(&["ping"]).await?;
+        let us = start.elapsed().as_micros();
+        (min, max, total, n) = (min.min(us), max.max(us), total + us, n + 1);
+
+        let ms = |us: u128| us as f64 / 1000.;
+        let line = format!(
+            "min: {:.2}, max: {:.2}, avg: {:.2} ({n} samples)",
+            ms(min),
+            ms(max),
+            ms(total) / n as f64
+        );
+        if tty {
+            print!("\x1b[0G\x1b[2K{line}");
+            std::io::stdout().flush()?;
+        } else if last_print.elapsed() >= Duration::from_secs(1) {
+            println!("{line}");
+            last_print = Instant::now();
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Parse `INFO` into a map of fields.
+fn parse_info(info: &str) -> HashMap<&str, &str> {
+    info.lines()
+        .filter(|l| !l.starts_with('#'))
+        .filter_map(|l| l.split_once(':'))
+        .collect()
+}
+
+/// Print general statistics from `INFO`, forever.
+async fn stat(client: &Client, interval: Duration) -> Result<()> {
+    let mut prev_requests = None;
+    for i in 0.. {
+        if i % 20 == 0 {
+            println!(
+                "------- data ------ ------------ load ------------\n\
+                {:<10} {:<8} {:<8} {:<18} connections",
+                "keys", "mem", "clients", "requests"
+            );
+        }
+        let info = client.info(Some("all")).await?;
+        let info = parse_info(&info);
+        let get = |k: &str| info.get(k).copied().unwrap_or("0");
+        let keys: u64 = info
+            .iter()
+            .filter(|(k, _)| k.starts_with("db"))
+            .filter_map(|(_, v)| v.strip_prefix("keys="))
+            .filter_map(|v| v.split(',').next()?.parse::<u64>().ok())
+            .sum();
+        let requests: u64 = get("total_commands_processed").parse()?;
+        let delta = prev_requests.map_or(String::new(), |p| {
+            format!(" (+{})", requests.saturating_sub(p))
+        });
+        prev_requests = Some(requests);
+        println!(
+            "{:<10} {:<8} {:<8} {:<18} {}",
+            keys,
+            get("used_memory_human"),
+            get("connected_clients"),
+            format!("{requests}{delta}"),
+            get("total_connections_received"),
+        );
+        tokio::time::sleep(interval).await;
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn main() -> Result<()> {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help") {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    if args.iter().any(|a| a == "-v" || a == "--version") {
+        println!("mini-redis-cli v{}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    let args = parse_args(args)?;
+
+    // a single connection, so the session state (SELECT, …) is kept
+    let opts = Options {
+        pool_size: 1,
+        db: args.db,
+        retries: 0,
+        ..Options::default()
+    };
+    let addr = format!("{}:{}", args.host, args.port);
+    let client = Client::with_options(addr, opts.clone());
+
+    match &args.mode {
+        Mode::Repl => repl(&args, opts).await,
+        Mode::OneShot(cmd) => {
+            let reply = query(&client, cmd).await?;
+            print!("{}", format_reply(&reply, args.raw));
+            Ok(())
+        }
+        Mode::Pipe => pipe(&client).await,
+        Mode::Scan { pattern, count } => {
+            scan(&client, pattern.as_deref(), *count).await
+        }
+        Mode::Latency => {
+            let interval = args.interval.unwrap_or(Duration::from_millis(10));
+            latency(&client, interval).await
+        }
+        Mode::Stat => {
+            let interval = args.interval.unwrap_or(Duration::from_secs(1));
+            stat(&client, interval).await
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Proxy injecting faults between clients and a server, to test how
+//! clients cope with a misbehaving server.
+//!
+//! Usage: `mini-redis-proxy [options]`
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use mini_redis_rs::proxy::{self, Rules};
+use tokio::net::TcpListener;
+
+const USAGE: &str = "\
+usage: mini-redis-proxy [options]
+
+options:
+    --listen <addr>     address to accept clients on
+                        (default: 127.0.0.1:6380)
+    --backend <addr>    address of the server to forward commands to
+                        (default: 127.0.0.1:6379)
+    --rules <file>      rule file describing the faults to inject
+                        (default: forward everything as is)
+    --help              print this help
+
+Rules are one per line:
+
+    <action> [<argument>] [cmd <name>[,<name>...]] [key <pattern>] [prob <p>]
+
+with actions `delay <ms>` or `delay <min>-<max>`, `error <message>`,
+`drop`, `partial` and `reorder`.
+
+examples:
+    mini-redis-proxy --rules faults.txt
+    mini-redis-proxy --listen 0.0.0.0:7000 --backend 10.0.0.5:6379
+";
+
+struct Args {
+    listen: String,
+    backend: String,
+    rules: Rules,
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args> {
+    let mut parsed = Args {
+        listen: "127.0.0.1:6380".to_string(),
+        backend: "127.0.0.1:6379".to_string(),
+        rules: Rules::default(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .with_context(|| format!("missing value for {name}"))
+        };
+        match arg.as_str() {
+            "--listen" => parsed.listen = value("--listen")?,
+            "--backend" => parsed.backend = value("--backend")?,
+            "--rules" => {
+                parsed.rules = Rules::load(Path::new(&value("--rules")?))?
+            }
+            a => anyhow::bail!("unknown option {a:?}, see --help"),
+        }
+    }
+    Ok(parsed)
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn main() -> Result<()> {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help") {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    let args = parse_args(args)?;
+
+    let listen = TcpListener::bind(&args.listen)
+        .await
+        .with_context(|| format!("binding socket {}", args.listen))?;
+    log::info!(
+        "proxying {} to {} with {} rules",
+        listen.local_addr()?,
+        args.backend,
+        args.rules.len()
+    );
+    proxy::run(listen, args.backend, args.rules).await;
+    Ok(())
+}
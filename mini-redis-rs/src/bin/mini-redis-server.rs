@@ -0,0 +1,92 @@
+//! The mini-redis server.
+//!
+//! Usage: `mini-redis-server [/path/to/redis.conf] [--<param> <value>...]`
+
+use anyhow::{Context, Result};
+use mini_redis_rs::{
+    config::Config,
+    server::{self, ShutdownMode, State},
+};
+use std::sync::Arc;
+
+const USAGE: &str = "\
+usage: mini-redis-server [/path/to/redis.conf] [--<param> <value>...]
+
+examples:
+    mini-redis-server --port 7777
+    mini-redis-server /etc/redis/6379.conf --loglevel verbose
+";
+
+/// Setup logging according to `config`.
+///
+/// `RUST_LOG`, if set, takes precedence over `loglevel`.
+fn init_logging(config: &Config) -> Result<()> {
+    let mut builder = env_logger::Builder::from_default_env();
+    if std::env::var_os("RUST_LOG").is_none() {
+        // filter with `log::set_max_level` so it can change at runtime
+        builder.filter_level(log::LevelFilter::Trace);
+    }
+    if !config.logfile.is_empty() {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.logfile)
+            .with_context(|| {
+                format!("opening log file {:?}", config.logfile)
+            })?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+    builder.init();
+    if std::env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(config.loglevel.to_filter());
+    }
+    Ok(())
+}
+
+/// Wait for SIGINT or SIGTERM, and shut the server down gracefully.
+async fn shutdown_on_signal(st: Arc<State>) -> Result<()> {
+    #[cfg(unix)]
+    let name = {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut int = signal(SignalKind::interrupt())?;
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = int.recv() => "SIGINT",
+            _ = term.recv() => "SIGTERM",
+        }
+    };
+    #[cfg(not(unix))]
+    let name = {
+        tokio::signal::ctrl_c().await?;
+        "Ctrl-C"
+    };
+    log::warn!("received {name}, scheduling shutdown...");
+    st.shutdown(ShutdownMode::Default);
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    if args.iter().any(|a| a == "-v" || a == "--version") {
+        println!("mini-redis-server v{}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    let (config, config_file) = Config::from_args(args)?;
+    init_logging(&config)?;
+    log::debug!("config: {config:?}");
+
+    let st = Arc::new(State::new(config, config_file));
+    let signal_st = st.clone();
+    tokio::spawn(async move {
+        if let Err(e) = shutdown_on_signal(signal_st).await {
+            log::error!("could not handle signals: {e:#}");
+        }
+    });
+    server::run(st).await
+}
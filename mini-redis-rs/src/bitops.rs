@@ -0,0 +1,165 @@
+//! Bitmaps: `SETBIT`, `GETBIT` and `BITCOUNT`.
+//!
+//! A bitmap is a string, where bit 0 is the most significant bit of the
+//! first byte, like in redis.
+
+use crate::{
+    db::Value,
+    notify,
+    server::{wrong_arity, State, WRONG_TYPE},
+    wire::Frame,
+};
+
+/// Largest bitmap, in bits: strings are limited to 512MB in redis.
+const MAX_BITS: u64 = 512 * 1024 * 1024 * 8;
+
+/// Execute a bitmap command on the selected database `db_index`. `args`
+/// includes the command name.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let lfu = st.config().lfu_params();
+    let parse_offset =
+        |s: &str| s.parse::<u64>().ok().filter(|&o| o < MAX_BITS);
+    match (cmd, &args[1..]) {
+        ("setbit", &[k, offset, bit]) => {
+            let Some(offset) = parse_offset(offset) else {
+                return Frame::Error(
+                    "ERR bit offset is not an integer or out of range",
+                );
+            };
+            let bit = match bit {
+                "0" => false,
+                "1" => true,
+                _ => {
+                    return Frame::Error(
+                        "ERR bit is not an integer or out of range",
+                    )
+                }
+            };
+            let is_new = db.peek(k).is_none();
+            let new = || Some(Value::String(vec![]));
+            let res = db.update(k, lfu, new, |v| {
+                v.as_bytes_mut().map(|s| set_bit(s, offset, bit))
+            });
+            match res.flatten() {
+                Some(old) => {
+                    if is_new {
+                        st.notify(notify::NEW, "new", k, db_index);
+                    }
+                    st.notify(notify::STRING, "setbit", k, db_index);
+                    Frame::Int(old as isize)
+                }
+                None => Frame::Error(WRONG_TYPE),
+            }
+        }
+        ("getbit", &[k, offset]) => {
+            let Some(offset) = parse_offset(offset) else {
+                return Frame::Error(
+                    "ERR bit offset is not an integer or out of range",
+                );
+            };
+            match db.get(k, lfu).as_deref().map(|e| e.value.as_bytes()) {
+                None => Frame::Int(0),
+                Some(Some(s)) => Frame::Int(get_bit(&s, offset) as isize),
+                Some(None) => Frame::Error(WRONG_TYPE),
+            }
+        }
+        ("bitcount", [k, range @ ..]) => {
+            let range = match range {
+                [] => None,
+                [start, end, unit @ ..] => {
+                    let bits = match unit {
+                        [] => false,
+                        [u] if u.eq_ignore_ascii_case("byte") => false,
+                        [u] if u.eq_ignore_ascii_case("bit") => true,
+                        _ => return Frame::Error("ERR syntax error"),
+                    };
+                    let (Ok(start), Ok(end)) =
+                        (start.parse::<i64>(), end.parse::<i64>())
+                    else {
+                        return Frame::Error(
+                            "ERR value is not an integer or out of range",
+                        );
+                    };
+                    Some((start, end, bits))
+                }
+                _ => return Frame::Error("ERR syntax error"),
+            };
+            match db.get(k, lfu).as_deref().map(|e| e.value.as_bytes()) {
+                None => Frame::Int(0),
+                Some(Some(s)) => Frame::Int(count(&s, range) as isize),
+                Some(None) => Frame::Error(WRONG_TYPE),
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
+
+/// Set bit `offset` of `s` to `bit`, growing `s` with zeros if needed.
+/// Returns the previous value of the bit.
+fn set_bit(s: &mut Vec<u8>, offset: u64, bit: bool) -> bool {
+    let byte = (offset / 8) as usize;
+    let mask = 0x80 >> (offset % 8);
+    if byte >= s.len() {
+        s.resize(byte + 1, 0);
+    }
+    let old = s[byte] & mask != 0;
+    if bit {
+        s[byte] |= mask;
+    } else {
+        s[byte] &= !mask;
+    }
+    old
+}
+
+fn get_bit(s: &[u8], offset: u64) -> bool {
+    let mask = 0x80 >> (offset % 8);
+    s.get((offset / 8) as usize).is_some_and(|b| b & mask != 0)
+}
+
+/// Number of bits set in `s`, or in the inclusive `(start, end)` range of
+/// bytes (or of bits if the flag is set). Negative positions count from
+/// the end.
+fn count(s: &[u8], range: Option<(i64, i64, bool)>) -> u64 {
+    let Some((start, end, bits)) = range else {
+        return s.iter().map(|b| b.count_ones() as u64).sum();
+    };
+    let len = if bits {
+        s.len() as i64 * 8
+    } else {
+        s.len() as i64
+    };
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        (len + end).max(0)
+    } else {
+        end.min(len - 1)
+    };
+    if len == 0 || start > end {
+        return 0;
+    }
+    if !bits {
+        let bytes = &s[start as usize..=end as usize];
+        return bytes.iter().map(|b| b.count_ones() as u64).sum();
+    }
+
+    // whole bytes in the middle, then the bits of the partial bytes at
+    // both ends
+    let (first, last) = ((start / 8) as usize, (end / 8) as usize);
+    let mut n: u64 =
+        s[first..=last].iter().map(|b| b.count_ones() as u64).sum();
+    let head = s[first] & !(0xffu8 >> (start % 8));
+    let tail = s[last] & (0x7fu8 >> (end % 8));
+    n -= head.count_ones() as u64 + tail.count_ones() as u64;
+    n
+}
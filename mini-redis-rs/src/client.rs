@@ -1,71 +1,1364 @@
-//! Basic redis client.
+//! Redis client.
+//!
+//! A [`Client`] is cheap to clone and can be shared between tasks and
+//! threads. Each command borrows a connection from a pool, which opens
+//! connections lazily (up to [`Options::pool_size`]) and re-opens them
+//! with exponential backoff when the server goes away.
+//!
+//! Connection state is per connection, so `SELECT` and `CLIENT SETNAME`
+//! are set once for the whole pool with [`Options::db`] and
+//! [`Options::name`] rather than as commands, and subscribing uses a
+//! dedicated connection (see [`Client::subscribe`]).
 
-use crate::{
-    wire::Conn,
-    wire::{self, Frame},
+use std::{
+    collections::{HashSet, VecDeque},
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use anyhow::Result;
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
 
-/// A basic client.
-pub struct Client<'a> {
-    conn: Conn<'a>,
+use anyhow::{Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Semaphore,
+};
+
+use crate::wire::{self, Value};
+
+/// Typed commands, shared by the async [`Client`] and
+/// [`blocking::Client`]: they only differ in whether `cmd` is async.
+macro_rules! typed_commands {
+    ($($async_:ident)?; $($await_:tt)*) => {
+        // ## Keys
+
+        /// `GET key`.
+        pub $($async_)? fn get(&self, key: &str) -> Result<Option<String>> {
+            self.cmd(&["get", key])$($await_)*?.into_opt_string()
+        }
+
+        /// `GET key`, for values that may not be valid UTF-8.
+        pub $($async_)? fn get_bytes(
+            &self,
+            key: &str,
+        ) -> Result<Option<Vec<u8>>> {
+            self.cmd(&["get", key])$($await_)*?.into_opt_bytes()
+        }
+
+        /// `SET key value`.
+        pub $($async_)? fn set(&self, key: &str, value: &str) -> Result<()> {
+            self.cmd(&["set", key, value])$($await_)*?.into_ok()
+        }
+
+        /// `SET key value PX ttl`.
+        pub $($async_)? fn set_ex(
+            &self,
+            key: &str,
+            value: &str,
+            ttl: Duration,
+        ) -> Result<()> {
+            let ms = ttl.as_millis().to_string();
+            self.cmd(&["set", key, value, "px", &ms])$($await_)*?.into_ok()
+        }
+
+        /// `EXPIRE key seconds`. Returns `false` if the key doesn't exist.
+        pub $($async_)? fn expire(
+            &self,
+            key: &str,
+            seconds: i64,
+        ) -> Result<bool> {
+            let s = seconds.to_string();
+            self.cmd(&["expire", key, &s])$($await_)*?.into_bool()
+        }
+
+        /// `PEXPIRE key milliseconds`.
+        pub $($async_)? fn pexpire(&self, key: &str, ms: i64) -> Result<bool> {
+            let ms = ms.to_string();
+            self.cmd(&["pexpire", key, &ms])$($await_)*?.into_bool()
+        }
+
+        /// `TTL key`: -2 if the key doesn't exist, -1 if it has no TTL.
+        pub $($async_)? fn ttl(&self, key: &str) -> Result<i64> {
+            self.cmd(&["ttl", key])$($await_)*?.into_int()
+        }
+
+        /// `PTTL key`.
+        pub $($async_)? fn pttl(&self, key: &str) -> Result<i64> {
+            self.cmd(&["pttl", key])$($await_)*?.into_int()
+        }
+
+        /// `PERSIST key`. Returns `false` if the key had no TTL.
+        pub $($async_)? fn persist(&self, key: &str) -> Result<bool> {
+            self.cmd(&["persist", key])$($await_)*?.into_bool()
+        }
+
+        /// `DEL key [key ...]`. Returns how many keys were deleted.
+        pub $($async_)? fn del(&self, keys: &[&str]) -> Result<i64> {
+            self.cmd(&command("del", keys))$($await_)*?.into_int()
+        }
+
+        /// `EXISTS key [key ...]`.
+        pub $($async_)? fn exists(&self, keys: &[&str]) -> Result<i64> {
+            self.cmd(&command("exists", keys))$($await_)*?.into_int()
+        }
+
+        /// `KEYS pattern`.
+        pub $($async_)? fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+            self.cmd(&["keys", pattern])$($await_)*?.into_strings()
+        }
+
+        /// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`. Returns
+        /// the next cursor (0 when done) and a batch of keys.
+        pub $($async_)? fn scan(
+            &self,
+            cursor: u64,
+            pattern: Option<&str>,
+            count: Option<usize>,
+            type_: Option<&str>,
+        ) -> Result<(u64, Vec<String>)> {
+            let mut args = vec!["scan".to_string(), cursor.to_string()];
+            for (opt, v) in [
+                ("match", pattern.map(str::to_string)),
+                ("count", count.map(|c| c.to_string())),
+                ("type", type_.map(str::to_string)),
+            ] {
+                if let Some(v) = v {
+                    args.extend([opt.to_string(), v]);
+                }
+            }
+            let reply = self.cmd(&args)$($await_)*?;
+            match reply {
+                Value::Bulk(v) => match <[Value; 2]>::try_from(v) {
+                    Ok([cursor, keys]) => {
+                        let cursor = cursor.into_string()?;
+                        let cursor = cursor
+                            .parse()
+                            .with_context(|| {
+                                format!("invalid cursor {cursor}")
+                            })?;
+                        Ok((cursor, keys.into_strings()?))
+                    }
+                    Err(v) => unexpected(Value::Bulk(v)),
+                },
+                v => unexpected(v),
+            }
+        }
+
+        /// `TYPE key`.
+        pub $($async_)? fn key_type(&self, key: &str) -> Result<String> {
+            self.cmd(&["type", key])$($await_)*?.into_string()
+        }
+
+        /// `RENAME key newkey`.
+        pub $($async_)? fn rename(
+            &self,
+            key: &str,
+            newkey: &str,
+        ) -> Result<()> {
+            self.cmd(&["rename", key, newkey])$($await_)*?.into_ok()
+        }
+
+        /// `RENAMENX key newkey`. Returns `false` if `newkey` exists.
+        pub $($async_)? fn renamenx(
+            &self,
+            key: &str,
+            newkey: &str,
+        ) -> Result<bool> {
+            self.cmd(&["renamenx", key, newkey])$($await_)*?.into_bool()
+        }
+
+        /// `MOVE key db`.
+        pub $($async_)? fn move_key(
+            &self,
+            key: &str,
+            db: usize,
+        ) -> Result<bool> {
+            let db = db.to_string();
+            self.cmd(&["move", key, &db])$($await_)*?.into_bool()
+        }
+
+        /// `MEMORY USAGE key`, in bytes.
+        pub $($async_)? fn memory_usage(
+            &self,
+            key: &str,
+        ) -> Result<Option<i64>> {
+            match self.cmd(&["memory", "usage", key])$($await_)*? {
+                Value::Null => Ok(None),
+                v => v.into_int().map(Some),
+            }
+        }
+
+        /// `OBJECT ENCODING key`, such as `int` or `listpack`.
+        pub $($async_)? fn object_encoding(
+            &self,
+            key: &str,
+        ) -> Result<Option<String>> {
+            self.cmd(&["object", "encoding", key])$($await_)*?
+                .into_opt_string()
+        }
+
+        /// `DUMP key`: the serialized value, for [`Self::restore`].
+        pub $($async_)? fn dump(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.cmd(&["dump", key])$($await_)*?.into_opt_bytes()
+        }
+
+        /// `RESTORE key ttl payload [REPLACE]`, with no TTL if `ttl` is
+        /// `None`.
+        pub $($async_)? fn restore(
+            &self,
+            key: &str,
+            ttl: Option<Duration>,
+            payload: &[u8],
+            replace: bool,
+        ) -> Result<()> {
+            let ms = ttl.map_or(0, |t| t.as_millis().max(1)).to_string();
+            let mut args =
+                vec![&b"restore"[..], key.as_bytes(), ms.as_bytes(), payload];
+            if replace {
+                args.push(b"replace");
+            }
+            self.cmd(&args)$($await_)*?.into_ok()
+        }
+
+        /// `MIGRATE host port "" db timeout [REPLACE] KEYS key [key ...]`.
+        /// Returns `false` if none of the keys exist.
+        pub $($async_)? fn migrate(
+            &self,
+            host: &str,
+            port: u16,
+            db: usize,
+            keys: &[&str],
+            timeout: Duration,
+            replace: bool,
+        ) -> Result<bool> {
+            let mut args = vec![
+                "migrate".to_string(),
+                host.to_string(),
+                port.to_string(),
+                String::new(),
+                db.to_string(),
+                timeout.as_millis().to_string(),
+            ];
+            if replace {
+                args.push("replace".to_string());
+            }
+            args.push("keys".to_string());
+            args.extend(keys.iter().map(|k| k.to_string()));
+            match self.cmd(&args)$($await_)*?.into_string()?.as_str() {
+                "OK" => Ok(true),
+                "NOKEY" => Ok(false),
+                s => anyhow::bail!(
+                    "server replied with unexpected value {s:?}"
+                ),
+            }
+        }
+
+        // ## Hashes, lists and sets
+
+        /// `HSET key field value [field value ...]`. Returns how many
+        /// fields were added.
+        pub $($async_)? fn hset(
+            &self,
+            key: &str,
+            fields: &[(&str, &str)],
+        ) -> Result<i64> {
+            let mut args = vec!["hset", key];
+            for (field, value) in fields {
+                args.extend([field, value]);
+            }
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `HGET key field`.
+        pub $($async_)? fn hget(
+            &self,
+            key: &str,
+            field: &str,
+        ) -> Result<Option<String>> {
+            self.cmd(&["hget", key, field])$($await_)*?.into_opt_string()
+        }
+
+        /// `HDEL key field [field ...]`.
+        pub $($async_)? fn hdel(
+            &self,
+            key: &str,
+            fields: &[&str],
+        ) -> Result<i64> {
+            let mut args = vec!["hdel", key];
+            args.extend(fields);
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `HLEN key`.
+        pub $($async_)? fn hlen(&self, key: &str) -> Result<i64> {
+            self.cmd(&["hlen", key])$($await_)*?.into_int()
+        }
+
+        /// `HGETALL key`, as pairs of fields and values.
+        pub $($async_)? fn hgetall(
+            &self,
+            key: &str,
+        ) -> Result<Vec<(String, String)>> {
+            let items = self.cmd(&["hgetall", key])$($await_)*?.into_strings()?;
+            let mut items = items.into_iter();
+            let mut pairs = vec![];
+            while let (Some(field), Some(value)) = (items.next(), items.next())
+            {
+                pairs.push((field, value));
+            }
+            Ok(pairs)
+        }
+
+        /// `LPUSH key element [element ...]`. Returns the length of the
+        /// list.
+        pub $($async_)? fn lpush(
+            &self,
+            key: &str,
+            elements: &[&str],
+        ) -> Result<i64> {
+            let mut args = vec!["lpush", key];
+            args.extend(elements);
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `RPUSH key element [element ...]`. Returns the length of the
+        /// list.
+        pub $($async_)? fn rpush(
+            &self,
+            key: &str,
+            elements: &[&str],
+        ) -> Result<i64> {
+            let mut args = vec!["rpush", key];
+            args.extend(elements);
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `LPOP key`.
+        pub $($async_)? fn lpop(&self, key: &str) -> Result<Option<String>> {
+            self.cmd(&["lpop", key])$($await_)*?.into_opt_string()
+        }
+
+        /// `RPOP key`.
+        pub $($async_)? fn rpop(&self, key: &str) -> Result<Option<String>> {
+            self.cmd(&["rpop", key])$($await_)*?.into_opt_string()
+        }
+
+        /// `LLEN key`.
+        pub $($async_)? fn llen(&self, key: &str) -> Result<i64> {
+            self.cmd(&["llen", key])$($await_)*?.into_int()
+        }
+
+        /// `LRANGE key start stop`.
+        pub $($async_)? fn lrange(
+            &self,
+            key: &str,
+            start: i64,
+            stop: i64,
+        ) -> Result<Vec<String>> {
+            let (start, stop) = (start.to_string(), stop.to_string());
+            self.cmd(&["lrange", key, &start, &stop])
+                $($await_)*?
+                .into_strings()
+        }
+
+        /// `SADD key member [member ...]`. Returns how many members were
+        /// added.
+        pub $($async_)? fn sadd(
+            &self,
+            key: &str,
+            members: &[&str],
+        ) -> Result<i64> {
+            let mut args = vec!["sadd", key];
+            args.extend(members);
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `SREM key member [member ...]`.
+        pub $($async_)? fn srem(
+            &self,
+            key: &str,
+            members: &[&str],
+        ) -> Result<i64> {
+            let mut args = vec!["srem", key];
+            args.extend(members);
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `SISMEMBER key member`.
+        pub $($async_)? fn sismember(
+            &self,
+            key: &str,
+            member: &str,
+        ) -> Result<bool> {
+            self.cmd(&["sismember", key, member])$($await_)*?.into_bool()
+        }
+
+        /// `SCARD key`.
+        pub $($async_)? fn scard(&self, key: &str) -> Result<i64> {
+            self.cmd(&["scard", key])$($await_)*?.into_int()
+        }
+
+        /// `SMEMBERS key`, in no particular order.
+        pub $($async_)? fn smembers(&self, key: &str) -> Result<Vec<String>> {
+            self.cmd(&["smembers", key])$($await_)*?.into_strings()
+        }
+
+        // ## Bitmaps, HyperLogLogs and sorted sets
+
+        /// `SETBIT key offset value`. Returns the previous value of the bit.
+        pub $($async_)? fn setbit(
+            &self,
+            key: &str,
+            offset: u64,
+            value: bool,
+        ) -> Result<bool> {
+            let offset = offset.to_string();
+            let value = if value { "1" } else { "0" };
+            self.cmd(&["setbit", key, &offset, value])$($await_)*?.into_bool()
+        }
+
+        /// `GETBIT key offset`.
+        pub $($async_)? fn getbit(
+            &self,
+            key: &str,
+            offset: u64,
+        ) -> Result<bool> {
+            let offset = offset.to_string();
+            self.cmd(&["getbit", key, &offset])$($await_)*?.into_bool()
+        }
+
+        /// `BITCOUNT key`.
+        pub $($async_)? fn bitcount(&self, key: &str) -> Result<i64> {
+            self.cmd(&["bitcount", key])$($await_)*?.into_int()
+        }
+
+        /// `PFADD key [element ...]`. Returns whether the estimated
+        /// cardinality may have changed.
+        pub $($async_)? fn pfadd(
+            &self,
+            key: &str,
+            elements: &[&str],
+        ) -> Result<bool> {
+            let mut args = vec!["pfadd", key];
+            args.extend(elements);
+            self.cmd(&args)$($await_)*?.into_bool()
+        }
+
+        /// `PFCOUNT key [key ...]`.
+        pub $($async_)? fn pfcount(&self, keys: &[&str]) -> Result<i64> {
+            self.cmd(&command("pfcount", keys))$($await_)*?.into_int()
+        }
+
+        /// `PFMERGE destkey [sourcekey ...]`.
+        pub $($async_)? fn pfmerge(
+            &self,
+            dest: &str,
+            sources: &[&str],
+        ) -> Result<()> {
+            let mut args = vec!["pfmerge", dest];
+            args.extend(sources);
+            self.cmd(&args)$($await_)*?.into_ok()
+        }
+
+        /// `ZADD key score member [score member ...]`. Returns how many
+        /// members were added.
+        pub $($async_)? fn zadd(
+            &self,
+            key: &str,
+            members: &[(f64, &str)],
+        ) -> Result<i64> {
+            let mut args = vec!["zadd".to_string(), key.to_string()];
+            for (score, member) in members {
+                args.extend([score.to_string(), member.to_string()]);
+            }
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `ZSCORE key member`.
+        pub $($async_)? fn zscore(
+            &self,
+            key: &str,
+            member: &str,
+        ) -> Result<Option<f64>> {
+            let score = self
+                .cmd(&["zscore", key, member])
+                $($await_)*?
+                .into_opt_string()?;
+            score
+                .map(|s| {
+                    s.parse().with_context(|| format!("invalid score {s}"))
+                })
+                .transpose()
+        }
+
+        /// `ZREM key member [member ...]`.
+        pub $($async_)? fn zrem(
+            &self,
+            key: &str,
+            members: &[&str],
+        ) -> Result<i64> {
+            let mut args = vec!["zrem", key];
+            args.extend(members);
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `ZCARD key`.
+        pub $($async_)? fn zcard(&self, key: &str) -> Result<i64> {
+            self.cmd(&["zcard", key])$($await_)*?.into_int()
+        }
+
+        /// `GEOADD key longitude latitude member [...]`. Returns how many
+        /// members were added.
+        pub $($async_)? fn geoadd(
+            &self,
+            key: &str,
+            locations: &[(f64, f64, &str)],
+        ) -> Result<i64> {
+            let mut args = vec!["geoadd".to_string(), key.to_string()];
+            for (lon, lat, member) in locations {
+                args.extend([lon.to_string(), lat.to_string()]);
+                args.push(member.to_string());
+            }
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `GEODIST key member1 member2`, in meters.
+        pub $($async_)? fn geodist(
+            &self,
+            key: &str,
+            a: &str,
+            b: &str,
+        ) -> Result<Option<f64>> {
+            let dist = self
+                .cmd(&["geodist", key, a, b])
+                $($await_)*?
+                .into_opt_string()?;
+            dist.map(|d| {
+                d.parse().with_context(|| format!("invalid distance {d}"))
+            })
+            .transpose()
+        }
+
+        // ## Databases
+
+        /// `SWAPDB index1 index2`.
+        pub $($async_)? fn swapdb(&self, a: usize, b: usize) -> Result<()> {
+            let (a, b) = (a.to_string(), b.to_string());
+            self.cmd(&["swapdb", &a, &b])$($await_)*?.into_ok()
+        }
+
+        /// `DBSIZE`.
+        pub $($async_)? fn dbsize(&self) -> Result<i64> {
+            self.cmd(&["dbsize"])$($await_)*?.into_int()
+        }
+
+        /// `FLUSHDB`.
+        pub $($async_)? fn flushdb(&self) -> Result<()> {
+            self.cmd(&["flushdb"])$($await_)*?.into_ok()
+        }
+
+        /// `FLUSHALL`.
+        pub $($async_)? fn flushall(&self) -> Result<()> {
+            self.cmd(&["flushall"])$($await_)*?.into_ok()
+        }
+
+        // ## Server
+
+        /// `INFO [section]`.
+        pub $($async_)? fn info(
+            &self,
+            section: Option<&str>,
+        ) -> Result<String> {
+            let mut args = vec!["info"];
+            args.extend(section);
+            self.cmd(&args)$($await_)*?.into_string()
+        }
+
+        /// `CONFIG GET pattern`, as (parameter, value) pairs.
+        pub $($async_)? fn config_get(
+            &self,
+            pattern: &str,
+        ) -> Result<Vec<(String, String)>> {
+            let v = self
+                .cmd(&["config", "get", pattern])
+                $($await_)*?
+                .into_strings()?;
+            let mut v = v.into_iter();
+            let mut pairs = vec![];
+            while let (Some(k), Some(v)) = (v.next(), v.next()) {
+                pairs.push((k, v));
+            }
+            Ok(pairs)
+        }
+
+        /// `CONFIG SET parameter value`.
+        pub $($async_)? fn config_set(
+            &self,
+            param: &str,
+            value: &str,
+        ) -> Result<()> {
+            self.cmd(&["config", "set", param, value])$($await_)*?.into_ok()
+        }
+
+        /// `CONFIG REWRITE`.
+        pub $($async_)? fn config_rewrite(&self) -> Result<()> {
+            self.cmd(&["config", "rewrite"])$($await_)*?.into_ok()
+        }
+
+        /// `CONFIG RESETSTAT`.
+        pub $($async_)? fn config_resetstat(&self) -> Result<()> {
+            self.cmd(&["config", "resetstat"])$($await_)*?.into_ok()
+        }
+
+        /// `CLIENT ID`, of one of the connections of the pool.
+        pub $($async_)? fn client_id(&self) -> Result<i64> {
+            self.cmd(&["client", "id"])$($await_)*?.into_int()
+        }
+
+        /// `CLIENT GETNAME`.
+        pub $($async_)? fn client_getname(&self) -> Result<Option<String>> {
+            self.cmd(&["client", "getname"])$($await_)*?.into_opt_string()
+        }
+
+        /// `CLIENT LIST`.
+        pub $($async_)? fn client_list(&self) -> Result<String> {
+            self.cmd(&["client", "list"])$($await_)*?.into_string()
+        }
+
+        /// `CLIENT KILL ID id`. Returns whether the client existed.
+        pub $($async_)? fn client_kill(&self, id: u64) -> Result<bool> {
+            let id = id.to_string();
+            self.cmd(&["client", "kill", "id", &id])$($await_)*?.into_bool()
+        }
+
+        /// `SLOWLOG GET [count]`.
+        pub $($async_)? fn slowlog_get(
+            &self,
+            count: Option<i64>,
+        ) -> Result<Vec<SlowlogEntry>> {
+            let count = count.map(|c| c.to_string());
+            let mut args = vec!["slowlog", "get"];
+            args.extend(count.as_deref());
+            match self.cmd(&args)$($await_)*? {
+                Value::Bulk(entries) => {
+                    entries.into_iter().map(SlowlogEntry::from_value).collect()
+                }
+                v => unexpected(v),
+            }
+        }
+
+        /// `SLOWLOG LEN`.
+        pub $($async_)? fn slowlog_len(&self) -> Result<i64> {
+            self.cmd(&["slowlog", "len"])$($await_)*?.into_int()
+        }
+
+        /// `SLOWLOG RESET`.
+        pub $($async_)? fn slowlog_reset(&self) -> Result<()> {
+            self.cmd(&["slowlog", "reset"])$($await_)*?.into_ok()
+        }
+
+        // ## Pub/sub
+
+        /// `PUBLISH channel message`. Returns how many clients received it.
+        pub $($async_)? fn publish(
+            &self,
+            channel: &str,
+            message: impl AsRef<[u8]>,
+        ) -> Result<i64> {
+            let args = [&b"publish"[..], channel.as_bytes(), message.as_ref()];
+            self.cmd(&args)$($await_)*?.into_int()
+        }
+
+        /// `PUBSUB CHANNELS [pattern]`.
+        pub $($async_)? fn pubsub_channels(
+            &self,
+            pattern: Option<&str>,
+        ) -> Result<Vec<String>> {
+            let mut args = vec!["pubsub", "channels"];
+            args.extend(pattern);
+            self.cmd(&args)$($await_)*?.into_strings()
+        }
+
+        /// `PUBSUB NUMSUB [channel ...]`.
+        pub $($async_)? fn pubsub_numsub(
+            &self,
+            channels: &[&str],
+        ) -> Result<Vec<(String, i64)>> {
+            let mut args = vec!["pubsub", "numsub"];
+            args.extend(channels);
+            let Value::Bulk(v) = self.cmd(&args)$($await_)*? else {
+                anyhow::bail!("server replied with unexpected value");
+            };
+            let mut v = v.into_iter();
+            let mut pairs = vec![];
+            while let (Some(c), Some(n)) = (v.next(), v.next()) {
+                pairs.push((c.into_string()?, n.into_int()?));
+            }
+            Ok(pairs)
+        }
+
+        /// `PUBSUB NUMPAT`.
+        pub $($async_)? fn pubsub_numpat(&self) -> Result<i64> {
+            self.cmd(&["pubsub", "numpat"])$($await_)*?.into_int()
+        }
+
+        // ## Scripting
+
+        /// `EVAL script numkeys [key ...] [arg ...]`.
+        pub $($async_)? fn eval(
+            &self,
+            script: &str,
+            keys: &[&str],
+            args: &[&str],
+        ) -> Result<Value> {
+            self.cmd(&script_command("eval", script, keys, args))$($await_)*
+        }
+
+        /// `EVALSHA sha1 numkeys [key ...] [arg ...]`.
+        pub $($async_)? fn evalsha(
+            &self,
+            sha: &str,
+            keys: &[&str],
+            args: &[&str],
+        ) -> Result<Value> {
+            self.cmd(&script_command("evalsha", sha, keys, args))$($await_)*
+        }
+
+        /// `SCRIPT LOAD script`. Returns the SHA1 of the script.
+        pub $($async_)? fn script_load(&self, script: &str) -> Result<String> {
+            self.cmd(&["script", "load", script])$($await_)*?.into_string()
+        }
+
+        /// `SCRIPT EXISTS sha1 [sha1 ...]`.
+        pub $($async_)? fn script_exists(
+            &self,
+            shas: &[&str],
+        ) -> Result<Vec<bool>> {
+            let mut args = vec!["script", "exists"];
+            args.extend(shas);
+            match self.cmd(&args)$($await_)*? {
+                Value::Bulk(v) => v.into_iter().map(Value::into_bool).collect(),
+                v => unexpected(v),
+            }
+        }
+
+        /// `SCRIPT FLUSH`.
+        pub $($async_)? fn script_flush(&self) -> Result<()> {
+            self.cmd(&["script", "flush"])$($await_)*?.into_ok()
+        }
+    };
+}
+
+/// Methods of [`Subscription`] and [`blocking::Subscription`].
+macro_rules! subscription_commands {
+    ($($async_:ident)?; $($await_:tt)*) => {
+        /// `SUBSCRIBE channel [channel ...]`.
+        pub $($async_)? fn subscribe(
+            &mut self,
+            channels: &[&str],
+        ) -> Result<()> {
+            self.conn.send(&command("subscribe", channels))$($await_)*?;
+            self.confirm(channels.len())$($await_)*?;
+            self.channels.extend(channels.iter().map(|c| c.to_string()));
+            Ok(())
+        }
+
+        /// `PSUBSCRIBE pattern [pattern ...]`.
+        pub $($async_)? fn psubscribe(
+            &mut self,
+            patterns: &[&str],
+        ) -> Result<()> {
+            self.conn.send(&command("psubscribe", patterns))$($await_)*?;
+            self.confirm(patterns.len())$($await_)*?;
+            self.patterns.extend(patterns.iter().map(|p| p.to_string()));
+            Ok(())
+        }
+
+        /// `UNSUBSCRIBE [channel ...]`. No channel means all of them.
+        pub $($async_)? fn unsubscribe(
+            &mut self,
+            channels: &[&str],
+        ) -> Result<()> {
+            self.conn.send(&command("unsubscribe", channels))$($await_)*?;
+            let n = match channels {
+                [] => self.channels.len().max(1),
+                _ => channels.len(),
+            };
+            self.confirm(n)$($await_)*?;
+            match channels {
+                [] => self.channels.clear(),
+                _ => channels.iter().for_each(|c| {
+                    self.channels.remove(*c);
+                }),
+            }
+            Ok(())
+        }
+
+        /// `PUNSUBSCRIBE [pattern ...]`. No pattern means all of them.
+        pub $($async_)? fn punsubscribe(
+            &mut self,
+            patterns: &[&str],
+        ) -> Result<()> {
+            self.conn.send(&command("punsubscribe", patterns))$($await_)*?;
+            let n = match patterns {
+                [] => self.patterns.len().max(1),
+                _ => patterns.len(),
+            };
+            self.confirm(n)$($await_)*?;
+            match patterns {
+                [] => self.patterns.clear(),
+                _ => patterns.iter().for_each(|p| {
+                    self.patterns.remove(*p);
+                }),
+            }
+            Ok(())
+        }
+
+        /// Wait for the next message.
+        pub $($async_)? fn next_message(&mut self) -> Result<Message> {
+            if let Some(msg) = self.pending.pop_front() {
+                return Ok(msg);
+            }
+            loop {
+                let v = self.conn.recv()$($await_)*?.into_result()?;
+                if let Some(msg) = Message::from_value(&v)? {
+                    return Ok(msg);
+                }
+            }
+        }
+
+        /// Read `n` confirmations, queuing the messages received meanwhile.
+        $($async_)? fn confirm(&mut self, n: usize) -> Result<()> {
+            let mut n_confirmed = 0;
+            while n_confirmed < n {
+                let v = self.conn.recv()$($await_)*?.into_result()?;
+                match Message::from_value(&v)? {
+                    Some(msg) => self.pending.push_back(msg),
+                    None => n_confirmed += 1,
+                }
+            }
+            Ok(())
+        }
+    };
 }
 
-impl<'a> Client<'a> {
-    pub fn new(sock: &'a mut TcpStream, addr: SocketAddr) -> Self {
-        let conn = Conn::new(sock, addr);
-        Self { conn }
+pub mod blocking;
+
+/// Error replied by the server.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServerError(pub String);
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
     }
+}
+
+impl std::error::Error for ServerError {}
 
-    pub async fn q_get<'are>(
+/// The server closed the connection.
+#[derive(Debug)]
+struct Closed;
+
+impl std::fmt::Display for Closed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("connection closed by the server")
+    }
+}
+
+impl std::error::Error for Closed {}
+
+/// Client options.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Maximum number of connections in the pool. Commands wait for a
+    /// connection to be available past that.
+    pub pool_size: usize,
+    /// Database selected on each connection.
+    pub db: usize,
+    /// Name set with `CLIENT SETNAME` on each connection.
+    pub name: Option<String>,
+    /// Timeout for establishing a connection.
+    pub connect_timeout: Option<Duration>,
+    /// Timeout for a command, from sending it to reading its reply.
+    pub timeout: Option<Duration>,
+    /// Number of attempts to connect after the first one fails.
+    pub retries: u32,
+    /// Delay before the first retry; it doubles after each attempt.
+    pub backoff: Duration,
+    /// Maximum delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            pool_size: 8,
+            db: 0,
+            name: None,
+            connect_timeout: Some(Duration::from_secs(5)),
+            timeout: None,
+            retries: 5,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A single connection to the server.
+pub struct Connection {
+    sock: TcpStream,
+    /// Bytes read but not decoded yet.
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+}
+
+impl Connection {
+    /// Connect to `addr` and prepare the connection according to `opts`
+    /// (but without retrying).
+    pub async fn connect(addr: &str, opts: &Options) -> Result<Self> {
+        let sock = with_timeout(opts.connect_timeout, async {
+            TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("connecting to {addr}"))
+        })
+        .await?;
+        sock.set_nodelay(true)?;
+        let mut conn = Self {
+            sock,
+            rbuf: Vec::with_capacity(16 * 1024),
+            wbuf: vec![],
+        };
+
+        if opts.db != 0 {
+            let db = opts.db.to_string();
+            conn.request(&["select", &db]).await?.into_result()?;
+        }
+        if let Some(name) = &opts.name {
+            conn.request(&["client", "setname", name])
+                .await?
+                .into_result()?;
+        }
+        Ok(conn)
+    }
+
+    /// Send a command without waiting for the reply.
+    pub async fn send<S: AsRef<[u8]>>(&mut self, args: &[S]) -> Result<()> {
+        self.wbuf.clear();
+        wire::encode_command(args, &mut self.wbuf);
+        self.sock.write_all(&self.wbuf).await?;
+        Ok(())
+    }
+
+    /// Send several commands in a single write.
+    pub async fn send_all<S: AsRef<[u8]>>(
         &mut self,
-        key: &str,
-        arena: &'are bumpalo::Bump,
-    ) -> Result<&'are str> {
-        let query = Frame::Bulk(arena.alloc_slice_copy(&[
-            Frame::String(arena.alloc_str("get")),
-            Frame::String(arena.alloc_str(key)),
-        ]));
-        wire::write_frame(&mut self.conn, &query).await?;
+        cmds: &[Vec<S>],
+    ) -> Result<()> {
+        self.wbuf.clear();
+        for args in cmds {
+            wire::encode_command(args, &mut self.wbuf);
+        }
+        self.sock.write_all(&self.wbuf).await?;
+        Ok(())
+    }
 
-        let res = wire::read_frame(&mut self.conn, arena).await?;
-        match res {
-            Some(Frame::String(s)) => Ok(s),
-            Some(Frame::Error(e)) => {
-                anyhow::bail!("server replied with error {e}")
+    /// Read the next value sent by the server.
+    pub async fn recv(&mut self) -> Result<Value> {
+        loop {
+            if let Some((v, n)) = wire::decode(&self.rbuf)? {
+                self.rbuf.drain(..n);
+                return Ok(v);
             }
-            Some(f) => {
-                anyhow::bail!("server replied with unexpected frame {f:?}")
+            if self.sock.read_buf(&mut self.rbuf).await? == 0 {
+                return Err(Closed.into());
             }
-            None => anyhow::bail!("could not read a frame"),
         }
     }
 
-    pub async fn q_set<'are>(
+    /// Send a command and read its reply. Error replies are returned as
+    /// [`Value::Error`].
+    pub async fn request<S: AsRef<[u8]>>(
         &mut self,
-        key: &str,
-        value: &str,
-        arena: &'are bumpalo::Bump,
-    ) -> Result<bool> {
-        let query = Frame::Bulk(arena.alloc_slice_copy(&[
-            Frame::String(arena.alloc_str("set")),
-            Frame::String(arena.alloc_str(key)),
-            Frame::String(arena.alloc_str(value)),
-        ]));
-        wire::write_frame(&mut self.conn, &query).await?;
-
-        let res = wire::read_frame(&mut self.conn, arena).await?;
-        match res {
-            Some(Frame::String("OK")) => Ok(true),
-            Some(Frame::Error(e)) => {
-                anyhow::bail!("server replied with error {e}")
-            }
-            Some(f) => {
-                anyhow::bail!("server replied with unexpected frame {f:?}")
-            }
-            None => anyhow::bail!("could not read a frame"),
+        args: &[S],
+    ) -> Result<Value> {
+        self.send(args).await?;
+        self.recv().await
+    }
+}
+
+/// Pooled async client.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    addr: String,
+    opts: Options,
+    /// Connections not in use.
+    idle: Mutex<Vec<Connection>>,
+    /// One permit per connection of the pool.
+    permits: Semaphore,
+}
+
+impl Client {
+    /// Client for the server at `addr`, with default options.
+    ///
+    /// Connections are only opened when needed; use [`Client::connect`]
+    /// to check that the server is reachable.
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self::with_options(addr, Options::default())
+    }
+
+    pub fn with_options(addr: impl Into<String>, opts: Options) -> Self {
+        let permits = Semaphore::new(opts.pool_size.max(1));
+        Self {
+            inner: Arc::new(Inner {
+                addr: addr.into(),
+                opts,
+                idle: Mutex::new(vec![]),
+                permits,
+            }),
         }
     }
+
+    /// Client for the server at `addr`, after opening a first connection.
+    pub async fn connect(addr: impl Into<String>) -> Result<Self> {
+        let client = Self::new(addr);
+        let conn = client.inner.connect().await?;
+        client.inner.idle.lock().unwrap().push(conn);
+        Ok(client)
+    }
+
+    /// Address of the server.
+    pub fn addr(&self) -> &str {
+        &self.inner.addr
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.inner.opts
+    }
+
+    /// Run any command, and return its reply. Error replies are returned
+    /// as a [`ServerError`].
+    pub async fn cmd<S: AsRef<[u8]>>(&self, args: &[S]) -> Result<Value> {
+        let inner = &*self.inner;
+        let _permit = inner.permits.acquire().await?;
+
+        let idle = inner.idle.lock().unwrap().pop();
+        let (mut conn, pooled) = match idle {
+            Some(conn) => (conn, true),
+            None => (inner.connect().await?, false),
+        };
+        let reply = match inner.request(&mut conn, args).await {
+            Err(e) if pooled && is_disconnect(&e) => {
+                // the server closed the connection while it was idle
+                log::debug!("reconnecting to {}: {e}", inner.addr);
+                conn = inner.connect().await?;
+                inner.request(&mut conn, args).await?
+            }
+            res => res?,
+        };
+        inner.idle.lock().unwrap().push(conn);
+        reply.into_result()
+    }
+
+    /// Send several commands at once, and return their replies in order.
+    /// Error replies are returned as [`Value::Error`].
+    pub async fn pipeline<S: AsRef<[u8]>>(
+        &self,
+        cmds: &[Vec<S>],
+    ) -> Result<Vec<Value>> {
+        let inner = &*self.inner;
+        let _permit = inner.permits.acquire().await?;
+        let idle = inner.idle.lock().unwrap().pop();
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => inner.connect().await?,
+        };
+        let replies = with_timeout(inner.opts.timeout, async {
+            conn.send_all(cmds).await?;
+            let mut replies = Vec::with_capacity(cmds.len());
+            for _ in cmds {
+                replies.push(conn.recv().await?);
+            }
+            Ok(replies)
+        })
+        .await?;
+        inner.idle.lock().unwrap().push(conn);
+        Ok(replies)
+    }
+
+    /// Open a dedicated connection for pub/sub.
+    pub async fn subscribe(&self) -> Result<Subscription> {
+        Ok(Subscription {
+            conn: self.inner.connect().await?,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Open a dedicated connection that receives the commands the server
+    /// runs, with `MONITOR`.
+    pub async fn monitor(&self) -> Result<Monitor> {
+        let mut conn = self.inner.connect().await?;
+        conn.request(&["monitor"]).await?.into_ok()?;
+        Ok(Monitor { conn })
+    }
+
+    typed_commands!(async; .await);
+}
+
+impl Inner {
+    /// Open a new connection, retrying with exponential backoff.
+    async fn connect(&self) -> Result<Connection> {
+        let mut delay = self.opts.backoff;
+        let mut attempt = 0;
+        loop {
+            match Connection::connect(&self.addr, &self.opts).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) if attempt < self.opts.retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "could not connect to {} (attempt {attempt}): {e:#}",
+                        self.addr
+                    );
+                    // jitter, so clients don't all come back at once
+                    let jitter = delay.mul_f64(fastrand::f64() * 0.25);
+                    tokio::time::sleep(delay + jitter).await;
+                    delay = (delay * 2).min(self.opts.max_backoff);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn request<S: AsRef<[u8]>>(
+        &self,
+        conn: &mut Connection,
+        args: &[S],
+    ) -> Result<Value> {
+        with_timeout(self.opts.timeout, conn.request(args)).await
+    }
+}
+
+/// A dedicated connection in pub/sub mode.
+pub struct Subscription {
+    conn: Connection,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    /// Messages received while waiting for a confirmation.
+    pending: VecDeque<Message>,
+}
+
+/// A message received by a [`Subscription`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    /// The pattern that matched, for `PSUBSCRIBE`.
+    pub pattern: Option<String>,
+    pub channel: String,
+    pub payload: Vec<u8>,
+}
+
+impl Subscription {
+    subscription_commands!(async; .await);
+}
+
+/// A dedicated connection in `MONITOR` mode.
+pub struct Monitor {
+    conn: Connection,
+}
+
+impl Monitor {
+    /// Wait for the next command, described like in [`crate::monitor`].
+    pub async fn next_line(&mut self) -> Result<String> {
+        self.conn.recv().await?.into_result()?.into_string()
+    }
+}
+
+impl Message {
+    /// Parse a value received in pub/sub mode. Returns `None` for
+    /// confirmations of (un)subscriptions.
+    fn from_value(v: &Value) -> Result<Option<Self>> {
+        use Value::String as S;
+        let Value::Bulk(items) = v else {
+            anyhow::bail!("server replied with unexpected value {v:?}");
+        };
+        match &items[..] {
+            [S(kind), S(channel), payload] if kind == "message" => {
+                Ok(Some(Self {
+                    pattern: None,
+                    channel: channel.clone(),
+                    payload: payload.clone().into_bytes()?,
+                }))
+            }
+            [S(kind), S(pattern), S(channel), payload]
+                if kind == "pmessage" =>
+            {
+                Ok(Some(Self {
+                    pattern: Some(pattern.clone()),
+                    channel: channel.clone(),
+                    payload: payload.clone().into_bytes()?,
+                }))
+            }
+            [S(_), _, Value::Int(_)] => Ok(None),
+            _ => anyhow::bail!("server replied with unexpected value {v:?}"),
+        }
+    }
+}
+
+/// An entry of `SLOWLOG GET`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SlowlogEntry {
+    pub id: i64,
+    /// Unix timestamp, in seconds.
+    pub timestamp: i64,
+    /// Execution time, in microseconds.
+    pub duration: i64,
+    pub args: Vec<String>,
+    pub addr: String,
+    pub client_name: String,
+}
+
+impl SlowlogEntry {
+    fn from_value(v: Value) -> Result<Self> {
+        let Value::Bulk(fields) = v else {
+            return unexpected(v);
+        };
+        match <[Value; 6]>::try_from(fields) {
+            Ok([id, timestamp, duration, args, addr, client_name]) => {
+                Ok(Self {
+                    id: id.into_int()?,
+                    timestamp: timestamp.into_int()?,
+                    duration: duration.into_int()?,
+                    args: args.into_strings()?,
+                    addr: addr.into_string()?,
+                    client_name: client_name.into_string()?,
+                })
+            }
+            Err(fields) => unexpected(Value::Bulk(fields)),
+        }
+    }
+}
+
+/// Conversions of replies.
+impl Value {
+    /// Turn an error reply into a [`ServerError`].
+    pub fn into_result(self) -> Result<Value> {
+        match self {
+            Value::Error(e) => Err(ServerError(e).into()),
+            v => Ok(v),
+        }
+    }
+
+    pub fn into_int(self) -> Result<i64> {
+        match self {
+            Value::Int(i) => Ok(i),
+            v => unexpected(v),
+        }
+    }
+
+    pub fn into_bool(self) -> Result<bool> {
+        self.into_int().map(|i| i != 0)
+    }
+
+    pub fn into_string(self) -> Result<String> {
+        match self {
+            Value::String(s) => Ok(s),
+            v => unexpected(v),
+        }
+    }
+
+    pub fn into_opt_string(self) -> Result<Option<String>> {
+        match self {
+            Value::Null => Ok(None),
+            v => v.into_string().map(Some),
+        }
+    }
+
+    pub fn into_bytes(self) -> Result<Vec<u8>> {
+        match self {
+            Value::String(s) => Ok(s.into_bytes()),
+            Value::Bytes(b) => Ok(b),
+            v => unexpected(v),
+        }
+    }
+
+    pub fn into_opt_bytes(self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Value::Null => Ok(None),
+            v => v.into_bytes().map(Some),
+        }
+    }
+
+    pub fn into_strings(self) -> Result<Vec<String>> {
+        match self {
+            Value::Bulk(v) => v.into_iter().map(Value::into_string).collect(),
+            v => unexpected(v),
+        }
+    }
+
+    /// Expect an `OK` reply.
+    pub fn into_ok(self) -> Result<()> {
+        match self {
+            Value::String(s) if s == "OK" => Ok(()),
+            v => unexpected(v),
+        }
+    }
+}
+
+fn unexpected<T>(v: Value) -> Result<T> {
+    match v {
+        Value::Error(e) => Err(ServerError(e).into()),
+        v => anyhow::bail!("server replied with unexpected value {v:?}"),
+    }
+}
+
+/// `name` followed by `args`.
+fn command<'a>(name: &'a str, args: &[&'a str]) -> Vec<&'a str> {
+    let mut cmd = Vec::with_capacity(args.len() + 1);
+    cmd.push(name);
+    cmd.extend(args);
+    cmd
+}
+
+/// `EVAL`/`EVALSHA` arguments.
+fn script_command(
+    name: &str,
+    script: &str,
+    keys: &[&str],
+    args: &[&str],
+) -> Vec<String> {
+    let mut cmd = vec![name.to_string(), script.to_string()];
+    cmd.push(keys.len().to_string());
+    cmd.extend(keys.iter().chain(args).map(|a| a.to_string()));
+    cmd
+}
+
+/// Did `e` happen because the connection was closed?
+fn is_disconnect(e: &anyhow::Error) -> bool {
+    e.is::<Closed>()
+        || e.downcast_ref::<io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+            )
+        })
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(d) => tokio::time::timeout(d, fut)
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out after {d:?}"))?,
+        None => fut.await,
+    }
 }
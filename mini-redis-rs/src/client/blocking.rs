@@ -0,0 +1,273 @@
+//! Blocking client, for callers without an async runtime.
+//!
+//! It has the same typed commands as the async [`super::Client`], over a
+//! single [`std::net::TcpStream`] that is re-opened when the server goes
+//! away. [`Options::pool_size`] is ignored.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use super::{
+    command, is_disconnect, script_command, unexpected, Closed, Message,
+    Options, SlowlogEntry,
+};
+use crate::wire::{self, Value};
+
+/// A single connection to the server.
+pub struct Connection {
+    sock: TcpStream,
+    /// Bytes read but not decoded yet.
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+}
+
+impl Connection {
+    /// Connect to `addr` and prepare the connection according to `opts`
+    /// (but without retrying).
+    pub fn connect(addr: &str, opts: &Options) -> Result<Self> {
+        let sock = match opts.connect_timeout {
+            Some(timeout) => {
+                let mut last_err = None;
+                let mut sock = None;
+                for a in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&a, timeout) {
+                        Ok(s) => {
+                            sock = Some(s);
+                            break;
+                        }
+                        Err(e) => last_err = Some(e),
+                    }
+                }
+                match (sock, last_err) {
+                    (Some(sock), _) => sock,
+                    (None, Some(e)) => return Err(e.into()),
+                    (None, None) => anyhow::bail!("no address for {addr}"),
+                }
+            }
+            None => TcpStream::connect(addr)?,
+        };
+        sock.set_nodelay(true)?;
+        sock.set_read_timeout(opts.timeout)?;
+        sock.set_write_timeout(opts.timeout)?;
+        let mut conn = Self {
+            sock,
+            rbuf: Vec::with_capacity(16 * 1024),
+            wbuf: vec![],
+        };
+
+        if opts.db != 0 {
+            let db = opts.db.to_string();
+            conn.request(&["select", &db])?.into_result()?;
+        }
+        if let Some(name) = &opts.name {
+            conn.request(&["client", "setname", name])?.into_result()?;
+        }
+        Ok(conn)
+    }
+
+    /// Send a command without waiting for the reply.
+    pub fn send<S: AsRef<[u8]>>(&mut self, args: &[S]) -> Result<()> {
+        self.wbuf.clear();
+        wire::encode_command(args, &mut self.wbuf);
+        self.sock.write_all(&self.wbuf)?;
+        Ok(())
+    }
+
+    /// Send several commands in a single write.
+    pub fn send_all<S: AsRef<[u8]>>(&mut self, cmds: &[Vec<S>]) -> Result<()> {
+        self.wbuf.clear();
+        for args in cmds {
+            wire::encode_command(args, &mut self.wbuf);
+        }
+        self.sock.write_all(&self.wbuf)?;
+        Ok(())
+    }
+
+    /// Read the next value sent by the server.
+    pub fn recv(&mut self) -> Result<Value> {
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            if let Some((v, n)) = wire::decode(&self.rbuf)? {
+                self.rbuf.drain(..n);
+                return Ok(v);
+            }
+            match self.sock.read(&mut chunk) {
+                Ok(0) => return Err(Closed.into()),
+                Ok(n) => self.rbuf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    anyhow::bail!("timed out")
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Send a command and read its reply. Error replies are returned as
+    /// [`Value::Error`].
+    pub fn request<S: AsRef<[u8]>>(&mut self, args: &[S]) -> Result<Value> {
+        self.send(args)?;
+        self.recv()
+    }
+}
+
+/// Blocking client.
+pub struct Client {
+    addr: String,
+    opts: Options,
+    /// `None` until the first command, and after an error.
+    conn: Mutex<Option<Connection>>,
+}
+
+impl Client {
+    /// Client for the server at `addr`, with default options.
+    ///
+    /// The connection is only opened when needed; use [`Client::connect`]
+    /// to check that the server is reachable.
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self::with_options(addr, Options::default())
+    }
+
+    pub fn with_options(addr: impl Into<String>, opts: Options) -> Self {
+        Self {
+            addr: addr.into(),
+            opts,
+            conn: Mutex::new(None),
+        }
+    }
+
+    /// Client for the server at `addr`, after opening the connection.
+    pub fn connect(addr: impl Into<String>) -> Result<Self> {
+        let client = Self::new(addr);
+        *client.conn.lock().unwrap() = Some(client.open()?);
+        Ok(client)
+    }
+
+    /// Address of the server.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.opts
+    }
+
+    /// Run any command, and return its reply. Error replies are returned
+    /// as a [`super::ServerError`].
+    pub fn cmd<S: AsRef<[u8]>>(&self, args: &[S]) -> Result<Value> {
+        let mut guard = self.conn.lock().unwrap();
+        let (conn, pooled) = match guard.take() {
+            Some(conn) => (guard.insert(conn), true),
+            None => (guard.insert(self.open()?), false),
+        };
+        let reply = match conn.request(args) {
+            Err(e) if pooled && is_disconnect(&e) => {
+                // the server closed the connection while it was idle
+                log::debug!("reconnecting to {}: {e}", self.addr);
+                *guard = None;
+                let conn = guard.insert(self.open()?);
+                conn.request(args)
+            }
+            res => res,
+        };
+        if reply.is_err() {
+            // the connection is in an unknown state
+            *guard = None;
+        }
+        reply?.into_result()
+    }
+
+    /// Send several commands at once, and return their replies in order.
+    /// Error replies are returned as [`Value::Error`].
+    pub fn pipeline<S: AsRef<[u8]>>(
+        &self,
+        cmds: &[Vec<S>],
+    ) -> Result<Vec<Value>> {
+        let mut guard = self.conn.lock().unwrap();
+        let conn = match guard.take() {
+            Some(conn) => guard.insert(conn),
+            None => guard.insert(self.open()?),
+        };
+        let replies = conn
+            .send_all(cmds)
+            .and_then(|()| cmds.iter().map(|_| conn.recv()).collect());
+        if replies.is_err() {
+            *guard = None;
+        }
+        replies
+    }
+
+    /// Open a dedicated connection for pub/sub.
+    pub fn subscribe(&self) -> Result<Subscription> {
+        Ok(Subscription {
+            conn: self.open()?,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Open a dedicated connection that receives the commands the server
+    /// runs, with `MONITOR`.
+    pub fn monitor(&self) -> Result<Monitor> {
+        let mut conn = self.open()?;
+        conn.request(&["monitor"])?.into_ok()?;
+        Ok(Monitor { conn })
+    }
+
+    typed_commands!(; );
+
+    /// Open a new connection, retrying with exponential backoff.
+    fn open(&self) -> Result<Connection> {
+        let mut delay = self.opts.backoff;
+        let mut attempt = 0;
+        loop {
+            match Connection::connect(&self.addr, &self.opts) {
+                Ok(conn) => return Ok(conn),
+                Err(e) if attempt < self.opts.retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "could not connect to {} (attempt {attempt}): {e:#}",
+                        self.addr
+                    );
+                    let jitter = delay.mul_f64(fastrand::f64() * 0.25);
+                    std::thread::sleep(delay + jitter);
+                    delay = (delay * 2).min(self.opts.max_backoff);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A dedicated connection in pub/sub mode.
+pub struct Subscription {
+    conn: Connection,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    /// Messages received while waiting for a confirmation.
+    pending: VecDeque<Message>,
+}
+
+impl Subscription {
+    subscription_commands!(; );
+}
+
+/// A dedicated connection in `MONITOR` mode.
+pub struct Monitor {
+    conn: Connection,
+}
+
+impl Monitor {
+    /// Wait for the next command, described like in [`crate::monitor`].
+    pub fn next_line(&mut self) -> Result<String> {
+        self.conn.recv()?.into_result()?.into_string()
+    }
+}
@@ -0,0 +1,345 @@
+//! Registry of connected clients, for `CLIENT LIST/KILL` and `INFO`, and
+//! their output buffer limits.
+
+use std::{
+    fmt::{self, Write as _},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use dashmap::DashMap;
+use tokio::sync::Notify;
+
+use crate::{db::now_ms, pubsub};
+
+/// Class of a client, for `client-output-buffer-limit`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Class {
+    Normal,
+    /// Replicas. There is no replication yet, so no client is in this
+    /// class, but its limits can be configured like in redis.
+    Replica,
+    /// Clients subscribed to channels or patterns.
+    PubSub,
+}
+
+impl Class {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Class::Normal => "normal",
+            // `CONFIG GET` still uses the old name in redis
+            Class::Replica => "slave",
+            Class::PubSub => "pubsub",
+        }
+    }
+}
+
+impl FromStr for Class {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match &*s.to_ascii_lowercase() {
+            "normal" => Class::Normal,
+            "replica" | "slave" => Class::Replica,
+            "pubsub" => Class::PubSub,
+            _ => anyhow::bail!("invalid client class {s:?}"),
+        })
+    }
+}
+
+/// Output buffer limit of a class of clients. A limit of 0 is disabled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BufferLimit {
+    /// Close the client as soon as its output buffer reaches this size.
+    pub hard: u64,
+    /// Close the client if its output buffer stays at least this large
+    /// for `soft_seconds`.
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+/// Value of `client-output-buffer-limit`: the limits of each class.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OutputBufferLimits {
+    pub normal: BufferLimit,
+    pub replica: BufferLimit,
+    pub pubsub: BufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        const MB: u64 = 1024 * 1024;
+        Self {
+            normal: BufferLimit::default(),
+            replica: BufferLimit {
+                hard: 256 * MB,
+                soft: 64 * MB,
+                soft_seconds: 60,
+            },
+            pubsub: BufferLimit {
+                hard: 32 * MB,
+                soft: 8 * MB,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    pub fn get(&self, class: Class) -> BufferLimit {
+        match class {
+            Class::Normal => self.normal,
+            Class::Replica => self.replica,
+            Class::PubSub => self.pubsub,
+        }
+    }
+
+    pub fn get_mut(&mut self, class: Class) -> &mut BufferLimit {
+        match class {
+            Class::Normal => &mut self.normal,
+            Class::Replica => &mut self.replica,
+            Class::PubSub => &mut self.pubsub,
+        }
+    }
+}
+
+impl fmt::Display for OutputBufferLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, class) in [Class::Normal, Class::Replica, Class::PubSub]
+            .into_iter()
+            .enumerate()
+        {
+            let l = self.get(class);
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            let name = class.as_str();
+            write!(f, "{name} {} {} {}", l.hard, l.soft, l.soft_seconds)?;
+        }
+        Ok(())
+    }
+}
+
+/// Source of unique client IDs.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Information about a connected client, shared between its handler and
+/// the registry.
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    /// Name set by `CLIENT SETNAME`.
+    pub name: Mutex<String>,
+    created: Instant,
+    /// Time of the last command, in milliseconds since the UNIX epoch.
+    last_interaction: AtomicU64,
+    /// Name of the last command.
+    last_cmd: Mutex<&'static str>,
+    /// Selected database.
+    db: AtomicUsize,
+    /// Notified when the client is killed.
+    pub(crate) killed: Notify,
+    /// Queue of messages to push to the client.
+    tx: pubsub::Sender,
+    /// Is the client subscribed to channels or patterns?
+    subscribed: AtomicBool,
+    /// Did the client call `MONITOR`?
+    monitor: AtomicBool,
+    /// Size in bytes of the messages waiting in `tx`, and of the reply
+    /// being written.
+    obuf: AtomicUsize,
+    /// Notified when `obuf` grows because of a pushed message.
+    pub(crate) obuf_grew: Notify,
+    /// When `obuf` went over the soft limit, in milliseconds since the
+    /// UNIX epoch (0 if it's under).
+    soft_limit_since: AtomicU64,
+    /// Set when the client is closed for going over its output buffer
+    /// limit.
+    obuf_limit_reached: AtomicBool,
+}
+
+impl ClientInfo {
+    pub fn new(addr: SocketAddr, tx: pubsub::Sender) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            name: Mutex::new(String::new()),
+            created: Instant::now(),
+            last_interaction: AtomicU64::new(now_ms()),
+            last_cmd: Mutex::new("NULL"),
+            db: AtomicUsize::new(0),
+            killed: Notify::new(),
+            tx,
+            subscribed: AtomicBool::new(false),
+            monitor: AtomicBool::new(false),
+            obuf: AtomicUsize::new(0),
+            obuf_grew: Notify::new(),
+            soft_limit_since: AtomicU64::new(0),
+            obuf_limit_reached: AtomicBool::new(false),
+        }
+    }
+
+    /// Record that the client just ran `cmd`.
+    pub fn touch(&self, cmd: &'static str) {
+        self.last_interaction.store(now_ms(), Ordering::Relaxed);
+        *self.last_cmd.lock().unwrap() = cmd;
+    }
+
+    /// Record that the client selected database `db`.
+    pub fn select(&self, db: usize) {
+        self.db.store(db, Ordering::Relaxed);
+    }
+
+    /// Record whether the client is subscribed to anything.
+    pub fn set_subscribed(&self, subscribed: bool) {
+        self.subscribed.store(subscribed, Ordering::Relaxed);
+    }
+
+    /// Record that the client called `MONITOR`.
+    pub fn set_monitor(&self) {
+        self.monitor.store(true, Ordering::Relaxed);
+    }
+
+    pub fn class(&self) -> Class {
+        if self.subscribed.load(Ordering::Relaxed) {
+            Class::PubSub
+        } else {
+            Class::Normal
+        }
+    }
+
+    /// Ask the client's handler to close the connection.
+    pub fn kill(&self) {
+        self.killed.notify_one();
+    }
+
+    /// Queue a message for the client. Returns `false` if the client is
+    /// gone.
+    pub fn push(&self, msg: pubsub::Message) -> bool {
+        let size = msg.size();
+        if self.tx.send(msg).is_err() {
+            return false;
+        }
+        self.obuf.fetch_add(size, Ordering::Relaxed);
+        self.obuf_grew.notify_one();
+        true
+    }
+
+    /// Size of the output buffer, in bytes.
+    pub fn output_buffer_size(&self) -> usize {
+        self.obuf.load(Ordering::Relaxed)
+    }
+
+    /// Account for `n` more bytes in the output buffer.
+    pub fn grow_output(&self, n: usize) {
+        self.obuf.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Account for `n` bytes that left the output buffer.
+    pub fn shrink_output(&self, n: usize) {
+        if self.obuf.fetch_sub(n, Ordering::Relaxed) == n {
+            self.soft_limit_since.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Check the output buffer against `limit`. If it's over the hard
+    /// limit, or has been over the soft limit for too long, kill the
+    /// client and return `false`.
+    pub fn check_output_buffer(&self, limit: BufferLimit, now: u64) -> bool {
+        let size = self.output_buffer_size() as u64;
+        let over_hard = limit.hard > 0 && size >= limit.hard;
+        let over_soft = if limit.soft > 0 && size >= limit.soft {
+            let since = match self.soft_limit_since.load(Ordering::Relaxed) {
+                0 => {
+                    self.soft_limit_since.store(now, Ordering::Relaxed);
+                    now
+                }
+                since => since,
+            };
+            now.saturating_sub(since) >= limit.soft_seconds * 1000
+        } else {
+            self.soft_limit_since.store(0, Ordering::Relaxed);
+            false
+        };
+        if over_hard || over_soft {
+            self.obuf_limit_reached.store(true, Ordering::Relaxed);
+            self.kill();
+            return false;
+        }
+        true
+    }
+
+    /// Was the client closed for going over its output buffer limit?
+    pub fn output_buffer_limit_reached(&self) -> bool {
+        self.obuf_limit_reached.load(Ordering::Relaxed)
+    }
+
+    /// Line describing the client in `CLIENT LIST`.
+    pub fn describe(&self) -> String {
+        let mut s = String::new();
+        let idle = now_ms()
+            .saturating_sub(self.last_interaction.load(Ordering::Relaxed));
+        let _ = write!(
+            s,
+            "id={} addr={} name={} age={} idle={} flags={} db={} omem={} \
+            cmd={}",
+            self.id,
+            self.addr,
+            self.name.lock().unwrap(),
+            self.created.elapsed().as_secs(),
+            idle / 1000,
+            if self.monitor.load(Ordering::Relaxed) {
+                "O"
+            } else if self.class() == Class::PubSub {
+                "P"
+            } else {
+                "N"
+            },
+            self.db.load(Ordering::Relaxed),
+            self.output_buffer_size(),
+            self.last_cmd.lock().unwrap(),
+        );
+        s
+    }
+}
+
+/// Connected clients, by ID.
+#[derive(Debug, Default)]
+pub struct Clients {
+    clients: DashMap<u64, Arc<ClientInfo>>,
+}
+
+impl Clients {
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    pub fn register(&self, info: Arc<ClientInfo>) {
+        self.clients.insert(info.id, info);
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.clients.remove(&id);
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<ClientInfo>> {
+        self.clients.get(&id).map(|c| c.clone())
+    }
+
+    /// All connected clients, sorted by ID.
+    pub fn all(&self) -> Vec<Arc<ClientInfo>> {
+        let mut v: Vec<_> = self.clients.iter().map(|c| c.clone()).collect();
+        v.sort_by_key(|c| c.id);
+        v
+    }
+}
@@ -0,0 +1,685 @@
+//! Table of supported commands.
+
+/// Static description of a command.
+#[derive(Debug)]
+pub struct Command {
+    /// Lowercase name.
+    pub name: &'static str,
+    /// Number of arguments, including the command name. Negative means
+    /// "at least `-arity`".
+    pub arity: i32,
+    /// Flags, with the same names as in redis' `COMMAND` output.
+    pub flags: &'static [&'static str],
+    /// Position of the first key, last key (negative counts from the end)
+    /// and step between keys, like in redis' `COMMAND` output. All zero
+    /// if the command takes no key.
+    pub keys: (i32, i32, i32),
+    /// Arguments, as shown in the documentation.
+    pub syntax: &'static str,
+    pub summary: &'static str,
+}
+
+impl Command {
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// The keys among `args` (including the command name).
+    pub fn keys<'a>(&self, args: &[&'a str]) -> Vec<&'a str> {
+        // `MIGRATE` takes its keys after `KEYS` when its key is empty
+        if self.name == "migrate" && args.get(3) == Some(&"") {
+            let keys = args
+                .iter()
+                .skip(6)
+                .position(|a| a.eq_ignore_ascii_case("keys"));
+            return match keys {
+                Some(i) => args[6 + i + 1..].to_vec(),
+                None => vec![],
+            };
+        }
+        self.key_positions(args.len()).map(|i| args[i]).collect()
+    }
+
+    /// Positions of the keys in `argc` arguments (including the command
+    /// name), according to the `keys` field. Ignores `MIGRATE`'s keys
+    /// after `KEYS`.
+    pub fn key_positions(&self, argc: usize) -> impl Iterator<Item = usize> {
+        let (first, last, step) = self.keys;
+        let last = if first <= 0 {
+            // no keys
+            first - 1
+        } else if last < 0 {
+            argc as i32 + last
+        } else {
+            last.min(argc as i32 - 1)
+        };
+        (first..=last)
+            .step_by(step.max(1) as usize)
+            .map(|i| i as usize)
+    }
+
+    /// Is `argc` (including the command name) a valid number of arguments?
+    pub fn check_arity(&self, argc: usize) -> bool {
+        if self.arity >= 0 {
+            argc == self.arity as usize
+        } else {
+            argc >= (-self.arity) as usize
+        }
+    }
+}
+
+macro_rules! cmd {
+    ($name:literal, $arity:expr, [$($flag:literal),*], $syntax:literal,
+     $summary:literal) => {
+        cmd!($name, $arity, [$($flag),*], (0, 0, 0), $syntax, $summary)
+    };
+    ($name:literal, $arity:expr, [$($flag:literal),*], $keys:expr,
+     $syntax:literal, $summary:literal) => {
+        Command {
+            name: $name,
+            arity: $arity,
+            flags: &[$($flag),*],
+            keys: $keys,
+            syntax: $syntax,
+            summary: $summary,
+        }
+    };
+}
+
+pub const COMMANDS: &[Command] = &[
+    cmd!(
+        "get",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the value of a key"
+    ),
+    cmd!(
+        "set",
+        -3,
+        ["write", "denyoom"],
+        (1, 1, 1),
+        "key value [EX seconds|PX milliseconds]",
+        "Set the string value of a key"
+    ),
+    cmd!(
+        "setbit",
+        4,
+        ["write", "denyoom"],
+        (1, 1, 1),
+        "key offset value",
+        "Set or clear the bit at offset in the string value stored at key"
+    ),
+    cmd!(
+        "getbit",
+        3,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key offset",
+        "Return the bit value at offset in the string value stored at key"
+    ),
+    cmd!(
+        "bitcount",
+        -2,
+        ["readonly"],
+        (1, 1, 1),
+        "key [start end [BYTE|BIT]]",
+        "Count set bits in a string"
+    ),
+    cmd!(
+        "pfadd",
+        -2,
+        ["write", "denyoom", "fast"],
+        (1, 1, 1),
+        "key [element ...]",
+        "Add the specified elements to the specified HyperLogLog"
+    ),
+    cmd!(
+        "pfcount",
+        -2,
+        ["readonly"],
+        (1, -1, 1),
+        "key [key ...]",
+        "Return the approximated cardinality of the set(s) observed by the \
+        HyperLogLog at key(s)"
+    ),
+    cmd!(
+        "pfmerge",
+        -2,
+        ["write", "denyoom"],
+        (1, -1, 1),
+        "destkey [sourcekey ...]",
+        "Merge N different HyperLogLogs into a single one"
+    ),
+    cmd!(
+        "hset",
+        -4,
+        ["write", "denyoom", "fast"],
+        (1, 1, 1),
+        "key field value [field value ...]",
+        "Set the string value of one or more hash fields"
+    ),
+    cmd!(
+        "hget",
+        3,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key field",
+        "Get the value of a hash field"
+    ),
+    cmd!(
+        "hdel",
+        -3,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key field [field ...]",
+        "Delete one or more hash fields"
+    ),
+    cmd!(
+        "hlen",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the number of fields in a hash"
+    ),
+    cmd!(
+        "hexists",
+        3,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key field",
+        "Determine if a hash field exists"
+    ),
+    cmd!(
+        "hgetall",
+        2,
+        ["readonly"],
+        (1, 1, 1),
+        "key",
+        "Get all the fields and values in a hash"
+    ),
+    cmd!(
+        "lpush",
+        -3,
+        ["write", "denyoom", "fast"],
+        (1, 1, 1),
+        "key element [element ...]",
+        "Prepend one or multiple elements to a list"
+    ),
+    cmd!(
+        "rpush",
+        -3,
+        ["write", "denyoom", "fast"],
+        (1, 1, 1),
+        "key element [element ...]",
+        "Append one or multiple elements to a list"
+    ),
+    cmd!(
+        "lpop",
+        -2,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key [count]",
+        "Remove and get the first elements in a list"
+    ),
+    cmd!(
+        "rpop",
+        -2,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key [count]",
+        "Remove and get the last elements in a list"
+    ),
+    cmd!(
+        "llen",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the length of a list"
+    ),
+    cmd!(
+        "lrange",
+        4,
+        ["readonly"],
+        (1, 1, 1),
+        "key start stop",
+        "Get a range of elements from a list"
+    ),
+    cmd!(
+        "sadd",
+        -3,
+        ["write", "denyoom", "fast"],
+        (1, 1, 1),
+        "key member [member ...]",
+        "Add one or more members to a set"
+    ),
+    cmd!(
+        "srem",
+        -3,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key member [member ...]",
+        "Remove one or more members from a set"
+    ),
+    cmd!(
+        "sismember",
+        3,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key member",
+        "Determine if a given value is a member of a set"
+    ),
+    cmd!(
+        "scard",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the number of members in a set"
+    ),
+    cmd!(
+        "smembers",
+        2,
+        ["readonly"],
+        (1, 1, 1),
+        "key",
+        "Get all the members in a set"
+    ),
+    cmd!(
+        "zadd",
+        -4,
+        ["write", "denyoom", "fast"],
+        (1, 1, 1),
+        "key [NX|XX] [GT|LT] [CH] score member [score member ...]",
+        "Add one or more members to a sorted set, or update their scores"
+    ),
+    cmd!(
+        "zscore",
+        3,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key member",
+        "Get the score associated with the given member in a sorted set"
+    ),
+    cmd!(
+        "zcard",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the number of members in a sorted set"
+    ),
+    cmd!(
+        "zrem",
+        -3,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key member [member ...]",
+        "Remove one or more members from a sorted set"
+    ),
+    cmd!(
+        "zrange",
+        -4,
+        ["readonly"],
+        (1, 1, 1),
+        "key start stop [WITHSCORES]",
+        "Return a range of members in a sorted set, by index"
+    ),
+    cmd!(
+        "geoadd",
+        -5,
+        ["write", "denyoom"],
+        (1, 1, 1),
+        "key [NX|XX] [CH] longitude latitude member \
+        [longitude latitude member ...]",
+        "Add one or more geospatial items in the geospatial index \
+        represented using a sorted set"
+    ),
+    cmd!(
+        "geopos",
+        -2,
+        ["readonly"],
+        (1, 1, 1),
+        "key [member ...]",
+        "Return longitude and latitude of members of a geospatial index"
+    ),
+    cmd!(
+        "geodist",
+        -4,
+        ["readonly"],
+        (1, 1, 1),
+        "key member1 member2 [M|KM|FT|MI]",
+        "Return the distance between two members of a geospatial index"
+    ),
+    cmd!(
+        "geohash",
+        -2,
+        ["readonly"],
+        (1, 1, 1),
+        "key [member ...]",
+        "Return members of a geospatial index as standard geohash strings"
+    ),
+    cmd!(
+        "geosearch",
+        -7,
+        ["readonly"],
+        (1, 1, 1),
+        "key FROMMEMBER member|FROMLONLAT longitude latitude \
+        BYRADIUS radius M|KM|FT|MI|BYBOX width height M|KM|FT|MI \
+        [ASC|DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]",
+        "Query a geospatial index for members inside an area of a box or a \
+        circle"
+    ),
+    cmd!(
+        "expire",
+        3,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key seconds",
+        "Set a key's time to live in seconds"
+    ),
+    cmd!(
+        "pexpire",
+        3,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key milliseconds",
+        "Set a key's time to live in milliseconds"
+    ),
+    cmd!(
+        "ttl",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the time to live for a key in seconds"
+    ),
+    cmd!(
+        "pttl",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Get the time to live for a key in milliseconds"
+    ),
+    cmd!(
+        "persist",
+        2,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key",
+        "Remove the expiration from a key"
+    ),
+    cmd!(
+        "del",
+        -2,
+        ["write"],
+        (1, -1, 1),
+        "key [key ...]",
+        "Delete keys"
+    ),
+    cmd!(
+        "exists",
+        -2,
+        ["readonly", "fast"],
+        (1, -1, 1),
+        "key [key ...]",
+        "Count how many of the given keys exist"
+    ),
+    cmd!(
+        "keys",
+        2,
+        ["readonly"],
+        "pattern",
+        "Find all keys matching the given pattern"
+    ),
+    cmd!(
+        "scan",
+        -2,
+        ["readonly"],
+        "cursor [MATCH pattern] [COUNT count] [TYPE type]",
+        "Incrementally iterate the keys space"
+    ),
+    cmd!(
+        "type",
+        2,
+        ["readonly", "fast"],
+        (1, 1, 1),
+        "key",
+        "Determine the type stored at key"
+    ),
+    cmd!(
+        "object",
+        -2,
+        ["readonly"],
+        (2, 2, 1),
+        "ENCODING key",
+        "Inspect the internals of Redis objects"
+    ),
+    cmd!(
+        "rename",
+        3,
+        ["write"],
+        (1, 2, 1),
+        "key newkey",
+        "Rename a key"
+    ),
+    cmd!(
+        "renamenx",
+        3,
+        ["write", "fast"],
+        (1, 2, 1),
+        "key newkey",
+        "Rename a key, only if the new key does not exist"
+    ),
+    cmd!(
+        "move",
+        3,
+        ["write", "fast"],
+        (1, 1, 1),
+        "key db",
+        "Move a key to another database"
+    ),
+    cmd!(
+        "dump",
+        2,
+        ["readonly"],
+        (1, 1, 1),
+        "key",
+        "Return a serialized version of the value stored at the specified \
+        key"
+    ),
+    cmd!(
+        "restore",
+        -4,
+        ["write", "denyoom"],
+        (1, 1, 1),
+        "key ttl serialized-value [REPLACE] [ABSTTL]",
+        "Create a key using the provided serialized value, previously \
+        obtained using DUMP"
+    ),
+    cmd!(
+        "migrate",
+        -6,
+        ["write", "movablekeys"],
+        (3, 3, 1),
+        "host port key|\"\" destination-db timeout [COPY] [REPLACE] \
+        [KEYS key [key ...]]",
+        "Atomically transfer a key from a Redis instance to another one"
+    ),
+    cmd!(
+        "swapdb",
+        3,
+        ["write", "fast"],
+        "index1 index2",
+        "Swap two databases"
+    ),
+    cmd!(
+        "select",
+        2,
+        ["loading", "stale", "fast"],
+        "index",
+        "Change the selected database for the current connection"
+    ),
+    cmd!(
+        "dbsize",
+        1,
+        ["readonly", "fast"],
+        "",
+        "Return the number of keys"
+    ),
+    cmd!(
+        "flushdb",
+        -1,
+        ["write"],
+        "[ASYNC|SYNC]",
+        "Remove all keys from the current database"
+    ),
+    cmd!(
+        "flushall",
+        -1,
+        ["write"],
+        "[ASYNC|SYNC]",
+        "Remove all keys from all databases"
+    ),
+    cmd!(
+        "memory",
+        -2,
+        ["readonly"],
+        "USAGE key [SAMPLES count]",
+        "Estimate the memory usage of a key"
+    ),
+    cmd!(
+        "info",
+        -1,
+        ["loading", "stale"],
+        "[section ...]",
+        "Get information and statistics about the server"
+    ),
+    cmd!(
+        "config",
+        -2,
+        ["admin", "noscript"],
+        "GET|SET|REWRITE|RESETSTAT ...",
+        "Get or set configuration parameters"
+    ),
+    cmd!(
+        "client",
+        -2,
+        ["admin", "noscript"],
+        "ID|LIST|KILL|SETNAME|GETNAME ...",
+        "Inspect and manage client connections"
+    ),
+    cmd!(
+        "slowlog",
+        -2,
+        ["admin"],
+        "GET [count]|LEN|RESET",
+        "Inspect the slow log"
+    ),
+    cmd!(
+        "monitor",
+        1,
+        ["admin", "noscript", "loading", "stale"],
+        "",
+        "Listen for all requests received by the server in real time"
+    ),
+    cmd!(
+        "subscribe",
+        -2,
+        ["pubsub", "noscript", "loading", "stale"],
+        "channel [channel ...]",
+        "Listen for messages published to the given channels"
+    ),
+    cmd!(
+        "unsubscribe",
+        -1,
+        ["pubsub", "noscript", "loading", "stale"],
+        "[channel [channel ...]]",
+        "Stop listening for messages posted to the given channels"
+    ),
+    cmd!(
+        "psubscribe",
+        -2,
+        ["pubsub", "noscript", "loading", "stale"],
+        "pattern [pattern ...]",
+        "Listen for messages published to channels matching the given \
+        patterns"
+    ),
+    cmd!(
+        "punsubscribe",
+        -1,
+        ["pubsub", "noscript", "loading", "stale"],
+        "[pattern [pattern ...]]",
+        "Stop listening for messages posted to channels matching the \
+        given patterns"
+    ),
+    cmd!(
+        "publish",
+        3,
+        ["pubsub", "loading", "stale", "fast"],
+        "channel message",
+        "Post a message to a channel"
+    ),
+    cmd!(
+        "pubsub",
+        -2,
+        ["pubsub", "loading", "stale"],
+        "CHANNELS [pattern]|NUMSUB [channel ...]|NUMPAT",
+        "Inspect the state of the Pub/Sub subsystem"
+    ),
+    #[cfg(feature = "lua")]
+    cmd!(
+        "eval",
+        -3,
+        ["noscript"],
+        "script numkeys [key ...] [arg ...]",
+        "Execute a Lua script server side"
+    ),
+    #[cfg(feature = "lua")]
+    cmd!(
+        "evalsha",
+        -3,
+        ["noscript"],
+        "sha1 numkeys [key ...] [arg ...]",
+        "Execute a cached Lua script server side"
+    ),
+    #[cfg(feature = "lua")]
+    cmd!(
+        "script",
+        -2,
+        ["noscript"],
+        "LOAD|EXISTS|FLUSH ...",
+        "Manage the script cache"
+    ),
+    cmd!(
+        "command",
+        -1,
+        ["loading", "stale"],
+        "[COUNT|DOCS [name ...]|INFO [name ...]|LIST]",
+        "Get details about commands"
+    ),
+    cmd!(
+        "shutdown",
+        -1,
+        ["admin", "noscript", "loading", "stale"],
+        "[NOSAVE|SAVE]",
+        "Synchronously save the dataset to disk and then shut down the \
+        server"
+    ),
+    cmd!(
+        "ping",
+        -1,
+        ["stale", "fast"],
+        "[message]",
+        "Ping the server"
+    ),
+];
+
+/// Find a command by name, case-insensitively.
+pub fn lookup(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,796 @@
+//! Server configuration.
+//!
+//! The configuration is read from a `redis.conf`-style file (one
+//! `name value...` directive per line, `#` comments), possibly overridden
+//! on the command line with `--name value...`, and can be inspected or
+//! changed at runtime with `CONFIG GET/SET/REWRITE`.
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    clients::OutputBufferLimits,
+    db::{EncodingLimits, LfuParams, ListpackLimits},
+    evict,
+    list::ListpackSize,
+    notify,
+    set::SetLimits,
+};
+
+/// Log verbosity, with the same names as redis.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+    Nothing,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+            LogLevel::Nothing => "nothing",
+        }
+    }
+
+    /// Corresponding filter for the `log` crate.
+    pub fn to_filter(self) -> log::LevelFilter {
+        match self {
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Verbose => log::LevelFilter::Info,
+            LogLevel::Notice => log::LevelFilter::Info,
+            LogLevel::Warning => log::LevelFilter::Warn,
+            LogLevel::Nothing => log::LevelFilter::Off,
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "debug" => LogLevel::Debug,
+            "verbose" => LogLevel::Verbose,
+            "notice" => LogLevel::Notice,
+            "warning" => LogLevel::Warning,
+            "nothing" => LogLevel::Nothing,
+            _ => anyhow::bail!("invalid log level {s:?}"),
+        })
+    }
+}
+
+/// Server configuration.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Addresses to listen on.
+    pub bind: Vec<String>,
+    pub port: u16,
+    /// Number of logical databases.
+    pub databases: usize,
+    /// Maximum number of simultaneously connected clients.
+    pub maxclients: usize,
+    /// Close a client after it's been idle for this many seconds (0 to
+    /// disable).
+    pub timeout: u64,
+    /// Interval of TCP keepalive probes on client connections, in seconds
+    /// (0 to disable).
+    pub tcp_keepalive: u64,
+    /// Close clients whose replies and pushed messages pile up.
+    pub client_output_buffer_limit: OutputBufferLimits,
+    /// Working directory, where the snapshot is written.
+    pub dir: PathBuf,
+    /// Name of the snapshot file, within `dir`.
+    pub dbfilename: String,
+    /// Snapshot rules, as `(seconds, changes)` pairs: a snapshot is saved
+    /// once there were at least `changes` write commands and `seconds`
+    /// seconds since the last one, for any of the rules. One is also saved
+    /// on shutdown, unless there are no rules (`save ""`).
+    pub save: Vec<(u64, u64)>,
+    /// Seconds to let clients finish their current command when shutting
+    /// down, before closing their connection anyway.
+    pub shutdown_timeout: u64,
+    pub loglevel: LogLevel,
+    /// File to log into. Empty means stderr.
+    pub logfile: String,
+    /// Memory limit in bytes (0 for no limit).
+    pub maxmemory: u64,
+    /// What to do when `maxmemory` is reached.
+    pub maxmemory_policy: evict::Policy,
+    /// Number of keys to sample for each eviction.
+    pub maxmemory_samples: usize,
+    pub lfu_log_factor: u32,
+    /// Minutes after which LFU counters decay.
+    pub lfu_decay_time: u32,
+    /// Log commands that take at least this many microseconds (negative
+    /// to disable).
+    pub slowlog_log_slower_than: i64,
+    /// Maximum number of entries in the slow log.
+    pub slowlog_max_len: usize,
+    /// Which keyspace notifications to publish.
+    pub notify_keyspace_events: notify::Flags,
+    /// Hashes use the compact listpack encoding while they have at most
+    /// this many fields...
+    pub hash_max_listpack_entries: usize,
+    /// ...and their fields and values are at most this long.
+    pub hash_max_listpack_value: usize,
+    /// Lists use the compact listpack encoding up to this many elements if
+    /// positive, or up to 4, 8, 16, 32 or 64 kB for -1 to -5.
+    pub list_max_listpack_size: i64,
+    /// Sets of integers use the compact intset encoding while they have at
+    /// most this many members.
+    pub set_max_intset_entries: usize,
+    /// Other sets use the compact listpack encoding while they have at
+    /// most this many members...
+    pub set_max_listpack_entries: usize,
+    /// ...and their members are at most this long.
+    pub set_max_listpack_value: usize,
+    /// Sorted sets use the compact listpack encoding while they have at
+    /// most this many members...
+    pub zset_max_listpack_entries: usize,
+    /// ...and their members are at most this long.
+    pub zset_max_listpack_value: usize,
+    /// File to log write commands into. Empty disables the audit log.
+    pub audit_log: String,
+    /// Rotate the audit log when it would grow over this size in bytes (0
+    /// to never rotate it).
+    pub audit_log_max_size: u64,
+    /// Number of rotated audit logs to keep.
+    pub audit_log_max_files: usize,
+    /// Address for the Prometheus metrics endpoint.
+    pub metrics_bind: String,
+    /// Port for the Prometheus metrics endpoint (0 to disable it).
+    pub metrics_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            databases: 16,
+            maxclients: 10_000,
+            timeout: 0,
+            tcp_keepalive: 300,
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![(3600, 1), (300, 100), (60, 10_000)],
+            shutdown_timeout: 10,
+            loglevel: LogLevel::Notice,
+            logfile: String::new(),
+            maxmemory: 0,
+            maxmemory_policy: evict::Policy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            slowlog_log_slower_than: 10_000,
+            slowlog_max_len: 128,
+            notify_keyspace_events: notify::Flags::default(),
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+            audit_log: String::new(),
+            audit_log_max_size: 64 * 1024 * 1024,
+            audit_log_max_files: 5,
+            metrics_bind: "127.0.0.1".to_string(),
+            metrics_port: 0,
+        }
+    }
+}
+
+/// Known parameters, and whether they can be modified with `CONFIG SET`.
+const PARAMS: &[(&str, bool)] = &[
+    ("bind", false),
+    ("port", false),
+    ("databases", false),
+    ("maxclients", true),
+    ("timeout", true),
+    ("tcp-keepalive", true),
+    ("client-output-buffer-limit", true),
+    ("dir", true),
+    ("dbfilename", true),
+    ("save", true),
+    ("shutdown-timeout", true),
+    ("loglevel", true),
+    ("logfile", false),
+    ("maxmemory", true),
+    ("maxmemory-policy", true),
+    ("maxmemory-samples", true),
+    ("lfu-log-factor", true),
+    ("lfu-decay-time", true),
+    ("slowlog-log-slower-than", true),
+    ("slowlog-max-len", true),
+    ("notify-keyspace-events", true),
+    ("hash-max-listpack-entries", true),
+    ("hash-max-listpack-value", true),
+    ("list-max-listpack-size", true),
+    ("set-max-intset-entries", true),
+    ("set-max-listpack-entries", true),
+    ("set-max-listpack-value", true),
+    ("zset-max-listpack-entries", true),
+    ("zset-max-listpack-value", true),
+    ("audit-log", true),
+    ("audit-log-max-size", true),
+    ("audit-log-max-files", true),
+    ("metrics-bind", false),
+    ("metrics-port", false),
+];
+
+/// Parameters that take several values, separated by spaces.
+fn is_multi_valued(name: &str) -> bool {
+    matches!(name, "bind" | "save" | "client-output-buffer-limit")
+}
+
+/// Canonical name of parameter `name`.
+fn find_param(name: &str) -> Option<&'static str> {
+    PARAMS
+        .iter()
+        .find(|(p, _)| p.eq_ignore_ascii_case(name))
+        .map(|(p, _)| *p)
+}
+
+impl Config {
+    /// Names of all the known parameters.
+    pub fn param_names() -> impl Iterator<Item = &'static str> {
+        PARAMS.iter().map(|(p, _)| *p)
+    }
+
+    /// Is `name` a parameter that can be changed at runtime?
+    pub fn is_mutable(name: &str) -> bool {
+        PARAMS
+            .iter()
+            .any(|(p, m)| *m && p.eq_ignore_ascii_case(name))
+    }
+
+    /// Current value of parameter `name`, as it would appear in a
+    /// configuration file.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let p = find_param(name)?;
+        let v = match p {
+            "bind" => self.bind.join(" "),
+            "port" => self.port.to_string(),
+            "databases" => self.databases.to_string(),
+            "maxclients" => self.maxclients.to_string(),
+            "timeout" => self.timeout.to_string(),
+            "tcp-keepalive" => self.tcp_keepalive.to_string(),
+            "client-output-buffer-limit" => {
+                self.client_output_buffer_limit.to_string()
+            }
+            "dir" => self.dir.display().to_string(),
+            "dbfilename" => self.dbfilename.clone(),
+            "save" => {
+                let mut s = String::new();
+                for (i, (secs, changes)) in self.save.iter().enumerate() {
+                    if i > 0 {
+                        s.push(' ');
+                    }
+                    write!(&mut s, "{secs} {changes}").unwrap();
+                }
+                s
+            }
+            "shutdown-timeout" => self.shutdown_timeout.to_string(),
+            "loglevel" => self.loglevel.as_str().to_string(),
+            "logfile" => self.logfile.clone(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.as_str().to_string(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "lfu-log-factor" => self.lfu_log_factor.to_string(),
+            "lfu-decay-time" => self.lfu_decay_time.to_string(),
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than.to_string()
+            }
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "notify-keyspace-events" => self.notify_keyspace_events.to_string(),
+            "hash-max-listpack-entries" => {
+                self.hash_max_listpack_entries.to_string()
+            }
+            "hash-max-listpack-value" => {
+                self.hash_max_listpack_value.to_string()
+            }
+            "list-max-listpack-size" => self.list_max_listpack_size.to_string(),
+            "set-max-intset-entries" => self.set_max_intset_entries.to_string(),
+            "set-max-listpack-entries" => {
+                self.set_max_listpack_entries.to_string()
+            }
+            "set-max-listpack-value" => self.set_max_listpack_value.to_string(),
+            "zset-max-listpack-entries" => {
+                self.zset_max_listpack_entries.to_string()
+            }
+            "zset-max-listpack-value" => {
+                self.zset_max_listpack_value.to_string()
+            }
+            "audit-log" => self.audit_log.clone(),
+            "audit-log-max-size" => self.audit_log_max_size.to_string(),
+            "audit-log-max-files" => self.audit_log_max_files.to_string(),
+            "metrics-bind" => self.metrics_bind.clone(),
+            "metrics-port" => self.metrics_port.to_string(),
+            _ => unreachable!("unhandled parameter {p}"),
+        };
+        Some(v)
+    }
+
+    /// Set parameter `name` to `value`.
+    ///
+    /// Multi-valued parameters such as `bind` or `save` take their values
+    /// separated by spaces.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let Some(p) = find_param(name) else {
+            anyhow::bail!("unknown parameter {name:?}")
+        };
+        let parse_err = || format!("invalid value {value:?} for {p}");
+        match p {
+            "bind" => {
+                self.bind =
+                    value.split_whitespace().map(|s| s.to_string()).collect();
+                if self.bind.is_empty() {
+                    anyhow::bail!("bind needs at least one address");
+                }
+            }
+            "port" => self.port = value.parse().with_context(parse_err)?,
+            "databases" => {
+                self.databases = value.parse().with_context(parse_err)?;
+                if self.databases == 0 {
+                    anyhow::bail!("databases must be at least 1");
+                }
+            }
+            "maxclients" => {
+                self.maxclients = value.parse().with_context(parse_err)?
+            }
+            "timeout" => {
+                self.timeout = value.parse().with_context(parse_err)?
+            }
+            "tcp-keepalive" => {
+                self.tcp_keepalive = value.parse().with_context(parse_err)?
+            }
+            "client-output-buffer-limit" => {
+                // only the classes given are changed
+                let args: Vec<&str> = value.split_whitespace().collect();
+                if args.is_empty() || !args.len().is_multiple_of(4) {
+                    anyhow::bail!(
+                        "client-output-buffer-limit takes groups of \
+                        <class> <hard> <soft> <soft seconds>"
+                    );
+                }
+                let mut limits = self.client_output_buffer_limit;
+                for a in args.chunks(4) {
+                    let limit = limits.get_mut(a[0].parse()?);
+                    limit.hard = parse_memory(a[1])?;
+                    limit.soft = parse_memory(a[2])?;
+                    limit.soft_seconds =
+                        a[3].parse().with_context(parse_err)?;
+                }
+                self.client_output_buffer_limit = limits;
+            }
+            "dir" => self.dir = PathBuf::from(value),
+            "dbfilename" => {
+                if value.contains('/') {
+                    anyhow::bail!("dbfilename can't be a path, just a name");
+                }
+                self.dbfilename = value.to_string()
+            }
+            "save" => {
+                let nums = value
+                    .split_whitespace()
+                    .map(|s| s.parse::<u64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(parse_err)?;
+                if nums.len() % 2 != 0 {
+                    anyhow::bail!("save takes pairs of <seconds> <changes>");
+                }
+                self.save = nums.chunks(2).map(|c| (c[0], c[1])).collect();
+            }
+            "shutdown-timeout" => {
+                self.shutdown_timeout = value.parse().with_context(parse_err)?
+            }
+            "loglevel" => self.loglevel = value.parse()?,
+            "logfile" => self.logfile = value.to_string(),
+            "maxmemory" => self.maxmemory = parse_memory(value)?,
+            "maxmemory-policy" => self.maxmemory_policy = value.parse()?,
+            "maxmemory-samples" => {
+                self.maxmemory_samples =
+                    value.parse().with_context(parse_err)?
+            }
+            "lfu-log-factor" => {
+                self.lfu_log_factor = value.parse().with_context(parse_err)?
+            }
+            "lfu-decay-time" => {
+                self.lfu_decay_time = value.parse().with_context(parse_err)?
+            }
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than =
+                    value.parse().with_context(parse_err)?
+            }
+            "slowlog-max-len" => {
+                self.slowlog_max_len = value.parse().with_context(parse_err)?
+            }
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = value.parse()?
+            }
+            "hash-max-listpack-entries" => {
+                self.hash_max_listpack_entries =
+                    value.parse().with_context(parse_err)?
+            }
+            "hash-max-listpack-value" => {
+                self.hash_max_listpack_value =
+                    value.parse().with_context(parse_err)?
+            }
+            "list-max-listpack-size" => {
+                self.list_max_listpack_size =
+                    value.parse().with_context(parse_err)?
+            }
+            "set-max-intset-entries" => {
+                self.set_max_intset_entries =
+                    value.parse().with_context(parse_err)?
+            }
+            "set-max-listpack-entries" => {
+                self.set_max_listpack_entries =
+                    value.parse().with_context(parse_err)?
+            }
+            "set-max-listpack-value" => {
+                self.set_max_listpack_value =
+                    value.parse().with_context(parse_err)?
+            }
+            "zset-max-listpack-entries" => {
+                self.zset_max_listpack_entries =
+                    value.parse().with_context(parse_err)?
+            }
+            "zset-max-listpack-value" => {
+                self.zset_max_listpack_value =
+                    value.parse().with_context(parse_err)?
+            }
+            "audit-log" => self.audit_log = value.to_string(),
+            "audit-log-max-size" => {
+                self.audit_log_max_size = parse_memory(value)?
+            }
+            "audit-log-max-files" => {
+                self.audit_log_max_files =
+                    value.parse().with_context(parse_err)?
+            }
+            "metrics-bind" => self.metrics_bind = value.to_string(),
+            "metrics-port" => {
+                self.metrics_port = value.parse().with_context(parse_err)?
+            }
+            _ => unreachable!("unhandled parameter {p}"),
+        }
+        Ok(())
+    }
+
+    pub fn hash_listpack_limits(&self) -> ListpackLimits {
+        ListpackLimits {
+            entries: self.hash_max_listpack_entries,
+            value: self.hash_max_listpack_value,
+        }
+    }
+
+    pub fn list_listpack_size(&self) -> ListpackSize {
+        ListpackSize(self.list_max_listpack_size)
+    }
+
+    pub fn set_limits(&self) -> SetLimits {
+        SetLimits {
+            intset_entries: self.set_max_intset_entries,
+            listpack: ListpackLimits {
+                entries: self.set_max_listpack_entries,
+                value: self.set_max_listpack_value,
+            },
+        }
+    }
+
+    /// Limits of the compact encodings of all types.
+    pub fn encoding_limits(&self) -> EncodingLimits {
+        EncodingLimits {
+            hash: self.hash_listpack_limits(),
+            list: self.list_listpack_size(),
+            set: self.set_limits(),
+            zset: self.zset_listpack_limits(),
+        }
+    }
+
+    pub fn zset_listpack_limits(&self) -> ListpackLimits {
+        ListpackLimits {
+            entries: self.zset_max_listpack_entries,
+            value: self.zset_max_listpack_value,
+        }
+    }
+
+    pub fn lfu_params(&self) -> LfuParams {
+        LfuParams {
+            log_factor: self.lfu_log_factor,
+            decay_time: self.lfu_decay_time,
+        }
+    }
+
+    /// Apply the directives in the content of a configuration file.
+    pub fn load_str(&mut self, content: &str) -> Result<()> {
+        for (i, line) in content.lines().enumerate() {
+            let args = split_str_args(line)
+                .with_context(|| format!("parsing line {}", i + 1))?;
+            let Some((name, values)) = args.split_first() else {
+                continue;
+            };
+            self.set(name, &values.join(" "))
+                .with_context(|| format!("at line {}", i + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Read configuration from the given file.
+    pub fn load_file(&mut self, path: &Path) -> Result<()> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {path:?}"))?;
+        self.load_str(&content)
+            .with_context(|| format!("in config file {path:?}"))
+    }
+
+    /// Parse command line arguments, in the same style as `redis-server`:
+    /// an optional configuration file, followed by `--name value...`
+    /// overrides.
+    ///
+    /// Returns the configuration and the path to the configuration file,
+    /// if any.
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(Config, Option<PathBuf>)> {
+        let mut config = Config::default();
+        let mut args = args.into_iter().peekable();
+
+        let mut file = None;
+        if let Some(a) = args.next_if(|a| !a.starts_with("--")) {
+            let path = PathBuf::from(a);
+            config.load_file(&path)?;
+            file = Some(path);
+        }
+
+        while let Some(a) = args.next() {
+            let Some(name) = a.strip_prefix("--") else {
+                anyhow::bail!("expected `--<parameter>`, got {a:?}");
+            };
+            let mut values = vec![];
+            while let Some(v) = args.next_if(|a| !a.starts_with("--")) {
+                values.push(v);
+            }
+            config
+                .set(name, &values.join(" "))
+                .with_context(|| format!("on the command line: --{name}"))?;
+        }
+
+        Ok((config, file))
+    }
+
+    /// Write the configuration back into `path`.
+    ///
+    /// Lines that set a known parameter are updated in place (and
+    /// duplicates removed), other lines such as comments are kept as is,
+    /// and parameters that are not mentioned yet are appended, unless they
+    /// have their default value.
+    pub fn rewrite(&self, path: &Path) -> Result<()> {
+        let old = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("reading {path:?}"))
+            }
+        };
+
+        let mut out = String::new();
+        let mut done: Vec<&'static str> = vec![];
+        for line in old.lines() {
+            let param = split_str_args(line)
+                .ok()
+                .and_then(|args| args.first().and_then(|n| find_param(n)));
+            match param {
+                Some(p) if done.contains(&p) => (), // duplicate
+                Some(p) => {
+                    done.push(p);
+                    self.write_directive(&mut out, p);
+                }
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+
+        let default = Config::default();
+        for &(p, _) in PARAMS {
+            if !done.contains(&p) && self.get(p) != default.get(p) {
+                self.write_directive(&mut out, p);
+            }
+        }
+
+        let tmp = path.with_extension("tmp-rewrite");
+        std::fs::write(&tmp, out)
+            .with_context(|| format!("writing {tmp:?}"))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("renaming {tmp:?} to {path:?}"))?;
+        Ok(())
+    }
+
+    /// Write `name` and its value, quoted if needed. The values of
+    /// multi-valued parameters are written as separate arguments.
+    fn write_directive(&self, out: &mut String, name: &str) {
+        let value = self.get(name).unwrap_or_default();
+        out.push_str(name);
+        if is_multi_valued(name) && !value.is_empty() {
+            for v in value.split_whitespace() {
+                out.push(' ');
+                out.push_str(&quote_arg(v));
+            }
+        } else {
+            out.push(' ');
+            out.push_str(&quote_arg(&value));
+        }
+        out.push('\n');
+    }
+}
+
+/// Parse a memory amount such as `100mb` or `1g`, like redis: `k`, `m`
+/// and `g` are powers of 1000, `kb`, `mb`, `gb` powers of 1024.
+pub fn parse_memory(s: &str) -> Result<u64> {
+    let lower = s.to_ascii_lowercase();
+    let idx = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (num, unit) = lower.split_at(idx);
+    let mul: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1_000,
+        "kb" => 1 << 10,
+        "m" => 1_000_000,
+        "mb" => 1 << 20,
+        "g" => 1_000_000_000,
+        "gb" => 1 << 30,
+        _ => anyhow::bail!("invalid memory unit in {s:?}"),
+    };
+    let n: u64 = num
+        .parse()
+        .with_context(|| format!("invalid memory amount {s:?}"))?;
+    n.checked_mul(mul)
+        .ok_or_else(|| anyhow::anyhow!("memory amount {s:?} is too large"))
+}
+
+/// Quote `s` if it contains characters that `split_args` would not read
+/// back verbatim.
+fn quote_arg(s: &str) -> String {
+    if !s.is_empty()
+        && !s.starts_with('#')
+        && s.bytes()
+            .all(|c| c.is_ascii_graphic() && c != b'"' && c != b'\'')
+    {
+        return s.to_string();
+    }
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                write!(&mut r, "\\x{:02x}", c as u8).unwrap()
+            }
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+/// Split a line into arguments, like redis' `sdssplitargs`.
+///
+/// Arguments are separated by whitespace and can be quoted with `"..."`
+/// (with `\n`, `\xff`-style escapes) or `'...'` (verbatim except for
+/// `\'`). Everything after a `#` at the beginning of an argument is a
+/// comment.
+///
+/// Arguments are bytes, since `\x` escapes can produce any byte: see
+/// [`split_str_args`] for arguments that must be text.
+pub fn split_args(line: &str) -> Result<Vec<Vec<u8>>> {
+    let line = line.as_bytes();
+    let space = |i: usize| line.get(i).is_some_and(u8::is_ascii_whitespace);
+    // like in redis, `\x` is only an escape if two hex digits follow
+    let hex = |i: usize| {
+        let h = line.get(i..i + 2)?;
+        h.iter().all(u8::is_ascii_hexdigit).then(|| {
+            let digit = |c: u8| (c as char).to_digit(16).unwrap() as u8;
+            digit(h[0]) << 4 | digit(h[1])
+        })
+    };
+
+    let mut res = vec![];
+    let mut i = 0;
+    loop {
+        while space(i) {
+            i += 1;
+        }
+        let Some(&c) = line.get(i) else { break };
+        if c == b'#' {
+            break;
+        }
+
+        let mut arg = vec![];
+        match c {
+            b'"' => {
+                i += 1;
+                loop {
+                    match line.get(i) {
+                        None => anyhow::bail!("unbalanced quotes"),
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            i += 1;
+                            match (line.get(i), hex(i + 1)) {
+                                (Some(b'x'), Some(b)) => {
+                                    arg.push(b);
+                                    i += 2;
+                                }
+                                (Some(b'n'), _) => arg.push(b'\n'),
+                                (Some(b'r'), _) => arg.push(b'\r'),
+                                (Some(b't'), _) => arg.push(b'\t'),
+                                (Some(b'b'), _) => arg.push(b'\x08'),
+                                (Some(b'a'), _) => arg.push(b'\x07'),
+                                (Some(&c), _) => arg.push(c),
+                                (None, _) => anyhow::bail!("unbalanced quotes"),
+                            }
+                        }
+                        Some(&c) => arg.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'\'' => {
+                i += 1;
+                loop {
+                    match line.get(i) {
+                        None => anyhow::bail!("unbalanced quotes"),
+                        Some(b'\'') => break,
+                        Some(b'\\') if line.get(i + 1) == Some(&b'\'') => {
+                            arg.push(b'\'');
+                            i += 1;
+                        }
+                        Some(&c) => arg.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => {
+                while let Some(&c) = line.get(i).filter(|_| !space(i)) {
+                    arg.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        // closing quote must be followed by a space
+        if matches!(c, b'"' | b'\'') && i < line.len() && !space(i) {
+            anyhow::bail!("closing quote must be followed by a space");
+        }
+        res.push(arg);
+    }
+    Ok(res)
+}
+
+/// [`split_args`], for arguments that must be valid UTF-8.
+pub fn split_str_args(line: &str) -> Result<Vec<String>> {
+    split_args(line)?
+        .into_iter()
+        .map(|a| String::from_utf8(a).context("argument is not valid UTF-8"))
+        .collect()
+}
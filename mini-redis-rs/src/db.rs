@@ -0,0 +1,695 @@
+//! Keyspace storage.
+//!
+//! Each key maps to an [`Entry`], which holds the [`Value`] along with the
+//! metadata needed for expiration and eviction. [`Db`] keeps an
+//! approximation of the memory used by its entries.
+
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::{
+    mapref::{entry::Entry as MapEntry, one::Ref},
+    DashMap,
+};
+
+use crate::{
+    hash::Hash,
+    list::{List, ListpackSize},
+    set::{Set, SetLimits},
+    zset::ZSet,
+};
+
+/// Approximate per-key overhead, in bytes, on top of the key and value.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Number of keys with an expiration time sampled at once by
+/// [`Db::active_expire`].
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+
+/// Maximum number of random probes per entry asked of [`Db::sample`]. A
+/// probe finds an entry about one time in ten when the shard is full, and
+/// less often when it's sparse.
+const SAMPLE_PROBES: usize = 64;
+
+/// Age in milliseconds past which the snapshot of a shard taken by
+/// [`Db::scan`] is dropped, in case the iteration was abandoned.
+const SCAN_SNAPSHOT_MAX_AGE: u64 = 10_000;
+
+/// Longest string reported as `embstr` by `OBJECT ENCODING`, like in
+/// redis.
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// Initial value of the LFU counter, so that new keys are not evicted
+/// right away.
+const LFU_INIT_VAL: u8 = 5;
+
+/// Current time, in milliseconds since the UNIX epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Parameters of the LFU counters, from the configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct LfuParams {
+    /// How many accesses it takes to saturate the counter (logarithmic).
+    pub log_factor: u32,
+    /// Number of idle minutes after which the counter is decremented.
+    pub decay_time: u32,
+}
+
+/// Sizes under which hashes, sets and sorted sets use the listpack
+/// encoding, from the `*-max-listpack-entries` and `*-max-listpack-value`
+/// parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ListpackLimits {
+    /// Maximum number of entries.
+    pub entries: usize,
+    /// Maximum length of an entry.
+    pub value: usize,
+}
+
+impl Default for ListpackLimits {
+    fn default() -> Self {
+        Self {
+            entries: 128,
+            value: 64,
+        }
+    }
+}
+
+/// Limits of the compact encodings of each type, from the configuration.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EncodingLimits {
+    pub hash: ListpackLimits,
+    pub list: ListpackSize,
+    pub set: SetLimits,
+    pub zset: ListpackLimits,
+}
+
+/// The integer `s` is the canonical representation of, if any: no sign or
+/// leading zeros that would be lost when formatting it back.
+pub fn parse_int(s: &[u8]) -> Option<i64> {
+    std::str::from_utf8(s)
+        .ok()
+        .filter(|s| s.len() <= 20)
+        .and_then(|s| s.parse::<i64>().ok().filter(|i| i.to_string() == s))
+}
+
+/// A value.
+#[derive(Clone, Debug)]
+pub enum Value {
+    /// A string. Bitmaps and HyperLogLogs are strings too, which is why
+    /// strings may hold binary data.
+    String(Vec<u8>),
+    /// A string holding an integer, stored as one. See [`Value::string`].
+    Int(i64),
+    Hash(Hash),
+    List(List),
+    Set(Set),
+    ZSet(ZSet),
+}
+
+impl Value {
+    /// A string value, stored as an integer if `s` is the canonical
+    /// representation of one (see [`parse_int`]).
+    pub fn string(s: Vec<u8>) -> Self {
+        match parse_int(&s) {
+            Some(i) => Value::Int(i),
+            None => Value::String(s),
+        }
+    }
+
+    /// Content of a string, whatever its encoding, or `None` for other
+    /// types.
+    pub fn as_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Value::String(s) => Some(Cow::Borrowed(s)),
+            Value::Int(i) => Some(Cow::Owned(i.to_string().into_bytes())),
+            _ => None,
+        }
+    }
+
+    /// Content of a string, to modify it in place. Integers are turned
+    /// into plain strings first.
+    pub fn as_bytes_mut(&mut self) -> Option<&mut Vec<u8>> {
+        if let Value::Int(i) = self {
+            *self = Value::String(i.to_string().into_bytes());
+        }
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Name of the type, as returned by `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) | Value::Int(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+        }
+    }
+
+    /// Is this a hash, list, set or sorted set without any element? Those
+    /// are removed rather than stored.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            Value::String(_) | Value::Int(_) => false,
+            Value::Hash(h) => h.is_empty(),
+            Value::List(l) => l.is_empty(),
+            Value::Set(s) => s.is_empty(),
+            Value::ZSet(z) => z.is_empty(),
+        }
+    }
+
+    /// Apply the current `limits`, switching to another encoding if the
+    /// value is over them, e.g. after loading it.
+    pub fn set_limits(&mut self, limits: &EncodingLimits) {
+        match self {
+            Value::String(_) | Value::Int(_) => (),
+            Value::Hash(h) => h.set_limits(limits.hash),
+            Value::List(l) => l.set_limit(limits.list),
+            Value::Set(s) => s.set_limits(limits.set),
+            Value::ZSet(z) => z.set_limits(limits.zset),
+        }
+    }
+
+    /// Name of the encoding, as returned by `OBJECT ENCODING`.
+    ///
+    /// Strings that are not integers are all stored the same way, but are
+    /// reported as `embstr` or `raw` depending on their length, like in
+    /// redis.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(s) if s.len() <= EMBSTR_MAX_LEN => "embstr",
+            Value::String(_) => "raw",
+            Value::Int(_) => "int",
+            Value::Hash(h) => h.encoding(),
+            Value::List(l) => l.encoding(),
+            Value::Set(s) => s.encoding(),
+            Value::ZSet(z) => z.encoding(),
+        }
+    }
+
+    /// Approximate memory used by the value.
+    fn size(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            // stored in the entry itself
+            Value::Int(_) => 0,
+            Value::Hash(h) => h.mem_usage(),
+            Value::List(l) => l.mem_usage(),
+            Value::Set(s) => s.mem_usage(),
+            Value::ZSet(z) => z.mem_usage(),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::string(s.as_bytes().to_vec())
+    }
+}
+
+/// A value along with its metadata.
+#[derive(Debug)]
+pub struct Entry {
+    pub value: Value,
+    /// Expiration time, in milliseconds since the UNIX epoch.
+    pub expires_at: Option<u64>,
+    /// Time of last access, in seconds since the UNIX epoch.
+    lru: AtomicU32,
+    /// Last decrement time in minutes (high 24 bits) and logarithmic
+    /// access counter (low 8 bits), like in redis.
+    lfu: AtomicU32,
+}
+
+impl Entry {
+    pub fn new(value: Value, expires_at: Option<u64>) -> Self {
+        let now = now_ms();
+        Self {
+            value,
+            expires_at,
+            lru: AtomicU32::new((now / 1000) as u32),
+            lfu: AtomicU32::new(
+                ((now / 60_000) as u32) << 8 | LFU_INIT_VAL as u32,
+            ),
+        }
+    }
+
+    /// Approximate memory used by this entry, key included.
+    pub fn mem_usage(&self, key: &str) -> usize {
+        key.len() + self.value_size()
+    }
+
+    fn value_size(&self) -> usize {
+        self.value.size() + ENTRY_OVERHEAD
+    }
+
+    /// Name of the type of the value, as returned by `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        self.value.type_name()
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    /// Number of seconds since the last access.
+    pub fn idle_time(&self, now: u64) -> u64 {
+        (now / 1000).saturating_sub(self.lru.load(Ordering::Relaxed) as u64)
+    }
+
+    /// Current LFU counter, taking decay into account.
+    pub fn lfu_counter(&self, now: u64, params: LfuParams) -> u8 {
+        let lfu = self.lfu.load(Ordering::Relaxed);
+        let counter = (lfu & 0xff) as u8;
+        if params.decay_time == 0 {
+            return counter;
+        }
+        let now_min = (now / 60_000) as u32 & 0xff_ffff;
+        let last_min = lfu >> 8;
+        let elapsed = if now_min >= last_min {
+            now_min - last_min
+        } else {
+            0xff_ffff - last_min + now_min // wrapped around
+        };
+        let decr = elapsed / params.decay_time;
+        counter.saturating_sub(decr.min(255) as u8)
+    }
+
+    /// Record an access, for the LRU and LFU policies.
+    pub fn touch(&self, now: u64, params: LfuParams) {
+        self.lru.store((now / 1000) as u32, Ordering::Relaxed);
+
+        let mut counter = self.lfu_counter(now, params);
+        if counter < 255 {
+            // increment with probability 1/((counter-init)*factor+1)
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            let p = 1. / (base * params.log_factor as f64 + 1.);
+            if fastrand::f64() < p {
+                counter += 1;
+            }
+        }
+        let now_min = (now / 60_000) as u32 & 0xff_ffff;
+        self.lfu
+            .store(now_min << 8 | counter as u32, Ordering::Relaxed);
+    }
+}
+
+/// Keys of a shard along with their position in [`Db::scan`] order,
+/// sorted by position.
+type ScanSnapshot = Arc<Vec<(u64, String)>>;
+
+fn drop_old_snapshots(snapshots: &mut [Option<(u64, ScanSnapshot)>], now: u64) {
+    for s in snapshots {
+        if s.as_ref().is_some_and(|(taken_at, _)| {
+            now.saturating_sub(*taken_at) > SCAN_SNAPSHOT_MAX_AGE
+        }) {
+            *s = None;
+        }
+    }
+}
+
+/// A keyspace.
+#[derive(Debug, Default)]
+pub struct Db {
+    kv: DashMap<String, Entry>,
+    /// Sum of [`Entry::mem_usage`] for all entries.
+    used_memory: AtomicUsize,
+    /// Number of keys with an expiration time.
+    expires: AtomicUsize,
+    /// Number of keys removed because they expired.
+    expired_keys: AtomicU64,
+    /// Keys that expired since the last call to [`Db::take_expired`], for
+    /// keyspace notifications.
+    expired: Mutex<Vec<String>>,
+    /// Sorted keys of the shards that a [`Db::scan`] is going through, by
+    /// shard index, along with when they were taken.
+    scan_snapshots: Mutex<Vec<Option<(u64, ScanSnapshot)>>>,
+}
+
+impl Db {
+    /// Number of keys, including expired keys that were not removed yet.
+    pub fn len(&self) -> usize {
+        self.kv.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kv.is_empty()
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// Number of keys with an expiration time.
+    pub fn expires(&self) -> usize {
+        self.expires.load(Ordering::Relaxed)
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    /// Access the entry for `key`, if it exists and is not expired.
+    ///
+    /// This does not count as an access for eviction purposes, see
+    /// [`Db::get`] for that.
+    pub fn peek(&self, key: &str) -> Option<Ref<'_, String, Entry>> {
+        let now = now_ms();
+        let e = self.kv.get(key)?;
+        if !e.is_expired(now) {
+            return Some(e);
+        }
+        drop(e); // release the lock before removing
+        if self.remove_if(key, |e| e.is_expired(now)).is_some() {
+            self.expired(key);
+        }
+        None
+    }
+
+    /// Record that `key` was removed because it expired.
+    fn expired(&self, key: &str) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+        self.expired.lock().unwrap().push(key.to_string());
+    }
+
+    /// Keys that expired since the last call.
+    pub fn take_expired(&self) -> Vec<String> {
+        std::mem::take(&mut *self.expired.lock().unwrap())
+    }
+
+    /// Remove some of the expired keys, like redis' active expire cycle:
+    /// sample keys with an expiration time, remove the expired ones, and
+    /// start again as long as more than a quarter of them had expired.
+    ///
+    /// At most `max_rounds` samples are taken. Returns the number of keys
+    /// removed.
+    pub fn active_expire(&self, max_rounds: usize) -> usize {
+        let mut removed = 0;
+        for _ in 0..max_rounds {
+            if self.expires() == 0 {
+                break;
+            }
+            let now = now_ms();
+            let sampled = self.sample(
+                ACTIVE_EXPIRE_SAMPLES,
+                |e| e.expires_at.is_some(),
+                |k, e| (k.to_string(), e.is_expired(now)),
+            );
+            let mut n = 0;
+            for (k, _) in sampled.iter().filter(|(_, expired)| *expired) {
+                if self.remove_if(k, |e| e.is_expired(now)).is_some() {
+                    self.expired(k);
+                    n += 1;
+                }
+            }
+            removed += n;
+            if n * 4 <= sampled.len() {
+                break;
+            }
+        }
+        removed
+    }
+
+    /// Access the entry for `key` and record the access.
+    pub fn get(
+        &self,
+        key: &str,
+        lfu: LfuParams,
+    ) -> Option<Ref<'_, String, Entry>> {
+        let e = self.peek(key)?;
+        e.touch(now_ms(), lfu);
+        Some(e)
+    }
+
+    /// Insert or replace `key`. Returns `true` if the key is new.
+    pub fn insert(&self, key: String, entry: Entry) -> bool {
+        // an expired entry is removed (and counted as expired) first
+        let is_new = self.peek(&key).is_none();
+        let key_len = key.len();
+        let size = entry.mem_usage(&key);
+        self.used_memory.fetch_add(size, Ordering::Relaxed);
+        if entry.expires_at.is_some() {
+            self.expires.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(old) = self.kv.insert(key, entry) {
+            let old_size = key_len + old.value_size();
+            self.used_memory.fetch_sub(old_size, Ordering::Relaxed);
+            if old.expires_at.is_some() {
+                self.expires.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        is_new
+    }
+
+    /// Modify the value of `key` in place with `f`, and record the access.
+    ///
+    /// If the key does not exist, it's created with the value returned by
+    /// `new` first, unless that's `None`, in which case `None` is returned
+    /// and `f` is not called.
+    pub fn update<R>(
+        &self,
+        key: &str,
+        lfu: LfuParams,
+        new: impl FnOnce() -> Option<Value>,
+        f: impl FnOnce(&mut Value) -> R,
+    ) -> Option<R> {
+        // an expired entry is removed (and counted as expired) first
+        self.peek(key);
+        let mut e = match self.kv.entry(key.to_string()) {
+            MapEntry::Occupied(e) => e.into_ref(),
+            MapEntry::Vacant(e) => {
+                let entry = Entry::new(new()?, None);
+                let size = entry.mem_usage(key);
+                self.used_memory.fetch_add(size, Ordering::Relaxed);
+                e.insert(entry)
+            }
+        };
+        e.touch(now_ms(), lfu);
+        let before = e.value_size();
+        let res = f(&mut e.value);
+        let after = e.value_size();
+        self.used_memory.fetch_add(after, Ordering::Relaxed);
+        self.used_memory.fetch_sub(before, Ordering::Relaxed);
+        Some(res)
+    }
+
+    /// Change the expiration time of `key`. Returns `false` if the key
+    /// does not exist.
+    pub fn set_expire(&self, key: &str, expires_at: Option<u64>) -> bool {
+        if self.peek(key).is_none() {
+            return false;
+        }
+        match self.kv.get_mut(key) {
+            Some(mut e) => {
+                match (e.expires_at, expires_at) {
+                    (None, Some(_)) => {
+                        self.expires.fetch_add(1, Ordering::Relaxed);
+                    }
+                    (Some(_), None) => {
+                        self.expires.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    _ => (),
+                }
+                e.expires_at = expires_at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove `key`, returning its entry if it was present and not
+    /// expired.
+    pub fn remove(&self, key: &str) -> Option<Entry> {
+        let e = self.remove_if(key, |_| true)?;
+        if e.is_expired(now_ms()) {
+            self.expired(key);
+            return None;
+        }
+        Some(e)
+    }
+
+    /// Remove all keys.
+    pub fn clear(&self) {
+        self.scan_snapshots.lock().unwrap().clear();
+        self.kv.retain(|k, e| {
+            self.used_memory
+                .fetch_sub(e.mem_usage(k), Ordering::Relaxed);
+            if e.expires_at.is_some() {
+                self.expires.fetch_sub(1, Ordering::Relaxed);
+            }
+            false
+        });
+    }
+
+    /// Call `f` on every entry that is not expired.
+    ///
+    /// `f` must not access `self`, as the shard it's iterating on is
+    /// locked.
+    pub fn for_each(&self, mut f: impl FnMut(&str, &Entry)) {
+        let now = now_ms();
+        for r in self.kv.iter() {
+            if !r.is_expired(now) {
+                f(r.key(), r.value())
+            }
+        }
+    }
+
+    /// Return at least `count` keys (if there are enough left), starting
+    /// from `cursor`, along with the cursor to continue from. A cursor of
+    /// 0 starts an iteration, and is returned when it's complete.
+    ///
+    /// Keys are ordered by shard, then by hash within a shard, and the
+    /// cursor is the position in that order. A key that is present during
+    /// the whole iteration is thus returned exactly once, even if the
+    /// keyspace is modified between calls.
+    ///
+    /// The keys of a shard are sorted once, when an iteration enters it,
+    /// so that a full iteration takes `O(n log n)` rather than sorting the
+    /// shard again on each call. That copy is dropped when the iteration
+    /// leaves the shard, or after [`SCAN_SNAPSHOT_MAX_AGE`] if it was
+    /// abandoned; an iteration that is still going sorts it again.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let shards = self.kv.shards();
+        // the shard count is a power of two; keep the hash bits that
+        // don't fit next to the shard index in the cursor
+        let shard_bits = shards.len().trailing_zeros();
+        let hash_bits = 64 - shard_bits;
+        let mask = u64::MAX.checked_shr(shard_bits).unwrap_or(0);
+        let mut shard = cursor.checked_shr(hash_bits).unwrap_or(0) as usize;
+        let mut pos = cursor & mask;
+
+        let now = now_ms();
+        let mut res = vec![];
+        while shard < shards.len() {
+            let keys = self.scan_snapshot(shard, pos == 0, mask);
+            let start = keys.partition_point(|(h, _)| *h < pos);
+
+            let mut keys = keys[start..].iter().peekable();
+            while let Some((h, k)) = keys.next() {
+                // the snapshot may be older than the last changes
+                if self.kv.get(k).is_some_and(|e| !e.is_expired(now)) {
+                    res.push(k.clone());
+                }
+                // stop if we have enough, but never in the middle of
+                // keys with the same position
+                if res.len() >= count
+                    && keys.peek().is_some_and(|(h2, _)| h2 != h)
+                {
+                    let (next_shard, next_pos) = match h.checked_add(1) {
+                        Some(h) if h <= mask => (shard as u64, h),
+                        _ => (shard as u64 + 1, 0),
+                    };
+                    let cursor = next_shard.checked_shl(hash_bits).unwrap_or(0)
+                        | next_pos;
+                    return (cursor, res);
+                }
+            }
+            self.scan_snapshots.lock().unwrap()[shard] = None;
+
+            shard += 1;
+            pos = 0;
+            if res.len() >= count && shard < shards.len() {
+                let cursor = (shard as u64).checked_shl(hash_bits).unwrap_or(0);
+                return (cursor, res);
+            }
+        }
+        (0, res)
+    }
+
+    /// Snapshot of the keys of `shard`, for [`Db::scan`]. It is taken
+    /// again if `fresh` is set or if there is none.
+    ///
+    /// A snapshot taken after an iteration entered the shard holds all
+    /// the keys that are present during the whole iteration.
+    fn scan_snapshot(
+        &self,
+        shard: usize,
+        fresh: bool,
+        mask: u64,
+    ) -> ScanSnapshot {
+        let shards = self.kv.shards();
+        let now = now_ms();
+        let mut snapshots = self.scan_snapshots.lock().unwrap();
+        drop_old_snapshots(&mut snapshots, now);
+        snapshots.resize(shards.len(), None);
+        match &snapshots[shard] {
+            Some((_, keys)) if !fresh => keys.clone(),
+            _ => {
+                let mut keys: Vec<(u64, String)> = shards[shard]
+                    .read()
+                    .keys()
+                    .map(|k| (self.kv.hash_usize(k) as u64 & mask, k.clone()))
+                    .collect();
+                keys.sort_unstable();
+                let keys = Arc::new(keys);
+                snapshots[shard] = Some((now, keys.clone()));
+                keys
+            }
+        }
+    }
+
+    /// Drop the [`Db::scan`] snapshots older than
+    /// [`SCAN_SNAPSHOT_MAX_AGE`], whose iteration was likely abandoned.
+    pub fn drop_old_scan_snapshots(&self) {
+        drop_old_snapshots(&mut self.scan_snapshots.lock().unwrap(), now_ms());
+    }
+
+    fn remove_if(
+        &self,
+        key: &str,
+        f: impl FnOnce(&Entry) -> bool,
+    ) -> Option<Entry> {
+        let (k, e) = self.kv.remove_if(key, |_, e| f(e))?;
+        self.used_memory
+            .fetch_sub(e.mem_usage(&k), Ordering::Relaxed);
+        if e.expires_at.is_some() {
+            self.expires.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(e)
+    }
+
+    /// Call `f` on up to `n` entries picked at random among the entries
+    /// that satisfy `filter`.
+    ///
+    /// Like redis, this takes a bounded number of random probes rather
+    /// than a pass over the keys: each probe looks up a random hash in a
+    /// random shard, which finds one of the entries of the buckets it
+    /// lands on, if any. Every entry is as likely to be found, but fewer
+    /// than `n` entries are returned when few of them satisfy `filter`.
+    pub fn sample<R>(
+        &self,
+        n: usize,
+        filter: impl Fn(&Entry) -> bool,
+        mut f: impl FnMut(&str, &Entry) -> R,
+    ) -> Vec<R> {
+        let shards = self.kv.shards();
+        let mut res: Vec<R> = Vec::with_capacity(n);
+        for _ in 0..n * SAMPLE_PROBES {
+            if res.len() >= n || self.is_empty() {
+                break;
+            }
+            let shard = shards[fastrand::usize(..shards.len())].read();
+            let hash = fastrand::u64(..);
+            if let Some((k, v)) = shard.raw_entry().from_hash(hash, |_| true) {
+                if filter(v.get()) {
+                    res.push(f(k, v.get()));
+                }
+            }
+        }
+        res
+    }
+}
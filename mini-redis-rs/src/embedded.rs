@@ -0,0 +1,180 @@
+//! A server running in the background of another program, typically
+//! tests.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use mini_redis_rs::{client::blocking::Client, db::Entry, Server};
+//!
+//! let server = Server::builder().set("databases", "4")?.start()?;
+//! server.state().db(0).insert("k".into(), Entry::new("v".into(), None));
+//! let client = Client::connect(server.addr().to_string())?;
+//! assert_eq!(client.get("k")?.as_deref(), Some("v"));
+//! server.shutdown()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    thread::JoinHandle,
+};
+
+use anyhow::Result;
+
+use crate::{
+    config::Config,
+    rdb,
+    server::{self, Listeners, ShutdownMode, State},
+};
+
+/// Handle on a server running on its own thread, with its own runtime.
+///
+/// The server is shut down when the handle is dropped, without saving a
+/// snapshot.
+pub struct Server {
+    addr: SocketAddr,
+    st: Arc<State>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+/// Configuration of a [`Server`], from [`Server::builder`].
+pub struct Builder {
+    config: Config,
+    config_file: Option<PathBuf>,
+}
+
+impl Server {
+    /// Configure a server. Unlike with [`Config::default`], it listens on
+    /// a port chosen by the OS, and never saves snapshots: see
+    /// [`Builder::config`] for the whole configuration.
+    pub fn builder() -> Builder {
+        Builder {
+            config: Config {
+                port: 0,
+                save: vec![],
+                ..Default::default()
+            },
+            config_file: None,
+        }
+    }
+
+    /// Address clients can connect to, the first one if the server
+    /// listens on several.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// State of the server, to seed or inspect data without going through
+    /// a client.
+    pub fn state(&self) -> &Arc<State> {
+        &self.st
+    }
+
+    /// Shut the server down like `SHUTDOWN` does, and wait until it's
+    /// done. Returns the error that stopped the server, if any.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop(ShutdownMode::Default)
+    }
+
+    fn stop(&mut self, mode: ShutdownMode) -> Result<()> {
+        self.st.shutdown(mode);
+        match self.thread.take() {
+            Some(thread) => match thread.join() {
+                Ok(res) => res,
+                Err(_) => anyhow::bail!("the server thread panicked"),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop(ShutdownMode::NoSave) {
+            log::error!("server stopped with an error: {e:#}");
+        }
+    }
+}
+
+impl Builder {
+    /// Replace the whole configuration.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Apply the directives of the configuration file at `path`, which
+    /// `CONFIG REWRITE` then updates.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        self.config.load_file(&path)?;
+        self.config_file = Some(path);
+        Ok(self)
+    }
+
+    /// Set a parameter, like in a configuration file.
+    pub fn set(mut self, name: &str, value: &str) -> Result<Self> {
+        self.config.set(name, value)?;
+        Ok(self)
+    }
+
+    /// Load the snapshot if there is one, and start serving clients. This
+    /// returns once the server listens.
+    pub fn start(self) -> Result<Server> {
+        let st = Arc::new(State::new(self.config, self.config_file));
+        let (tx, rx) = mpsc::channel();
+        let thread_st = st.clone();
+        let thread = std::thread::Builder::new()
+            .name("mini-redis-server".to_string())
+            .spawn(move || -> Result<()> {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                rt.block_on(async {
+                    let bound = async {
+                        rdb::load(&thread_st)?;
+                        let listeners = Listeners::bind(&thread_st).await?;
+                        let addrs = listeners.local_addrs()?;
+                        anyhow::ensure!(
+                            !addrs.is_empty(),
+                            "no address to listen on"
+                        );
+                        anyhow::Ok((listeners, addrs[0]))
+                    };
+                    match bound.await {
+                        Ok((listeners, addr)) => {
+                            let _ = tx.send(Ok(addr));
+                            server::serve(thread_st, listeners).await
+                        }
+                        Err(e) => {
+                            let _ = tx.send(Err(e));
+                            Ok(())
+                        }
+                    }
+                })
+            })?;
+
+        let addr = match rx.recv() {
+            Ok(addr) => addr?,
+            // the thread exited before binding
+            Err(_) => {
+                return match thread.join() {
+                    Ok(Err(e)) => Err(e),
+                    _ => Err(anyhow::anyhow!("the server thread panicked")),
+                }
+            }
+        };
+        Ok(Server {
+            addr,
+            st,
+            thread: Some(thread),
+        })
+    }
+}
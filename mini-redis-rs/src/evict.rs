@@ -0,0 +1,200 @@
+//! Eviction of keys when `maxmemory` is reached.
+//!
+//! Like redis, this doesn't look for the globally best key to evict but
+//! approximates it by sampling: before each eviction, a few random keys
+//! of each database are merged into a small pool of the best candidates
+//! seen so far, and the best one is evicted.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crate::{
+    config::Config,
+    db::{now_ms, Db, Entry},
+};
+
+/// Which keys to evict when memory is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Don't evict, reject writes instead.
+    NoEviction,
+    /// Evict the least recently used keys.
+    AllKeysLru,
+    /// Evict the least frequently used keys.
+    AllKeysLfu,
+    /// Evict the keys with an expiration time that expire the soonest.
+    VolatileTtl,
+    /// Evict keys at random.
+    AllKeysRandom,
+}
+
+impl Policy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Policy::NoEviction => "noeviction",
+            Policy::AllKeysLru => "allkeys-lru",
+            Policy::AllKeysLfu => "allkeys-lfu",
+            Policy::VolatileTtl => "volatile-ttl",
+            Policy::AllKeysRandom => "allkeys-random",
+        }
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "noeviction" => Policy::NoEviction,
+            "allkeys-lru" => Policy::AllKeysLru,
+            "allkeys-lfu" => Policy::AllKeysLfu,
+            "volatile-ttl" => Policy::VolatileTtl,
+            "allkeys-random" => Policy::AllKeysRandom,
+            _ => anyhow::bail!("invalid eviction policy {s:?}"),
+        })
+    }
+}
+
+/// Number of candidates kept in the pool.
+const EVPOOL_SIZE: usize = 16;
+
+/// A key that could be evicted. The higher the score, the better.
+#[derive(Debug)]
+struct Candidate {
+    /// Index of the database holding the key.
+    db: usize,
+    key: String,
+    score: u64,
+}
+
+/// Eviction state.
+#[derive(Debug, Default)]
+pub struct Evictor {
+    /// Best candidates sampled so far, sorted by increasing score, along
+    /// with the policy used to score them.
+    pool: Mutex<Option<(Policy, Vec<Candidate>)>>,
+    /// Number of keys evicted so far.
+    evicted_keys: AtomicU64,
+}
+
+impl Evictor {
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    /// Forget the candidates, when databases are swapped or flushed: they
+    /// may now refer to keys of another database.
+    pub fn clear_pool(&self) {
+        *self.pool.lock().unwrap() = None;
+    }
+
+    /// Evict keys from `dbs` until their memory usage is below
+    /// `maxmemory`, calling `on_evict` with the index of the db and the
+    /// key for each evicted key.
+    ///
+    /// Returns `false` if it's not possible, either because the policy
+    /// forbids it or because there are no suitable keys left.
+    pub fn free_memory(
+        &self,
+        dbs: &[Arc<Db>],
+        config: &Config,
+        mut on_evict: impl FnMut(usize, &str),
+    ) -> bool {
+        let policy = config.maxmemory_policy;
+        let mut pool = self.pool.lock().unwrap();
+        let used_memory =
+            || dbs.iter().map(|db| db.used_memory()).sum::<usize>() as u64;
+
+        while config.maxmemory > 0 && used_memory() > config.maxmemory {
+            if policy == Policy::NoEviction {
+                return false;
+            }
+
+            let candidates = match &mut *pool {
+                Some((p, c)) if *p == policy => c,
+                _ => &mut pool.insert((policy, vec![])).1,
+            };
+            merge(candidates, sample(dbs, config));
+            let Some(c) = candidates.pop() else {
+                log::warn!("no key left to evict");
+                return false;
+            };
+            let removed = dbs.get(c.db).and_then(|db| db.remove(&c.key));
+            if removed.is_some() {
+                log::debug!(
+                    "evicted {:?} from db{} (score {})",
+                    c.key,
+                    c.db,
+                    c.score
+                );
+                self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                on_evict(c.db, &c.key);
+            }
+        }
+        true
+    }
+}
+
+/// Sample `maxmemory-samples` keys from every non-empty db.
+///
+/// The random probes of [`Db::sample`] can miss the few keys that may be
+/// evicted, e.g. with `volatile-ttl` and few keys with an expiration time:
+/// if they find none, all the keys of the dbs that have some are looked
+/// at instead.
+fn sample(dbs: &[Arc<Db>], config: &Config) -> Vec<Candidate> {
+    let policy = config.maxmemory_policy;
+    let lfu = config.lfu_params();
+    let now = now_ms();
+    let n = config.maxmemory_samples.max(1);
+    let filter =
+        |e: &Entry| policy != Policy::VolatileTtl || e.expires_at.is_some();
+    let candidate = |db: usize, k: &str, e: &Entry| {
+        let score = match policy {
+            Policy::NoEviction => 0,
+            Policy::AllKeysLru => e.idle_time(now),
+            Policy::AllKeysLfu => 255 - e.lfu_counter(now, lfu) as u64,
+            Policy::VolatileTtl => u64::MAX - e.expires_at.unwrap_or(0),
+            Policy::AllKeysRandom => fastrand::u64(..),
+        };
+        Candidate {
+            db,
+            key: k.to_string(),
+            score,
+        }
+    };
+    let has_keys = |db: &Db| match policy {
+        Policy::VolatileTtl => db.expires() > 0,
+        _ => !db.is_empty(),
+    };
+
+    let mut candidates = vec![];
+    for (i, db) in dbs.iter().enumerate().filter(|(_, db)| has_keys(db)) {
+        candidates.extend(db.sample(n, filter, |k, e| candidate(i, k, e)));
+    }
+    if candidates.is_empty() {
+        for (i, db) in dbs.iter().enumerate().filter(|(_, db)| has_keys(db)) {
+            db.for_each(|k, e| {
+                if filter(e) {
+                    candidates.push(candidate(i, k, e));
+                }
+            });
+        }
+    }
+    candidates
+}
+
+/// Add `candidates` to `pool`, keeping it sorted by increasing score and
+/// only its [`EVPOOL_SIZE`] best candidates.
+fn merge(pool: &mut Vec<Candidate>, candidates: Vec<Candidate>) {
+    for c in candidates {
+        if !pool.iter().any(|p| p.db == c.db && p.key == c.key) {
+            let pos = pool.partition_point(|p| p.score <= c.score);
+            pool.insert(pos, c);
+            if pool.len() > EVPOOL_SIZE {
+                pool.remove(0);
+            }
+        }
+    }
+}
@@ -0,0 +1,608 @@
+//! Geospatial indexes: `GEOADD`, `GEOPOS`, `GEODIST`, `GEOHASH` and
+//! `GEOSEARCH`.
+//!
+//! Like in redis, locations are members of a sorted set, whose score is a
+//! 52-bit geohash of the coordinates, interleaving 26 bits of longitude
+//! and 26 bits of latitude. Sets can thus be loaded from redis snapshots,
+//! and inspected or modified with sorted set commands.
+
+use crate::{
+    notify,
+    server::{wrong_arity, State},
+    wire::Frame,
+    zset::{self, ZSet},
+};
+
+/// Bits of each coordinate in a geohash.
+const STEP: u32 = 26;
+
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+/// Latitudes are limited to what EPSG:3857 can represent.
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+
+/// Radius of the Earth used for distances, in meters.
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Execute a geo command on the selected database `db_index`. `args`
+/// includes the command name, and `raw` are the same arguments as bytes,
+/// for the members.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let lfu = st.config().lfu_params();
+    match (cmd, &args[1..]) {
+        ("geoadd", [k, rest @ ..]) => {
+            geoadd(st, db_index, k, rest, &raw[2..], arena)
+        }
+        ("geopos", [k, members @ ..]) => {
+            let res = zset::read(db, k, lfu, |z| {
+                raw[2..]
+                    .iter()
+                    .map(|m| match z.score(m) {
+                        Some(score) => {
+                            let (lon, lat) = decode(score as u64);
+                            coords(lon, lat, arena)
+                        }
+                        None => Frame::Null,
+                    })
+                    .collect::<Vec<_>>()
+            });
+            match res {
+                Ok(Some(items)) => Frame::Bulk(arena.alloc_slice_copy(&items)),
+                Ok(None) => {
+                    let items = vec![Frame::Null; members.len()];
+                    Frame::Bulk(arena.alloc_slice_copy(&items))
+                }
+                Err(e) => e,
+            }
+        }
+        ("geodist", [k, _, _, unit @ ..]) => {
+            let unit = match unit {
+                [] => 1.0,
+                [u] => match parse_unit(u) {
+                    Ok(u) => u,
+                    Err(e) => return e,
+                },
+                _ => return Frame::Error("ERR syntax error"),
+            };
+            let res = zset::read(db, k, lfu, |z| {
+                let a = decode(z.score(raw[2])? as u64);
+                let b = decode(z.score(raw[3])? as u64);
+                Some(distance(a, b))
+            });
+            match res {
+                Ok(Some(Some(d))) => {
+                    Frame::String(arena.alloc_str(&format!("{:.4}", d / unit)))
+                }
+                Ok(_) => Frame::Null,
+                Err(e) => e,
+            }
+        }
+        ("geohash", [k, members @ ..]) => {
+            let res = zset::read(db, k, lfu, |z| {
+                raw[2..]
+                    .iter()
+                    .map(|m| match z.score(m) {
+                        Some(score) => {
+                            let (lon, lat) = decode(score as u64);
+                            Frame::String(arena.alloc_str(&geohash(lon, lat)))
+                        }
+                        None => Frame::Null,
+                    })
+                    .collect::<Vec<_>>()
+            });
+            match res {
+                Ok(Some(items)) => Frame::Bulk(arena.alloc_slice_copy(&items)),
+                Ok(None) => {
+                    let items = vec![Frame::Null; members.len()];
+                    Frame::Bulk(arena.alloc_slice_copy(&items))
+                }
+                Err(e) => e,
+            }
+        }
+        ("geosearch", [k, opts @ ..]) => {
+            let search = match Search::parse(opts, &raw[2..], arena) {
+                Ok(s) => s,
+                Err(e) => return e,
+            };
+            let res = zset::read(db, k, lfu, |z| {
+                search.run(z).map(|found| search.reply(&found, arena))
+            });
+            match res {
+                Ok(Some(Ok(reply))) => reply,
+                Ok(Some(Err(e))) | Err(e) => e,
+                Ok(None) => Frame::Bulk(&[]),
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
+
+/// `GEOADD key [NX|XX] [CH] longitude latitude member [...]`. `raw` are
+/// the same arguments as `args`, as bytes.
+fn geoadd<'are>(
+    st: &State,
+    db_index: usize,
+    key: &str,
+    args: &[&str],
+    raw: &[&[u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let (mut nx, mut xx, mut ch) = (false, false, false);
+    let mut args = args;
+    while let [opt, rest @ ..] = args {
+        match &*opt.to_ascii_lowercase() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "ch" => ch = true,
+            _ => break,
+        }
+        args = rest;
+    }
+    if nx && xx {
+        return Frame::Error(
+            "ERR XX and NX options at the same time are not compatible",
+        );
+    }
+    if args.is_empty() || !args.len().is_multiple_of(3) {
+        return Frame::Error("ERR syntax error");
+    }
+    let raw = &raw[raw.len() - args.len()..];
+    let mut locations = Vec::with_capacity(args.len() / 3);
+    for (loc, raw) in args.chunks(3).zip(raw.chunks(3)) {
+        let (lon, lat) = match parse_coords(loc[0], loc[1], arena) {
+            Ok(c) => c,
+            Err(e) => return e,
+        };
+        locations.push((encode(lon, lat) as f64, raw[2]));
+    }
+
+    let db = st.db(db_index);
+    let is_new = db.peek(key).is_none();
+    let (lfu, limits) = {
+        let config = st.config();
+        (config.lfu_params(), config.zset_listpack_limits())
+    };
+    let res = zset::update(&db, key, lfu, limits, !xx, |z| {
+        let (mut added, mut changed) = (0, 0);
+        for (score, member) in locations {
+            match z.score(member) {
+                None if !xx => {
+                    z.insert(member, score);
+                    added += 1;
+                }
+                Some(old) if !nx && old != score => {
+                    z.insert(member, score);
+                    changed += 1;
+                }
+                _ => (),
+            }
+        }
+        (added, changed)
+    });
+    match res {
+        Ok(Some((added, changed))) => {
+            if is_new && added > 0 {
+                st.notify(notify::NEW, "new", key, db_index);
+            }
+            if added + changed > 0 {
+                // like in redis, where `GEOADD` is a `ZADD`
+                st.notify(notify::ZSET, "zadd", key, db_index);
+            }
+            Frame::Int(if ch { added + changed } else { added })
+        }
+        Ok(None) => Frame::Int(0),
+        Err(e) => e,
+    }
+}
+
+/// Parse a longitude and a latitude, and check that they can be indexed.
+fn parse_coords<'are>(
+    lon: &str,
+    lat: &str,
+    arena: &'are bumpalo::Bump,
+) -> Result<(f64, f64), Frame<'are>> {
+    let (Some(lon), Some(lat)) = (parse_float(lon), parse_float(lat)) else {
+        return Err(Frame::Error("ERR value is not a valid float"));
+    };
+    if !(LON_MIN..=LON_MAX).contains(&lon)
+        || !(LAT_MIN..=LAT_MAX).contains(&lat)
+    {
+        let msg =
+            format!("ERR invalid longitude,latitude pair {lon:.6},{lat:.6}");
+        return Err(Frame::Error(arena.alloc_str(&msg)));
+    }
+    Ok((lon, lat))
+}
+
+fn parse_float(s: &str) -> Option<f64> {
+    s.parse::<f64>().ok().filter(|f| f.is_finite())
+}
+
+/// Number of meters in `unit`.
+fn parse_unit(unit: &str) -> Result<f64, Frame<'static>> {
+    match &*unit.to_ascii_lowercase() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(Frame::Error(
+            "ERR unsupported unit provided. please use M, KM, FT, MI",
+        )),
+    }
+}
+
+/// A reply with coordinates.
+fn coords(lon: f64, lat: f64, arena: &bumpalo::Bump) -> Frame<'_> {
+    let pair = [
+        Frame::String(arena.alloc_str(&lon.to_string())),
+        Frame::String(arena.alloc_str(&lat.to_string())),
+    ];
+    Frame::Bulk(arena.alloc_slice_copy(&pair))
+}
+
+/// Area of a search, with distances in meters.
+#[derive(Clone, Copy, Debug)]
+enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/// Options of `GEOSEARCH`.
+#[derive(Debug)]
+struct Search<'a> {
+    from_member: Option<&'a [u8]>,
+    from_coords: Option<(f64, f64)>,
+    shape: Shape,
+    /// Meters per unit of the shape, for distances in the reply.
+    unit: f64,
+    /// Sort by increasing (`false`) or decreasing (`true`) distance.
+    desc: Option<bool>,
+    count: Option<usize>,
+    /// Return the first `count` matches found, rather than the closest.
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+}
+
+/// A location found by `GEOSEARCH`.
+struct Found<'a> {
+    member: &'a [u8],
+    /// Distance from the center, in meters.
+    dist: f64,
+    score: f64,
+}
+
+impl<'a> Search<'a> {
+    /// Parse the arguments of `GEOSEARCH` after the key. `raw` are the
+    /// same arguments as bytes, for the member of `FROMMEMBER`.
+    fn parse<'are>(
+        args: &[&str],
+        raw: &[&'a [u8]],
+        arena: &'are bumpalo::Bump,
+    ) -> Result<Self, Frame<'are>> {
+        let float = |s: &str| {
+            parse_float(s).ok_or(Frame::Error("ERR value is not a valid float"))
+        };
+        let mut from_member = None;
+        let mut from_coords = None;
+        let mut shape = None;
+        let mut unit = 1.0;
+        let mut desc = None;
+        let mut count = None;
+        let mut any = false;
+        let (mut with_coord, mut with_dist, mut with_hash) =
+            (false, false, false);
+
+        let mut args = args;
+        while let [opt, rest @ ..] = args {
+            args = rest;
+            match (&*opt.to_ascii_lowercase(), args) {
+                ("frommember", [_, rest @ ..]) if from_member.is_none() => {
+                    from_member = Some(raw[raw.len() - args.len()]);
+                    args = rest;
+                }
+                ("fromlonlat", [lon, lat, rest @ ..])
+                    if from_coords.is_none() =>
+                {
+                    from_coords = Some(parse_coords(lon, lat, arena)?);
+                    args = rest;
+                }
+                ("byradius", [r, u, rest @ ..]) if shape.is_none() => {
+                    let r = float(r)?;
+                    if r < 0.0 {
+                        return Err(Frame::Error(
+                            "ERR radius cannot be negative",
+                        ));
+                    }
+                    unit = parse_unit(u)?;
+                    shape = Some(Shape::Radius(r * unit));
+                    args = rest;
+                }
+                ("bybox", [w, h, u, rest @ ..]) if shape.is_none() => {
+                    let (w, h) = (float(w)?, float(h)?);
+                    if w < 0.0 || h < 0.0 {
+                        return Err(Frame::Error(
+                            "ERR height or width cannot be negative",
+                        ));
+                    }
+                    unit = parse_unit(u)?;
+                    shape = Some(Shape::Box {
+                        width: w * unit,
+                        height: h * unit,
+                    });
+                    args = rest;
+                }
+                ("asc", _) => desc = Some(false),
+                ("desc", _) => desc = Some(true),
+                ("count", [n, rest @ ..]) => {
+                    match n.parse::<i64>() {
+                        Ok(n) if n > 0 => count = Some(n as usize),
+                        Ok(_) => {
+                            return Err(Frame::Error("ERR COUNT must be > 0"))
+                        }
+                        Err(_) => {
+                            return Err(Frame::Error(
+                                "ERR value is not an integer or out of range",
+                            ))
+                        }
+                    }
+                    args = rest;
+                    if let [a, rest @ ..] = args {
+                        if a.eq_ignore_ascii_case("any") {
+                            any = true;
+                            args = rest;
+                        }
+                    }
+                }
+                ("withcoord", _) => with_coord = true,
+                ("withdist", _) => with_dist = true,
+                ("withhash", _) => with_hash = true,
+                _ => return Err(Frame::Error("ERR syntax error")),
+            }
+        }
+
+        if from_member.is_some() == from_coords.is_some() {
+            return Err(Frame::Error(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be \
+                specified for GEOSEARCH",
+            ));
+        }
+        let Some(shape) = shape else {
+            return Err(Frame::Error(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for \
+                GEOSEARCH",
+            ));
+        };
+        if any && count.is_none() {
+            return Err(Frame::Error(
+                "ERR the ANY argument requires COUNT argument",
+            ));
+        }
+        // like redis, return the closest matches when there's a count
+        if count.is_some() && !any && desc.is_none() {
+            desc = Some(false);
+        }
+        Ok(Self {
+            from_member,
+            from_coords,
+            shape,
+            unit,
+            desc,
+            count,
+            any,
+            with_coord,
+            with_dist,
+            with_hash,
+        })
+    }
+
+    /// Find the matching members of `z`.
+    ///
+    /// All the members are checked, rather than only the geohash cells
+    /// around the center: this is simpler, and fast enough for the sizes
+    /// of sets this server is meant for.
+    fn run<'z>(&self, z: &'z ZSet) -> Result<Vec<Found<'z>>, Frame<'static>> {
+        let center = match (self.from_member, self.from_coords) {
+            (Some(m), _) => match z.score(m) {
+                Some(score) => decode(score as u64),
+                None => {
+                    return Err(Frame::Error(
+                        "ERR could not decode requested zset member",
+                    ))
+                }
+            },
+            (None, Some(c)) => c,
+            (None, None) => unreachable!("checked when parsing"),
+        };
+
+        let mut found = vec![];
+        for (member, score) in z.iter() {
+            let pos = decode(score as u64);
+            let dist = match self.shape {
+                Shape::Radius(r) => {
+                    Some(distance(center, pos)).filter(|&d| d <= r)
+                }
+                Shape::Box { width, height } => {
+                    in_box(center, pos, width, height)
+                }
+            };
+            if let Some(dist) = dist {
+                found.push(Found {
+                    member,
+                    dist,
+                    score,
+                });
+                if self.any && Some(found.len()) == self.count {
+                    break;
+                }
+            }
+        }
+
+        match self.desc {
+            Some(false) => found.sort_by(|a, b| a.dist.total_cmp(&b.dist)),
+            Some(true) => found.sort_by(|a, b| b.dist.total_cmp(&a.dist)),
+            None => (),
+        }
+        if let Some(count) = self.count {
+            found.truncate(count);
+        }
+        Ok(found)
+    }
+
+    fn reply<'are>(
+        &self,
+        found: &[Found],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let items: Vec<Frame> = found
+            .iter()
+            .map(|f| {
+                let member = Frame::Bytes(arena.alloc_slice_copy(f.member));
+                if !(self.with_dist || self.with_hash || self.with_coord) {
+                    return member;
+                }
+                let mut fields = vec![member];
+                if self.with_dist {
+                    let d = format!("{:.4}", f.dist / self.unit);
+                    fields.push(Frame::String(arena.alloc_str(&d)));
+                }
+                if self.with_hash {
+                    fields.push(Frame::Int(f.score as isize));
+                }
+                if self.with_coord {
+                    let (lon, lat) = decode(f.score as u64);
+                    fields.push(coords(lon, lat, arena));
+                }
+                Frame::Bulk(arena.alloc_slice_copy(&fields))
+            })
+            .collect();
+        Frame::Bulk(arena.alloc_slice_copy(&items))
+    }
+}
+
+/// Geohash of a location, with the ranges used for indexing.
+fn encode(lon: f64, lat: f64) -> u64 {
+    encode_in(lon, lat, (LON_MIN, LON_MAX), (LAT_MIN, LAT_MAX))
+}
+
+fn encode_in(
+    lon: f64,
+    lat: f64,
+    lon_range: (f64, f64),
+    lat_range: (f64, f64),
+) -> u64 {
+    let scale = (1u64 << STEP) as f64;
+    let lat = (lat - lat_range.0) / (lat_range.1 - lat_range.0) * scale;
+    let lon = (lon - lon_range.0) / (lon_range.1 - lon_range.0) * scale;
+    interleave(lat as u32, lon as u32)
+}
+
+/// Center of the cell of a geohash.
+fn decode(hash: u64) -> (f64, f64) {
+    let (lat, lon) = deinterleave(hash);
+    let scale = (1u64 << STEP) as f64;
+    let cell = |i: u32, min: f64, max: f64| {
+        let lo = min + (i as f64 / scale) * (max - min);
+        let hi = min + ((i as f64 + 1.0) / scale) * (max - min);
+        ((lo + hi) / 2.0).clamp(min, max)
+    };
+    (cell(lon, LON_MIN, LON_MAX), cell(lat, LAT_MIN, LAT_MAX))
+}
+
+/// Standard 11-character geohash, as returned by `GEOHASH`: this one uses
+/// the whole range of latitudes.
+fn geohash(lon: f64, lat: f64) -> String {
+    let hash = encode_in(lon, lat, (-180.0, 180.0), (-90.0, 90.0));
+    (0..11)
+        .map(|i| {
+            // the last character only has 2 bits, and redis leaves them
+            // out
+            let idx = if i == 10 {
+                0
+            } else {
+                (hash >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            BASE32[idx as usize] as char
+        })
+        .collect()
+}
+
+/// Interleave the bits of `x` (even bits) and `y` (odd bits).
+fn interleave(x: u32, y: u32) -> u64 {
+    spread(x) | spread(y) << 1
+}
+
+/// Inverse of [`interleave`].
+fn deinterleave(h: u64) -> (u32, u32) {
+    (squash(h), squash(h >> 1))
+}
+
+/// Move bit `i` of `x` to bit `2i`.
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | x << 16) & 0x0000ffff0000ffff;
+    x = (x | x << 8) & 0x00ff00ff00ff00ff;
+    x = (x | x << 4) & 0x0f0f0f0f0f0f0f0f;
+    x = (x | x << 2) & 0x3333333333333333;
+    (x | x << 1) & 0x5555555555555555
+}
+
+/// Move bit `2i` of `x` to bit `i`, dropping odd bits.
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555555555555555;
+    x = (x | x >> 1) & 0x3333333333333333;
+    x = (x | x >> 2) & 0x0f0f0f0f0f0f0f0f;
+    x = (x | x >> 4) & 0x00ff00ff00ff00ff;
+    x = (x | x >> 8) & 0x0000ffff0000ffff;
+    ((x | x >> 16) & 0x00000000ffffffff) as u32
+}
+
+/// Distance between two locations along the surface of the Earth, in
+/// meters, with the haversine formula.
+fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Distance between two latitudes, in radians, in meters.
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS * (lat2 - lat1).abs()
+}
+
+/// Distance from `center` to `pos`, if `pos` is in the box of the given
+/// size (in meters) around `center`.
+fn in_box(
+    center: (f64, f64),
+    pos: (f64, f64),
+    width: f64,
+    height: f64,
+) -> Option<f64> {
+    let lat_dist = lat_distance(center.1.to_radians(), pos.1.to_radians());
+    if lat_dist > height / 2.0 {
+        return None;
+    }
+    // the width is measured at the latitude of `pos`
+    let lon_dist = distance((center.0, pos.1), pos);
+    if lon_dist > width / 2.0 {
+        return None;
+    }
+    Some(distance(center, pos))
+}
@@ -0,0 +1,149 @@
+//! Glob-style pattern matching, as used by `KEYS`, `SCAN` and
+//! `CONFIG GET`.
+//!
+//! This is a port of redis' `stringmatchlen`, so that patterns behave
+//! exactly the same, including corner cases such as unterminated `[`
+//! classes or reversed ranges.
+
+/// Does `string` match the glob `pattern`?
+///
+/// Supported syntax: `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to
+/// escape special characters.
+pub fn string_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let mut skip_longer_matches = false;
+    match_impl(pattern, string, nocase, &mut skip_longer_matches, 0)
+}
+
+fn lower(c: u8) -> u8 {
+    c.to_ascii_lowercase()
+}
+
+fn match_impl(
+    pattern: &[u8],
+    string: &[u8],
+    nocase: bool,
+    skip_longer_matches: &mut bool,
+    nesting: usize,
+) -> bool {
+    // protection against abuse, since the function is recursive
+    if nesting > 1000 {
+        return false;
+    }
+
+    // like in C, reading past the end of the pattern yields a NUL byte
+    let pat = |i: usize| pattern.get(i).copied().unwrap_or(0);
+    let mut p = 0;
+    let mut s = 0;
+
+    while p < pattern.len() && s < string.len() {
+        match pattern[p] {
+            b'*' => {
+                while p < pattern.len() && pat(p + 1) == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true; // trailing `*` matches everything
+                }
+                while s < string.len() {
+                    if match_impl(
+                        &pattern[p + 1..],
+                        &string[s..],
+                        nocase,
+                        skip_longer_matches,
+                        nesting + 1,
+                    ) {
+                        return true;
+                    }
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                    s += 1;
+                }
+                // the rest of the pattern matches nowhere in the rest of
+                // the string, so earlier `*` can't help either.
+                *skip_longer_matches = true;
+                return false;
+            }
+            b'?' => {
+                s += 1;
+            }
+            b'[' => {
+                p += 1;
+                let not = pat(p) == b'^';
+                if not {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    if pat(p) == b'\\' && pattern.len() - p >= 2 {
+                        p += 1;
+                        if pattern[p] == string[s] {
+                            matched = true;
+                        }
+                    } else if pat(p) == b']' {
+                        break;
+                    } else if p >= pattern.len() {
+                        // unterminated class
+                        p -= 1;
+                        break;
+                    } else if pattern.len() - p >= 3 && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        let mut c = string[s];
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        if nocase {
+                            start = lower(start);
+                            end = lower(end);
+                            c = lower(c);
+                        }
+                        p += 2;
+                        if c >= start && c <= end {
+                            matched = true;
+                        }
+                    } else if !nocase {
+                        if pattern[p] == string[s] {
+                            matched = true;
+                        }
+                    } else if lower(pattern[p]) == lower(string[s]) {
+                        matched = true;
+                    }
+                    p += 1;
+                }
+                if not {
+                    matched = !matched;
+                }
+                if !matched {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                let c = if c == b'\\' && pattern.len() - p >= 2 {
+                    p += 1;
+                    pattern[p]
+                } else {
+                    c
+                };
+                let eq = if nocase {
+                    lower(c) == lower(string[s])
+                } else {
+                    c == string[s]
+                };
+                if !eq {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+        if s == string.len() {
+            while p < pattern.len() && pattern[p] == b'*' {
+                p += 1;
+            }
+            break;
+        }
+    }
+
+    p >= pattern.len() && s == string.len()
+}
@@ -0,0 +1,324 @@
+//! Hashes, and the `HSET`, `HGET`, `HDEL`, `HLEN`, `HEXISTS` and
+//! `HGETALL` commands.
+//!
+//! Fields and values are binary, like strings.
+
+use std::collections::HashMap;
+
+use crate::{
+    db::{Db, LfuParams, ListpackLimits, Value},
+    notify,
+    server::{wrong_arity, State, WRONG_TYPE},
+    wire::Frame,
+};
+
+/// Approximate per-field overhead of the hashtable encoding, in bytes, on
+/// top of the field and the value.
+const FIELD_OVERHEAD: usize = 56;
+
+/// Per-field overhead of the listpack encoding: pointers to the field and
+/// the value.
+const LISTPACK_FIELD_OVERHEAD: usize = 32;
+
+/// A field and its value, in the listpack encoding.
+type Pair = (Box<[u8]>, Box<[u8]>);
+
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// Fields and values in insertion order, looked up linearly: compact,
+    /// and fast enough for small hashes.
+    Listpack(Vec<Pair>),
+    Hashtable(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Listpack(vec![])
+    }
+}
+
+/// A hash. Small hashes start in the listpack encoding, and switch to the
+/// hashtable encoding for good once they outgrow their [`ListpackLimits`].
+#[derive(Clone, Debug, Default)]
+pub struct Hash {
+    encoding: Encoding,
+    limits: ListpackLimits,
+    /// Total length of the fields and values.
+    data_len: usize,
+}
+
+impl Hash {
+    /// An empty hash, that switches to the hashtable encoding past
+    /// `limits`.
+    pub fn new(limits: ListpackLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(v) => v.len(),
+            Encoding::Hashtable(m) => m.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as returned by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "listpack",
+            Encoding::Hashtable(_) => "hashtable",
+        }
+    }
+
+    /// Change the limits, e.g. after `CONFIG SET`, switching to the
+    /// hashtable encoding if the hash is now over them.
+    pub fn set_limits(&mut self, limits: ListpackLimits) {
+        self.limits = limits;
+        if let Encoding::Listpack(v) = &self.encoding {
+            let too_long = v.iter().any(|(f, value)| {
+                f.len() > limits.value || value.len() > limits.value
+            });
+            if too_long || v.len() > limits.entries {
+                self.convert();
+            }
+        }
+    }
+
+    /// Switch to the hashtable encoding.
+    fn convert(&mut self) {
+        let Encoding::Listpack(v) = std::mem::take(&mut self.encoding) else {
+            return;
+        };
+        let map = v
+            .into_iter()
+            .map(|(f, value)| (Vec::from(f), Vec::from(value)))
+            .collect();
+        self.encoding = Encoding::Hashtable(map);
+    }
+
+    /// Approximate memory used by the hash.
+    pub fn mem_usage(&self) -> usize {
+        let overhead = match self.encoding {
+            Encoding::Listpack(_) => LISTPACK_FIELD_OVERHEAD,
+            Encoding::Hashtable(_) => FIELD_OVERHEAD,
+        };
+        self.data_len + self.len() * overhead
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        match &self.encoding {
+            Encoding::Listpack(v) => v
+                .iter()
+                .find(|(f, _)| &**f == field)
+                .map(|(_, value)| &**value),
+            Encoding::Hashtable(m) => m.get(field).map(Vec::as_slice),
+        }
+    }
+
+    /// Set `field` to `value`. Returns `true` if the field is new.
+    pub fn insert(&mut self, field: &[u8], value: &[u8]) -> bool {
+        let old = match &mut self.encoding {
+            Encoding::Listpack(v) => {
+                match v.iter_mut().find(|(f, _)| &**f == field) {
+                    Some((_, old)) => {
+                        Some(std::mem::replace(old, value.into()).len())
+                    }
+                    None => {
+                        v.push((field.into(), value.into()));
+                        None
+                    }
+                }
+            }
+            Encoding::Hashtable(m) => m
+                .insert(field.to_vec(), value.to_vec())
+                .map(|old| old.len()),
+        };
+        match old {
+            Some(len) => self.data_len -= len,
+            None => self.data_len += field.len(),
+        }
+        self.data_len += value.len();
+        if matches!(self.encoding, Encoding::Listpack(_))
+            && (self.len() > self.limits.entries
+                || field.len() > self.limits.value
+                || value.len() > self.limits.value)
+        {
+            self.convert();
+        }
+        old.is_none()
+    }
+
+    /// Remove `field`. Returns `true` if it was present.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        let value = match &mut self.encoding {
+            Encoding::Listpack(v) => {
+                let Some(i) = v.iter().position(|(f, _)| &**f == field) else {
+                    return false;
+                };
+                Vec::from(v.remove(i).1)
+            }
+            Encoding::Hashtable(m) => match m.remove(field) {
+                Some(value) => value,
+                None => return false,
+            },
+        };
+        self.data_len -= field.len() + value.len();
+        true
+    }
+
+    /// Fields with their values, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let (listpack, hashtable) = match &self.encoding {
+            Encoding::Listpack(v) => (Some(v.iter()), None),
+            Encoding::Hashtable(m) => (None, Some(m.iter())),
+        };
+        let listpack = listpack.into_iter().flatten();
+        let hashtable = hashtable.into_iter().flatten();
+        listpack
+            .map(|(f, v)| (&**f, &**v))
+            .chain(hashtable.map(|(f, v)| (f.as_slice(), v.as_slice())))
+    }
+}
+
+/// Call `f` on the hash at `key`. Returns `Ok(None)` if there is no such
+/// key, and a `WRONGTYPE` error if the key holds another type.
+fn read<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    f: impl FnOnce(&Hash) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    match db.get(key, lfu) {
+        None => Ok(None),
+        Some(e) => match &e.value {
+            Value::Hash(h) => Ok(Some(f(h))),
+            _ => Err(Frame::Error(WRONG_TYPE)),
+        },
+    }
+}
+
+/// Modify the hash at `key` with `f`, creating an empty one first if
+/// `create` is set. Returns `Ok(None)` if there is no such key, and a
+/// `WRONGTYPE` error if the key holds another type.
+///
+/// The hash switches encodings according to the current `limits`, and the
+/// key is removed if the hash ends up empty.
+fn update<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    limits: ListpackLimits,
+    create: bool,
+    f: impl FnOnce(&mut Hash) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    let new = || create.then(|| Value::Hash(Hash::new(limits)));
+    let res = db.update(key, lfu, new, |v| match v {
+        Value::Hash(h) => {
+            h.set_limits(limits);
+            Some((f(h), h.is_empty()))
+        }
+        _ => None,
+    });
+    match res {
+        None => Ok(None),
+        Some(None) => Err(Frame::Error(WRONG_TYPE)),
+        Some(Some((r, is_empty))) => {
+            if is_empty {
+                db.remove(key);
+            }
+            Ok(Some(r))
+        }
+    }
+}
+
+/// Execute a hash command on the selected database `db_index`. `args`
+/// includes the command name, and `raw` are the same arguments as bytes,
+/// for the fields and values.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let (lfu, limits) = {
+        let config = st.config();
+        (config.lfu_params(), config.hash_listpack_limits())
+    };
+    let bytes = |b: &[u8]| Frame::Bytes(arena.alloc_slice_copy(b));
+    match (cmd, &args[1..]) {
+        ("hset", [k, rest @ ..])
+            if !rest.is_empty() && rest.len().is_multiple_of(2) =>
+        {
+            let is_new = db.peek(k).is_none();
+            let res = update(db, k, lfu, limits, true, |h| {
+                raw[2..].chunks(2).filter(|p| h.insert(p[0], p[1])).count()
+            });
+            match res {
+                Ok(n) => {
+                    if is_new {
+                        st.notify(notify::NEW, "new", k, db_index);
+                    }
+                    st.notify(notify::HASH, "hset", k, db_index);
+                    Frame::Int(n.unwrap_or(0) as isize)
+                }
+                Err(e) => e,
+            }
+        }
+        ("hget", &[k, _]) => {
+            match read(db, k, lfu, |h| h.get(raw[2]).map(bytes)) {
+                Ok(Some(Some(value))) => value,
+                Ok(_) => Frame::Null,
+                Err(e) => e,
+            }
+        }
+        ("hdel", [k, _, ..]) => {
+            let res = update(db, k, lfu, limits, false, |h| {
+                raw[2..].iter().filter(|f| h.remove(f)).count()
+            });
+            match res {
+                Ok(Some(n)) if n > 0 => {
+                    st.notify(notify::HASH, "hdel", k, db_index);
+                    if db.peek(k).is_none() {
+                        st.notify(notify::GENERIC, "del", k, db_index);
+                    }
+                    Frame::Int(n as isize)
+                }
+                Ok(_) => Frame::Int(0),
+                Err(e) => e,
+            }
+        }
+        ("hlen", &[k]) => match read(db, k, lfu, |h| h.len()) {
+            Ok(n) => Frame::Int(n.unwrap_or(0) as isize),
+            Err(e) => e,
+        },
+        ("hexists", &[k, _]) => {
+            match read(db, k, lfu, |h| h.get(raw[2]).is_some()) {
+                Ok(found) => Frame::Int(found.unwrap_or(false) as isize),
+                Err(e) => e,
+            }
+        }
+        ("hgetall", &[k]) => {
+            let res = read(db, k, lfu, |h| {
+                h.iter()
+                    .flat_map(|(f, v)| [bytes(f), bytes(v)])
+                    .collect::<Vec<_>>()
+            });
+            match res {
+                Ok(items) => Frame::Bulk(
+                    arena.alloc_slice_copy(&items.unwrap_or_default()),
+                ),
+                Err(e) => e,
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
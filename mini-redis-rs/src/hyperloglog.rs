@@ -0,0 +1,377 @@
+//! HyperLogLogs: `PFADD`, `PFCOUNT` and `PFMERGE`.
+//!
+//! A HyperLogLog is a string in the same format as in redis, so that
+//! values can be copied between servers with `GET`/`SET` or in snapshots:
+//! a 16-byte header (`HYLL`, the encoding, 3 unused bytes and the cached
+//! cardinality), followed by 16384 6-bit registers, either densely packed
+//! or run-length encoded in the sparse encoding. Elements are hashed with
+//! MurmurHash64A, and the cardinality is estimated with the same
+//! algorithm as redis, so both return the same counts.
+
+use crate::{
+    db::{Db, LfuParams, Value},
+    notify,
+    server::{wrong_arity, State, WRONG_TYPE},
+    wire::Frame,
+};
+
+/// Number of bits of the hash used to select a register.
+const P: u32 = 14;
+/// Number of bits of the hash used to count leading zeros.
+const Q: u32 = 64 - P;
+const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + REGISTERS * REGISTER_BITS / 8;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+/// Sparse representations larger than this, header included, are
+/// converted to dense, like with redis' default `hll-sparse-max-bytes`.
+const SPARSE_MAX_BYTES: usize = 3000;
+/// Largest register value the sparse encoding can hold.
+const SPARSE_VAL_MAX: u8 = 32;
+
+const INVALID: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+const CORRUPTED: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+/// Execute a HyperLogLog command on the selected database `db_index`.
+/// `args` includes the command name, and `raw` are the same arguments as
+/// bytes, for the elements.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let lfu = st.config().lfu_params();
+    match (cmd, &args[1..]) {
+        ("pfadd", [k, ..]) => {
+            let is_new = db.peek(k).is_none();
+            let new = || Some(Value::String(empty()));
+            let res = db.update(k, lfu, new, |v| {
+                let s = match v {
+                    Value::String(s) => s,
+                    Value::Int(_) => return Err(INVALID),
+                    _ => return Err(WRONG_TYPE),
+                };
+                let mut regs = decode(s)?;
+                let mut changed = false;
+                for e in &raw[2..] {
+                    let (index, count) = hash(e);
+                    if regs[index] < count {
+                        regs[index] = count;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    *s = encode(&regs, None);
+                }
+                Ok(changed)
+            });
+            match res {
+                Some(Ok(changed)) => {
+                    if is_new {
+                        st.notify(notify::NEW, "new", k, db_index);
+                    }
+                    if is_new || changed {
+                        st.notify(notify::STRING, "pfadd", k, db_index);
+                    }
+                    Frame::Int((is_new || changed) as isize)
+                }
+                Some(Err(e)) => Frame::Error(e),
+                None => unreachable!("PFADD always creates the key"),
+            }
+        }
+        ("pfcount", &[k]) => {
+            // use the cached cardinality, or cache it
+            let res = db.update(
+                k,
+                lfu,
+                || None,
+                |v| {
+                    let s = match v {
+                        Value::String(s) => s,
+                        Value::Int(_) => return Err(INVALID),
+                        _ => return Err(WRONG_TYPE),
+                    };
+                    check(s)?;
+                    if let Some(card) = cached(s) {
+                        return Ok(card);
+                    }
+                    let card = count(&decode(s)?);
+                    s[8..HEADER_LEN].copy_from_slice(&card.to_le_bytes());
+                    Ok(card)
+                },
+            );
+            match res {
+                None => Frame::Int(0),
+                Some(Ok(card)) => Frame::Int(card as isize),
+                Some(Err(e)) => Frame::Error(e),
+            }
+        }
+        ("pfcount", keys) => match merge(db, lfu, keys) {
+            Ok(regs) => Frame::Int(count(&regs) as isize),
+            Err(e) => Frame::Error(e),
+        },
+        ("pfmerge", [dest, sources @ ..]) => {
+            let mut keys = vec![*dest];
+            keys.extend(sources);
+            let regs = match merge(db, lfu, &keys) {
+                Ok(regs) => regs,
+                Err(e) => return Frame::Error(e),
+            };
+            let is_new = db.peek(dest).is_none();
+            let new = || Some(Value::String(vec![]));
+            db.update(dest, lfu, new, |v| {
+                *v = Value::String(encode(&regs, None))
+            });
+            if is_new {
+                st.notify(notify::NEW, "new", dest, db_index);
+            }
+            st.notify(notify::STRING, "pfadd", dest, db_index);
+            Frame::String("OK")
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
+
+/// The registers of the union of the HyperLogLogs at `keys`. Missing keys
+/// count as empty HyperLogLogs.
+fn merge(
+    db: &Db,
+    lfu: LfuParams,
+    keys: &[&str],
+) -> Result<Vec<u8>, &'static str> {
+    let mut regs = vec![0; REGISTERS];
+    for k in keys {
+        let Some(e) = db.get(k, lfu) else {
+            continue;
+        };
+        let Some(s) = e.value.as_bytes() else {
+            return Err(WRONG_TYPE);
+        };
+        for (r, v) in regs.iter_mut().zip(decode(&s)?) {
+            *r = (*r).max(v);
+        }
+    }
+    Ok(regs)
+}
+
+/// An empty HyperLogLog, in the sparse encoding.
+fn empty() -> Vec<u8> {
+    encode(&[0; REGISTERS], Some(0))
+}
+
+/// The cached cardinality in the header, if it's valid.
+fn cached(s: &[u8]) -> Option<u64> {
+    let card = u64::from_le_bytes(s[8..HEADER_LEN].try_into().unwrap());
+    // the most significant bit marks the cache as invalid
+    (card >> 63 == 0).then_some(card)
+}
+
+/// Index of the register for `element`, and the value it should have at
+/// least: the position of the first set bit in the rest of the hash.
+fn hash(element: &[u8]) -> (usize, u8) {
+    let h = murmurhash64a(element, 0xadc83b19);
+    let index = (h & (REGISTERS as u64 - 1)) as usize;
+    // make sure the count is at most `Q + 1`
+    let rest = (h >> P) | 1 << Q;
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+/// MurmurHash64A, by Austin Appleby, as used by redis.
+fn murmurhash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &b) in tail.iter().enumerate() {
+            h ^= (b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Check that `s` looks like a HyperLogLog: it has a valid header, and
+/// the right size if it's dense.
+fn check(s: &[u8]) -> Result<(), &'static str> {
+    let valid = s.len() >= HEADER_LEN
+        && &s[..4] == b"HYLL"
+        && (s[4] == SPARSE || s[4] == DENSE && s.len() == DENSE_LEN);
+    valid.then_some(()).ok_or(INVALID)
+}
+
+/// Check that `s` is a HyperLogLog, and extract its registers.
+fn decode(s: &[u8]) -> Result<Vec<u8>, &'static str> {
+    check(s)?;
+    let data = &s[HEADER_LEN..];
+    if s[4] == DENSE {
+        return Ok((0..REGISTERS).map(|i| dense_get(data, i)).collect());
+    }
+    let mut regs = Vec::with_capacity(REGISTERS);
+    let mut i = 0;
+    while i < data.len() {
+        let op = data[i];
+        let (value, run) = if op & 0x80 != 0 {
+            // VAL: 1vvvvvxx
+            ((op >> 2 & 0x1f) + 1, (op & 0x3) as usize + 1)
+        } else if op & 0x40 != 0 {
+            // XZERO: 01xxxxxx yyyyyyyy
+            i += 1;
+            let low = *data.get(i).ok_or(CORRUPTED)? as usize;
+            (0, (((op & 0x3f) as usize) << 8 | low) + 1)
+        } else {
+            // ZERO: 00xxxxxx
+            (0, (op & 0x3f) as usize + 1)
+        };
+        if regs.len() + run > REGISTERS {
+            return Err(CORRUPTED);
+        }
+        regs.extend(std::iter::repeat_n(value, run));
+        i += 1;
+    }
+    if regs.len() != REGISTERS {
+        return Err(CORRUPTED);
+    }
+    Ok(regs)
+}
+
+/// Encode `regs`, in the sparse encoding if it's small enough, or the
+/// dense one. The cardinality cache is set to `card`, or invalidated.
+fn encode(regs: &[u8], card: Option<u64>) -> Vec<u8> {
+    let mut out = b"HYLL".to_vec();
+    out.extend_from_slice(&[SPARSE, 0, 0, 0]);
+    out.extend_from_slice(&card.unwrap_or(1 << 63).to_le_bytes());
+
+    if regs.iter().all(|&r| r <= SPARSE_VAL_MAX) {
+        let mut i = 0;
+        while i < regs.len() && out.len() <= SPARSE_MAX_BYTES {
+            let value = regs[i];
+            let run = regs[i..].iter().take_while(|&&r| r == value).count();
+            i += run;
+            if value == 0 {
+                for chunk in chunks(run, 1 << 14) {
+                    if chunk > 64 {
+                        let n = chunk - 1;
+                        out.extend_from_slice(&[
+                            0x40 | (n >> 8) as u8,
+                            n as u8,
+                        ]);
+                    } else {
+                        out.push((chunk - 1) as u8);
+                    }
+                }
+            } else {
+                for chunk in chunks(run, 4) {
+                    out.push(0x80 | (value - 1) << 2 | (chunk - 1) as u8);
+                }
+            }
+        }
+        if out.len() <= SPARSE_MAX_BYTES {
+            return out;
+        }
+    }
+
+    out.truncate(HEADER_LEN);
+    out[4] = DENSE;
+    // one more byte, so that the last register can be set like the others
+    out.resize(DENSE_LEN + 1, 0);
+    for (i, &r) in regs.iter().enumerate() {
+        dense_set(&mut out[HEADER_LEN..], i, r);
+    }
+    out.truncate(DENSE_LEN);
+    out
+}
+
+/// Split `n` in chunks of at most `max`.
+fn chunks(n: usize, max: usize) -> impl Iterator<Item = usize> {
+    (0..n.div_ceil(max)).map(move |i| (n - i * max).min(max))
+}
+
+fn dense_get(data: &[u8], i: usize) -> u8 {
+    let (byte, shift) = (i * REGISTER_BITS / 8, i * REGISTER_BITS % 8);
+    let b0 = data[byte] as u16;
+    let b1 = data.get(byte + 1).copied().unwrap_or(0) as u16;
+    ((b0 >> shift | b1 << (8 - shift)) & 0x3f) as u8
+}
+
+fn dense_set(data: &mut [u8], i: usize, value: u8) {
+    let (byte, shift) = (i * REGISTER_BITS / 8, i * REGISTER_BITS % 8);
+    let v = (value as u16) << shift;
+    let mask = !(0x3fu16 << shift);
+    data[byte] = (data[byte] as u16 & mask | v) as u8;
+    data[byte + 1] = ((data[byte + 1] as u16) & (mask >> 8) | v >> 8) as u8;
+}
+
+/// Estimate the cardinality, with the algorithm from Otmar Ertl's "New
+/// cardinality estimation algorithms for HyperLogLog sketches", like
+/// redis.
+fn count(regs: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for &r in regs {
+        histogram[r as usize] += 1;
+    }
+    let m = REGISTERS as f64;
+    let q = Q as usize;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for j in (1..=q).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    const ALPHA_INF: f64 = 0.7213475204444817;
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if prev == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if prev == z {
+            return z / 3.0;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! The `INFO` command.
+
+use std::fmt::Write;
+
+use crate::{server::State, stats};
+
+/// Sections shown by `INFO` without arguments, in order.
+const DEFAULT_SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "keyspace",
+];
+
+/// Sections that are only shown when asked for explicitly, or with `all`.
+const EXTRA_SECTIONS: &[&str] = &["commandstats"];
+
+/// Content of `INFO` for the given sections (all default sections if
+/// `sections` is empty).
+pub fn info(st: &State, sections: &[&str]) -> String {
+    let mut wanted: Vec<&str> = vec![];
+    if sections.is_empty() {
+        wanted.extend(DEFAULT_SECTIONS);
+    }
+    for s in sections {
+        let s = s.to_ascii_lowercase();
+        match s.as_str() {
+            "default" => wanted.extend(DEFAULT_SECTIONS),
+            "all" | "everything" => {
+                wanted.extend(DEFAULT_SECTIONS);
+                wanted.extend(EXTRA_SECTIONS);
+            }
+            s => {
+                if let Some(s) = DEFAULT_SECTIONS
+                    .iter()
+                    .chain(EXTRA_SECTIONS)
+                    .find(|x| **x == s)
+                {
+                    wanted.push(s)
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for section in DEFAULT_SECTIONS.iter().chain(EXTRA_SECTIONS) {
+        if !wanted.contains(section) {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        match *section {
+            "server" => server(st, &mut out),
+            "clients" => clients(st, &mut out),
+            "memory" => memory(st, &mut out),
+            "persistence" => persistence(st, &mut out),
+            "stats" => stats(st, &mut out),
+            "keyspace" => keyspace(st, &mut out),
+            "commandstats" => commandstats(st, &mut out),
+            _ => unreachable!("unknown section {section}"),
+        }
+    }
+    out
+}
+
+fn server(st: &State, out: &mut String) {
+    let config = st.config();
+    let uptime = st.stats.started.elapsed().as_secs();
+    let config_file = st
+        .config_file
+        .as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    let _ = write!(
+        out,
+        "# Server\r\n\
+        redis_version:7.0.0\r\n\
+        mini_redis_version:{}\r\n\
+        redis_mode:standalone\r\n\
+        os:{}\r\n\
+        process_id:{}\r\n\
+        tcp_port:{}\r\n\
+        uptime_in_seconds:{uptime}\r\n\
+        uptime_in_days:{}\r\n\
+        config_file:{config_file}\r\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::process::id(),
+        config.port,
+        uptime / 86_400,
+    );
+}
+
+fn clients(st: &State, out: &mut String) {
+    let _ = write!(
+        out,
+        "# Clients\r\n\
+        connected_clients:{}\r\n\
+        maxclients:{}\r\n\
+        tracking_clients:{}\r\n",
+        st.clients.len(),
+        st.config().maxclients,
+        st.tracking.n_clients(),
+    );
+}
+
+fn memory(st: &State, out: &mut String) {
+    let config = st.config();
+    let used = st.used_memory() as u64;
+    let _ = write!(
+        out,
+        "# Memory\r\n\
+        used_memory:{used}\r\n\
+        used_memory_human:{}\r\n\
+        maxmemory:{}\r\n\
+        maxmemory_human:{}\r\n\
+        maxmemory_policy:{}\r\n",
+        human_bytes(used),
+        config.maxmemory,
+        human_bytes(config.maxmemory),
+        config.maxmemory_policy.as_str(),
+    );
+}
+
+fn persistence(st: &State, out: &mut String) {
+    let _ = write!(
+        out,
+        "# Persistence\r\n\
+        rdb_changes_since_last_save:{}\r\n\
+        rdb_last_save_time:{}\r\n",
+        stats::get(&st.stats.dirty),
+        stats::get(&st.stats.last_save),
+    );
+}
+
+fn stats(st: &State, out: &mut String) {
+    let s = &st.stats;
+    let _ = write!(
+        out,
+        "# Stats\r\n\
+        total_connections_received:{}\r\n\
+        total_commands_processed:{}\r\n\
+        rejected_connections:{}\r\n\
+        expired_keys:{}\r\n\
+        evicted_keys:{}\r\n\
+        keyspace_hits:{}\r\n\
+        keyspace_misses:{}\r\n\
+        client_output_buffer_limit_disconnections:{}\r\n\
+        client_idle_timeout_disconnections:{}\r\n\
+        pubsub_channels:{}\r\n\
+        pubsub_patterns:{}\r\n\
+        tracking_total_keys:{}\r\n\
+        tracking_total_prefixes:{}\r\n\
+        slowlog_len:{}\r\n",
+        stats::get(&s.total_connections_received),
+        stats::get(&s.total_commands_processed),
+        stats::get(&s.rejected_connections),
+        st.expired_keys(),
+        st.evictor.evicted_keys(),
+        stats::get(&s.keyspace_hits),
+        stats::get(&s.keyspace_misses),
+        stats::get(&s.client_output_buffer_limit_disconnections),
+        stats::get(&s.client_idle_timeout_disconnections),
+        st.pubsub.channels(None).len(),
+        st.pubsub.numpat(),
+        st.tracking.n_keys(),
+        st.tracking.n_prefixes(),
+        st.slowlog.len(),
+    );
+}
+
+fn keyspace(st: &State, out: &mut String) {
+    out.push_str("# Keyspace\r\n");
+    for (i, db) in st.dbs().iter().enumerate() {
+        if !db.is_empty() {
+            let _ = write!(
+                out,
+                "db{i}:keys={},expires={},avg_ttl=0\r\n",
+                db.len(),
+                db.expires()
+            );
+        }
+    }
+}
+
+fn commandstats(st: &State, out: &mut String) {
+    out.push_str("# Commandstats\r\n");
+    for (name, c) in st.stats.command_stats() {
+        let per_call = c.usec as f64 / c.calls.max(1) as f64;
+        let _ = write!(
+            out,
+            "cmdstat_{name}:calls={},usec={},usec_per_call={per_call:.2},\
+            rejected_calls={},failed_calls={}\r\n",
+            c.calls, c.usec, c.rejected_calls, c.failed_calls,
+        );
+    }
+}
+
+/// Format a number of bytes like redis does in `INFO`, e.g. `1.50M`.
+fn human_bytes(n: u64) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (1 << 40, "T"),
+        (1 << 30, "G"),
+        (1 << 20, "M"),
+        (1 << 10, "K"),
+    ];
+    for &(size, unit) in UNITS {
+        if n >= size {
+            return format!("{:.2}{unit}", n as f64 / size as f64);
+        }
+    }
+    format!("{n}B")
+}
@@ -0,0 +1,199 @@
+//! Generic key commands: `DEL`, `EXISTS`, `KEYS`, `SCAN`, `TYPE`,
+//! `OBJECT`, `RENAME`, `MOVE`, `SWAPDB`, `DBSIZE`, `FLUSHDB`, `FLUSHALL`.
+
+use crate::{
+    db::Db,
+    glob, notify,
+    server::{parse_db_index, wrong_arity, State},
+    wire::Frame,
+};
+
+/// Execute a keyspace command on the selected database `db_index`.
+/// `args` includes the command name.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    match (cmd, &args[1..]) {
+        ("del", keys) => {
+            let mut n = 0;
+            for k in keys {
+                if db.remove(k).is_some() {
+                    st.notify(notify::GENERIC, "del", k, db_index);
+                    n += 1;
+                }
+            }
+            Frame::Int(n)
+        }
+        ("exists", keys) => {
+            let n = keys.iter().filter(|k| db.peek(k).is_some()).count();
+            Frame::Int(n as isize)
+        }
+        ("keys", &[pattern]) => {
+            let mut keys = vec![];
+            db.for_each(|k, _| {
+                if glob::string_match(pattern.as_bytes(), k.as_bytes(), false) {
+                    keys.push(Frame::String(arena.alloc_str(k)));
+                }
+            });
+            Frame::Bulk(arena.alloc_slice_copy(&keys))
+        }
+        ("scan", [cursor, opts @ ..]) => scan(db, cursor, opts, arena),
+        ("type", &[k]) => match db.peek(k) {
+            Some(e) => Frame::String(e.type_name()),
+            None => Frame::String("none"),
+        },
+        ("object", [sub, rest @ ..]) => {
+            match (&*sub.to_ascii_lowercase(), rest) {
+                ("encoding", &[k]) => match db.peek(k) {
+                    Some(e) => Frame::String(e.value.encoding()),
+                    None => Frame::Null,
+                },
+                ("encoding", _) => Frame::Error(
+                    arena.alloc_str(&wrong_arity("object|encoding")),
+                ),
+                _ => Frame::Error(
+                    arena.alloc_str(&format!("ERR unknown subcommand '{sub}'")),
+                ),
+            }
+        }
+        ("rename" | "renamenx", &[src, dst]) => {
+            if db.peek(src).is_none() {
+                return Frame::Error("ERR no such key");
+            }
+            if cmd == "renamenx" && db.peek(dst).is_some() {
+                return Frame::Int(0);
+            }
+            if src != dst {
+                if let Some(e) = db.remove(src) {
+                    db.insert(dst.to_string(), e);
+                    st.notify(notify::GENERIC, "rename_from", src, db_index);
+                    st.notify(notify::GENERIC, "rename_to", dst, db_index);
+                }
+            }
+            if cmd == "renamenx" {
+                Frame::Int(1)
+            } else {
+                Frame::String("OK")
+            }
+        }
+        ("move", &[k, target]) => {
+            let target = match parse_db_index(st, target) {
+                Ok(i) => i,
+                Err(e) => return Frame::Error(e),
+            };
+            if target == db_index {
+                return Frame::Error(
+                    "ERR source and destination objects are the same",
+                );
+            }
+            let target_db = st.db(target);
+            if db.peek(k).is_none() || target_db.peek(k).is_some() {
+                return Frame::Int(0);
+            }
+            match db.remove(k) {
+                Some(e) => {
+                    target_db.insert(k.to_string(), e);
+                    st.notify(notify::GENERIC, "move_from", k, db_index);
+                    st.notify(notify::GENERIC, "move_to", k, target);
+                    Frame::Int(1)
+                }
+                None => Frame::Int(0),
+            }
+        }
+        ("swapdb", &[a, b]) => {
+            if a.parse::<i64>().is_err() {
+                return Frame::Error("ERR invalid first DB index");
+            }
+            if b.parse::<i64>().is_err() {
+                return Frame::Error("ERR invalid second DB index");
+            }
+            let (a, b) = match (parse_db_index(st, a), parse_db_index(st, b)) {
+                (Ok(a), Ok(b)) => (a, b),
+                (Err(e), _) | (_, Err(e)) => return Frame::Error(e),
+            };
+            st.swap_dbs(a, b);
+            st.tracking.invalidate_all(&st.clients);
+            Frame::String("OK")
+        }
+        ("dbsize", []) => Frame::Int(db.len() as isize),
+        ("flushdb" | "flushall", [] | [_]) => {
+            if let Some(mode) = args.get(1) {
+                if !mode.eq_ignore_ascii_case("sync")
+                    && !mode.eq_ignore_ascii_case("async")
+                {
+                    return Frame::Error("ERR syntax error");
+                }
+            }
+            if cmd == "flushall" {
+                st.dbs().iter().for_each(|db| db.clear());
+            } else {
+                db.clear();
+            }
+            st.evictor.clear_pool();
+            st.tracking.invalidate_all(&st.clients);
+            Frame::String("OK")
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`.
+fn scan<'are>(
+    db: &Db,
+    cursor: &str,
+    opts: &[&str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let Ok(cursor) = cursor.parse::<u64>() else {
+        return Frame::Error("ERR invalid cursor");
+    };
+
+    let mut pattern = None;
+    let mut count = 10;
+    let mut ty = None;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        let Some(value) = opts.next() else {
+            return Frame::Error("ERR syntax error");
+        };
+        match &*opt.to_ascii_lowercase() {
+            "match" => pattern = Some(*value),
+            "count" => match value.parse::<usize>() {
+                Ok(n) if n >= 1 => count = n,
+                _ => {
+                    return Frame::Error(
+                        "ERR value is not an integer or out of range",
+                    )
+                }
+            },
+            "type" => ty = Some(value.to_ascii_lowercase()),
+            _ => return Frame::Error("ERR syntax error"),
+        }
+    }
+
+    let (next, keys) = db.scan(cursor, count);
+    let keys: Vec<Frame> = keys
+        .iter()
+        .filter(|k| {
+            pattern.is_none_or(|p| {
+                glob::string_match(p.as_bytes(), k.as_bytes(), false)
+            })
+        })
+        .filter(|k| match &ty {
+            None => true,
+            Some(ty) => db.peek(k).is_some_and(|e| e.type_name() == ty),
+        })
+        .map(|k| Frame::String(arena.alloc_str(k)))
+        .collect();
+
+    let reply = [
+        Frame::String(arena.alloc_str(&next.to_string())),
+        Frame::Bulk(arena.alloc_slice_copy(&keys)),
+    ];
+    Frame::Bulk(arena.alloc_slice_copy(&reply))
+}
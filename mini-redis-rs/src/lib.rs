@@ -1,6 +1,35 @@
+pub mod audit;
+pub mod bitops;
 pub mod client;
+pub mod clients;
+pub mod commands;
+pub mod config;
+pub mod db;
+pub mod embedded;
+pub mod evict;
+pub mod geo;
+pub mod glob;
+pub mod hash;
+pub mod hyperloglog;
+pub mod info;
+pub mod keyspace;
+pub mod list;
+pub mod metrics;
+pub mod migrate;
+pub mod monitor;
+pub mod notify;
+pub mod proxy;
+pub mod pubsub;
+pub mod rdb;
+#[cfg(feature = "lua")]
+pub mod scripting;
 pub mod server;
+pub mod set;
+pub mod stats;
+pub mod tracking;
 pub mod wire;
+pub mod zset;
 
 pub use client::Client;
+pub use embedded::Server;
 pub use server::ClientHandler;
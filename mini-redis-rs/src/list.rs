@@ -0,0 +1,357 @@
+//! Lists, and the `LPUSH`, `RPUSH`, `LPOP`, `RPOP`, `LLEN` and `LRANGE`
+//! commands.
+//!
+//! Elements are binary, like strings.
+
+use std::collections::VecDeque;
+
+use crate::{
+    db::{Db, LfuParams, Value},
+    notify,
+    server::{wrong_arity, State, WRONG_TYPE},
+    wire::Frame,
+};
+
+/// Approximate per-element overhead of the quicklist encoding, in bytes,
+/// on top of the element.
+const ELEMENT_OVERHEAD: usize = 24;
+
+/// Per-element overhead of the listpack encoding: the length prefix and
+/// the backlength of redis' listpack entries.
+const LISTPACK_ELEMENT_OVERHEAD: usize = 2;
+
+/// Maximum size of a list in the listpack encoding, as redis'
+/// `list-max-listpack-size`: a positive value is a number of elements, and
+/// -1 to -5 are a size of 4, 8, 16, 32 or 64 kB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListpackSize(pub i64);
+
+impl Default for ListpackSize {
+    fn default() -> Self {
+        Self(-2)
+    }
+}
+
+impl ListpackSize {
+    /// Whether a listpack of `len` elements using `bytes` bytes fits.
+    fn fits(self, len: usize, bytes: usize) -> bool {
+        match usize::try_from(self.0) {
+            Ok(max) => len <= max,
+            Err(_) => bytes <= 4096 << (self.0.unsigned_abs().min(5) - 1),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// Elements in order, in a single allocation's worth of pointers.
+    Listpack(Vec<Box<[u8]>>),
+    /// This plays the role of redis' quicklist.
+    Quicklist(VecDeque<Vec<u8>>),
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Listpack(vec![])
+    }
+}
+
+/// A list. Small lists are in the listpack encoding, and switch to the
+/// quicklist encoding when they outgrow their [`ListpackSize`]. Like in
+/// redis 7.2, they switch back once they shrink to half of it.
+#[derive(Clone, Debug, Default)]
+pub struct List {
+    encoding: Encoding,
+    limit: ListpackSize,
+    /// Total length of the elements.
+    data_len: usize,
+}
+
+impl List {
+    /// An empty list, that switches to the quicklist encoding past
+    /// `limit`.
+    pub fn new(limit: ListpackSize) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(v) => v.len(),
+            Encoding::Quicklist(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as returned by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "listpack",
+            Encoding::Quicklist(_) => "quicklist",
+        }
+    }
+
+    /// Change the limit, e.g. after `CONFIG SET`, switching encodings if
+    /// needed.
+    pub fn set_limit(&mut self, limit: ListpackSize) {
+        self.limit = limit;
+        self.convert();
+    }
+
+    /// Size of the list in the listpack encoding.
+    fn listpack_bytes(&self) -> usize {
+        self.data_len + self.len() * LISTPACK_ELEMENT_OVERHEAD
+    }
+
+    /// Switch to the quicklist encoding if the list is over the limit, or
+    /// back to the listpack encoding if it is under half of it.
+    fn convert(&mut self) {
+        let (len, bytes) = (self.len(), self.listpack_bytes());
+        match std::mem::take(&mut self.encoding) {
+            Encoding::Listpack(v) if !self.limit.fits(len, bytes) => {
+                let v = v.into_iter().map(Vec::from).collect();
+                self.encoding = Encoding::Quicklist(v);
+            }
+            Encoding::Quicklist(v) if self.limit.fits(len * 2, bytes * 2) => {
+                let v = v.into_iter().map(Vec::into_boxed_slice).collect();
+                self.encoding = Encoding::Listpack(v);
+            }
+            encoding => self.encoding = encoding,
+        }
+    }
+
+    /// Approximate memory used by the list.
+    pub fn mem_usage(&self) -> usize {
+        match self.encoding {
+            Encoding::Listpack(_) => self.listpack_bytes(),
+            Encoding::Quicklist(_) => {
+                self.data_len + self.len() * ELEMENT_OVERHEAD
+            }
+        }
+    }
+
+    pub fn push_front(&mut self, element: &[u8]) {
+        match &mut self.encoding {
+            Encoding::Listpack(v) => v.insert(0, element.into()),
+            Encoding::Quicklist(v) => v.push_front(element.to_vec()),
+        }
+        self.data_len += element.len();
+        self.convert();
+    }
+
+    pub fn push_back(&mut self, element: &[u8]) {
+        match &mut self.encoding {
+            Encoding::Listpack(v) => v.push(element.into()),
+            Encoding::Quicklist(v) => v.push_back(element.to_vec()),
+        }
+        self.data_len += element.len();
+        self.convert();
+    }
+
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        let element = match &mut self.encoding {
+            Encoding::Listpack(v) if !v.is_empty() => Vec::from(v.remove(0)),
+            Encoding::Listpack(_) => return None,
+            Encoding::Quicklist(v) => v.pop_front()?,
+        };
+        self.data_len -= element.len();
+        self.convert();
+        Some(element)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        let element = match &mut self.encoding {
+            Encoding::Listpack(v) => Vec::from(v.pop()?),
+            Encoding::Quicklist(v) => v.pop_back()?,
+        };
+        self.data_len -= element.len();
+        self.convert();
+        Some(element)
+    }
+
+    /// Elements from head to tail.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let (listpack, quicklist) = match &self.encoding {
+            Encoding::Listpack(v) => (Some(v.iter()), None),
+            Encoding::Quicklist(v) => (None, Some(v.iter())),
+        };
+        let listpack = listpack.into_iter().flatten().map(|e| &**e);
+        let quicklist = quicklist.into_iter().flatten().map(Vec::as_slice);
+        listpack.chain(quicklist)
+    }
+}
+
+/// Call `f` on the list at `key`. Returns `Ok(None)` if there is no such
+/// key, and a `WRONGTYPE` error if the key holds another type.
+fn read<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    f: impl FnOnce(&List) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    match db.get(key, lfu) {
+        None => Ok(None),
+        Some(e) => match &e.value {
+            Value::List(l) => Ok(Some(f(l))),
+            _ => Err(Frame::Error(WRONG_TYPE)),
+        },
+    }
+}
+
+/// Modify the list at `key` with `f`, creating an empty one first if
+/// `create` is set. Returns `Ok(None)` if there is no such key, and a
+/// `WRONGTYPE` error if the key holds another type.
+///
+/// The list switches encodings according to the current `limit`, and the
+/// key is removed if the list ends up empty.
+fn update<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    limit: ListpackSize,
+    create: bool,
+    f: impl FnOnce(&mut List) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    let new = || create.then(|| Value::List(List::new(limit)));
+    let res = db.update(key, lfu, new, |v| match v {
+        Value::List(l) => {
+            l.set_limit(limit);
+            Some((f(l), l.is_empty()))
+        }
+        _ => None,
+    });
+    match res {
+        None => Ok(None),
+        Some(None) => Err(Frame::Error(WRONG_TYPE)),
+        Some(Some((r, is_empty))) => {
+            if is_empty {
+                db.remove(key);
+            }
+            Ok(Some(r))
+        }
+    }
+}
+
+/// Execute a list command on the selected database `db_index`. `args`
+/// includes the command name, and `raw` are the same arguments as bytes,
+/// for the elements.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let (lfu, limit) = {
+        let config = st.config();
+        (config.lfu_params(), config.list_listpack_size())
+    };
+    let bytes = |b: &[u8]| Frame::Bytes(arena.alloc_slice_copy(b));
+    match (cmd, &args[1..]) {
+        ("lpush" | "rpush", [k, _, ..]) => {
+            let is_new = db.peek(k).is_none();
+            let res = update(db, k, lfu, limit, true, |l| {
+                for element in &raw[2..] {
+                    if cmd == "lpush" {
+                        l.push_front(element);
+                    } else {
+                        l.push_back(element);
+                    }
+                }
+                l.len()
+            });
+            match res {
+                Ok(len) => {
+                    if is_new {
+                        st.notify(notify::NEW, "new", k, db_index);
+                    }
+                    st.notify(notify::LIST, cmd, k, db_index);
+                    Frame::Int(len.unwrap_or(0) as isize)
+                }
+                Err(e) => e,
+            }
+        }
+        ("lpop" | "rpop", [k, count @ ..]) if count.len() <= 1 => {
+            let count = match count.first().map(|c| c.parse::<i64>()) {
+                None => None,
+                Some(Ok(n)) if n >= 0 => Some(n as usize),
+                Some(_) => {
+                    return Frame::Error(
+                        "ERR value is out of range, must be positive",
+                    )
+                }
+            };
+            let res = update(db, k, lfu, limit, false, |l| {
+                let n = count.unwrap_or(1);
+                let pop = |l: &mut List| {
+                    if cmd == "lpop" {
+                        l.pop_front()
+                    } else {
+                        l.pop_back()
+                    }
+                };
+                std::iter::from_fn(|| pop(l))
+                    .take(n)
+                    .map(|e| bytes(&e))
+                    .collect::<Vec<_>>()
+            });
+            match res {
+                Ok(Some(items)) => {
+                    if !items.is_empty() {
+                        st.notify(notify::LIST, cmd, k, db_index);
+                        if db.peek(k).is_none() {
+                            st.notify(notify::GENERIC, "del", k, db_index);
+                        }
+                    }
+                    match count {
+                        None => items.into_iter().next().unwrap_or(Frame::Null),
+                        Some(_) => Frame::Bulk(arena.alloc_slice_copy(&items)),
+                    }
+                }
+                Ok(None) => Frame::Null,
+                Err(e) => e,
+            }
+        }
+        ("llen", &[k]) => match read(db, k, lfu, |l| l.len()) {
+            Ok(n) => Frame::Int(n.unwrap_or(0) as isize),
+            Err(e) => e,
+        },
+        ("lrange", &[k, start, stop]) => {
+            let (Ok(start), Ok(stop)) =
+                (start.parse::<i64>(), stop.parse::<i64>())
+            else {
+                return Frame::Error(
+                    "ERR value is not an integer or out of range",
+                );
+            };
+            let res = read(db, k, lfu, |l| {
+                let len = l.len() as i64;
+                let start = if start < 0 { len + start } else { start };
+                let stop = if stop < 0 { len + stop } else { stop };
+                let (start, stop) = (start.max(0), stop.min(len - 1));
+                let mut items = vec![];
+                if start <= stop {
+                    let n = (stop - start + 1) as usize;
+                    items.extend(
+                        l.iter().skip(start as usize).take(n).map(bytes),
+                    );
+                }
+                items
+            });
+            match res {
+                Ok(items) => Frame::Bulk(
+                    arena.alloc_slice_copy(&items.unwrap_or_default()),
+                ),
+                Err(e) => e,
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
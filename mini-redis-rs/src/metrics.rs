@@ -0,0 +1,279 @@
+//! Prometheus metrics endpoint.
+//!
+//! A tiny HTTP server, on its own port, that answers `GET /metrics` with
+//! the server's counters in the Prometheus text format.
+
+use std::{fmt::Write as _, sync::Arc};
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    server::State,
+    stats::{self, LATENCY_BUCKETS},
+};
+
+/// Maximum size of an HTTP request head.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Serve metrics on `listen` forever.
+pub async fn serve(listen: TcpListener, st: Arc<State>) {
+    loop {
+        let Ok((sock, addr)) = listen.accept().await else {
+            tokio::task::yield_now().await;
+            continue;
+        };
+        log::debug!("metrics request from {addr:?}");
+        let st = st.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(sock, &st).await {
+                log::debug!("error serving metrics to {addr:?}: {e:#}");
+            }
+        });
+    }
+}
+
+/// Answer a single HTTP request, then close the connection.
+async fn handle(mut sock: TcpStream, st: &State) -> Result<()> {
+    let mut buf = vec![];
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = sock.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before end of request");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_SIZE {
+            anyhow::bail!("request too large");
+        }
+    }
+
+    let line = buf.split(|c| *c == b'\r').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(line)?.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(st)),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n",
+        body.len()
+    );
+    sock.write_all(head.as_bytes()).await?;
+    sock.write_all(body.as_bytes()).await?;
+    sock.shutdown().await?;
+    Ok(())
+}
+
+/// Write the `# HELP` and `# TYPE` lines of a metric.
+fn header(out: &mut String, name: &str, ty: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {ty}");
+}
+
+/// Write a metric without labels.
+fn metric(out: &mut String, name: &str, ty: &str, help: &str, v: u64) {
+    header(out, name, ty, help);
+    let _ = writeln!(out, "{name} {v}");
+}
+
+/// Current metrics, in the Prometheus text format.
+pub fn render(st: &State) -> String {
+    let mut out = String::new();
+    let s = &st.stats;
+
+    metric(
+        &mut out,
+        "mini_redis_uptime_seconds",
+        "gauge",
+        "Time since the server started.",
+        s.started.elapsed().as_secs(),
+    );
+    metric(
+        &mut out,
+        "mini_redis_connected_clients",
+        "gauge",
+        "Number of connected clients.",
+        st.n_clients() as u64,
+    );
+    metric(
+        &mut out,
+        "mini_redis_connections_received_total",
+        "counter",
+        "Number of connections accepted.",
+        stats::get(&s.total_connections_received),
+    );
+    metric(
+        &mut out,
+        "mini_redis_rejected_connections_total",
+        "counter",
+        "Number of connections rejected because of maxclients.",
+        stats::get(&s.rejected_connections),
+    );
+    metric(
+        &mut out,
+        "mini_redis_memory_used_bytes",
+        "gauge",
+        "Approximate memory used by keys and values.",
+        st.used_memory() as u64,
+    );
+    metric(
+        &mut out,
+        "mini_redis_memory_max_bytes",
+        "gauge",
+        "Value of maxmemory (0 for no limit).",
+        st.config().maxmemory,
+    );
+    metric(
+        &mut out,
+        "mini_redis_evicted_keys_total",
+        "counter",
+        "Number of keys evicted because of maxmemory.",
+        st.evictor.evicted_keys(),
+    );
+    metric(
+        &mut out,
+        "mini_redis_expired_keys_total",
+        "counter",
+        "Number of keys removed because they expired.",
+        st.expired_keys(),
+    );
+    metric(
+        &mut out,
+        "mini_redis_keyspace_hits_total",
+        "counter",
+        "Number of successful key lookups.",
+        stats::get(&s.keyspace_hits),
+    );
+    metric(
+        &mut out,
+        "mini_redis_keyspace_misses_total",
+        "counter",
+        "Number of failed key lookups.",
+        stats::get(&s.keyspace_misses),
+    );
+
+    header(
+        &mut out,
+        "mini_redis_client_disconnections_total",
+        "counter",
+        "Number of clients closed by the server, per reason.",
+    );
+    for (reason, c) in [
+        (
+            "output_buffer_limit",
+            &s.client_output_buffer_limit_disconnections,
+        ),
+        ("idle_timeout", &s.client_idle_timeout_disconnections),
+    ] {
+        let _ = writeln!(
+            out,
+            "mini_redis_client_disconnections_total{{reason=\"{reason}\"}} {}",
+            stats::get(c)
+        );
+    }
+
+    header(
+        &mut out,
+        "mini_redis_keys",
+        "gauge",
+        "Number of keys per db.",
+    );
+    let dbs = st.dbs();
+    for (i, db) in dbs.iter().enumerate().filter(|(_, db)| !db.is_empty()) {
+        let _ = writeln!(out, "mini_redis_keys{{db=\"{i}\"}} {}", db.len());
+    }
+    header(
+        &mut out,
+        "mini_redis_expiring_keys",
+        "gauge",
+        "Number of keys with an expiration time, per db.",
+    );
+    for (i, db) in dbs.iter().enumerate().filter(|(_, db)| !db.is_empty()) {
+        let _ = writeln!(
+            out,
+            "mini_redis_expiring_keys{{db=\"{i}\"}} {}",
+            db.expires()
+        );
+    }
+
+    let cmds = s.command_stats();
+    header(
+        &mut out,
+        "mini_redis_commands_total",
+        "counter",
+        "Number of calls per command.",
+    );
+    for (name, c) in &cmds {
+        let _ = writeln!(
+            out,
+            "mini_redis_commands_total{{cmd=\"{name}\"}} {}",
+            c.calls
+        );
+    }
+    header(
+        &mut out,
+        "mini_redis_commands_failed_total",
+        "counter",
+        "Number of calls per command that returned an error.",
+    );
+    for (name, c) in &cmds {
+        let _ = writeln!(
+            out,
+            "mini_redis_commands_failed_total{{cmd=\"{name}\"}} {}",
+            c.failed_calls
+        );
+    }
+    header(
+        &mut out,
+        "mini_redis_commands_rejected_total",
+        "counter",
+        "Number of calls per command refused before execution.",
+    );
+    for (name, c) in &cmds {
+        let _ = writeln!(
+            out,
+            "mini_redis_commands_rejected_total{{cmd=\"{name}\"}} {}",
+            c.rejected_calls
+        );
+    }
+
+    let hist = "mini_redis_command_duration_seconds";
+    header(
+        &mut out,
+        hist,
+        "histogram",
+        "Time spent executing commands.",
+    );
+    for (name, c) in &cmds {
+        let mut cumulative = 0;
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += c.latency[i];
+            let le = *bound as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "{hist}_bucket{{cmd=\"{name}\",le=\"{le}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{hist}_bucket{{cmd=\"{name}\",le=\"+Inf\"}} {}",
+            c.calls
+        );
+        let _ = writeln!(
+            out,
+            "{hist}_sum{{cmd=\"{name}\"}} {}",
+            c.usec as f64 / 1e6
+        );
+        let _ = writeln!(out, "{hist}_count{{cmd=\"{name}\"}} {}", c.calls);
+    }
+
+    out
+}
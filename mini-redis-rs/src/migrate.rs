@@ -0,0 +1,255 @@
+//! `DUMP`, `RESTORE` and `MIGRATE`, to move keys between servers.
+//!
+//! Values are serialized like redis does (see [`rdb::dump`]), so payloads
+//! can be exchanged with redis as long as they only hold types that this
+//! server supports.
+//!
+//! Like in redis, `MIGRATE` blocks the server while it talks to the target
+//! instance, so that no other client can see a key on both sides, or on
+//! neither.
+
+use std::time::Duration;
+
+use crate::{
+    client::{blocking::Connection, Options},
+    db::{now_ms, Entry},
+    notify, rdb,
+    server::{wrong_arity, State},
+    wire::{Frame, Value},
+};
+
+/// Execute `DUMP`, `RESTORE` or `MIGRATE` on the selected database
+/// `db_index`. `args` includes the command name, and `raw` are the same
+/// arguments as bytes, for the payload of `RESTORE`.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    match (cmd, &args[1..]) {
+        ("dump", &[k]) => match db.get(k, st.config().lfu_params()) {
+            Some(e) => {
+                Frame::Bytes(arena.alloc_slice_copy(&rdb::dump(&e.value)))
+            }
+            None => Frame::Null,
+        },
+        ("restore", [k, ttl, _, opts @ ..]) => {
+            restore(st, db_index, k, ttl, raw[3], opts)
+        }
+        ("migrate", [host, port, key, dest_db, timeout, opts @ ..]) => {
+            let target = Target::parse(host, port, dest_db, timeout);
+            match target {
+                Ok(target) => migrate(st, db_index, key, target, opts, arena),
+                Err(e) => Frame::Error(e),
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
+
+/// `RESTORE key ttl serialized-value [REPLACE] [ABSTTL]`.
+fn restore<'are>(
+    st: &State,
+    db_index: usize,
+    key: &str,
+    ttl: &str,
+    payload: &[u8],
+    opts: &[&str],
+) -> Frame<'are> {
+    let (mut replace, mut absttl) = (false, false);
+    for opt in opts {
+        match &*opt.to_ascii_lowercase() {
+            "replace" => replace = true,
+            "absttl" => absttl = true,
+            _ => return Frame::Error("ERR syntax error"),
+        }
+    }
+    let Ok(ttl) = ttl.parse::<i64>() else {
+        return Frame::Error("ERR value is not an integer or out of range");
+    };
+    if ttl < 0 {
+        return Frame::Error("ERR Invalid TTL value, must be >= 0");
+    }
+
+    let db = st.db(db_index);
+    if !replace && db.peek(key).is_some() {
+        return Frame::Error("BUSYKEY Target key name already exists.");
+    }
+    if !rdb::check_dump(payload) {
+        return Frame::Error("ERR DUMP payload version or checksum are wrong");
+    }
+    let mut value = match rdb::undump(payload) {
+        Ok(value) => value,
+        Err(e) => {
+            log::debug!("restore {key:?}: {e:#}");
+            return Frame::Error("ERR Bad data format");
+        }
+    };
+
+    value.set_limits(&st.config().encoding_limits());
+
+    let expires_at = match ttl as u64 {
+        0 => None,
+        t if absttl => Some(t),
+        t => Some(now_ms().saturating_add(t)),
+    };
+    if expires_at.is_some_and(|t| t <= now_ms()) {
+        // the key would expire right away: it's only deleted
+        if db.remove(key).is_some() {
+            st.notify(notify::GENERIC, "del", key, db_index);
+        }
+        return Frame::String("OK");
+    }
+    if db.insert(key.to_string(), Entry::new(value, expires_at)) {
+        st.notify(notify::NEW, "new", key, db_index);
+    }
+    st.notify(notify::GENERIC, "restore", key, db_index);
+    Frame::String("OK")
+}
+
+/// Where `MIGRATE` sends keys.
+struct Target {
+    addr: String,
+    db: usize,
+    timeout: Duration,
+}
+
+impl Target {
+    fn parse(
+        host: &str,
+        port: &str,
+        db: &str,
+        timeout: &str,
+    ) -> Result<Self, &'static str> {
+        let (Ok(port), Ok(db), Ok(timeout)) = (
+            port.parse::<u16>(),
+            db.parse::<usize>(),
+            timeout.parse::<i64>(),
+        ) else {
+            return Err("ERR value is not an integer or out of range");
+        };
+        let addr = if host.contains(':') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+        // like in redis, a timeout that is not positive means 1 second
+        let timeout = Duration::from_millis(match timeout {
+            t if t > 0 => t as u64,
+            _ => 1000,
+        });
+        Ok(Self { addr, db, timeout })
+    }
+}
+
+/// `MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [KEYS key [key ...]]`.
+///
+/// Keys are sent with `RESTORE`, and deleted once the target acknowledged
+/// them, unless `COPY` is set.
+fn migrate<'are>(
+    st: &State,
+    db_index: usize,
+    key: &'are str,
+    target: Target,
+    opts: &[&'are str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let (mut copy, mut replace, mut keys) = (false, false, None);
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match &*opt.to_ascii_lowercase() {
+            "copy" => copy = true,
+            "replace" => replace = true,
+            "keys" => {
+                if !key.is_empty() {
+                    return Frame::Error(
+                        "ERR When using MIGRATE KEYS option, the key \
+                        argument must be set to the empty string",
+                    );
+                }
+                keys = Some(opts.as_slice());
+                break;
+            }
+            _ => return Frame::Error("ERR syntax error"),
+        }
+    }
+    let keys = keys.unwrap_or(std::slice::from_ref(&key));
+
+    // the remaining TTL and payload of the keys that exist
+    let db = st.db(db_index);
+    let now = now_ms();
+    let mut found = vec![];
+    for &k in keys {
+        if let Some(e) = db.peek(k) {
+            let ttl = e.expires_at.map_or(0, |t| t.saturating_sub(now).max(1));
+            found.push((k, ttl.to_string(), rdb::dump(&e.value)));
+        }
+    }
+    if found.is_empty() {
+        return Frame::String("NOKEY");
+    }
+    let cmds: Vec<Vec<&[u8]>> = found
+        .iter()
+        .map(|(k, ttl, payload)| {
+            let mut cmd =
+                vec![&b"restore"[..], k.as_bytes(), ttl.as_bytes(), payload];
+            if replace {
+                cmd.push(b"replace");
+            }
+            cmd
+        })
+        .collect();
+
+    let opts = Options {
+        db: target.db,
+        connect_timeout: Some(target.timeout),
+        timeout: Some(target.timeout),
+        ..Default::default()
+    };
+    let addr = &target.addr;
+    let mut conn = match Connection::connect(addr, &opts) {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::warn!("MIGRATE could not connect to {addr}: {e:#}");
+            return Frame::Error(
+                "IOERR error or timeout connecting to the client",
+            );
+        }
+    };
+    if let Err(e) = conn.send_all(&cmds) {
+        log::warn!("MIGRATE could not write to {addr}: {e:#}");
+        return Frame::Error(
+            "IOERR error or timeout writing to target instance",
+        );
+    }
+    let mut error = None;
+    for (k, ..) in &found {
+        match conn.recv() {
+            Ok(Value::Error(e)) => {
+                error.get_or_insert(e);
+            }
+            Ok(_) => {
+                if !copy && db.remove(k).is_some() {
+                    st.notify(notify::GENERIC, "del", k, db_index);
+                }
+            }
+            Err(e) => {
+                log::warn!("MIGRATE could not read from {addr}: {e:#}");
+                return Frame::Error(
+                    "IOERR error or timeout reading to target instance",
+                );
+            }
+        }
+    }
+    match error {
+        Some(e) => Frame::Error(arena.alloc_str(&format!(
+            "ERR Target instance replied with error: {e}"
+        ))),
+        None => Frame::String("OK"),
+    }
+}
@@ -0,0 +1,94 @@
+//! `MONITOR`: clients that receive every command the server runs.
+//!
+//! Commands are described like in redis, with the time in seconds, the
+//! selected database, the address of the client (or `lua` for commands
+//! called by scripts) and the quoted arguments:
+//!
+//! ```text
+//! 1339518083.107412 [0 127.0.0.1:60866] "set" "k" "v"
+//! ```
+//!
+//! The same lines are written to the audit log, see [`crate::audit`].
+
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+
+use crate::{clients::ClientInfo, pubsub::Message};
+
+/// Clients that called `MONITOR`, by ID.
+#[derive(Debug, Default)]
+pub struct Monitors {
+    clients: DashMap<u64, Arc<ClientInfo>>,
+}
+
+impl Monitors {
+    pub fn add(&self, client: &Arc<ClientInfo>) {
+        self.clients.insert(client.id, client.clone());
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.clients.remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Is anyone monitoring? Used to skip describing commands nobody will
+    /// see.
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Send `line`, from [`line`], to all the monitors.
+    pub fn feed(&self, line: &str) {
+        let line: Arc<str> = line.into();
+        for c in self.clients.iter() {
+            c.push(Message::Monitor(line.clone()));
+        }
+    }
+}
+
+/// Describe a command run on database `db` by `source`.
+pub fn line(db: usize, source: &str, args: &[&[u8]]) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut s = format!(
+        "{}.{:06} [{db} {source}]",
+        now.as_secs(),
+        now.subsec_micros()
+    );
+    for a in args {
+        s.push(' ');
+        repr(a, &mut s);
+    }
+    s
+}
+
+/// Quote `s` like redis does: bytes that are not printable ASCII are
+/// escaped.
+fn repr(s: &[u8], out: &mut String) {
+    out.push('"');
+    for &b in s {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'\x07' => out.push_str("\\a"),
+            b'\x08' => out.push_str("\\b"),
+            b' '..=b'~' => out.push(b as char),
+            b => {
+                let _ = write!(out, "\\x{b:02x}");
+            }
+        }
+    }
+    out.push('"');
+}
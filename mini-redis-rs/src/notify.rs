@@ -0,0 +1,122 @@
+//! Keyspace notifications.
+//!
+//! When enabled with `notify-keyspace-events`, commands that modify a key
+//! publish an event on `__keyspace@<db>__:<key>` (with the event name as
+//! payload) and/or `__keyevent@<db>__:<event>` (with the key as payload).
+
+use std::fmt;
+
+/// Key-space events, published on `__keyspace@<db>__:<key>`.
+pub const KEYSPACE: u32 = 1 << 0;
+/// Key-event events, published on `__keyevent@<db>__:<event>`.
+pub const KEYEVENT: u32 = 1 << 1;
+/// Generic commands: `DEL`, `EXPIRE`, `RENAME`, …
+pub const GENERIC: u32 = 1 << 2;
+/// String commands.
+pub const STRING: u32 = 1 << 3;
+pub const LIST: u32 = 1 << 4;
+pub const SET: u32 = 1 << 5;
+pub const HASH: u32 = 1 << 6;
+pub const ZSET: u32 = 1 << 7;
+/// Keys removed because they expired.
+pub const EXPIRED: u32 = 1 << 8;
+/// Keys removed because of `maxmemory`.
+pub const EVICTED: u32 = 1 << 9;
+pub const STREAM: u32 = 1 << 10;
+/// Reads of keys that don't exist.
+pub const KEY_MISS: u32 = 1 << 11;
+pub const MODULE: u32 = 1 << 12;
+/// Keys that are created.
+pub const NEW: u32 = 1 << 13;
+
+/// The classes included in `A`.
+const ALL: u32 = GENERIC
+    | STRING
+    | LIST
+    | SET
+    | HASH
+    | ZSET
+    | EXPIRED
+    | EVICTED
+    | STREAM
+    | MODULE;
+
+/// Classes in the order used to print them.
+const CLASSES: &[(char, u32)] = &[
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('d', MODULE),
+];
+
+/// Which notifications are enabled, as set by `notify-keyspace-events`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flags(pub u32);
+
+impl Flags {
+    /// Should an event of class `class` be published on the key-space
+    /// channel?
+    pub fn keyspace(self, class: u32) -> bool {
+        self.0 & KEYSPACE != 0 && self.0 & class != 0
+    }
+
+    /// Should an event of class `class` be published on the key-event
+    /// channel?
+    pub fn keyevent(self, class: u32) -> bool {
+        self.0 & KEYEVENT != 0 && self.0 & class != 0
+    }
+}
+
+impl std::str::FromStr for Flags {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut flags = 0;
+        for c in s.chars() {
+            flags |= match c {
+                'A' => ALL,
+                'K' => KEYSPACE,
+                'E' => KEYEVENT,
+                'm' => KEY_MISS,
+                'n' => NEW,
+                c => match CLASSES.iter().find(|(c2, _)| *c2 == c) {
+                    Some((_, class)) => *class,
+                    None => anyhow::bail!("invalid event class {c:?}"),
+                },
+            };
+        }
+        Ok(Flags(flags))
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = self.0;
+        if flags & ALL == ALL {
+            f.write_str("A")?;
+        } else {
+            for (c, class) in CLASSES {
+                if flags & class != 0 {
+                    write!(f, "{c}")?;
+                }
+            }
+        }
+        for (c, class) in [
+            ('K', KEYSPACE),
+            ('E', KEYEVENT),
+            ('m', KEY_MISS),
+            ('n', NEW),
+        ] {
+            if flags & class != 0 {
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
+}
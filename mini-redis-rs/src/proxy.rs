@@ -0,0 +1,424 @@
+//! Fault-injection proxy, to test how clients cope with a misbehaving
+//! server.
+//!
+//! The proxy forwards commands to a backend server, with one backend
+//! connection per client, and applies the rules of a rule file to each
+//! command. Rules are one per line, with the same quoting as in
+//! `redis.conf`:
+//!
+//! ```text
+//! <action> [<argument>] [cmd <name>[,<name>...]] [key <pattern>] [prob <p>]
+//! ```
+//!
+//! Actions are:
+//! - `delay <ms>` or `delay <min>-<max>`: wait before forwarding the
+//!   command, a random time in the range for the latter.
+//! - `error <message>`: reply with an error instead of forwarding the
+//!   command.
+//! - `drop`: close the client connection instead of forwarding the
+//!   command.
+//! - `partial`: forward the command, but only write a part of the reply
+//!   before closing the client connection.
+//! - `reorder`: send the replies of pipelined commands in reverse order.
+//!
+//! A rule applies to the commands named by `cmd` (all of them by
+//! default), whose keys match the glob-style `pattern` if `key` is given,
+//! with probability `p` (1 by default). Delays of all the rules that
+//! apply add up, and the first `error`, `drop` or `partial` rule that
+//! applies wins:
+//!
+//! ```text
+//! # slow writes, and a flaky GET
+//! delay 50-200 cmd set,del
+//! error "ERR injected failure" cmd get key user:* prob 0.1
+//! drop prob 0.01
+//! ```
+//!
+//! Once a client sends `SUBSCRIBE`, `PSUBSCRIBE`, `SSUBSCRIBE` or
+//! `MONITOR`, the backend pushes messages that don't answer any command,
+//! so the proxy passes them through as they arrive until the client
+//! disconnects. `delay`, `error` and `drop` still apply to the commands of
+//! such a client, but `partial` and `reorder` don't.
+
+use std::{net::SocketAddr, path::Path, rc::Rc, time::Duration};
+
+use anyhow::{Context, Result};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::LocalSet,
+};
+
+use crate::{
+    commands,
+    config::split_str_args,
+    glob,
+    wire::{self, Conn, Frame},
+};
+
+/// What a [`Rule`] does to the commands it applies to.
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Delay(Duration, Duration),
+    Error(String),
+    Drop,
+    Partial,
+    Reorder,
+}
+
+/// A line of the rule file.
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    action: Action,
+    /// Lowercase names of the commands the rule applies to, or all of
+    /// them if empty.
+    cmds: Vec<String>,
+    key: Option<String>,
+    prob: f64,
+}
+
+/// The rules of a rule file, in order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules(Vec<Rule>);
+
+/// The faults to inject for a command.
+#[derive(Default)]
+struct Faults<'a> {
+    delay: Duration,
+    /// The first `error`, `drop` or `partial` action.
+    action: Option<&'a Action>,
+    reorder: bool,
+}
+
+impl Rule {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut args = args.iter();
+        let name = args.next().context("empty rule")?;
+        let mut value =
+            |what: &str| args.next().with_context(|| format!("missing {what}"));
+        let action = match &*name.to_ascii_lowercase() {
+            "delay" => {
+                let ms = |s: &str| {
+                    s.parse().map(Duration::from_millis).with_context(|| {
+                        format!("invalid delay {s:?}, expected milliseconds")
+                    })
+                };
+                let delay = value("delay")?;
+                let (min, max) = match delay.split_once('-') {
+                    Some((min, max)) => (ms(min)?, ms(max)?),
+                    None => (ms(delay)?, ms(delay)?),
+                };
+                anyhow::ensure!(min <= max, "invalid delay range {delay:?}");
+                Action::Delay(min, max)
+            }
+            "error" => Action::Error(value("error message")?.clone()),
+            "drop" => Action::Drop,
+            "partial" => Action::Partial,
+            "reorder" => Action::Reorder,
+            _ => anyhow::bail!("unknown action {name:?}"),
+        };
+
+        let mut rule = Rule {
+            action,
+            cmds: vec![],
+            key: None,
+            prob: 1.0,
+        };
+        while let Some(opt) = args.next() {
+            let mut value = |what: &str| {
+                args.next().with_context(|| format!("missing {what}"))
+            };
+            match &*opt.to_ascii_lowercase() {
+                "cmd" => {
+                    rule.cmds = value("command names")?
+                        .split(',')
+                        .map(|c| c.to_ascii_lowercase())
+                        .collect();
+                }
+                "key" => rule.key = Some(value("key pattern")?.clone()),
+                "prob" => {
+                    let p = value("probability")?;
+                    rule.prob = p
+                        .parse()
+                        .ok()
+                        .filter(|p| (0.0..=1.0).contains(p))
+                        .with_context(|| {
+                            format!("invalid probability {p:?}")
+                        })?;
+                }
+                _ => anyhow::bail!("unknown option {opt:?}"),
+            }
+        }
+        Ok(rule)
+    }
+
+    /// Does the rule apply to `args`, the command name and its arguments?
+    fn applies(&self, args: &[&str]) -> bool {
+        let Some(name) = args.first() else {
+            return self.cmds.is_empty() && self.key.is_none();
+        };
+        if !self.cmds.is_empty()
+            && !self.cmds.iter().any(|c| c.eq_ignore_ascii_case(name))
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.key {
+            let keys = commands::lookup(name)
+                .map(|c| c.keys(args))
+                .unwrap_or_default();
+            let matches = |k: &&str| {
+                glob::string_match(pattern.as_bytes(), k.as_bytes(), false)
+            };
+            if !keys.iter().any(matches) {
+                return false;
+            }
+        }
+        self.prob >= 1.0 || fastrand::f64() < self.prob
+    }
+}
+
+impl Rules {
+    /// Parse the content of a rule file.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut rules = vec![];
+        for (i, line) in content.lines().enumerate() {
+            let args = split_str_args(line)
+                .with_context(|| format!("parsing line {}", i + 1))?;
+            if args.is_empty() {
+                continue;
+            }
+            let rule = Rule::parse(&args)
+                .with_context(|| format!("at line {}", i + 1))?;
+            rules.push(rule);
+        }
+        Ok(Self(rules))
+    }
+
+    /// Read the rules from a file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("reading rule file {path:?}"))?;
+        Self::parse(&content).with_context(|| format!("in rule file {path:?}"))
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn faults(&self, args: &[&str]) -> Faults<'_> {
+        let mut faults = Faults::default();
+        for rule in &self.0 {
+            if !rule.applies(args) {
+                continue;
+            }
+            match &rule.action {
+                Action::Delay(min, max) => {
+                    let ms = fastrand::u128(min.as_millis()..=max.as_millis());
+                    faults.delay += Duration::from_millis(ms as u64);
+                }
+                Action::Reorder => faults.reorder = true,
+                action => {
+                    faults.action.get_or_insert(action);
+                }
+            }
+        }
+        faults
+    }
+}
+
+/// Accept clients on `listen` forever, and proxy their commands to
+/// `backend` according to `rules`.
+pub async fn run(listen: TcpListener, backend: String, rules: Rules) {
+    let rules = Rc::new(rules);
+    let backend = Rc::new(backend);
+    let local = LocalSet::new(); // spawn on same thread
+    local
+        .run_until(async move {
+            loop {
+                let (sock, addr) = match listen.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // e.g. too many open files: wait for some to be
+                        // closed instead of spinning
+                        log::warn!("error accepting a client: {e}");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let (backend, rules) = (backend.clone(), rules.clone());
+                tokio::task::spawn_local(async move {
+                    if let Err(e) = handle(sock, addr, &backend, &rules).await {
+                        log::info!("error proxying client {addr:?}: {e:#}");
+                    }
+                });
+            }
+        })
+        .await
+}
+
+/// The command name and arguments of a request, or nothing if it's not an
+/// array of strings.
+fn command_args<'a>(
+    frame: &Frame<'a>,
+    arena: &'a bumpalo::Bump,
+) -> Vec<&'a str> {
+    let Frame::Bulk(items) = frame else {
+        return vec![];
+    };
+    let mut args = Vec::with_capacity(items.len());
+    for item in items.iter() {
+        match item {
+            Frame::String(s) => args.push(*s),
+            Frame::Bytes(b) => {
+                args.push(&*arena.alloc_str(&String::from_utf8_lossy(b)))
+            }
+            _ => return vec![],
+        }
+    }
+    args
+}
+
+/// Does the backend push messages to the client after `cmd`, rather than
+/// sending one reply per command?
+fn pushes_messages(cmd: &str) -> bool {
+    ["subscribe", "psubscribe", "ssubscribe", "monitor"]
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(cmd))
+}
+
+/// Proxy the commands of a client, until either side closes the
+/// connection or a rule drops it.
+async fn handle(
+    mut sock: TcpStream,
+    addr: SocketAddr,
+    backend: &str,
+    rules: &Rules,
+) -> Result<()> {
+    let mut backend_sock = TcpStream::connect(backend)
+        .await
+        .with_context(|| format!("connecting to backend {backend}"))?;
+    let backend_addr = backend_sock.peer_addr()?;
+    let mut client = Conn::new(&mut sock, addr);
+    let mut server = Conn::new(&mut backend_sock, backend_addr);
+
+    loop {
+        let arena = bumpalo::Bump::new();
+        // the commands the client pipelined are handled together, so that
+        // their replies can be reordered
+        let mut replies = vec![];
+        let mut reorder = false;
+        let mut passthrough = false;
+        loop {
+            let Some(frame) = wire::read_frame(&mut client, &arena).await?
+            else {
+                return Ok(());
+            };
+            let args = command_args(&frame, &arena);
+            let faults = rules.faults(&args);
+            if !faults.delay.is_zero() {
+                log::debug!("delaying {args:?} by {:?}", faults.delay);
+                tokio::time::sleep(faults.delay).await;
+            }
+            reorder |= faults.reorder;
+            let reply = match faults.action {
+                Some(Action::Error(e)) => Frame::Error(e),
+                Some(Action::Drop) => {
+                    log::debug!("dropping {addr:?} on {args:?}");
+                    return Ok(());
+                }
+                _ if args.first().is_some_and(|c| pushes_messages(c)) => {
+                    // its replies are passed through below
+                    wire::write_frame(&mut server, &frame).await?;
+                    passthrough = true;
+                    break;
+                }
+                _ => {
+                    wire::write_frame(&mut server, &frame).await?;
+                    let reply = wire::read_frame(&mut server, &arena)
+                        .await?
+                        .context("the backend closed the connection")?;
+                    reply
+                }
+            };
+            let partial = faults.action == Some(&Action::Partial);
+            replies.push((reply, partial));
+            if partial || !wire::has_buffered(&client) {
+                break;
+            }
+        }
+
+        if reorder {
+            replies.reverse();
+        }
+        for (reply, partial) in replies {
+            if partial {
+                let mut buf = vec![];
+                wire::encode(&reply, &mut buf);
+                let len = fastrand::usize(1..buf.len());
+                log::debug!("writing {len}/{} bytes to {addr:?}", buf.len());
+                wire::write_bytes(&mut client, &buf[..len]).await?;
+                return Ok(());
+            }
+            wire::write_frame(&mut client, &reply).await?;
+        }
+        if passthrough {
+            return passthrough_messages(&mut client, &mut server, rules).await;
+        }
+    }
+}
+
+/// Forward the commands of a client to the backend, and whatever the
+/// backend sends to the client, as they arrive.
+async fn passthrough_messages(
+    client: &mut Conn<'_>,
+    server: &mut Conn<'_>,
+    rules: &Rules,
+) -> Result<()> {
+    log::debug!("passing messages through to {:?}", client.addr());
+    loop {
+        let arena = bumpalo::Bump::new();
+        // waiting for data doesn't consume it, so whichever side is not
+        // ready can be dropped
+        tokio::select! {
+            readable = wire::wait_readable(server) => {
+                anyhow::ensure!(
+                    readable?,
+                    "the backend closed the connection"
+                );
+                let frame = wire::read_frame(server, &arena)
+                    .await?
+                    .context("the backend closed the connection")?;
+                wire::write_frame(client, &frame).await?;
+            }
+            readable = wire::wait_readable(client) => {
+                if !readable? {
+                    return Ok(());
+                }
+                let Some(frame) = wire::read_frame(client, &arena).await?
+                else {
+                    return Ok(());
+                };
+                let args = command_args(&frame, &arena);
+                let faults = rules.faults(&args);
+                if !faults.delay.is_zero() {
+                    log::debug!("delaying {args:?} by {:?}", faults.delay);
+                    tokio::time::sleep(faults.delay).await;
+                }
+                match faults.action {
+                    Some(Action::Error(e)) => {
+                        wire::write_frame(client, &Frame::Error(e)).await?
+                    }
+                    Some(Action::Drop) => {
+                        log::debug!("dropping {:?} on {args:?}", client.addr());
+                        return Ok(());
+                    }
+                    _ => wire::write_frame(server, &frame).await?,
+                }
+            }
+        }
+    }
+}
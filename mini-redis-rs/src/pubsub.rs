@@ -0,0 +1,173 @@
+//! Registry of pub/sub channels and patterns.
+//!
+//! Each subscribed client is registered per channel or pattern; messages
+//! are queued with [`ClientInfo::push`] and written by the client's
+//! handler.
+
+use std::{collections::HashMap, sync::Arc};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+
+use crate::{clients::ClientInfo, glob};
+
+/// A message pushed to a client.
+#[derive(Clone, Debug)]
+pub enum Message {
+    /// A message published on a channel.
+    Publish {
+        /// Pattern that matched, for `PSUBSCRIBE`.
+        pattern: Option<Arc<str>>,
+        channel: Arc<str>,
+        payload: Arc<[u8]>,
+    },
+    /// Keys to invalidate, for client-side caching. `None` means all the
+    /// keys.
+    Invalidate(Option<Arc<[String]>>),
+    /// A command run by a client, for `MONITOR`.
+    Monitor(Arc<str>),
+}
+
+impl Message {
+    /// Approximate size of the message once encoded, for the output
+    /// buffer limits.
+    pub fn size(&self) -> usize {
+        match self {
+            Message::Publish {
+                pattern,
+                channel,
+                payload,
+            } => {
+                pattern.as_ref().map_or(0, |p| p.len() + 16)
+                    + channel.len()
+                    + payload.len()
+                    + 40
+            }
+            Message::Invalidate(keys) => {
+                keys.iter()
+                    .flat_map(|k| k.iter())
+                    .map(|k| k.len() + 16)
+                    .sum::<usize>()
+                    + 64
+            }
+            Message::Monitor(line) => line.len() + 16,
+        }
+    }
+}
+
+/// Queue of messages for a client.
+pub type Sender = mpsc::UnboundedSender<Message>;
+
+/// Subscribers, by channel and by pattern.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    /// Channel → client ID → client.
+    channels: Subscribers,
+    /// Pattern → client ID → client.
+    patterns: Subscribers,
+}
+
+type Subscribers = DashMap<String, HashMap<u64, Arc<ClientInfo>>>;
+
+/// Add `client` to `map[name]`.
+fn add(map: &Subscribers, name: &str, client: &Arc<ClientInfo>) {
+    map.entry(name.to_string())
+        .or_default()
+        .insert(client.id, client.clone());
+}
+
+/// Remove client `id` from `map[name]`, dropping `name` if it has no
+/// subscribers left.
+fn remove(map: &Subscribers, name: &str, id: u64) {
+    map.remove_if_mut(name, |_, subs| {
+        subs.remove(&id);
+        subs.is_empty()
+    });
+}
+
+impl PubSub {
+    pub fn subscribe(&self, channel: &str, client: &Arc<ClientInfo>) {
+        add(&self.channels, channel, client);
+    }
+
+    pub fn unsubscribe(&self, channel: &str, id: u64) {
+        remove(&self.channels, channel, id);
+    }
+
+    pub fn psubscribe(&self, pattern: &str, client: &Arc<ClientInfo>) {
+        add(&self.patterns, pattern, client);
+    }
+
+    pub fn punsubscribe(&self, pattern: &str, id: u64) {
+        remove(&self.patterns, pattern, id);
+    }
+
+    /// Is anyone subscribed to anything? Used to skip building messages
+    /// nobody will receive.
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Send `payload` to the subscribers of `channel`, and return how many
+    /// clients received it.
+    pub fn publish(&self, channel: &str, payload: &[u8]) -> usize {
+        let channel: Arc<str> = channel.into();
+        let payload: Arc<[u8]> = payload.into();
+        let mut n = 0;
+
+        if let Some(subs) = self.channels.get(&*channel) {
+            for c in subs.values() {
+                let msg = Message::Publish {
+                    pattern: None,
+                    channel: channel.clone(),
+                    payload: payload.clone(),
+                };
+                n += c.push(msg) as usize;
+            }
+        }
+        for r in self.patterns.iter() {
+            let matched = glob::string_match(
+                r.key().as_bytes(),
+                channel.as_bytes(),
+                false,
+            );
+            if !matched {
+                continue;
+            }
+            let pattern: Arc<str> = r.key().as_str().into();
+            for c in r.value().values() {
+                let msg = Message::Publish {
+                    pattern: Some(pattern.clone()),
+                    channel: channel.clone(),
+                    payload: payload.clone(),
+                };
+                n += c.push(msg) as usize;
+            }
+        }
+        n
+    }
+
+    /// Channels with at least one subscriber, optionally filtered by a
+    /// glob pattern.
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.channels
+            .iter()
+            .map(|r| r.key().clone())
+            .filter(|c| {
+                pattern.is_none_or(|p| {
+                    glob::string_match(p.as_bytes(), c.as_bytes(), false)
+                })
+            })
+            .collect()
+    }
+
+    /// Number of subscribers of `channel`.
+    pub fn numsub(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, |subs| subs.len())
+    }
+
+    /// Number of patterns with at least one subscriber.
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+}
@@ -0,0 +1,557 @@
+//! Snapshots in the RDB format.
+//!
+//! Only what this server can hold is supported: string and sorted set
+//! values, with their expiration time, in any number of databases. Files
+//! written by redis can be loaded as long as they only contain these
+//! types; integer-encoded and LZF-compressed strings are understood.
+//!
+//! Single values are serialized the same way for `DUMP` and `RESTORE`.
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    db::{now_ms, Db, Entry, Value},
+    hash::Hash,
+    list::List,
+    server::State,
+    set::Set,
+    zset::ZSet,
+};
+
+/// Version written in the header.
+pub const RDB_VERSION: u16 = 9;
+
+/// Latest version that can be read. Later versions only changed the
+/// encoding of types other than strings.
+const MAX_RDB_VERSION: u16 = 11;
+
+const OPCODE_FREQ: u8 = 0xf8;
+const OPCODE_IDLE: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+/// Sorted set with scores as strings, only read.
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+/// Sorted set with binary scores.
+const TYPE_ZSET_2: u8 = 5;
+
+/// Special encodings of strings, after a length starting with `0b11`.
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// Path of the snapshot file, according to the configuration.
+pub fn path(st: &State) -> PathBuf {
+    let config = st.config();
+    config.dir.join(&config.dbfilename)
+}
+
+/// Held while saving, so that a periodic snapshot and the one saved on
+/// shutdown don't write the same temporary file.
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// Write a snapshot of all the databases to the configured file.
+///
+/// The snapshot is written to a temporary file first, which then replaces
+/// the previous one, so that a failure can't leave a truncated file.
+pub fn save(st: &State) -> Result<()> {
+    let _saving = SAVING.lock().unwrap();
+    let path = path(st);
+    let mut data = vec![];
+    encode(&st.dbs(), &mut data);
+
+    let tmp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let write = || -> Result<()> {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    };
+    if let Err(e) = write() {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("saving to {path:?}"));
+    }
+    log::info!("DB saved on disk ({} bytes)", data.len());
+    Ok(())
+}
+
+/// Load the configured snapshot file into the databases, if it exists.
+/// Returns the number of keys loaded.
+pub fn load(st: &State) -> Result<usize> {
+    let path = path(st);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("reading {path:?}")),
+    };
+    let dbs = st.dbs();
+    let limits = st.config().encoding_limits();
+    let mut n = 0;
+    decode(&data, |db, key, mut entry| {
+        let Some(db) = dbs.get(db) else {
+            anyhow::bail!("DB index {db} is out of range");
+        };
+        entry.value.set_limits(&limits);
+        db.insert(key, entry);
+        n += 1;
+        Ok(())
+    })
+    .with_context(|| format!("loading {path:?}"))?;
+    log::info!("DB loaded from disk: {n} keys");
+    Ok(n)
+}
+
+/// Serialize the content of `dbs`, as a whole RDB file.
+pub fn encode(dbs: &[Arc<Db>], out: &mut Vec<u8>) {
+    out.extend_from_slice(format!("REDIS{RDB_VERSION:04}").as_bytes());
+    for (k, v) in [
+        ("redis-ver", "7.0.0"),
+        ("redis-bits", "64"),
+        ("ctime", &(now_ms() / 1000).to_string()),
+    ] {
+        out.push(OPCODE_AUX);
+        write_string(out, k.as_bytes());
+        write_string(out, v.as_bytes());
+    }
+
+    for (i, db) in dbs.iter().enumerate() {
+        if db.is_empty() {
+            continue;
+        }
+        out.push(OPCODE_SELECTDB);
+        write_len(out, i as u64);
+        out.push(OPCODE_RESIZEDB);
+        write_len(out, db.len() as u64);
+        write_len(out, db.expires() as u64);
+        db.for_each(|k, e| write_entry(out, k, e));
+    }
+
+    out.push(OPCODE_EOF);
+    let crc = crc64(0, out);
+    out.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// Parse a whole RDB file, and call `f` with the database index, key and
+/// entry of every key that is not expired.
+pub fn decode(
+    data: &[u8],
+    mut f: impl FnMut(usize, String, Entry) -> Result<()>,
+) -> Result<()> {
+    let mut r = Reader::new(data);
+    let magic = r.bytes(9).context("truncated header")?;
+    if &magic[..5] != b"REDIS" {
+        anyhow::bail!("wrong signature, not an RDB file");
+    }
+    let version: u16 = std::str::from_utf8(&magic[5..])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .context("invalid RDB version")?;
+    if version > MAX_RDB_VERSION {
+        anyhow::bail!("can't handle RDB format version {version}");
+    }
+
+    let now = now_ms();
+    let mut db = 0;
+    let mut expires_at = None;
+    loop {
+        match r.u8()? {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => db = r.len()? as usize,
+            OPCODE_RESIZEDB => {
+                r.len()?;
+                r.len()?;
+            }
+            OPCODE_AUX => {
+                r.string()?;
+                r.string()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let t = r.bytes(8)?.try_into().unwrap();
+                expires_at = Some(u64::from_le_bytes(t));
+            }
+            OPCODE_EXPIRETIME => {
+                let t = r.bytes(4)?.try_into().unwrap();
+                expires_at = Some(u32::from_le_bytes(t) as u64 * 1000);
+            }
+            OPCODE_IDLE => {
+                r.len()?;
+            }
+            OPCODE_FREQ => {
+                r.u8()?;
+            }
+            t @ (TYPE_STRING | TYPE_LIST | TYPE_SET | TYPE_ZSET | TYPE_HASH
+            | TYPE_ZSET_2) => {
+                let key = r.utf8_string()?;
+                let value = r.value(t)?;
+                let expires_at = expires_at.take();
+                // like a primary, don't load keys that already expired
+                if expires_at.is_none_or(|t| t > now) {
+                    f(db, key, Entry::new(value, expires_at))?;
+                }
+            }
+            t => anyhow::bail!("unsupported value type {t}"),
+        }
+    }
+
+    // a checksum of 0 means it was disabled when saving
+    if version >= 5 {
+        let end = r.pos;
+        let crc = u64::from_le_bytes(
+            r.bytes(8).context("missing checksum")?.try_into().unwrap(),
+        );
+        if crc != 0 && crc != crc64(0, &data[..end]) {
+            anyhow::bail!("wrong checksum");
+        }
+    }
+    Ok(())
+}
+
+/// Serialize a single key, with its expiration time.
+pub(crate) fn write_entry(out: &mut Vec<u8>, key: &str, entry: &Entry) {
+    if let Some(t) = entry.expires_at {
+        out.push(OPCODE_EXPIRETIME_MS);
+        out.extend_from_slice(&t.to_le_bytes());
+    }
+    out.push(value_type(&entry.value));
+    write_string(out, key.as_bytes());
+    write_value(out, &entry.value);
+}
+
+/// Type written before a key and its value.
+fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::String(_) | Value::Int(_) => TYPE_STRING,
+        Value::Hash(_) => TYPE_HASH,
+        Value::List(_) => TYPE_LIST,
+        Value::Set(_) => TYPE_SET,
+        Value::ZSet(_) => TYPE_ZSET_2,
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(s) => write_string(out, s),
+        Value::Int(i) => write_string(out, i.to_string().as_bytes()),
+        Value::Hash(h) => {
+            write_len(out, h.len() as u64);
+            for (field, value) in h.iter() {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        Value::List(l) => {
+            write_len(out, l.len() as u64);
+            for element in l.iter() {
+                write_string(out, element);
+            }
+        }
+        Value::Set(s) => {
+            write_len(out, s.len() as u64);
+            for member in s.iter() {
+                write_string(out, &member);
+            }
+        }
+        Value::ZSet(z) => {
+            write_len(out, z.len() as u64);
+            for (member, score) in z.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Serialize a value like `DUMP` does: its type and the value as in an
+/// RDB file, followed by the RDB version and a checksum of all that.
+pub fn dump(value: &Value) -> Vec<u8> {
+    let mut out = vec![value_type(value)];
+    write_value(&mut out, value);
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+/// Does `payload` end with an RDB version that can be read and a valid
+/// checksum, like the output of `DUMP`?
+pub fn check_dump(payload: &[u8]) -> bool {
+    let Some(end) = payload.len().checked_sub(10) else {
+        return false;
+    };
+    let footer = &payload[end..];
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let crc = u64::from_le_bytes(footer[2..].try_into().unwrap());
+    version <= MAX_RDB_VERSION && crc == crc64(0, &payload[..end + 2])
+}
+
+/// Parse a payload from `DUMP`, which must have been checked with
+/// [`check_dump`].
+pub fn undump(payload: &[u8]) -> Result<Value> {
+    let data = &payload[..payload.len() - 10];
+    let mut r = Reader::new(data);
+    let t = r.u8()?;
+    let value = r.value(t)?;
+    if r.pos != data.len() {
+        anyhow::bail!("trailing data after the value");
+    }
+    if value.is_empty_collection() {
+        anyhow::bail!("empty {}", value.type_name());
+    }
+    Ok(value)
+}
+
+/// Write a length, in the smallest of the possible encodings.
+pub(crate) fn write_len(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&(len as u16 | 0x4000).to_be_bytes());
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Write a string, as an integer if it's the canonical representation of
+/// one that fits in 32 bits, like redis does.
+pub(crate) fn write_string(out: &mut Vec<u8>, s: &[u8]) {
+    let int = std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok());
+    if let Some(i) = int {
+        if i.to_string().as_bytes() == s {
+            if let Ok(i) = i8::try_from(i) {
+                out.extend_from_slice(&[0xc0 | ENC_INT8, i as u8]);
+            } else if let Ok(i) = i16::try_from(i) {
+                out.push(0xc0 | ENC_INT16);
+                out.extend_from_slice(&i.to_le_bytes());
+            } else {
+                out.push(0xc0 | ENC_INT32);
+                out.extend_from_slice(&i.to_le_bytes());
+            }
+            return;
+        }
+    }
+    write_len(out, s.len() as u64);
+    out.extend_from_slice(s);
+}
+
+/// Cursor over serialized data.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.data.len());
+        let Some(end) = end else {
+            anyhow::bail!("unexpected end of data");
+        };
+        let b = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(b)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a length, or the special encoding of a string (as `Err`).
+    fn len_or_encoding(&mut self) -> Result<Result<u64, u8>> {
+        let b = self.u8()?;
+        Ok(match b >> 6 {
+            0 => Ok((b & 0x3f) as u64),
+            1 => Ok(((b & 0x3f) as u64) << 8 | self.u8()? as u64),
+            2 if b == 0x80 => {
+                let n = self.bytes(4)?.try_into().unwrap();
+                Ok(u32::from_be_bytes(n) as u64)
+            }
+            2 if b == 0x81 => {
+                Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+            }
+            2 => anyhow::bail!("unknown length encoding {b:#x}"),
+            _ => Err(b & 0x3f),
+        })
+    }
+
+    pub fn len(&mut self) -> Result<u64> {
+        match self.len_or_encoding()? {
+            Ok(len) => Ok(len),
+            Err(_) => anyhow::bail!("expected a length, got a string"),
+        }
+    }
+
+    pub fn string(&mut self) -> Result<Vec<u8>> {
+        match self.len_or_encoding()? {
+            Ok(len) => Ok(self.bytes(len as usize)?.to_vec()),
+            Err(ENC_INT8) => Ok((self.u8()? as i8).to_string().into_bytes()),
+            Err(ENC_INT16) => {
+                let i = i16::from_le_bytes(self.bytes(2)?.try_into().unwrap());
+                Ok(i.to_string().into_bytes())
+            }
+            Err(ENC_INT32) => {
+                let i = i32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+                Ok(i.to_string().into_bytes())
+            }
+            Err(ENC_LZF) => {
+                let clen = self.len()? as usize;
+                let len = self.len()? as usize;
+                lzf_decompress(self.bytes(clen)?, len)
+            }
+            Err(enc) => anyhow::bail!("unknown string encoding {enc}"),
+        }
+    }
+
+    /// Read a value of type `t`.
+    pub fn value(&mut self, t: u8) -> Result<Value> {
+        match t {
+            TYPE_STRING => Ok(Value::string(self.string()?)),
+            TYPE_LIST => {
+                let mut l = List::default();
+                for _ in 0..self.len()? {
+                    l.push_back(&self.string()?);
+                }
+                Ok(Value::List(l))
+            }
+            TYPE_SET => {
+                let mut s = Set::default();
+                for _ in 0..self.len()? {
+                    s.insert(&self.string()?);
+                }
+                Ok(Value::Set(s))
+            }
+            TYPE_HASH => {
+                let mut h = Hash::default();
+                for _ in 0..self.len()? {
+                    let field = self.string()?;
+                    h.insert(&field, &self.string()?);
+                }
+                Ok(Value::Hash(h))
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let mut z = ZSet::default();
+                for _ in 0..self.len()? {
+                    let member = self.string()?;
+                    let score = if t == TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.bytes(8)?.try_into().unwrap())
+                    } else {
+                        self.string_score()?
+                    };
+                    if score.is_nan() {
+                        anyhow::bail!("NaN score in a sorted set");
+                    }
+                    z.insert(&member, score);
+                }
+                Ok(Value::ZSet(z))
+            }
+            t => anyhow::bail!("unsupported value type {t}"),
+        }
+    }
+
+    /// Read a score written as a string, after its length or one of the
+    /// special values for NaN and infinities.
+    fn string_score(&mut self) -> Result<f64> {
+        Ok(match self.u8()? {
+            253 => anyhow::bail!("NaN score in a sorted set"),
+            254 => f64::INFINITY,
+            255 => f64::NEG_INFINITY,
+            len => std::str::from_utf8(self.bytes(len as usize)?)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .context("invalid score")?,
+        })
+    }
+
+    /// Read a string, which must be valid UTF-8 to be stored here.
+    pub fn utf8_string(&mut self) -> Result<String> {
+        String::from_utf8(self.string()?)
+            .map_err(|_| anyhow::anyhow!("binary strings are not supported"))
+    }
+}
+
+/// Decompress LZF data (as written by liblzf) of `len` bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    let corrupt = || anyhow::anyhow!("invalid LZF data");
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 1 << 5 {
+            // literal run
+            let lit = input.get(i..i + ctrl + 1).ok_or_else(corrupt)?;
+            out.extend_from_slice(lit);
+            i += ctrl + 1;
+        } else {
+            // back reference
+            let mut n = ctrl >> 5;
+            if n == 7 {
+                n += *input.get(i).ok_or_else(corrupt)? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(corrupt)? as usize;
+            i += 1;
+            let back = ((ctrl & 0x1f) << 8 | low) + 1;
+            let start = out.len().checked_sub(back).ok_or_else(corrupt)?;
+            // the reference may overlap with what it produces
+            for j in 0..n + 2 {
+                out.push(out[start + j]);
+            }
+        }
+    }
+    if out.len() != len {
+        return Err(corrupt());
+    }
+    Ok(out)
+}
+
+/// CRC-64 with the Jones polynomial, like redis uses for RDB files and
+/// `DUMP` payloads.
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const TABLE: [u64; 256] = {
+        // reflected 0xad93d23594c935a9
+        const POLY: u64 = 0x95ac9329ac4bc9b5;
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u64;
+            let mut j = 0;
+            while j < 8 {
+                c = if c & 1 == 1 { c >> 1 ^ POLY } else { c >> 1 };
+                j += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    for &b in data {
+        crc = TABLE[((crc ^ b as u64) & 0xff) as usize] ^ crc >> 8;
+    }
+    crc
+}
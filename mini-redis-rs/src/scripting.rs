@@ -0,0 +1,487 @@
+//! Lua scripting: `EVAL`, `EVALSHA` and `SCRIPT`.
+//!
+//! Scripts run in a Lua 5.1 interpreter, like in redis, and call back into
+//! the server with `redis.call` and `redis.pcall`, which go through the
+//! same command table as regular clients. A script runs synchronously on
+//! the client's thread, so no other command can be executed while it runs.
+//!
+//! Values are converted as in redis, except that this server doesn't
+//! distinguish status replies from bulk strings: both become Lua strings.
+//! In the other direction, a table with an `ok` field still becomes a
+//! (bulk) string reply.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use mlua::{
+    Function, Lua, LuaOptions, RegistryKey, StdLib, Table, Value, Variadic,
+};
+
+use crate::{
+    server::{State, NOT_UTF8},
+    wire::Frame,
+};
+
+/// Callback used by scripts to execute a command (including its name) and
+/// get its reply.
+pub type Call<'c> =
+    dyn for<'b> FnMut(&[&'b [u8]], &'b bumpalo::Bump) -> Frame<'b> + 'c;
+
+/// Error reply from a command called with `redis.call`, passed through to
+/// the script's caller.
+#[derive(Debug)]
+struct CommandError(String);
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Source of the scripts loaded so far, by SHA1.
+#[derive(Debug, Default)]
+pub struct Scripts {
+    scripts: Mutex<HashMap<String, Arc<str>>>,
+}
+
+impl Scripts {
+    pub fn len(&self) -> usize {
+        self.scripts.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.lock().unwrap().is_empty()
+    }
+
+    /// Remember `src` and return its SHA1.
+    pub fn insert(&self, src: &str) -> String {
+        let sha = sha1_hex(src);
+        self.scripts
+            .lock()
+            .unwrap()
+            .entry(sha.clone())
+            .or_insert_with(|| src.into());
+        sha
+    }
+
+    /// Source of the script with the given (lowercase) SHA1.
+    pub fn get(&self, sha: &str) -> Option<Arc<str>> {
+        self.scripts.lock().unwrap().get(sha).cloned()
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.scripts.lock().unwrap().contains_key(sha)
+    }
+
+    pub fn clear(&self) {
+        self.scripts.lock().unwrap().clear();
+    }
+}
+
+/// Hex-encoded SHA1 of `s`, as used to identify scripts.
+pub fn sha1_hex(s: &str) -> String {
+    sha1_smol::Sha1::from(s).digest().to_string()
+}
+
+/// Lua interpreter, along with the scripts it compiled.
+struct Interpreter {
+    lua: Lua,
+    /// Compiled scripts, by SHA1.
+    functions: RefCell<HashMap<String, RegistryKey>>,
+}
+
+thread_local! {
+    static INTERPRETER: Interpreter = Interpreter::new();
+}
+
+impl Interpreter {
+    fn new() -> Self {
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+            LuaOptions::new(),
+        )
+        .expect("creating Lua interpreter");
+        init_globals(&lua).expect("initializing Lua interpreter");
+        Self {
+            lua,
+            functions: Default::default(),
+        }
+    }
+
+    /// Compile script `src` unless it was already compiled.
+    fn compile(&self, sha: &str, src: &str) -> Result<(), String> {
+        if self.functions.borrow().contains_key(sha) {
+            return Ok(());
+        }
+        let f = self
+            .lua
+            .load(src)
+            .set_name("@user_script")
+            .into_function()
+            .map_err(|e| {
+                format!(
+                    "ERR Error compiling script (new function): {}",
+                    error_message(&e)
+                )
+            })?;
+        let key = self.lua.create_registry_value(f).map_err(|e| {
+            format!("ERR Error compiling script: {}", error_message(&e))
+        })?;
+        self.functions.borrow_mut().insert(sha.to_string(), key);
+        Ok(())
+    }
+
+    /// Run compiled script `sha`, using `call` to execute commands.
+    fn run<'are>(
+        &self,
+        sha: &str,
+        keys: &[&str],
+        argv: &[&[u8]],
+        arena: &'are bumpalo::Bump,
+        call: &mut Call,
+    ) -> Frame<'are> {
+        let lua = &self.lua;
+        let call = RefCell::new(call);
+        let res = lua.scope(|scope| {
+            let f: Function = {
+                let functions = self.functions.borrow();
+                lua.registry_value(&functions[sha])?
+            };
+            let redis: Table = lua.globals().get("redis")?;
+            redis.set(
+                "call",
+                scope.create_function_mut(|lua, args: Variadic<Value>| {
+                    match redis_call(lua, &mut **call.borrow_mut(), &args) {
+                        Ok(v) => Ok(v),
+                        Err(e) => Err(mlua::Error::external(CommandError(e))),
+                    }
+                })?,
+            )?;
+            redis.set(
+                "pcall",
+                scope.create_function_mut(|lua, args: Variadic<Value>| {
+                    match redis_call(lua, &mut **call.borrow_mut(), &args) {
+                        Ok(v) => Ok(v),
+                        Err(e) => error_table(lua, &e),
+                    }
+                })?,
+            )?;
+            lua.globals().set("KEYS", keys.to_vec())?;
+            let argv = argv
+                .iter()
+                .map(|a| lua.create_string(a))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            lua.globals().set("ARGV", argv)?;
+
+            let v: Value = f.call(())?;
+            Ok(to_frame(&v, arena))
+        });
+
+        match res {
+            Ok(frame) => frame,
+            Err(e) => {
+                let msg = match innermost(&e) {
+                    // errors from `redis.call` are passed through
+                    mlua::Error::ExternalError(e)
+                        if e.downcast_ref::<CommandError>().is_some() =>
+                    {
+                        e.to_string()
+                    }
+                    e => format!(
+                        "ERR Error running script (call to f_{sha}): {}",
+                        error_message(e)
+                    ),
+                };
+                Frame::Error(arena.alloc_str(&msg))
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.functions.borrow_mut().clear();
+        self.lua.expire_registry_values();
+    }
+}
+
+/// Set up the `redis` table and remove unsafe globals.
+fn init_globals(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for name in ["loadfile", "dofile"] {
+        globals.set(name, Value::Nil)?;
+    }
+
+    let redis = lua.create_table()?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, msg: String| error_table(lua, &msg))?,
+    )?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, msg: String| {
+            let t = lua.create_table()?;
+            t.set("ok", msg)?;
+            Ok(Value::Table(t))
+        })?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, s: String| Ok(sha1_hex(&s)))?,
+    )?;
+    redis.set(
+        "log",
+        lua.create_function(|_, (level, msg): (i64, String)| {
+            let level = match level {
+                0 => log::Level::Debug,
+                1 => log::Level::Info,
+                2 => log::Level::Info,
+                _ => log::Level::Warn,
+            };
+            log::log!(level, "script: {msg}");
+            Ok(())
+        })?,
+    )?;
+    for (i, name) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .iter()
+        .enumerate()
+    {
+        redis.set(*name, i)?;
+    }
+    globals.set("redis", redis)?;
+    Ok(())
+}
+
+/// `{err = msg}`, the Lua representation of an error reply.
+fn error_table<'lua>(lua: &'lua Lua, msg: &str) -> mlua::Result<Value<'lua>> {
+    let t = lua.create_table()?;
+    t.set("err", msg)?;
+    Ok(Value::Table(t))
+}
+
+/// Execute the command in `args` for `redis.call` or `redis.pcall`, and
+/// return its reply or error message.
+fn redis_call<'lua>(
+    lua: &'lua Lua,
+    call: &mut Call,
+    args: &[Value],
+) -> Result<Value<'lua>, String> {
+    if args.is_empty() {
+        return Err("ERR Please specify at least one argument for this \
+            redis lib call"
+            .to_string());
+    }
+    let arena = bumpalo::Bump::new();
+    let mut raw = Vec::with_capacity(args.len());
+    for a in args {
+        let s = match a {
+            Value::String(s) => s.as_bytes().to_vec(),
+            Value::Integer(i) => i.to_string().into_bytes(),
+            Value::Number(n) if n.fract() == 0.0 => {
+                (*n as i64).to_string().into_bytes()
+            }
+            Value::Number(n) => n.to_string().into_bytes(),
+            _ => {
+                return Err("ERR Lua redis lib command arguments must be \
+                    strings or integers"
+                    .to_string())
+            }
+        };
+        raw.push(&*arena.alloc_slice_copy(&s));
+    }
+
+    match call(&raw, &arena) {
+        Frame::Error(e) => Err(e.to_string()),
+        reply => to_lua(lua, &reply).map_err(|e| error_message(&e)),
+    }
+}
+
+/// Convert a reply to a Lua value.
+fn to_lua<'lua>(lua: &'lua Lua, frame: &Frame) -> mlua::Result<Value<'lua>> {
+    Ok(match frame {
+        Frame::String(s) => Value::String(lua.create_string(s)?),
+        Frame::Bytes(b) => Value::String(lua.create_string(b)?),
+        Frame::Int(i) => Value::Integer(*i as i64),
+        Frame::Bulk(items) => {
+            let t = lua.create_table_with_capacity(items.len(), 0)?;
+            for (i, item) in items.iter().enumerate() {
+                t.raw_set(i + 1, to_lua(lua, item)?)?;
+            }
+            Value::Table(t)
+        }
+        Frame::Error(e) => error_table(lua, e)?,
+        Frame::Null => Value::Boolean(false),
+    })
+}
+
+/// Convert a value returned by a script to a reply.
+fn to_frame<'are>(v: &Value, arena: &'are bumpalo::Bump) -> Frame<'are> {
+    match v {
+        Value::Boolean(true) => Frame::Int(1),
+        Value::Integer(i) => Frame::Int(*i as isize),
+        Value::Number(n) => Frame::Int(*n as isize),
+        Value::String(s) => match s.to_str() {
+            Ok(s) => Frame::String(arena.alloc_str(s)),
+            Err(_) => Frame::Bytes(arena.alloc_slice_copy(s.as_bytes())),
+        },
+        Value::Table(t) => {
+            // line breaks in the message are replaced when it is encoded
+            if let Ok(Value::String(e)) = t.raw_get("err") {
+                return Frame::Error(arena.alloc_str(&e.to_string_lossy()));
+            }
+            if let Ok(Value::String(s)) = t.raw_get("ok") {
+                return Frame::String(arena.alloc_str(&s.to_string_lossy()));
+            }
+            // like redis, stop at the first nil
+            let mut items = vec![];
+            for i in 1.. {
+                match t.raw_get::<_, Value>(i) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(v) => items.push(to_frame(&v, arena)),
+                }
+            }
+            Frame::Bulk(arena.alloc_slice_copy(&items))
+        }
+        _ => Frame::Null,
+    }
+}
+
+/// The error that caused `e`, skipping callback wrappers.
+fn innermost(e: &mlua::Error) -> &mlua::Error {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => innermost(cause),
+        e => e,
+    }
+}
+
+/// Message of a Lua error, without traceback, on a single line so that it
+/// can be sent as an error reply.
+fn error_message(e: &mlua::Error) -> String {
+    let msg = match innermost(e) {
+        mlua::Error::RuntimeError(msg)
+        | mlua::Error::SyntaxError { message: msg, .. } => msg.clone(),
+        e => e.to_string(),
+    };
+    let msg = match msg.find("\nstack traceback:") {
+        Some(i) => &msg[..i],
+        None => &msg,
+    };
+    msg.replace(['\r', '\n'], " ")
+}
+
+/// Parse the `numkeys key [key ...] arg [arg ...]` part of `EVAL`.
+fn split_keys<'a, T>(
+    numkeys: &str,
+    rest: &'a [T],
+) -> Result<(&'a [T], &'a [T]), &'static str> {
+    match numkeys.parse::<i64>() {
+        Ok(n) if n < 0 => Err("ERR Number of keys can't be negative"),
+        Ok(n) if n as usize > rest.len() => {
+            Err("ERR Number of keys can't be greater than number of args")
+        }
+        Ok(n) => Ok(rest.split_at(n as usize)),
+        Err(_) => Err("ERR value is not an integer or out of range"),
+    }
+}
+
+/// Execute `EVAL`, `EVALSHA` or `SCRIPT`. `args` includes the command
+/// name, `raw` are the same arguments as bytes, for `ARGV`, and `call`
+/// executes the commands issued by the script.
+pub fn exec<'are>(
+    st: &State,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+    call: &mut Call,
+) -> Frame<'are> {
+    let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+
+    match (cmd, &args[1..]) {
+        ("eval" | "evalsha", [script, numkeys, ..]) => {
+            let (keys, argv) = match split_keys(numkeys, &raw[3..]) {
+                Ok(v) => v,
+                Err(e) => return Frame::Error(e),
+            };
+            let Ok(keys) = keys
+                .iter()
+                .map(|k| std::str::from_utf8(k))
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return Frame::Error(NOT_UTF8);
+            };
+            let (sha, src) = if cmd == "eval" {
+                (st.scripts.insert(script), Arc::from(*script))
+            } else {
+                let sha = script.to_ascii_lowercase();
+                match st.scripts.get(&sha) {
+                    Some(src) => (sha, src),
+                    None => {
+                        return Frame::Error(
+                            "NOSCRIPT No matching script. Please use EVAL.",
+                        )
+                    }
+                }
+            };
+            INTERPRETER.with(|interp| {
+                if let Err(e) = interp.compile(&sha, &src) {
+                    return err(e);
+                }
+                interp.run(&sha, &keys, argv, arena, call)
+            })
+        }
+        ("script", [sub, rest @ ..]) => {
+            match (&*sub.to_ascii_lowercase(), rest) {
+                ("load", &[src]) => {
+                    let sha = sha1_hex(src);
+                    let res = INTERPRETER.with(|i| i.compile(&sha, src));
+                    match res {
+                        Ok(()) => {
+                            st.scripts.insert(src);
+                            Frame::String(arena.alloc_str(&sha))
+                        }
+                        Err(e) => err(e),
+                    }
+                }
+                ("exists", shas) if !shas.is_empty() => {
+                    let found: Vec<Frame> = shas
+                        .iter()
+                        .map(|sha| {
+                            let sha = sha.to_ascii_lowercase();
+                            Frame::Int(st.scripts.contains(&sha) as isize)
+                        })
+                        .collect();
+                    Frame::Bulk(arena.alloc_slice_copy(&found))
+                }
+                ("flush", [] | [_]) => {
+                    if let Some(mode) = rest.first() {
+                        if !mode.eq_ignore_ascii_case("sync")
+                            && !mode.eq_ignore_ascii_case("async")
+                        {
+                            return Frame::Error("ERR syntax error");
+                        }
+                    }
+                    st.scripts.clear();
+                    INTERPRETER.with(|i| i.flush());
+                    Frame::String("OK")
+                }
+                ("help", []) => {
+                    let lines: &[Frame] = &[
+                        Frame::String("SCRIPT LOAD <script>"),
+                        Frame::String("SCRIPT EXISTS <sha1> [<sha1> ...]"),
+                        Frame::String("SCRIPT FLUSH [ASYNC|SYNC]"),
+                    ];
+                    Frame::Bulk(arena.alloc_slice_copy(lines))
+                }
+                ("load" | "exists" | "flush" | "help", _) => err(format!(
+                    "ERR wrong number of arguments for 'script|{sub}' command"
+                )),
+                _ => err(format!("ERR unknown subcommand '{sub}'")),
+            }
+        }
+        _ => err(crate::server::wrong_arity(cmd)),
+    }
+}
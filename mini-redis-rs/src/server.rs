@@ -1,29 +1,362 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use crate::wire::{self, Conn, Frame};
-use anyhow::Result;
-use dashmap::DashMap;
-use tokio::net::TcpStream;
+#[cfg(feature = "lua")]
+use crate::scripting;
+use crate::{
+    audit::AuditLog,
+    bitops,
+    clients::{ClientInfo, Clients},
+    commands::{self, Command, COMMANDS},
+    config::Config,
+    db::{now_ms, Db, Entry, Value},
+    evict::Evictor,
+    geo, glob, hash, hyperloglog, info, keyspace, list, metrics, migrate,
+    monitor::{self, Monitors},
+    notify,
+    pubsub::{self, PubSub},
+    rdb, set,
+    stats::{self, SlowLog, Stats},
+    tracking::{self, Tracking},
+    wire::{self, Conn, Frame},
+    zset,
+};
+use anyhow::{Context, Result};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+    task::{JoinSet, LocalSet},
+};
+
+/// How often [`expire_loop`] runs.
+const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Maximum number of samples per db and per run of [`expire_loop`].
+const ACTIVE_EXPIRE_MAX_ROUNDS: usize = 16;
+
+/// How often [`save_loop`] checks the `save` rules.
+const SAVE_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// Seconds to wait after a failed snapshot before trying again, like
+/// redis.
+const SAVE_RETRY_DELAY: u64 = 5;
+
+/// How often [`clients_cron`] runs.
+const CLIENTS_CRON_PERIOD: Duration = Duration::from_secs(1);
+
+/// How long to wait for a client to close its side of the connection
+/// after we closed ours, when shutting down.
+const CLOSE_LINGER: Duration = Duration::from_millis(100);
+
+/// What to do with the data when shutting down, from `SHUTDOWN
+/// [NOSAVE|SAVE]`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ShutdownMode {
+    /// Save a snapshot if snapshots are configured with `save`.
+    Default,
+    Save,
+    NoSave,
+}
 
 /// Main state for the database.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct State {
-    kv: DashMap<String, String>,
+    /// Logical databases, selected with `SELECT`.
+    dbs: RwLock<Vec<Arc<Db>>>,
+    pub(crate) evictor: Evictor,
+    pub(crate) config: RwLock<Config>,
+    /// File the configuration was loaded from, used by `CONFIG REWRITE`.
+    pub(crate) config_file: Option<PathBuf>,
+    pub(crate) clients: Clients,
+    pub(crate) stats: Stats,
+    pub(crate) slowlog: SlowLog,
+    pub(crate) pubsub: PubSub,
+    /// Keys read by clients with `CLIENT TRACKING` enabled.
+    pub(crate) tracking: Tracking,
+    /// Clients that called `MONITOR`.
+    pub(crate) monitors: Monitors,
+    pub(crate) audit: AuditLog,
+    /// Scripts loaded with `EVAL` or `SCRIPT LOAD`.
+    #[cfg(feature = "lua")]
+    pub(crate) scripts: scripting::Scripts,
+    /// Set once the server starts shutting down.
+    shutdown: watch::Sender<Option<ShutdownMode>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new(Config::default(), None)
+    }
+}
+
+impl State {
+    pub fn new(config: Config, config_file: Option<PathBuf>) -> Self {
+        let dbs = (0..config.databases.max(1))
+            .map(|_| Arc::new(Db::default()))
+            .collect();
+        Self {
+            dbs: RwLock::new(dbs),
+            evictor: Default::default(),
+            config: RwLock::new(config),
+            config_file,
+            clients: Default::default(),
+            stats: Default::default(),
+            slowlog: Default::default(),
+            pubsub: Default::default(),
+            tracking: Default::default(),
+            monitors: Default::default(),
+            audit: Default::default(),
+            #[cfg(feature = "lua")]
+            scripts: Default::default(),
+            shutdown: watch::channel(None).0,
+        }
+    }
+
+    /// Number of logical databases.
+    pub fn n_dbs(&self) -> usize {
+        self.dbs.read().unwrap().len()
+    }
+
+    /// Database number `i`.
+    ///
+    /// Panics if `i` is out of range.
+    pub fn db(&self, i: usize) -> Arc<Db> {
+        self.dbs.read().unwrap()[i].clone()
+    }
+
+    /// All the databases, by index.
+    pub fn dbs(&self) -> Vec<Arc<Db>> {
+        self.dbs.read().unwrap().clone()
+    }
+
+    /// Exchange databases `i` and `j`, for `SWAPDB`.
+    pub fn swap_dbs(&self, i: usize, j: usize) {
+        self.dbs.write().unwrap().swap(i, j);
+        self.evictor.clear_pool();
+    }
+
+    /// Approximate memory used by all databases.
+    pub fn used_memory(&self) -> usize {
+        self.dbs().iter().map(|db| db.used_memory()).sum()
+    }
+
+    /// Number of keys that expired, in all databases.
+    pub fn expired_keys(&self) -> u64 {
+        self.dbs().iter().map(|db| db.expired_keys()).sum()
+    }
+
+    /// Access the current configuration.
+    pub fn config(&self) -> std::sync::RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap()
+    }
+
+    /// Number of currently connected clients.
+    pub fn n_clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Start shutting down: stop accepting clients, and close the
+    /// connected ones once they are done with their current command.
+    ///
+    /// Only the first call has an effect.
+    pub fn shutdown(&self, mode: ShutdownMode) {
+        self.shutdown.send_if_modified(|m| {
+            let first = m.is_none();
+            m.get_or_insert(mode);
+            first
+        });
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.borrow().is_some()
+    }
+
+    /// Wait until [`State::shutdown`] is called, and return its mode.
+    pub async fn shutdown_requested(&self) -> ShutdownMode {
+        let mut rx = self.shutdown.subscribe();
+        loop {
+            let mode = *rx.borrow();
+            if let Some(mode) = mode {
+                return mode;
+            }
+            // the sender lives as long as `self`
+            let _ = rx.changed().await;
+        }
+    }
+
+    /// Evict keys if needed to get below `maxmemory`. Returns `false` if
+    /// memory is full and nothing can be evicted.
+    fn free_memory(&self) -> bool {
+        let mut evicted = vec![];
+        let ok = {
+            let config = self.config();
+            config.maxmemory == 0
+                || self.evictor.free_memory(&self.dbs(), &config, |db, k| {
+                    evicted.push((db, k.to_string()))
+                })
+        };
+        for (db, k) in evicted {
+            self.notify(notify::EVICTED, "evicted", &k, db);
+            self.tracking.invalidate(&self.clients, &k, None);
+        }
+        ok
+    }
+
+    /// Publish a keyspace notification for `event` on `key` in database
+    /// `db`, if events of class `class` are enabled.
+    pub fn notify(&self, class: u32, event: &str, key: &str, db: usize) {
+        let flags = self.config().notify_keyspace_events;
+        if flags.keyspace(class) {
+            let channel = format!("__keyspace@{db}__:{key}");
+            self.pubsub.publish(&channel, event.as_bytes());
+        }
+        if flags.keyevent(class) {
+            let channel = format!("__keyevent@{db}__:{event}");
+            self.pubsub.publish(&channel, key.as_bytes());
+        }
+    }
+
+    /// Publish notifications for the keys that expired since the last
+    /// call.
+    pub fn notify_expired(&self) {
+        for (i, db) in self.dbs().iter().enumerate() {
+            for k in db.take_expired() {
+                self.notify(notify::EXPIRED, "expired", &k, i);
+                self.tracking.invalidate(&self.clients, &k, None);
+            }
+        }
+    }
 }
 
 /// Handler for a given client.
 pub struct ClientHandler<'a> {
     addr: SocketAddr,
     conn: Conn<'a>,
+    info: Arc<ClientInfo>,
+    /// Index of the database selected with `SELECT`.
+    db: usize,
+    /// Messages pushed to this client, see [`ClientInfo::tx`].
+    rx: mpsc::UnboundedReceiver<pubsub::Message>,
+    /// Channels subscribed with `SUBSCRIBE`.
+    channels: HashSet<String>,
+    /// Patterns subscribed with `PSUBSCRIBE`.
+    patterns: HashSet<String>,
+    /// Options of `CLIENT TRACKING`, if enabled.
+    tracking: Option<tracking::Options>,
+    /// Argument of `CLIENT CACHING`, which only applies to the next
+    /// command.
+    caching: Option<bool>,
+    /// Set by `MONITOR`.
+    monitor: bool,
+    /// Set while a script runs, so that `MONITOR` and the audit log show
+    /// its commands as coming from `lua`.
+    in_script: bool,
+    /// Set when the client called `SHUTDOWN`.
+    shutdown_called: bool,
+}
+
+/// Error reply for a command applied to a key of another type.
+pub(crate) const WRONG_TYPE: &str =
+    "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Error reply for a command called with the wrong number of arguments.
+pub(crate) fn wrong_arity(cmd: &str) -> String {
+    format!("ERR wrong number of arguments for '{cmd}' command")
+}
+
+/// Parse a database index, as given to `SELECT`, `MOVE` or `SWAPDB`.
+pub(crate) fn parse_db_index(
+    st: &State,
+    index: &str,
+) -> Result<usize, &'static str> {
+    match index.parse::<i64>() {
+        Ok(i) if i >= 0 && (i as usize) < st.n_dbs() => Ok(i as usize),
+        Ok(_) => Err("ERR DB index is out of range"),
+        Err(_) => Err("ERR value is not an integer or out of range"),
+    }
+}
+
+/// Is `cmd` one of the commands that are allowed while subscribed, and
+/// send one reply per channel?
+fn is_subscribe_cmd(cmd: &str) -> bool {
+    ["subscribe", "unsubscribe", "psubscribe", "punsubscribe"]
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(cmd))
+}
+
+/// Reply to an argument that must be valid UTF-8 but isn't.
+pub(crate) const NOT_UTF8: &str = "ERR invalid argument: must be valid UTF-8";
+
+/// Can argument `i` of `cmd` (0 being the command name), out of `argc`,
+/// be binary? Values can, like in redis. Keys can't, nor the arguments
+/// that are kept or matched as strings: channels, patterns, parameters,
+/// client names and scripts.
+fn is_binary_arg(cmd: &Command, argc: usize, i: usize) -> bool {
+    if i == 0 || cmd.key_positions(argc).any(|k| k == i) {
+        return false;
+    }
+    match cmd.name {
+        "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe"
+        | "pubsub" | "config" | "client" | "keys" | "scan" | "script"
+        | "migrate" => false,
+        // the channel, and the script
+        "publish" | "eval" => i != 1,
+        _ => true,
+    }
+}
+
+/// The arguments of a command as strings, given as bytes in `raw`. A
+/// binary argument is replaced by its lossy conversion: commands read it
+/// from `raw` instead. Fails if an argument that must be a string (see
+/// [`is_binary_arg`]) is not valid UTF-8.
+fn parse_args<'are>(
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Result<Vec<&'are str>, &'static str> {
+    // anything goes for unknown commands, they are rejected anyway
+    let cmd = std::str::from_utf8(raw[0]).ok().and_then(commands::lookup);
+    raw.iter()
+        .enumerate()
+        .map(|(i, a)| match std::str::from_utf8(a) {
+            Ok(s) => Ok(s),
+            Err(_) if cmd.is_none_or(|c| is_binary_arg(c, raw.len(), i)) => {
+                Ok(&*arena.alloc_str(&String::from_utf8_lossy(a)))
+            }
+            Err(_) => Err(NOT_UTF8),
+        })
+        .collect()
 }
 
 impl<'a> ClientHandler<'a> {
     pub fn new_from_conn(conn: Conn<'a>) -> Self {
         let addr = conn.addr();
-        Self { conn, addr }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let info = Arc::new(ClientInfo::new(addr, tx));
+        Self {
+            conn,
+            addr,
+            info,
+            db: 0,
+            rx,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            tracking: None,
+            caching: None,
+            monitor: false,
+            in_script: false,
+            shutdown_called: false,
+        }
     }
 
     pub fn new(sock: &'a mut TcpStream, addr: SocketAddr) -> Self {
+        // replies to pipelined requests are written one by one
+        let _ = sock.set_nodelay(true);
         let conn = Conn::new(sock, addr);
         Self::new_from_conn(conn)
     }
@@ -32,11 +365,92 @@ impl<'a> ClientHandler<'a> {
     ///
     /// The state is stored in `st`.
     pub async fn serve(&mut self, st: Arc<State>) -> Result<()> {
+        let addr = self.addr;
+        st.clients.register(self.info.clone());
+        let mut res = self.serve_loop(&st).await;
+        if self.info.output_buffer_limit_reached() {
+            log::warn!(
+                "client {} closed for overcoming of output buffer limits",
+                self.info.describe()
+            );
+            stats::incr(&st.stats.client_output_buffer_limit_disconnections);
+        } else if res.is_ok() && st.is_shutting_down() {
+            res = wire::close(&mut self.conn, CLOSE_LINGER).await;
+        }
+        for c in self.channels.drain() {
+            st.pubsub.unsubscribe(&c, self.info.id);
+        }
+        for p in self.patterns.drain() {
+            st.pubsub.punsubscribe(&p, self.info.id);
+        }
+        st.tracking.disable(self.info.id);
+        st.monitors.remove(self.info.id);
+        st.clients.unregister(self.info.id);
+        log::info!("done serving client {addr:?}");
+        res
+    }
+
+    async fn serve_loop(&mut self, st: &State) -> Result<()> {
         let addr = self.addr;
         let mut arena = bumpalo::Bump::new();
+        let mut shutdown = st.shutdown.subscribe();
 
-        loop {
-            let msg = match wire::read_frame(&mut self.conn, &arena).await {
+        // commands that were not read yet when shutting down are dropped
+        while !st.is_shutting_down() {
+            // subscribers and monitors are never considered idle
+            let timeout = match st.config().timeout {
+                t if t > 0 && !self.is_subscribed() && !self.monitor => Some(t),
+                _ => None,
+            };
+            let readable = async {
+                let readable = wire::wait_readable(&mut self.conn);
+                match timeout {
+                    Some(t) => {
+                        tokio::time::timeout(Duration::from_secs(t), readable)
+                            .await
+                            .ok()
+                    }
+                    None => Some(readable.await),
+                }
+            };
+            tokio::select! {
+                res = readable => match res {
+                    Some(Ok(true)) => (),
+                    Some(Ok(false)) => break,
+                    Some(Err(e)) => return Err(e),
+                    None => {
+                        log::info!("closing idle client {addr:?}");
+                        stats::incr(
+                            &st.stats.client_idle_timeout_disconnections,
+                        );
+                        break;
+                    }
+                },
+                Some(msg) = self.rx.recv() => {
+                    let written = self.write_message(st, &msg, &arena).await?;
+                    self.info.shrink_output(msg.size());
+                    arena.reset();
+                    if !written {
+                        break;
+                    }
+                    continue;
+                }
+                _ = self.info.killed.notified() => {
+                    log::info!("client {addr:?} was killed");
+                    break;
+                }
+                _ = shutdown.changed() => break,
+            }
+
+            let res = tokio::select! {
+                res = wire::read_frame(&mut self.conn, &arena) => res,
+                _ = self.info.killed.notified() => {
+                    log::info!("client {addr:?} was killed");
+                    break;
+                }
+            };
+
+            let msg = match res {
                 Ok(Some(msg)) => msg,
                 Ok(None) => break,
                 Err(e) => {
@@ -46,56 +460,1431 @@ impl<'a> ClientHandler<'a> {
             };
             log::debug!("got msg {msg:#?} from {addr:?}");
 
-            match msg {
-                Frame::Bulk(&[Frame::String("get"), Frame::String(k)]) => {
-                    log::debug!("get {k:?}");
-                    match st.kv.get(k) {
-                        Some(v) => {
-                            log::trace!("get: reply with {v:?}");
-                            let v = &*v;
-                            wire::write_frame(
-                                &mut self.conn,
-                                &Frame::String(v),
-                            )
-                            .await?;
-                        }
-                        None => {
-                            wire::write_frame(
-                                &mut self.conn,
-                                &Frame::String("-not found"),
-                            )
-                            .await?;
+            let (reply, per_channel) = match msg {
+                Frame::Bulk(args) if !args.is_empty() => {
+                    let raw: Option<Vec<&[u8]>> = args
+                        .iter()
+                        .map(|a| match *a {
+                            Frame::String(s) => Some(s.as_bytes()),
+                            Frame::Bytes(b) => Some(b),
+                            _ => None,
+                        })
+                        .collect();
+                    match raw.map(|raw| (parse_args(&raw, &arena), raw)) {
+                        Some((Ok(args), raw)) => (
+                            self.exec(st, &args, &raw, &arena),
+                            is_subscribe_cmd(args[0]),
+                        ),
+                        Some((Err(e), _)) => (Frame::Error(e), false),
+                        None => (
+                            Frame::Error(
+                                "ERR Protocol error: expected bulk strings",
+                            ),
+                            false,
+                        ),
+                    }
+                }
+                _ => {
+                    let msg = format!("unknown command {msg:?}");
+                    (Frame::Error(arena.alloc_str(&msg)), false)
+                }
+            };
+            // like in redis, a successful `SHUTDOWN` gets no reply
+            if self.shutdown_called {
+                break;
+            }
+            let replies = match reply {
+                // one reply per channel or pattern
+                Frame::Bulk(replies) if per_channel => replies,
+                _ => std::slice::from_ref(&reply),
+            };
+            if !self.write_replies(st, replies).await? {
+                break;
+            }
+
+            arena.reset();
+        }
+
+        Ok(())
+    }
+
+    /// Write the replies to a command, counting them in the output buffer
+    /// while they are being written. Returns `false` if the client must be
+    /// closed instead.
+    async fn write_replies(
+        &mut self,
+        st: &State,
+        replies: &[Frame<'_>],
+    ) -> Result<bool> {
+        let len = replies.iter().map(wire::encoded_len).sum();
+        self.info.grow_output(len);
+        let limit = st
+            .config()
+            .client_output_buffer_limit
+            .get(self.info.class());
+        let mut ok = self.info.check_output_buffer(limit, now_ms());
+        for r in replies {
+            if !ok {
+                break;
+            }
+            ok = self.write_frame(st, r).await?;
+        }
+        self.info.shrink_output(len);
+        Ok(ok)
+    }
+
+    /// Write a frame. While waiting for the client to read, give up if it
+    /// is killed or its output buffer goes over the limit, and return
+    /// `false`.
+    async fn write_frame(
+        &mut self,
+        st: &State,
+        frame: &Frame<'_>,
+    ) -> Result<bool> {
+        let write = wire::write_frame(&mut self.conn, frame);
+        tokio::pin!(write);
+        loop {
+            tokio::select! {
+                res = &mut write => return res.map(|()| true),
+                _ = self.info.killed.notified() => return Ok(false),
+                _ = self.info.obuf_grew.notified() => {
+                    let limit = st
+                        .config()
+                        .client_output_buffer_limit
+                        .get(self.info.class());
+                    if !self.info.check_output_buffer(limit, now_ms()) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Is the client subscribed to any channel or pattern?
+    fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty()
+    }
+
+    /// Write a message pushed by another client. Returns `false` if the
+    /// client must be closed instead, see [`Self::write_frame`].
+    async fn write_message(
+        &mut self,
+        st: &State,
+        msg: &pubsub::Message,
+        arena: &bumpalo::Bump,
+    ) -> Result<bool> {
+        let frame = match msg {
+            pubsub::Message::Monitor(line) => {
+                let line = Frame::String(arena.alloc_str(line));
+                return self.write_frame(st, &line).await;
+            }
+            pubsub::Message::Publish {
+                pattern: None,
+                channel,
+                payload,
+            } => arena.alloc_slice_copy(&[
+                Frame::String("message"),
+                Frame::String(arena.alloc_str(channel)),
+                Frame::Bytes(arena.alloc_slice_copy(payload)),
+            ]),
+            pubsub::Message::Publish {
+                pattern: Some(pattern),
+                channel,
+                payload,
+            } => arena.alloc_slice_copy(&[
+                Frame::String("pmessage"),
+                Frame::String(arena.alloc_str(pattern)),
+                Frame::String(arena.alloc_str(channel)),
+                Frame::Bytes(arena.alloc_slice_copy(payload)),
+            ]),
+            // without RESP3 push messages, invalidations can only be sent
+            // to clients in pub/sub mode, like in redis
+            pubsub::Message::Invalidate(_) if !self.is_subscribed() => {
+                return Ok(true)
+            }
+            pubsub::Message::Invalidate(keys) => {
+                let keys = match keys {
+                    Some(keys) => {
+                        let keys: Vec<Frame> = keys
+                            .iter()
+                            .map(|k| Frame::String(arena.alloc_str(k)))
+                            .collect();
+                        Frame::Bulk(arena.alloc_slice_copy(&keys))
+                    }
+                    None => Frame::Null,
+                };
+                arena.alloc_slice_copy(&[
+                    Frame::String("message"),
+                    Frame::String(tracking::INVALIDATE_CHANNEL),
+                    keys,
+                ])
+            }
+        };
+        self.write_frame(st, &Frame::Bulk(frame)).await
+    }
+
+    /// Reply to a (un)subscription: the kind of subscription, the channel
+    /// or pattern, and the number of subscriptions left.
+    fn subscription_reply<'are>(
+        &self,
+        kind: &'static str,
+        name: Option<&str>,
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let n = self.channels.len() + self.patterns.len();
+        let name = match name {
+            Some(name) => Frame::String(arena.alloc_str(name)),
+            None => Frame::Null,
+        };
+        let fields = [Frame::String(kind), name, Frame::Int(n as isize)];
+        Frame::Bulk(arena.alloc_slice_copy(&fields))
+    }
+
+    /// Execute a single command and return its reply, keeping statistics
+    /// along the way. `raw` are the same arguments as bytes, for the ones
+    /// that may not be valid UTF-8 (see [`parse_args`]).
+    fn exec<'are>(
+        &mut self,
+        st: &State,
+        args: &[&'are str],
+        raw: &[&'are [u8]],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let Some(cmd) = commands::lookup(args[0]) else {
+            let mut msg = format!(
+                "ERR unknown command '{}', with args beginning with:",
+                args[0]
+            );
+            for a in &args[1..] {
+                msg.push_str(&format!(" '{a}'"));
+            }
+            return Frame::Error(arena.alloc_str(&msg));
+        };
+        self.info.touch(cmd.name);
+
+        if self.is_subscribed() && !is_subscribe_cmd(cmd.name) {
+            st.stats.record_rejected(cmd.name);
+            return Frame::Error(arena.alloc_str(&format!(
+                "ERR Can't execute '{}': only (P)SUBSCRIBE / \
+                (P)UNSUBSCRIBE are allowed in this context",
+                cmd.name
+            )));
+        }
+        if !cmd.check_arity(args.len()) {
+            st.stats.record_rejected(cmd.name);
+            return Frame::Error(arena.alloc_str(&wrong_arity(cmd.name)));
+        }
+        if cmd.has_flag("denyoom") && !st.free_memory() {
+            st.stats.record_rejected(cmd.name);
+            return Frame::Error(
+                "OOM command not allowed when used memory > 'maxmemory'.",
+            );
+        }
+
+        // like in redis, admin commands are not shown to monitors
+        let monitored = !st.monitors.is_empty() && !cmd.has_flag("admin");
+        let audited =
+            cmd.has_flag("write") && !st.config().audit_log.is_empty();
+        let line = (monitored || audited).then(|| {
+            let addr = self.addr.to_string();
+            let source = if self.in_script { "lua" } else { &addr };
+            monitor::line(self.db, source, raw)
+        });
+        if let Some(line) = line.as_deref().filter(|_| monitored) {
+            st.monitors.feed(line);
+        }
+
+        let start = Instant::now();
+        let reply = self.exec_cmd(st, cmd, args, raw, arena);
+        let elapsed = start.elapsed();
+        if is_subscribe_cmd(cmd.name) {
+            self.info.set_subscribed(self.is_subscribed());
+        }
+
+        let ok = !matches!(reply, Frame::Error(_));
+        st.stats.record_call(cmd.name, elapsed, ok);
+        if ok && cmd.has_flag("write") {
+            stats::incr(&st.stats.dirty);
+        }
+        if let Some(line) = line.filter(|_| ok && audited) {
+            if let Err(e) = st.audit.write(&st.config(), &line) {
+                log::warn!("could not write to the audit log: {e:#}");
+            }
+        }
+        let (threshold, max_len) = {
+            let config = st.config();
+            (config.slowlog_log_slower_than, config.slowlog_max_len)
+        };
+        if threshold >= 0 && elapsed.as_micros() >= threshold as u128 {
+            let name = self.info.name.lock().unwrap().clone();
+            st.slowlog.push(args, elapsed, self.addr, name, max_len);
+        }
+        self.track_keys(st, cmd, args, ok);
+        // keys found expired while running the command
+        st.notify_expired();
+        reply
+    }
+
+    /// Update the tracking table after a successful command: remember the
+    /// keys it read, or invalidate the keys it modified.
+    fn track_keys(
+        &mut self,
+        st: &State,
+        cmd: &Command,
+        args: &[&str],
+        ok: bool,
+    ) {
+        // `CLIENT CACHING` applies to the command that follows it
+        let is_caching = cmd.name == "client"
+            && args
+                .get(1)
+                .is_some_and(|a| a.eq_ignore_ascii_case("caching"));
+        let caching = if is_caching {
+            self.caching
+        } else {
+            self.caching.take()
+        };
+        if !ok || cmd.keys.0 == 0 {
+            return;
+        }
+        if cmd.has_flag("write") {
+            for k in cmd.keys(args) {
+                st.tracking.invalidate(&st.clients, k, Some(self.info.id));
+            }
+        } else if let Some(opts) = &self.tracking {
+            let track = if opts.optin {
+                caching == Some(true)
+            } else if opts.optout {
+                caching != Some(false)
+            } else {
+                true
+            };
+            if track && !opts.bcast {
+                st.tracking.track(self.info.id, &cmd.keys(args));
+            }
+        }
+    }
+
+    /// Execute a command issued by a script with `redis.call`.
+    #[cfg(feature = "lua")]
+    fn exec_from_script<'are>(
+        &mut self,
+        st: &State,
+        raw: &[&'are [u8]],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let cmd = std::str::from_utf8(raw[0]).ok().and_then(commands::lookup);
+        match cmd {
+            None => {
+                Frame::Error("ERR Unknown Redis command called from script")
+            }
+            Some(cmd) if cmd.has_flag("noscript") => Frame::Error(
+                "ERR This Redis command is not allowed from script",
+            ),
+            Some(_) => match parse_args(raw, arena) {
+                Ok(args) => self.exec(st, &args, raw, arena),
+                Err(e) => Frame::Error(e),
+            },
+        }
+    }
+
+    /// Execute command `cmd` with arguments `args` (including the command
+    /// name), which have already been validated against the command table.
+    fn exec_cmd<'are>(
+        &mut self,
+        st: &State,
+        cmd: &'static Command,
+        args: &[&'are str],
+        raw: &[&'are [u8]],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+        let lfu = st.config().lfu_params();
+        let db = st.db(self.db);
+
+        match (cmd.name, &args[1..]) {
+            ("get", &[k]) => {
+                log::debug!("get {k:?}");
+                match db.get(k, lfu) {
+                    Some(e) => {
+                        log::trace!("get: reply with {:?}", e.value);
+                        stats::incr(&st.stats.keyspace_hits);
+                        match e.value.as_bytes() {
+                            Some(s) => Frame::Bytes(arena.alloc_slice_copy(&s)),
+                            None => Frame::Error(WRONG_TYPE),
                         }
+                    }
+                    None => {
+                        stats::incr(&st.stats.keyspace_misses);
+                        st.notify(notify::KEY_MISS, "keymiss", k, self.db);
+                        Frame::Null
+                    }
+                }
+            }
+            ("set", [k, v, opts @ ..]) => {
+                log::debug!("insert {k:?} => {v:?}");
+                let mut expires_at = None;
+                let mut opts = opts.iter();
+                while let Some(opt) = opts.next() {
+                    let unit = match &*opt.to_ascii_lowercase() {
+                        "ex" => 1000,
+                        "px" => 1,
+                        _ => return Frame::Error("ERR syntax error"),
+                    };
+                    let Some(Ok(n)) = opts.next().map(|n| n.parse::<u64>())
+                    else {
+                        return Frame::Error(
+                            "ERR value is not an integer or out of range",
+                        );
                     };
+                    if n == 0 {
+                        return Frame::Error(
+                            "ERR invalid expire time in 'set' command",
+                        );
+                    }
+                    expires_at = Some(now_ms().saturating_add(n * unit));
+                }
+                let entry =
+                    Entry::new(Value::string(raw[2].to_vec()), expires_at);
+                if db.insert(k.to_string(), entry) {
+                    st.notify(notify::NEW, "new", k, self.db);
+                }
+                st.notify(notify::STRING, "set", k, self.db);
+                Frame::String("OK")
+            }
+            ("expire" | "pexpire", &[k, n]) => {
+                let Ok(n) = n.parse::<i64>() else {
+                    return Frame::Error(
+                        "ERR value is not an integer or out of range",
+                    );
+                };
+                let unit = if cmd.name == "expire" { 1000 } else { 1 };
+                let t =
+                    (now_ms() as i64).saturating_add(n.saturating_mul(unit));
+                let (ok, event) = if t <= now_ms() as i64 {
+                    (db.remove(k).is_some(), "del")
+                } else {
+                    (db.set_expire(k, Some(t as u64)), "expire")
+                };
+                if ok {
+                    st.notify(notify::GENERIC, event, k, self.db);
+                }
+                Frame::Int(ok as isize)
+            }
+            ("ttl" | "pttl", &[k]) => match db.peek(k) {
+                None => Frame::Int(-2),
+                Some(e) => match e.expires_at {
+                    None => Frame::Int(-1),
+                    Some(t) => {
+                        let ms = t.saturating_sub(now_ms());
+                        let unit = if cmd.name == "ttl" { 1000 } else { 1 };
+                        Frame::Int(((ms + unit / 2) / unit) as isize)
+                    }
+                },
+            },
+            ("persist", &[k]) => {
+                let had_ttl =
+                    db.peek(k).is_some_and(|e| e.expires_at.is_some());
+                let ok = had_ttl && db.set_expire(k, None);
+                if ok {
+                    st.notify(notify::GENERIC, "persist", k, self.db);
                 }
-                Frame::Bulk(
-                    &[Frame::String("set"), Frame::String(k), Frame::String(v)],
-                ) => {
-                    log::debug!("insert {k:?} => {v:?}");
-                    st.kv.insert(k.to_string(), v.to_string());
-                    wire::write_frame(&mut self.conn, &Frame::String("OK"))
-                        .await?;
+                Frame::Int(ok as isize)
+            }
+            ("memory", [sub, rest @ ..])
+                if sub.eq_ignore_ascii_case("usage") =>
+            {
+                match rest {
+                    [k] | [k, _, _] => match db.peek(k) {
+                        Some(e) => Frame::Int(e.mem_usage(k) as isize),
+                        None => Frame::Null,
+                    },
+                    _ => err(wrong_arity("memory|usage")),
+                }
+            }
+            ("select", &[index]) => match parse_db_index(st, index) {
+                Ok(index) => {
+                    self.db = index;
+                    self.info.select(index);
+                    Frame::String("OK")
+                }
+                Err(e) => Frame::Error(e),
+            },
+            (
+                "del" | "exists" | "keys" | "scan" | "type" | "object"
+                | "rename" | "renamenx" | "move" | "swapdb" | "dbsize"
+                | "flushdb" | "flushall",
+                _,
+            ) => keyspace::exec(st, self.db, cmd.name, args, arena),
+            ("setbit" | "getbit" | "bitcount", _) => {
+                bitops::exec(st, self.db, cmd.name, args, arena)
+            }
+            ("pfadd" | "pfcount" | "pfmerge", _) => {
+                hyperloglog::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("hset" | "hget" | "hdel" | "hlen" | "hexists" | "hgetall", _) => {
+                hash::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("lpush" | "rpush" | "lpop" | "rpop" | "llen" | "lrange", _) => {
+                list::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("sadd" | "srem" | "sismember" | "scard" | "smembers", _) => {
+                set::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("zadd" | "zscore" | "zcard" | "zrem" | "zrange", _) => {
+                zset::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("geoadd" | "geopos" | "geodist" | "geohash" | "geosearch", _) => {
+                geo::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("dump" | "restore" | "migrate", _) => {
+                migrate::exec(st, self.db, cmd.name, args, raw, arena)
+            }
+            ("subscribe", channels) => {
+                let replies: Vec<Frame> = channels
+                    .iter()
+                    .map(|c| {
+                        if self.channels.insert(c.to_string()) {
+                            st.pubsub.subscribe(c, &self.info);
+                        }
+                        self.subscription_reply("subscribe", Some(c), arena)
+                    })
+                    .collect();
+                Frame::Bulk(arena.alloc_slice_copy(&replies))
+            }
+            ("psubscribe", patterns) => {
+                let replies: Vec<Frame> = patterns
+                    .iter()
+                    .map(|p| {
+                        if self.patterns.insert(p.to_string()) {
+                            st.pubsub.psubscribe(p, &self.info);
+                        }
+                        self.subscription_reply("psubscribe", Some(p), arena)
+                    })
+                    .collect();
+                Frame::Bulk(arena.alloc_slice_copy(&replies))
+            }
+            ("unsubscribe", channels) => {
+                let channels: Vec<String> = if channels.is_empty() {
+                    self.channels.iter().cloned().collect()
+                } else {
+                    channels.iter().map(|c| c.to_string()).collect()
+                };
+                let mut replies: Vec<Frame> = channels
+                    .iter()
+                    .map(|c| {
+                        if self.channels.remove(c) {
+                            st.pubsub.unsubscribe(c, self.info.id);
+                        }
+                        self.subscription_reply("unsubscribe", Some(c), arena)
+                    })
+                    .collect();
+                if replies.is_empty() {
+                    replies.push(self.subscription_reply(
+                        "unsubscribe",
+                        None,
+                        arena,
+                    ));
+                }
+                Frame::Bulk(arena.alloc_slice_copy(&replies))
+            }
+            ("punsubscribe", patterns) => {
+                let patterns: Vec<String> = if patterns.is_empty() {
+                    self.patterns.iter().cloned().collect()
+                } else {
+                    patterns.iter().map(|p| p.to_string()).collect()
+                };
+                let mut replies: Vec<Frame> = patterns
+                    .iter()
+                    .map(|p| {
+                        if self.patterns.remove(p) {
+                            st.pubsub.punsubscribe(p, self.info.id);
+                        }
+                        self.subscription_reply("punsubscribe", Some(p), arena)
+                    })
+                    .collect();
+                if replies.is_empty() {
+                    replies.push(self.subscription_reply(
+                        "punsubscribe",
+                        None,
+                        arena,
+                    ));
                 }
+                Frame::Bulk(arena.alloc_slice_copy(&replies))
+            }
+            ("publish", &[channel, _]) => {
+                Frame::Int(st.pubsub.publish(channel, raw[2]) as isize)
+            }
+            ("pubsub", rest) => exec_pubsub(st, rest, arena),
+            ("info", sections) => {
+                let info = info::info(st, sections);
+                Frame::String(arena.alloc_str(&info))
+            }
+            #[cfg(feature = "lua")]
+            ("eval" | "evalsha" | "script", _) => {
+                // `SELECT` in a script doesn't affect the caller
+                let db = self.db;
+                self.in_script = true;
+                let reply = scripting::exec(
+                    st,
+                    cmd.name,
+                    args,
+                    raw,
+                    arena,
+                    &mut |args, arena| self.exec_from_script(st, args, arena),
+                );
+                self.in_script = false;
+                self.db = db;
+                self.info.select(db);
+                reply
+            }
+            ("config", rest) => self.exec_config(st, rest, arena),
+            ("client", rest) => self.exec_client(st, rest, arena),
+            ("slowlog", rest) => exec_slowlog(st, rest, arena),
+            ("monitor", []) => {
+                self.monitor = true;
+                self.info.set_monitor();
+                st.monitors.add(&self.info);
+                Frame::String("OK")
+            }
+            ("command", rest) => exec_command(rest, arena),
+            ("shutdown", opts) => {
+                let mode = match opts {
+                    [] => ShutdownMode::Default,
+                    [o] if o.eq_ignore_ascii_case("nosave") => {
+                        ShutdownMode::NoSave
+                    }
+                    [o] if o.eq_ignore_ascii_case("save") => ShutdownMode::Save,
+                    _ => return Frame::Error("ERR syntax error"),
+                };
+                log::warn!("user requested shutdown...");
+                st.shutdown(mode);
+                self.shutdown_called = true;
+                Frame::String("OK")
+            }
+            ("ping", []) => Frame::String("PONG"),
+            ("ping", [_]) => Frame::Bytes(raw[1]),
+            _ => err(wrong_arity(cmd.name)),
+        }
+    }
 
-                Frame::Bulk(arr)
-                    if !arr.is_empty()
-                        && arr[0] == Frame::String("COMMAND") =>
+    /// `CLIENT ID/LIST/KILL/SETNAME/GETNAME`.
+    fn exec_client<'are>(
+        &mut self,
+        st: &State,
+        args: &[&'are str],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+        let sub = args[0];
+
+        match (&*sub.to_ascii_lowercase(), &args[1..]) {
+            ("id", []) => Frame::Int(self.info.id as isize),
+            ("getname", []) => {
+                let name = self.info.name.lock().unwrap();
+                if name.is_empty() {
+                    Frame::Null
+                } else {
+                    Frame::String(arena.alloc_str(&name))
+                }
+            }
+            ("setname", &[name]) => {
+                if name.bytes().any(|c| c <= b' ' || c > b'~') {
+                    return Frame::Error(
+                        "ERR Client names cannot contain spaces, newlines \
+                        or special characters.",
+                    );
+                }
+                *self.info.name.lock().unwrap() = name.to_string();
+                Frame::String("OK")
+            }
+            ("list", rest) => {
+                let ids = match rest {
+                    [] => None,
+                    [id_kw, ids @ ..]
+                        if id_kw.eq_ignore_ascii_case("id")
+                            && !ids.is_empty() =>
+                    {
+                        match ids.iter().map(|i| i.parse::<u64>()).collect() {
+                            Ok(ids) => Some(ids),
+                            Err(_) => {
+                                return Frame::Error("ERR Invalid client ID")
+                            }
+                        }
+                    }
+                    _ => return Frame::Error("ERR syntax error"),
+                };
+                let mut out = String::new();
+                for c in st.clients.all() {
+                    if ids
+                        .as_ref()
+                        .is_some_and(|ids: &Vec<u64>| !ids.contains(&c.id))
+                    {
+                        continue;
+                    }
+                    out.push_str(&c.describe());
+                    out.push('\n');
+                }
+                Frame::String(arena.alloc_str(&out))
+            }
+            ("kill", &[addr]) => {
+                // old style: `CLIENT KILL addr:port`
+                match st
+                    .clients
+                    .all()
+                    .iter()
+                    .find(|c| c.addr.to_string() == addr)
                 {
-                    // nothing to do
-                    let frame = &Frame::Error("unknown command");
-                    wire::write_frame(&mut self.conn, frame).await?;
+                    Some(c) => {
+                        c.kill();
+                        Frame::String("OK")
+                    }
+                    None => Frame::Error("ERR No such client"),
                 }
-                _ => {
-                    let msg = format!("unknown command {msg:?}");
-                    wire::write_frame(&mut self.conn, &Frame::Error(&msg))
-                        .await?;
+            }
+            ("kill", filters)
+                if !filters.is_empty() && filters.len() % 2 == 0 =>
+            {
+                let mut id = None;
+                let mut addr = None;
+                let mut skipme = true;
+                for f in filters.chunks(2) {
+                    match (&*f[0].to_ascii_lowercase(), f[1]) {
+                        ("id", v) => {
+                            match v.parse::<u64>() {
+                                Ok(v) => id = Some(v),
+                                Err(_) => return Frame::Error(
+                                    "ERR client-id should be greater than 0",
+                                ),
+                            }
+                        }
+                        ("addr", v) => addr = Some(v),
+                        ("skipme", v) if v.eq_ignore_ascii_case("yes") => {
+                            skipme = true
+                        }
+                        ("skipme", v) if v.eq_ignore_ascii_case("no") => {
+                            skipme = false
+                        }
+                        _ => return Frame::Error("ERR syntax error"),
+                    }
+                }
+                let mut n = 0;
+                for c in st.clients.all() {
+                    let matches = id.is_none_or(|id| c.id == id)
+                        && addr.is_none_or(|a| c.addr.to_string() == a)
+                        && !(skipme && c.id == self.info.id);
+                    if matches {
+                        c.kill();
+                        n += 1;
+                    }
+                }
+                Frame::Int(n)
+            }
+            ("help", []) => {
+                let lines: &[Frame] = &[
+                    Frame::String("CLIENT ID"),
+                    Frame::String("CLIENT LIST [ID <id> ...]"),
+                    Frame::String("CLIENT KILL <addr>"),
+                    Frame::String(
+                        "CLIENT KILL [ID <id>] [ADDR <addr>] [SKIPME yes|no]",
+                    ),
+                    Frame::String("CLIENT SETNAME <name>"),
+                    Frame::String("CLIENT GETNAME"),
+                    Frame::String(
+                        "CLIENT TRACKING ON|OFF [REDIRECT <id>] \
+                        [PREFIX <prefix> ...] [BCAST] [OPTIN] [OPTOUT] \
+                        [NOLOOP]",
+                    ),
+                    Frame::String("CLIENT CACHING YES|NO"),
+                    Frame::String("CLIENT GETREDIR"),
+                    Frame::String("CLIENT TRACKINGINFO"),
+                ];
+                Frame::Bulk(arena.alloc_slice_copy(lines))
+            }
+            ("tracking", [on_off, opts @ ..]) => {
+                self.exec_client_tracking(st, on_off, opts, arena)
+            }
+            ("caching", &[yes_no]) => {
+                let opts = self.tracking.as_ref();
+                match &*yes_no.to_ascii_lowercase() {
+                    "yes" if opts.is_some_and(|o| o.optin) => {
+                        self.caching = Some(true)
+                    }
+                    "no" if opts.is_some_and(|o| o.optout) => {
+                        self.caching = Some(false)
+                    }
+                    _ if !opts.is_some_and(|o| o.optin || o.optout) => {
+                        return Frame::Error(
+                            "ERR CLIENT CACHING can be called only when the \
+                            client is in tracking mode with OPTIN or OPTOUT \
+                            mode enabled",
+                        )
+                    }
+                    "yes" => {
+                        return Frame::Error(
+                            "ERR CLIENT CACHING YES is only valid when \
+                            tracking is enabled in OPTIN mode.",
+                        )
+                    }
+                    "no" => {
+                        return Frame::Error(
+                            "ERR CLIENT CACHING NO is only valid when \
+                            tracking is enabled in OPTOUT mode.",
+                        )
+                    }
+                    _ => return Frame::Error("ERR syntax error"),
+                }
+                Frame::String("OK")
+            }
+            ("getredir", []) => match &self.tracking {
+                None => Frame::Int(-1),
+                Some(opts) => Frame::Int(opts.redirect.unwrap_or(0) as isize),
+            },
+            ("trackinginfo", []) => {
+                let mut flags = vec![];
+                let mut prefixes = vec![];
+                let mut redirect = -1;
+                match &self.tracking {
+                    None => flags.push(Frame::String("off")),
+                    Some(opts) => {
+                        flags.push(Frame::String("on"));
+                        for (set, flag) in [
+                            (opts.bcast, "bcast"),
+                            (opts.optin, "optin"),
+                            (opts.optout, "optout"),
+                            (self.caching == Some(true), "caching-yes"),
+                            (self.caching == Some(false), "caching-no"),
+                            (opts.noloop, "noloop"),
+                        ] {
+                            if set {
+                                flags.push(Frame::String(flag));
+                            }
+                        }
+                        if let Some(id) = opts.redirect {
+                            if st.clients.get(id).is_none() {
+                                flags.push(Frame::String("broken_redirect"));
+                            }
+                        }
+                        redirect = opts.redirect.unwrap_or(0) as isize;
+                        for p in &opts.prefixes {
+                            prefixes.push(Frame::String(arena.alloc_str(p)));
+                        }
+                    }
                 }
+                let fields = [
+                    Frame::String("flags"),
+                    Frame::Bulk(arena.alloc_slice_copy(&flags)),
+                    Frame::String("redirect"),
+                    Frame::Int(redirect),
+                    Frame::String("prefixes"),
+                    Frame::Bulk(arena.alloc_slice_copy(&prefixes)),
+                ];
+                Frame::Bulk(arena.alloc_slice_copy(&fields))
             }
+            (
+                "id" | "getname" | "setname" | "kill" | "help" | "tracking"
+                | "caching" | "getredir" | "trackinginfo",
+                _,
+            ) => err(format!(
+                "ERR wrong number of arguments for 'client|{sub}' command"
+            )),
+            _ => err(format!("ERR unknown subcommand '{sub}'")),
+        }
+    }
 
-            arena.reset();
+    /// `CLIENT TRACKING ON|OFF [options]`.
+    fn exec_client_tracking<'are>(
+        &mut self,
+        st: &State,
+        on_off: &str,
+        args: &[&str],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+        let on = match &*on_off.to_ascii_lowercase() {
+            "on" => true,
+            "off" => false,
+            _ => return Frame::Error("ERR syntax error"),
+        };
+
+        let mut opts = tracking::Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match &*arg.to_ascii_lowercase() {
+                "redirect" => {
+                    let Some(Ok(id)) = args.next().map(|id| id.parse::<u64>())
+                    else {
+                        return Frame::Error("ERR Invalid client ID");
+                    };
+                    if id != self.info.id && st.clients.get(id).is_none() {
+                        return Frame::Error(
+                            "ERR The client ID you want redirect to does not \
+                            exist",
+                        );
+                    }
+                    opts.redirect = Some(id);
+                }
+                "prefix" => match args.next() {
+                    Some(p) => opts.prefixes.push(p.to_string()),
+                    None => return Frame::Error("ERR syntax error"),
+                },
+                "bcast" => opts.bcast = true,
+                "optin" => opts.optin = true,
+                "optout" => opts.optout = true,
+                "noloop" => opts.noloop = true,
+                _ => return Frame::Error("ERR syntax error"),
+            }
         }
 
-        log::info!("done serving client {addr:?}");
-        Ok(())
+        if !on {
+            st.tracking.disable(self.info.id);
+            self.tracking = None;
+            self.caching = None;
+            return Frame::String("OK");
+        }
+        if !opts.prefixes.is_empty() && !opts.bcast {
+            return Frame::Error(
+                "ERR PREFIX option requires BCAST mode to be enabled",
+            );
+        }
+        if opts.optin && opts.optout {
+            return Frame::Error("ERR You can't use both OPTIN and OPTOUT");
+        }
+        if opts.bcast && (opts.optin || opts.optout) {
+            return Frame::Error(
+                "ERR OPTIN and OPTOUT are not compatible with BCAST",
+            );
+        }
+        for (i, p) in opts.prefixes.iter().enumerate() {
+            for q in &opts.prefixes[i + 1..] {
+                if p.starts_with(q.as_str()) || q.starts_with(p.as_str()) {
+                    return err(format!(
+                        "ERR Prefix '{p}' overlaps with another provided \
+                        prefix '{q}'. Prefixes for a single client must not \
+                        overlap."
+                    ));
+                }
+            }
+        }
+        if let Some(old) = &self.tracking {
+            if old.bcast != opts.bcast {
+                return Frame::Error(
+                    "ERR You can't switch BCAST mode on/off before disabling \
+                    tracking for this client, and then re-enabling it with \
+                    a different mode.",
+                );
+            }
+        }
+
+        st.tracking.enable(self.info.id, opts.clone());
+        self.tracking = Some(opts);
+        Frame::String("OK")
+    }
+
+    /// `CONFIG GET/SET/REWRITE/HELP`.
+    fn exec_config<'are>(
+        &mut self,
+        st: &State,
+        args: &[&'are str],
+        arena: &'are bumpalo::Bump,
+    ) -> Frame<'are> {
+        let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+        let sub = args[0];
+
+        match (&*sub.to_ascii_lowercase(), &args[1..]) {
+            ("get", params) if !params.is_empty() => {
+                let config = st.config();
+                let mut res = vec![];
+                for name in Config::param_names() {
+                    let wanted = params.iter().any(|p| {
+                        glob::string_match(p.as_bytes(), name.as_bytes(), true)
+                    });
+                    if wanted {
+                        let v = config.get(name).unwrap_or_default();
+                        res.push(Frame::String(name));
+                        res.push(Frame::String(arena.alloc_str(&v)));
+                    }
+                }
+                Frame::Bulk(arena.alloc_slice_copy(&res))
+            }
+            ("set", params) if !params.is_empty() && params.len() % 2 == 0 => {
+                // validate everything first, so that `CONFIG SET` is atomic
+                let mut new_config = st.config().clone();
+                for kv in params.chunks(2) {
+                    let (name, value) = (kv[0], kv[1]);
+                    if !Config::is_mutable(name) {
+                        return err(format!(
+                            "ERR CONFIG SET failed (possibly related to \
+                            argument '{name}') - can't set immutable config"
+                        ));
+                    }
+                    if let Err(e) = new_config.set(name, value) {
+                        return err(format!(
+                            "ERR CONFIG SET failed (possibly related to \
+                            argument '{name}') - {e}"
+                        ));
+                    }
+                }
+                log::set_max_level(new_config.loglevel.to_filter());
+                *st.config.write().unwrap() = new_config;
+                Frame::String("OK")
+            }
+            ("rewrite", []) => {
+                let Some(path) = &st.config_file else {
+                    return Frame::Error(
+                        "ERR The server is running without a config file",
+                    );
+                };
+                match st.config().rewrite(path) {
+                    Ok(()) => Frame::String("OK"),
+                    Err(e) => {
+                        log::error!("CONFIG REWRITE failed: {e:#}");
+                        err(format!("ERR Rewriting config file: {e:#}"))
+                    }
+                }
+            }
+            ("resetstat", []) => {
+                st.stats.reset();
+                st.slowlog.reset();
+                Frame::String("OK")
+            }
+            ("help", []) => {
+                let lines: &[Frame] = &[
+                    Frame::String("CONFIG GET <pattern> [<pattern> ...]"),
+                    Frame::String(
+                        "CONFIG SET <name> <value> [<name> <value> ...]",
+                    ),
+                    Frame::String("CONFIG REWRITE"),
+                    Frame::String("CONFIG RESETSTAT"),
+                ];
+                Frame::Bulk(arena.alloc_slice_copy(lines))
+            }
+            ("get" | "set" | "rewrite" | "resetstat" | "help", _) => {
+                err(format!(
+                    "ERR wrong number of arguments for 'config|{sub}' command"
+                ))
+            }
+            _ => err(format!("ERR unknown subcommand '{sub}'")),
+        }
+    }
+}
+
+/// `PUBSUB CHANNELS/NUMSUB/NUMPAT`.
+fn exec_pubsub<'are>(
+    st: &State,
+    args: &[&'are str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+    let sub = args[0];
+
+    match (&*sub.to_ascii_lowercase(), &args[1..]) {
+        ("channels", rest @ ([] | [_])) => {
+            let channels: Vec<Frame> = st
+                .pubsub
+                .channels(rest.first().copied())
+                .iter()
+                .map(|c| Frame::String(arena.alloc_str(c)))
+                .collect();
+            Frame::Bulk(arena.alloc_slice_copy(&channels))
+        }
+        ("numsub", channels) => {
+            let mut res = vec![];
+            for c in channels {
+                res.push(Frame::String(c));
+                res.push(Frame::Int(st.pubsub.numsub(c) as isize));
+            }
+            Frame::Bulk(arena.alloc_slice_copy(&res))
+        }
+        ("numpat", []) => Frame::Int(st.pubsub.numpat() as isize),
+        ("help", []) => {
+            let lines: &[Frame] = &[
+                Frame::String("PUBSUB CHANNELS [<pattern>]"),
+                Frame::String("PUBSUB NUMSUB [<channel> ...]"),
+                Frame::String("PUBSUB NUMPAT"),
+            ];
+            Frame::Bulk(arena.alloc_slice_copy(lines))
+        }
+        ("channels" | "numpat" | "help", _) => err(format!(
+            "ERR wrong number of arguments for 'pubsub|{sub}' command"
+        )),
+        _ => err(format!("ERR unknown subcommand '{sub}'")),
+    }
+}
+
+/// `COMMAND [COUNT/DOCS/INFO/LIST]`.
+///
+/// Documentation only has the summary and, unlike in redis, the syntax as
+/// a single string rather than a tree of arguments.
+fn exec_command<'are>(
+    args: &[&'are str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+    let info = |c: &'static Command| {
+        let flags: Vec<Frame> =
+            c.flags.iter().map(|f| Frame::String(f)).collect();
+        let fields = [
+            Frame::String(c.name),
+            Frame::Int(c.arity as isize),
+            Frame::Bulk(arena.alloc_slice_copy(&flags)),
+            Frame::Int(c.keys.0 as isize),
+            Frame::Int(c.keys.1 as isize),
+            Frame::Int(c.keys.2 as isize),
+        ];
+        Frame::Bulk(arena.alloc_slice_copy(&fields))
+    };
+    let docs = |c: &'static Command| {
+        let fields = [
+            Frame::String("summary"),
+            Frame::String(c.summary),
+            Frame::String("syntax"),
+            Frame::String(c.syntax),
+        ];
+        Frame::Bulk(arena.alloc_slice_copy(&fields))
+    };
+    let commands = |names: &[&str]| -> Vec<Option<&'static Command>> {
+        match names {
+            [] => COMMANDS.iter().map(Some).collect(),
+            names => names.iter().map(|n| commands::lookup(n)).collect(),
+        }
+    };
+
+    let Some(sub) = args.first() else {
+        let all: Vec<Frame> = COMMANDS.iter().map(info).collect();
+        return Frame::Bulk(arena.alloc_slice_copy(&all));
+    };
+    match (&*sub.to_ascii_lowercase(), &args[1..]) {
+        ("count", []) => Frame::Int(COMMANDS.len() as isize),
+        ("list", []) => {
+            let names: Vec<Frame> =
+                COMMANDS.iter().map(|c| Frame::String(c.name)).collect();
+            Frame::Bulk(arena.alloc_slice_copy(&names))
+        }
+        ("info", names) => {
+            let infos: Vec<Frame> = commands(names)
+                .into_iter()
+                .map(|c| c.map_or(Frame::Null, info))
+                .collect();
+            Frame::Bulk(arena.alloc_slice_copy(&infos))
+        }
+        ("docs", names) => {
+            // unknown commands are skipped
+            let docs: Vec<Frame> = commands(names)
+                .into_iter()
+                .flatten()
+                .flat_map(|c| [Frame::String(c.name), docs(c)])
+                .collect();
+            Frame::Bulk(arena.alloc_slice_copy(&docs))
+        }
+        ("help", []) => {
+            let lines: &[Frame] = &[
+                Frame::String("COMMAND"),
+                Frame::String("COMMAND COUNT"),
+                Frame::String("COMMAND DOCS [<command-name> ...]"),
+                Frame::String("COMMAND INFO [<command-name> ...]"),
+                Frame::String("COMMAND LIST"),
+            ];
+            Frame::Bulk(arena.alloc_slice_copy(lines))
+        }
+        ("count" | "list" | "help", _) => err(format!(
+            "ERR wrong number of arguments for 'command|{sub}' command"
+        )),
+        _ => err(format!("ERR unknown subcommand '{sub}'")),
+    }
+}
+
+/// `SLOWLOG GET/LEN/RESET`.
+fn exec_slowlog<'are>(
+    st: &State,
+    args: &[&'are str],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let err = |msg: String| Frame::Error(arena.alloc_str(&msg));
+    let sub = args[0];
+
+    match (&*sub.to_ascii_lowercase(), &args[1..]) {
+        ("get", rest @ ([] | [_])) => {
+            let n = match rest.first().map(|n| n.parse::<i64>()) {
+                None => 10,
+                Some(Ok(n)) if n < 0 => usize::MAX,
+                Some(Ok(n)) => n as usize,
+                Some(Err(_)) => {
+                    return Frame::Error(
+                        "ERR value is not an integer or out of range",
+                    )
+                }
+            };
+            let entries: Vec<Frame> = st
+                .slowlog
+                .get(n)
+                .into_iter()
+                .map(|e| {
+                    let args: Vec<Frame> = e
+                        .args
+                        .iter()
+                        .map(|a| Frame::String(arena.alloc_str(a)))
+                        .collect();
+                    let fields = [
+                        Frame::Int(e.id as isize),
+                        Frame::Int(e.timestamp as isize),
+                        Frame::Int(e.duration as isize),
+                        Frame::Bulk(arena.alloc_slice_copy(&args)),
+                        Frame::String(arena.alloc_str(&e.addr.to_string())),
+                        Frame::String(arena.alloc_str(&e.client_name)),
+                    ];
+                    Frame::Bulk(arena.alloc_slice_copy(&fields))
+                })
+                .collect();
+            Frame::Bulk(arena.alloc_slice_copy(&entries))
+        }
+        ("len", []) => Frame::Int(st.slowlog.len() as isize),
+        ("reset", []) => {
+            st.slowlog.reset();
+            Frame::String("OK")
+        }
+        ("help", []) => {
+            let lines: &[Frame] = &[
+                Frame::String("SLOWLOG GET [<count>]"),
+                Frame::String("SLOWLOG LEN"),
+                Frame::String("SLOWLOG RESET"),
+            ];
+            Frame::Bulk(arena.alloc_slice_copy(lines))
+        }
+        ("get" | "len" | "reset" | "help", _) => err(format!(
+            "ERR wrong number of arguments for 'slowlog|{sub}' command"
+        )),
+        _ => err(format!("ERR unknown subcommand '{sub}'")),
+    }
+}
+
+/// Load the snapshot, listen on the configured addresses and serve
+/// clients until [`State::shutdown`] is called. The snapshot is then saved
+/// according to the [`ShutdownMode`].
+///
+/// Each client is served in its own task on a [`LocalSet`], so this
+/// must run in a single-threaded context.
+pub async fn run(st: Arc<State>) -> Result<()> {
+    rdb::load(&st)?;
+    let listeners = Listeners::bind(&st).await?;
+    serve(st, listeners).await
+}
+
+/// Sockets to accept clients (and metrics scrapes) on, bound before
+/// serving so that the actual addresses are known.
+pub(crate) struct Listeners {
+    clients: Vec<TcpListener>,
+    metrics: Option<TcpListener>,
+}
+
+impl Listeners {
+    /// Listen on the addresses of the configuration.
+    pub async fn bind(st: &State) -> Result<Self> {
+        let (bind, port, metrics_addr) = {
+            let config = st.config();
+            let metrics_addr = (config.metrics_port > 0).then(|| {
+                format!("{}:{}", config.metrics_bind, config.metrics_port)
+            });
+            (config.bind.clone(), config.port, metrics_addr)
+        };
+
+        let mut clients = vec![];
+        for host in bind {
+            let addr = format!("{host}:{port}");
+            let listen = TcpListener::bind(&addr)
+                .await
+                .with_context(|| format!("binding socket on {addr}"))?;
+            log::info!("serving on {}", listen.local_addr()?);
+            clients.push(listen);
+        }
+        let metrics = match metrics_addr {
+            Some(addr) => {
+                let listen =
+                    TcpListener::bind(&addr).await.with_context(|| {
+                        format!("binding metrics socket on {addr}")
+                    })?;
+                log::info!("serving metrics on http://{addr}/metrics");
+                Some(listen)
+            }
+            None => None,
+        };
+        Ok(Self { clients, metrics })
+    }
+
+    /// Addresses clients can connect to.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        let addrs = self.clients.iter().map(|l| l.local_addr());
+        Ok(addrs.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Serve clients on `listeners`, like [`run`] once they are bound.
+pub(crate) async fn serve(st: Arc<State>, listeners: Listeners) -> Result<()> {
+    let local = LocalSet::new(); // spawn on same thread
+    let mut accept_loops = vec![];
+    for listen in listeners.clients {
+        accept_loops.push(local.spawn_local(accept_loop(listen, st.clone())));
+    }
+    if let Some(listen) = listeners.metrics {
+        local.spawn_local(metrics::serve(listen, st.clone()));
+    }
+
+    local.spawn_local(expire_loop(st.clone()));
+    local.spawn_local(clients_cron(st.clone()));
+    local.spawn_local(save_loop(st.clone()));
+
+    let mode = local
+        .run_until(async {
+            let mode = st.shutdown_requested().await;
+            for l in accept_loops {
+                let _ = l.await;
+            }
+            mode
+        })
+        .await;
+    // stop the other tasks before saving
+    drop(local);
+
+    let save = match mode {
+        ShutdownMode::Default => !st.config().save.is_empty(),
+        ShutdownMode::Save => true,
+        ShutdownMode::NoSave => false,
+    };
+    if save {
+        log::info!("saving the final RDB snapshot before exiting");
+        rdb::save(&st)?;
+    }
+    log::warn!("mini-redis is now ready to exit, bye bye...");
+    Ok(())
+}
+
+/// Periodically remove expired keys, so that they are removed (and
+/// notified) even if nobody accesses them.
+async fn expire_loop(st: Arc<State>) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_PERIOD);
+    loop {
+        interval.tick().await;
+        for db in st.dbs() {
+            db.active_expire(ACTIVE_EXPIRE_MAX_ROUNDS);
+            db.drop_old_scan_snapshots();
+        }
+        st.notify_expired();
+    }
+}
+
+/// Save a snapshot whenever one of the `save` rules is met: at least
+/// `changes` write commands and `seconds` seconds since the last one.
+async fn save_loop(st: Arc<State>) {
+    let mut interval = tokio::time::interval(SAVE_CHECK_PERIOD);
+    let mut last_try = 0;
+    loop {
+        interval.tick().await;
+        let now = now_ms() / 1000;
+        let dirty = stats::get(&st.stats.dirty);
+        let since = now.saturating_sub(stats::get(&st.stats.last_save));
+        let due = st
+            .config()
+            .save
+            .iter()
+            .any(|&(secs, changes)| dirty >= changes && since >= secs);
+        if !due || now.saturating_sub(last_try) < SAVE_RETRY_DELAY {
+            continue;
+        }
+        last_try = now;
+        log::info!("{dirty} changes in {since} seconds. Saving...");
+        // encoding and writing the snapshot may take a while
+        let saving = st.clone();
+        match tokio::task::spawn_blocking(move || rdb::save(&saving)).await {
+            Ok(Ok(())) => {
+                st.stats.dirty.fetch_sub(dirty, Ordering::Relaxed);
+                st.stats.last_save.store(now, Ordering::Relaxed);
+            }
+            Ok(Err(e)) => log::warn!("background saving failed: {e:#}"),
+            Err(e) => log::warn!("background saving failed: {e}"),
+        }
+    }
+}
+
+/// Periodically check the output buffer of clients, to close those that
+/// stayed over their soft limit for too long even if nothing was added to
+/// their buffer since.
+async fn clients_cron(st: Arc<State>) {
+    let mut interval = tokio::time::interval(CLIENTS_CRON_PERIOD);
+    loop {
+        interval.tick().await;
+        let limits = st.config().client_output_buffer_limit;
+        let now = now_ms();
+        for c in st.clients.all() {
+            c.check_output_buffer(limits.get(c.class()), now);
+        }
+    }
+}
+
+/// Enable TCP keepalive on `sock`, with probes every `interval`, like
+/// redis.
+fn set_keepalive(sock: &TcpStream, interval: Duration) -> std::io::Result<()> {
+    let keepalive = socket2::TcpKeepalive::new().with_time(interval);
+    #[cfg(target_os = "linux")]
+    let keepalive = keepalive.with_interval(interval / 3);
+    socket2::SockRef::from(sock).set_tcp_keepalive(&keepalive)
+}
+
+/// Accept clients on `listen` and spawn a task for each, until shutdown.
+/// Then wait for the clients to be closed, up to `shutdown-timeout`.
+async fn accept_loop(listen: TcpListener, st: Arc<State>) {
+    let mut clients = JoinSet::new();
+    let mut shutdown = st.shutdown.subscribe();
+    while !st.is_shutting_down() {
+        let (mut sock, addr) = tokio::select! {
+            res = listen.accept() => match res {
+                Ok(accepted) => accepted,
+                Err(_) => {
+                    // https://github.com/tokio-rs/tokio/issues/4782
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+            },
+            // forget about clients that are gone
+            Some(_) = clients.join_next() => continue,
+            _ = shutdown.changed() => break,
+        };
+
+        stats::incr(&st.stats.total_connections_received);
+        if st.n_clients() >= st.config().maxclients {
+            log::warn!("rejecting client {addr:?}: too many clients");
+            stats::incr(&st.stats.rejected_connections);
+            let _ = sock
+                .write_all(b"-ERR max number of clients reached\r\n")
+                .await;
+            continue;
+        }
+
+        log::info!("new client on {a:?}", a = addr);
+        let keepalive = st.config().tcp_keepalive;
+        if keepalive > 0 {
+            if let Err(e) = set_keepalive(&sock, Duration::from_secs(keepalive))
+            {
+                log::warn!("could not set keepalive for {addr:?}: {e}");
+            }
+        }
+        let st = st.clone();
+        clients.spawn_local(async move {
+            log::trace!("hello client on {addr:?}");
+            let mut client = ClientHandler::new(&mut sock, addr);
+            if let Err(e) = client.serve(st.clone()).await {
+                log::error!("error serving client {addr:?}: {e:#}");
+            }
+        });
+    }
+    drop(listen);
+
+    let timeout = Duration::from_secs(st.config().shutdown_timeout);
+    let all_closed = async { while clients.join_next().await.is_some() {} };
+    if tokio::time::timeout(timeout, all_closed).await.is_err() {
+        log::warn!(
+            "closing {} clients that were still busy after {timeout:?}",
+            clients.len()
+        );
+        clients.shutdown().await;
     }
 }
@@ -0,0 +1,398 @@
+//! Sets, and the `SADD`, `SREM`, `SISMEMBER`, `SCARD` and `SMEMBERS`
+//! commands.
+//!
+//! Members are binary, like strings.
+
+use std::{borrow::Cow, collections::HashSet};
+
+use crate::{
+    db::{parse_int, Db, LfuParams, ListpackLimits, Value},
+    notify,
+    server::{wrong_arity, State, WRONG_TYPE},
+    wire::Frame,
+};
+
+/// Approximate per-member overhead of the hashtable encoding, in bytes, on
+/// top of the member.
+const MEMBER_OVERHEAD: usize = 40;
+
+/// Per-member overhead of the listpack encoding: a pointer to the member.
+const LISTPACK_MEMBER_OVERHEAD: usize = 16;
+
+/// Sizes under which sets use the intset or listpack encoding, from the
+/// `set-max-intset-entries` and `set-max-listpack-*` parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SetLimits {
+    /// Maximum number of members of an intset.
+    pub intset_entries: usize,
+    pub listpack: ListpackLimits,
+}
+
+impl Default for SetLimits {
+    fn default() -> Self {
+        Self {
+            intset_entries: 512,
+            listpack: ListpackLimits::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// Members that are all integers, sorted and binary searched.
+    Intset(Vec<i64>),
+    /// Members in insertion order, looked up linearly: compact, and fast
+    /// enough for small sets.
+    Listpack(Vec<Box<[u8]>>),
+    Hashtable(HashSet<Vec<u8>>),
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Intset(vec![])
+    }
+}
+
+/// A set. Like in redis, sets of integers start in the intset encoding,
+/// and switch to the listpack encoding when another member is added to a
+/// small one. Both switch to the hashtable encoding for good once they
+/// outgrow their [`SetLimits`].
+#[derive(Clone, Debug, Default)]
+pub struct Set {
+    encoding: Encoding,
+    limits: SetLimits,
+    /// Total length of the members that are not in an intset.
+    members_len: usize,
+}
+
+impl Set {
+    /// An empty set, that switches encodings according to `limits`.
+    pub fn new(limits: SetLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Intset(v) => v.len(),
+            Encoding::Listpack(v) => v.len(),
+            Encoding::Hashtable(s) => s.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as returned by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Intset(_) => "intset",
+            Encoding::Listpack(_) => "listpack",
+            Encoding::Hashtable(_) => "hashtable",
+        }
+    }
+
+    /// Change the limits, e.g. after `CONFIG SET`, switching to the
+    /// hashtable encoding if the set is now over them.
+    pub fn set_limits(&mut self, limits: SetLimits) {
+        self.limits = limits;
+        let over = match &self.encoding {
+            Encoding::Intset(v) => v.len() > limits.intset_entries,
+            Encoding::Listpack(v) => {
+                v.len() > limits.listpack.entries
+                    || v.iter().any(|m| m.len() > limits.listpack.value)
+            }
+            Encoding::Hashtable(_) => false,
+        };
+        if over {
+            self.convert_to_hashtable();
+        }
+    }
+
+    /// Switch to the hashtable encoding.
+    fn convert_to_hashtable(&mut self) {
+        let set = match std::mem::take(&mut self.encoding) {
+            Encoding::Intset(v) => {
+                let set: HashSet<_> =
+                    v.iter().map(|i| i.to_string().into_bytes()).collect();
+                self.members_len = set.iter().map(Vec::len).sum();
+                set
+            }
+            Encoding::Listpack(v) => v.into_iter().map(Vec::from).collect(),
+            Encoding::Hashtable(set) => set,
+        };
+        self.encoding = Encoding::Hashtable(set);
+    }
+
+    /// Switch from the intset encoding before adding `member`, which is
+    /// not an integer: to a listpack if the set would fit in one, and to a
+    /// hashtable otherwise.
+    fn convert_from_intset(&mut self, member: &[u8]) {
+        let Encoding::Intset(v) = &self.encoding else {
+            return;
+        };
+        let limits = self.limits.listpack;
+        // the longest integers are the smallest and the largest
+        let digits = v.first().into_iter().chain(v.last());
+        let fits = v.len() < limits.entries
+            && member.len() <= limits.value
+            && digits
+                .map(|i| i.to_string().len())
+                .all(|n| n <= limits.value);
+        if !fits {
+            self.convert_to_hashtable();
+            return;
+        }
+        let members: Vec<Box<[u8]>> = v
+            .iter()
+            .map(|i| i.to_string().into_bytes().into())
+            .collect();
+        self.members_len = members.iter().map(|m| m.len()).sum();
+        self.encoding = Encoding::Listpack(members);
+    }
+
+    /// Approximate memory used by the set.
+    pub fn mem_usage(&self) -> usize {
+        match self.encoding {
+            Encoding::Intset(_) => self.len() * size_of::<i64>(),
+            Encoding::Listpack(_) => {
+                self.members_len + self.len() * LISTPACK_MEMBER_OVERHEAD
+            }
+            Encoding::Hashtable(_) => {
+                self.members_len + self.len() * MEMBER_OVERHEAD
+            }
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.encoding {
+            Encoding::Intset(v) => {
+                parse_int(member).is_some_and(|i| v.binary_search(&i).is_ok())
+            }
+            Encoding::Listpack(v) => v.iter().any(|m| &**m == member),
+            Encoding::Hashtable(s) => s.contains(member),
+        }
+    }
+
+    /// Add `member`. Returns `true` if it is new.
+    pub fn insert(&mut self, member: &[u8]) -> bool {
+        if let Encoding::Intset(v) = &mut self.encoding {
+            if let Some(i) = parse_int(member) {
+                let Err(pos) = v.binary_search(&i) else {
+                    return false;
+                };
+                v.insert(pos, i);
+                if v.len() > self.limits.intset_entries {
+                    self.convert_to_hashtable();
+                }
+                return true;
+            }
+            self.convert_from_intset(member);
+        }
+        let is_new = match &mut self.encoding {
+            Encoding::Intset(_) => unreachable!("converted above"),
+            Encoding::Listpack(v) => {
+                let is_new = !v.iter().any(|m| &**m == member);
+                if is_new {
+                    v.push(member.into());
+                }
+                is_new
+            }
+            Encoding::Hashtable(s) => s.insert(member.to_vec()),
+        };
+        if is_new {
+            self.members_len += member.len();
+            if matches!(self.encoding, Encoding::Listpack(_))
+                && (self.len() > self.limits.listpack.entries
+                    || member.len() > self.limits.listpack.value)
+            {
+                self.convert_to_hashtable();
+            }
+        }
+        is_new
+    }
+
+    /// Remove `member`. Returns `true` if it was present.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let removed = match &mut self.encoding {
+            Encoding::Intset(v) => {
+                let pos = parse_int(member).map(|i| v.binary_search(&i));
+                match pos {
+                    Some(Ok(pos)) => {
+                        v.remove(pos);
+                        // not counted in members_len
+                        return true;
+                    }
+                    _ => false,
+                }
+            }
+            Encoding::Listpack(v) => {
+                match v.iter().position(|m| &**m == member) {
+                    Some(pos) => {
+                        v.remove(pos);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Encoding::Hashtable(s) => s.remove(member),
+        };
+        if removed {
+            self.members_len -= member.len();
+        }
+        removed
+    }
+
+    /// Members, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        let (intset, listpack, hashtable) = match &self.encoding {
+            Encoding::Intset(v) => (Some(v.iter()), None, None),
+            Encoding::Listpack(v) => (None, Some(v.iter()), None),
+            Encoding::Hashtable(s) => (None, None, Some(s.iter())),
+        };
+        let intset = intset
+            .into_iter()
+            .flatten()
+            .map(|i| Cow::Owned(i.to_string().into_bytes()));
+        let listpack =
+            listpack.into_iter().flatten().map(|m| Cow::Borrowed(&**m));
+        let hashtable = hashtable
+            .into_iter()
+            .flatten()
+            .map(|m| Cow::Borrowed(m.as_slice()));
+        intset.chain(listpack).chain(hashtable)
+    }
+}
+
+/// Call `f` on the set at `key`. Returns `Ok(None)` if there is no such
+/// key, and a `WRONGTYPE` error if the key holds another type.
+fn read<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    f: impl FnOnce(&Set) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    match db.get(key, lfu) {
+        None => Ok(None),
+        Some(e) => match &e.value {
+            Value::Set(s) => Ok(Some(f(s))),
+            _ => Err(Frame::Error(WRONG_TYPE)),
+        },
+    }
+}
+
+/// Modify the set at `key` with `f`, creating an empty one first if
+/// `create` is set. Returns `Ok(None)` if there is no such key, and a
+/// `WRONGTYPE` error if the key holds another type.
+///
+/// The set switches encodings according to the current `limits`, and the
+/// key is removed if the set ends up empty.
+fn update<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    limits: SetLimits,
+    create: bool,
+    f: impl FnOnce(&mut Set) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    let new = || create.then(|| Value::Set(Set::new(limits)));
+    let res = db.update(key, lfu, new, |v| match v {
+        Value::Set(s) => {
+            s.set_limits(limits);
+            Some((f(s), s.is_empty()))
+        }
+        _ => None,
+    });
+    match res {
+        None => Ok(None),
+        Some(None) => Err(Frame::Error(WRONG_TYPE)),
+        Some(Some((r, is_empty))) => {
+            if is_empty {
+                db.remove(key);
+            }
+            Ok(Some(r))
+        }
+    }
+}
+
+/// Execute a set command on the selected database `db_index`. `args`
+/// includes the command name, and `raw` are the same arguments as bytes,
+/// for the members.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let (lfu, limits) = {
+        let config = st.config();
+        (config.lfu_params(), config.set_limits())
+    };
+    match (cmd, &args[1..]) {
+        ("sadd", [k, _, ..]) => {
+            let is_new = db.peek(k).is_none();
+            let res = update(db, k, lfu, limits, true, |s| {
+                raw[2..].iter().filter(|m| s.insert(m)).count()
+            });
+            match res {
+                Ok(n) => {
+                    let n = n.unwrap_or(0);
+                    if is_new {
+                        st.notify(notify::NEW, "new", k, db_index);
+                    }
+                    if n > 0 {
+                        st.notify(notify::SET, "sadd", k, db_index);
+                    }
+                    Frame::Int(n as isize)
+                }
+                Err(e) => e,
+            }
+        }
+        ("srem", [k, _, ..]) => {
+            let res = update(db, k, lfu, limits, false, |s| {
+                raw[2..].iter().filter(|m| s.remove(m)).count()
+            });
+            match res {
+                Ok(Some(n)) if n > 0 => {
+                    st.notify(notify::SET, "srem", k, db_index);
+                    if db.peek(k).is_none() {
+                        st.notify(notify::GENERIC, "del", k, db_index);
+                    }
+                    Frame::Int(n as isize)
+                }
+                Ok(_) => Frame::Int(0),
+                Err(e) => e,
+            }
+        }
+        ("sismember", &[k, _]) => {
+            match read(db, k, lfu, |s| s.contains(raw[2])) {
+                Ok(found) => Frame::Int(found.unwrap_or(false) as isize),
+                Err(e) => e,
+            }
+        }
+        ("scard", &[k]) => match read(db, k, lfu, |s| s.len()) {
+            Ok(n) => Frame::Int(n.unwrap_or(0) as isize),
+            Err(e) => e,
+        },
+        ("smembers", &[k]) => {
+            let res = read(db, k, lfu, |s| {
+                s.iter()
+                    .map(|m| Frame::Bytes(arena.alloc_slice_copy(&m)))
+                    .collect::<Vec<_>>()
+            });
+            match res {
+                Ok(items) => Frame::Bulk(
+                    arena.alloc_slice_copy(&items.unwrap_or_default()),
+                ),
+                Err(e) => e,
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
@@ -0,0 +1,225 @@
+//! Server statistics: counters, per-command latency and the slow log.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::db::now_ms;
+
+/// Upper bounds of the latency histogram buckets, in microseconds. An
+/// implicit last bucket catches everything above.
+pub const LATENCY_BUCKETS: [u64; 14] = [
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000,
+    100_000, 1_000_000,
+];
+
+/// Calls and time spent for a single command.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommandStat {
+    pub calls: u64,
+    /// Total time spent in the command, in microseconds.
+    pub usec: u64,
+    /// Calls refused before execution (e.g. wrong arity).
+    pub rejected_calls: u64,
+    /// Calls that returned an error.
+    pub failed_calls: u64,
+    /// Number of calls in each latency bucket (not cumulative), see
+    /// [`LATENCY_BUCKETS`].
+    pub latency: [u64; LATENCY_BUCKETS.len() + 1],
+}
+
+/// Global counters.
+#[derive(Debug)]
+pub struct Stats {
+    pub started: Instant,
+    pub total_connections_received: AtomicU64,
+    pub rejected_connections: AtomicU64,
+    pub total_commands_processed: AtomicU64,
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    /// Clients closed for going over `client-output-buffer-limit`.
+    pub client_output_buffer_limit_disconnections: AtomicU64,
+    /// Clients closed for being idle longer than `timeout`.
+    pub client_idle_timeout_disconnections: AtomicU64,
+    /// Write commands since the last snapshot, for the `save` rules.
+    pub dirty: AtomicU64,
+    /// When the last snapshot was saved, or the server started, in seconds
+    /// since the epoch.
+    pub last_save: AtomicU64,
+    commands: Mutex<HashMap<&'static str, CommandStat>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            total_connections_received: Default::default(),
+            rejected_connections: Default::default(),
+            total_commands_processed: Default::default(),
+            keyspace_hits: Default::default(),
+            keyspace_misses: Default::default(),
+            client_output_buffer_limit_disconnections: Default::default(),
+            client_idle_timeout_disconnections: Default::default(),
+            dirty: Default::default(),
+            last_save: AtomicU64::new(now_ms() / 1000),
+            commands: Default::default(),
+        }
+    }
+}
+
+/// Increment a counter.
+pub fn incr(c: &AtomicU64) {
+    c.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Read a counter.
+pub fn get(c: &AtomicU64) -> u64 {
+    c.load(Ordering::Relaxed)
+}
+
+impl Stats {
+    /// Record a call to `cmd` that took `elapsed`.
+    pub fn record_call(&self, cmd: &'static str, elapsed: Duration, ok: bool) {
+        incr(&self.total_commands_processed);
+        let mut commands = self.commands.lock().unwrap();
+        let st = commands.entry(cmd).or_default();
+        let usec = elapsed.as_micros() as u64;
+        st.calls += 1;
+        st.usec += usec;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|b| usec <= *b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        st.latency[bucket] += 1;
+        if !ok {
+            st.failed_calls += 1;
+        }
+    }
+
+    /// Record a call to `cmd` that was refused before being executed.
+    pub fn record_rejected(&self, cmd: &'static str) {
+        let mut commands = self.commands.lock().unwrap();
+        commands.entry(cmd).or_default().rejected_calls += 1;
+    }
+
+    /// Statistics for each command that was called, sorted by name.
+    pub fn command_stats(&self) -> Vec<(&'static str, CommandStat)> {
+        let commands = self.commands.lock().unwrap();
+        let mut v: Vec<_> = commands.iter().map(|(k, v)| (*k, *v)).collect();
+        v.sort_by_key(|(k, _)| *k);
+        v
+    }
+
+    /// Reset counters, for `CONFIG RESETSTAT`.
+    pub fn reset(&self) {
+        for c in [
+            &self.total_connections_received,
+            &self.rejected_connections,
+            &self.total_commands_processed,
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+            &self.client_output_buffer_limit_disconnections,
+            &self.client_idle_timeout_disconnections,
+        ] {
+            c.store(0, Ordering::Relaxed);
+        }
+        self.commands.lock().unwrap().clear();
+    }
+}
+
+/// Maximum number of arguments kept in a slow log entry.
+const SLOWLOG_MAX_ARGC: usize = 32;
+
+/// Maximum length of an argument in a slow log entry.
+const SLOWLOG_MAX_ARG_LEN: usize = 128;
+
+/// A command that took longer than the threshold.
+#[derive(Clone, Debug)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// When the command was run, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// Time spent, in microseconds.
+    pub duration: u64,
+    /// Arguments, possibly truncated.
+    pub args: Vec<String>,
+    pub addr: SocketAddr,
+    pub client_name: String,
+}
+
+/// The slow log, most recent entries first.
+#[derive(Debug, Default)]
+pub struct SlowLog {
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+impl SlowLog {
+    /// Add an entry for the command `args`, keeping at most `max_len`
+    /// entries.
+    pub fn push(
+        &self,
+        args: &[&str],
+        duration: Duration,
+        addr: SocketAddr,
+        client_name: String,
+        max_len: usize,
+    ) {
+        let mut stored: Vec<String> = args
+            .iter()
+            .take(SLOWLOG_MAX_ARGC)
+            .map(|a| {
+                if a.len() <= SLOWLOG_MAX_ARG_LEN {
+                    return a.to_string();
+                }
+                let more = a.len() - SLOWLOG_MAX_ARG_LEN;
+                let mut end = SLOWLOG_MAX_ARG_LEN;
+                while !a.is_char_boundary(end) {
+                    end -= 1;
+                }
+                format!("{}... ({more} more bytes)", &a[..end])
+            })
+            .collect();
+        if args.len() > SLOWLOG_MAX_ARGC {
+            let more = args.len() - SLOWLOG_MAX_ARGC + 1;
+            stored[SLOWLOG_MAX_ARGC - 1] =
+                format!("... ({more} more arguments)");
+        }
+
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: now_ms() / 1000,
+            duration: duration.as_micros() as u64,
+            args: stored,
+            addr,
+            client_name,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(max_len);
+    }
+
+    /// The `n` most recent entries.
+    pub fn get(&self, n: usize) -> Vec<SlowLogEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().take(n).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
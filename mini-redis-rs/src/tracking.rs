@@ -0,0 +1,168 @@
+//! Client-side caching: `CLIENT TRACKING` and invalidation messages.
+//!
+//! In the default mode, the server remembers which clients read which
+//! keys, and sends each of them an invalidation message the next time the
+//! key is modified, after which the key is forgotten until it is read
+//! again. In broadcast mode, clients instead receive invalidations for
+//! every modified key that starts with one of their prefixes.
+//!
+//! Like in redis, keys are tracked regardless of the database they're in.
+//! Since this server only speaks RESP2, invalidations are sent as messages
+//! on [`INVALIDATE_CHANNEL`], to the client itself or to the client it
+//! redirects to, and only delivered if that client is in pub/sub mode.
+
+use std::{collections::HashSet, sync::Arc};
+
+use dashmap::DashMap;
+
+use crate::{clients::Clients, pubsub::Message};
+
+/// Channel the invalidation messages are sent on.
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Tracking options of a client, from `CLIENT TRACKING ON`.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Client that receives the invalidation messages instead.
+    pub redirect: Option<u64>,
+    /// Broadcast mode.
+    pub bcast: bool,
+    /// Prefixes, in broadcast mode. Empty means all keys.
+    pub prefixes: Vec<String>,
+    /// Only track keys read right after `CLIENT CACHING YES`.
+    pub optin: bool,
+    /// Don't track keys read right after `CLIENT CACHING NO`.
+    pub optout: bool,
+    /// Don't send invalidations for keys modified by the client itself.
+    pub noloop: bool,
+}
+
+/// Tracking table.
+#[derive(Debug, Default)]
+pub struct Tracking {
+    /// Options of the clients with tracking enabled, by ID.
+    clients: DashMap<u64, Options>,
+    /// Key → IDs of the clients that read it, in default mode.
+    keys: DashMap<String, HashSet<u64>>,
+    /// Prefix → IDs of the clients that track it, in broadcast mode.
+    prefixes: DashMap<String, HashSet<u64>>,
+}
+
+impl Tracking {
+    /// Number of clients with tracking enabled.
+    pub fn n_clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Number of keys tracked in default mode.
+    pub fn n_keys(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Number of prefixes tracked in broadcast mode.
+    pub fn n_prefixes(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    /// Tracking options of client `id`, if tracking is enabled.
+    pub fn options(&self, id: u64) -> Option<Options> {
+        self.clients.get(&id).map(|o| o.clone())
+    }
+
+    /// Enable tracking for client `id`, or change its options.
+    pub fn enable(&self, id: u64, mut opts: Options) {
+        self.disable(id);
+        if opts.bcast {
+            if opts.prefixes.is_empty() {
+                opts.prefixes.push(String::new());
+            }
+            for p in &opts.prefixes {
+                self.prefixes.entry(p.clone()).or_default().insert(id);
+            }
+        }
+        self.clients.insert(id, opts);
+    }
+
+    /// Disable tracking for client `id`.
+    ///
+    /// Keys it read are forgotten lazily, when they are invalidated.
+    pub fn disable(&self, id: u64) {
+        let Some((_, opts)) = self.clients.remove(&id) else {
+            return;
+        };
+        for p in &opts.prefixes {
+            self.prefixes.remove_if_mut(p, |_, ids| {
+                ids.remove(&id);
+                ids.is_empty()
+            });
+        }
+    }
+
+    /// Record that client `id` read `keys`, if it tracks keys in default
+    /// mode.
+    pub fn track(&self, id: u64, keys: &[&str]) {
+        if self.clients.get(&id).is_none_or(|o| o.bcast) {
+            return;
+        }
+        for k in keys {
+            self.keys.entry(k.to_string()).or_default().insert(id);
+        }
+    }
+
+    /// Send invalidation messages for `key`, which was modified by client
+    /// `origin` (or by the server itself, if `None`).
+    pub fn invalidate(
+        &self,
+        clients: &Clients,
+        key: &str,
+        origin: Option<u64>,
+    ) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let mut ids = HashSet::new();
+        if let Some((_, readers)) = self.keys.remove(key) {
+            ids.extend(readers);
+        }
+        for r in self.prefixes.iter() {
+            if key.starts_with(r.key().as_str()) {
+                ids.extend(r.value().iter().copied());
+            }
+        }
+
+        let keys: Arc<[String]> = Arc::new([key.to_string()]);
+        for id in ids {
+            self.send(clients, id, origin, Some(keys.clone()));
+        }
+    }
+
+    /// Send an invalidation message for all the keys to every tracking
+    /// client, after a flush.
+    pub fn invalidate_all(&self, clients: &Clients) {
+        self.keys.clear();
+        let ids: Vec<u64> = self.clients.iter().map(|r| *r.key()).collect();
+        for id in ids {
+            self.send(clients, id, None, None);
+        }
+    }
+
+    fn send(
+        &self,
+        clients: &Clients,
+        id: u64,
+        origin: Option<u64>,
+        keys: Option<Arc<[String]>>,
+    ) {
+        // the client may have disabled tracking since it read the key
+        let Some(opts) = self.options(id) else {
+            return;
+        };
+        if opts.noloop && origin == Some(id) {
+            return;
+        }
+        let target = opts.redirect.unwrap_or(id);
+        if let Some(c) = clients.get(target) {
+            c.push(Message::Invalidate(keys));
+        }
+    }
+}
@@ -1,6 +1,6 @@
 //! Wire protocol
 
-use std::{io::Write, net::SocketAddr};
+use std::{io::Write, net::SocketAddr, time::Duration};
 
 use anyhow::{Context, Result};
 use async_recursion::async_recursion;
@@ -24,9 +24,13 @@ pub struct Conn<'a> {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Frame<'a> {
     String(&'a str),
+    /// A bulk string that is not valid UTF-8.
+    Bytes(&'a [u8]),
     Int(isize),
     Bulk(&'a [Frame<'a>]),
     Error(&'a str),
+    /// Absence of value (`$-1` in the protocol).
+    Null,
 }
 
 impl<'a> Conn<'a> {
@@ -48,6 +52,40 @@ impl<'a> Conn<'a> {
     }
 }
 
+/// Wait until there is data to read, without consuming it. Returns
+/// `false` if the connection was closed.
+///
+/// Unlike [`read_frame`], this is cancel-safe, so it can be raced against
+/// other events.
+pub async fn wait_readable(conn: &mut Conn<'_>) -> Result<bool> {
+    Ok(!conn.read.fill_buf().await?.is_empty())
+}
+
+/// Is there data that was received but not read yet? This is the case
+/// when the peer pipelined several frames.
+pub fn has_buffered(conn: &Conn<'_>) -> bool {
+    !conn.read.buffer().is_empty()
+}
+
+/// Close the connection cleanly: flush the replies, close our side, and
+/// discard what the client still sends until it closes its side too, or
+/// sends nothing for `linger`.
+///
+/// Closing the socket with unread data would reset the connection, and
+/// the client could lose replies it did not read yet.
+pub async fn close(conn: &mut Conn<'_>, linger: Duration) -> Result<()> {
+    conn.write.shutdown().await?;
+    let mut buf = [0; 4096];
+    while let Ok(n) =
+        tokio::time::timeout(linger, conn.read.read(&mut buf)).await
+    {
+        if n? == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
 /// Read a Redis value using the given arena.
 #[async_recursion(?Send)]
 pub async fn read_frame<'arena>(
@@ -90,6 +128,7 @@ pub async fn read_frame<'arena>(
             let i: isize = data.parse().with_context(|| "decoding integer")?;
             Ok(Some(Frame::Int(i)))
         }
+        b'*' | b'$' if &buf[1..] == b"-1" => Ok(Some(Frame::Null)),
         b'*' => {
             // array
             log::trace!("read array");
@@ -131,9 +170,10 @@ pub async fn read_frame<'arena>(
             if b2 != [b'\r', b'\n'] {
                 anyhow::bail!("expect crlf after a bulk string");
             }
-            let data = std::str::from_utf8(v)
-                .with_context(|| " decoding bulk string")?;
-            Ok(Some(Frame::String(data)))
+            match std::str::from_utf8(v) {
+                Ok(data) => Ok(Some(Frame::String(data))),
+                Err(_) => Ok(Some(Frame::Bytes(v))),
+            }
         }
 
         _c => {
@@ -142,39 +182,142 @@ pub async fn read_frame<'arena>(
     }
 }
 
-#[async_recursion(?Send)]
-async fn write_frame_rec(conn: &mut Conn, frame: &Frame) -> Result<()> {
-    conn.buf.clear();
+/// Append the encoding of `frame` to `buf`.
+pub fn encode(frame: &Frame, buf: &mut Vec<u8>) {
     match frame {
-        Frame::String(s) => {
-            write!(&mut conn.buf, "${}\r\n", s.as_bytes().len()).unwrap();
-            conn.write.write_all(&conn.buf[..]).await?;
-            conn.write.write_all(s.as_bytes()).await?;
-            conn.write.write_all(b"\r\n").await?;
-        }
-        Frame::Int(i) => {
-            write!(&mut conn.buf, ":{}\r\n", i).unwrap();
-            conn.write.write_all(&conn.buf).await?
+        Frame::String(s) => encode(&Frame::Bytes(s.as_bytes()), buf),
+        Frame::Bytes(b) => {
+            write!(buf, "${}\r\n", b.len()).unwrap();
+            buf.extend_from_slice(b);
+            buf.extend_from_slice(b"\r\n");
         }
+        Frame::Int(i) => write!(buf, ":{}\r\n", i).unwrap(),
         Frame::Bulk(a) => {
-            write!(&mut conn.buf, "*{}\r\n", a.len()).unwrap();
-            conn.write.write_all(&conn.buf).await?;
+            write!(buf, "*{}\r\n", a.len()).unwrap();
             for x in &a[..] {
-                write_frame_rec(conn, x).await?;
+                encode(x, buf);
             }
         }
         Frame::Error(e) => {
-            let msg = format!("-{}\r\n", e);
-            conn.write.write_all(msg.as_bytes()).await?;
+            // errors often quote client input: like redis, replace line
+            // breaks so that they can't inject replies
+            buf.push(b'-');
+            buf.extend(e.bytes().map(|b| match b {
+                b'\r' | b'\n' => b' ',
+                b => b,
+            }));
+            buf.extend_from_slice(b"\r\n");
         }
+        Frame::Null => buf.extend_from_slice(b"$-1\r\n"),
     }
-    Ok(())
+}
+
+/// Length of the encoding of `frame`, without encoding it.
+pub fn encoded_len(frame: &Frame) -> usize {
+    let digits = |n: usize| n.checked_ilog10().unwrap_or(0) as usize + 1;
+    match frame {
+        Frame::String(s) => digits(s.len()) + s.len() + 5,
+        Frame::Bytes(b) => digits(b.len()) + b.len() + 5,
+        Frame::Int(i) => digits(i.unsigned_abs()) + (*i < 0) as usize + 3,
+        Frame::Bulk(a) => {
+            digits(a.len()) + 3 + a.iter().map(encoded_len).sum::<usize>()
+        }
+        Frame::Error(e) => e.len() + 3,
+        Frame::Null => 5,
+    }
+}
+
+/// Append the encoding of a command (an array of strings, which don't
+/// need to be valid UTF-8) to `buf`.
+pub fn encode_command<S: AsRef<[u8]>>(args: &[S], buf: &mut Vec<u8>) {
+    write!(buf, "*{}\r\n", args.len()).unwrap();
+    for a in args {
+        encode(&Frame::Bytes(a.as_ref()), buf);
+    }
+}
+
+/// Owned version of [`Frame`], for clients.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    String(String),
+    /// A bulk string that is not valid UTF-8.
+    Bytes(Vec<u8>),
+    Int(i64),
+    Bulk(Vec<Value>),
+    Error(String),
+    Null,
+}
+
+/// Decode a value from the beginning of `buf`.
+///
+/// Returns the value and the number of bytes it used, or `None` if `buf`
+/// doesn't contain a full value yet.
+pub fn decode(buf: &[u8]) -> Result<Option<(Value, usize)>> {
+    let Some(eol) = buf.iter().position(|&c| c == b'\n') else {
+        return Ok(None);
+    };
+    let line = buf[..eol].strip_suffix(b"\r").unwrap_or(&buf[..eol]);
+    let next = eol + 1;
+    if line.is_empty() {
+        anyhow::bail!("empty line");
+    }
+    let text =
+        std::str::from_utf8(&line[1..]).with_context(|| "decoding line")?;
+
+    let value = match line[0] {
+        b'+' => Value::String(text.to_string()),
+        b'-' => Value::Error(text.to_string()),
+        b':' => Value::Int(text.parse().with_context(|| "decoding integer")?),
+        b'*' | b'$' if text == "-1" => Value::Null,
+        b'$' => {
+            let len: usize =
+                text.parse().with_context(|| "decoding length of string")?;
+            let Some(data) = buf.get(next..next + len + 2) else {
+                return Ok(None);
+            };
+            if &data[len..] != b"\r\n" {
+                anyhow::bail!("expect crlf after a bulk string");
+            }
+            let value = match String::from_utf8(data[..len].to_vec()) {
+                Ok(s) => Value::String(s),
+                Err(e) => Value::Bytes(e.into_bytes()),
+            };
+            return Ok(Some((value, next + len + 2)));
+        }
+        b'*' => {
+            let len: usize =
+                text.parse().with_context(|| "decoding length of array")?;
+            let mut items = Vec::with_capacity(len.min(1024));
+            let mut pos = next;
+            for _ in 0..len {
+                match decode(&buf[pos..])? {
+                    Some((v, n)) => {
+                        items.push(v);
+                        pos += n;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            return Ok(Some((Value::Bulk(items), pos)));
+        }
+        c => anyhow::bail!("invalid first char: {c:?}"),
+    };
+    Ok(Some((value, next)))
 }
 
 /// Write a frame.
 pub async fn write_frame(conn: &mut Conn<'_>, frame: &Frame<'_>) -> Result<()> {
     log::debug!("sending msg {frame:?}");
-    write_frame_rec(conn, frame).await?;
+    conn.buf.clear();
+    encode(frame, &mut conn.buf);
+    conn.write.write_all(&conn.buf).await?;
+    conn.write.flush().await?;
+    Ok(())
+}
+
+/// Write data that is already encoded, such as a part of a frame.
+pub async fn write_bytes(conn: &mut Conn<'_>, bytes: &[u8]) -> Result<()> {
+    conn.write.write_all(bytes).await?;
     conn.write.flush().await?;
     Ok(())
 }
@@ -0,0 +1,469 @@
+//! Sorted sets, and the `ZADD`, `ZSCORE`, `ZREM`, `ZCARD` and `ZRANGE`
+//! commands.
+//!
+//! Members are ordered by score, then lexicographically, like in redis.
+//! They are binary, like strings.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+};
+
+use crate::{
+    db::{Db, LfuParams, ListpackLimits, Value},
+    notify,
+    server::{wrong_arity, State, WRONG_TYPE},
+    wire::Frame,
+};
+
+/// Approximate per-member overhead of the skiplist encoding, in bytes, on
+/// top of the member.
+const MEMBER_OVERHEAD: usize = 48;
+
+/// Per-member overhead of the listpack encoding: the score, and a pointer
+/// to the member.
+const LISTPACK_MEMBER_OVERHEAD: usize = 24;
+
+/// A score, ordered with [`f64::total_cmp`]. Scores are never NaN.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// Members in order, looked up linearly: compact, and fast enough for
+    /// small sets.
+    Listpack(Vec<(Score, Box<[u8]>)>),
+    /// An index by member, and the members in order. This plays the role
+    /// of redis' skiplist.
+    SkipList {
+        scores: HashMap<Vec<u8>, f64>,
+        order: BTreeSet<(Score, Vec<u8>)>,
+    },
+}
+
+/// A sorted set. Small sets start in the listpack encoding, and switch to
+/// the skiplist encoding for good once they outgrow their
+/// [`ListpackLimits`].
+#[derive(Clone, Debug, Default)]
+pub struct ZSet {
+    encoding: Encoding,
+    limits: ListpackLimits,
+    /// Total length of the members.
+    members_len: usize,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Listpack(vec![])
+    }
+}
+
+impl ZSet {
+    /// An empty set, that switches to the skiplist encoding past `limits`.
+    pub fn new(limits: ListpackLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(v) => v.len(),
+            Encoding::SkipList { scores, .. } => scores.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of the encoding, as returned by `OBJECT ENCODING`.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "listpack",
+            Encoding::SkipList { .. } => "skiplist",
+        }
+    }
+
+    /// Change the limits, e.g. after `CONFIG SET`, switching to the
+    /// skiplist encoding if the set is now over them.
+    pub fn set_limits(&mut self, limits: ListpackLimits) {
+        self.limits = limits;
+        if let Encoding::Listpack(v) = &self.encoding {
+            let too_long = v.iter().any(|(_, m)| m.len() > limits.value);
+            if too_long || v.len() > limits.entries {
+                self.convert();
+            }
+        }
+    }
+
+    /// Switch to the skiplist encoding.
+    fn convert(&mut self) {
+        let Encoding::Listpack(v) = std::mem::take(&mut self.encoding) else {
+            return;
+        };
+        let mut scores = HashMap::with_capacity(v.len());
+        let mut order = BTreeSet::new();
+        for (score, member) in v {
+            let member = Vec::from(member);
+            scores.insert(member.clone(), score.0);
+            order.insert((score, member));
+        }
+        self.encoding = Encoding::SkipList { scores, order };
+    }
+
+    /// Approximate memory used by the set.
+    pub fn mem_usage(&self) -> usize {
+        match self.encoding {
+            Encoding::Listpack(_) => {
+                self.members_len + self.len() * LISTPACK_MEMBER_OVERHEAD
+            }
+            // each member is stored twice
+            Encoding::SkipList { .. } => {
+                2 * self.members_len + self.len() * MEMBER_OVERHEAD
+            }
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        match &self.encoding {
+            Encoding::Listpack(v) => {
+                v.iter().find(|(_, m)| &**m == member).map(|(s, _)| s.0)
+            }
+            Encoding::SkipList { scores, .. } => scores.get(member).copied(),
+        }
+    }
+
+    /// Add `member`, or change its score. Returns `true` if it's new.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        // -0 and 0 are the same score
+        let score = if score == 0.0 { 0.0 } else { score };
+        let is_new = match &mut self.encoding {
+            Encoding::Listpack(v) => {
+                let old = v.iter().position(|(_, m)| &**m == member);
+                let is_new = match old {
+                    Some(i) => {
+                        v.remove(i);
+                        false
+                    }
+                    None => true,
+                };
+                let key = (Score(score), member);
+                let i = v
+                    .binary_search_by(|(s, m)| (*s, &**m).cmp(&key))
+                    .unwrap_or_else(|i| i);
+                v.insert(i, (Score(score), member.into()));
+                is_new
+            }
+            Encoding::SkipList { scores, order } => {
+                match scores.insert(member.to_vec(), score) {
+                    Some(old) => {
+                        order.remove(&(Score(old), member.to_vec()));
+                        order.insert((Score(score), member.to_vec()));
+                        false
+                    }
+                    None => {
+                        order.insert((Score(score), member.to_vec()));
+                        true
+                    }
+                }
+            }
+        };
+        if is_new {
+            self.members_len += member.len();
+            if matches!(self.encoding, Encoding::Listpack(_))
+                && (self.len() > self.limits.entries
+                    || member.len() > self.limits.value)
+            {
+                self.convert();
+            }
+        }
+        is_new
+    }
+
+    /// Remove `member`, returning its score if it was present.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = match &mut self.encoding {
+            Encoding::Listpack(v) => {
+                let i = v.iter().position(|(_, m)| &**m == member)?;
+                v.remove(i).0 .0
+            }
+            Encoding::SkipList { scores, order } => {
+                let (member, score) = scores.remove_entry(member)?;
+                order.remove(&(Score(score), member));
+                score
+            }
+        };
+        self.members_len -= member.len();
+        Some(score)
+    }
+
+    /// Members with their scores, in order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        let (listpack, skiplist) = match &self.encoding {
+            Encoding::Listpack(v) => (Some(v.iter()), None),
+            Encoding::SkipList { order, .. } => (None, Some(order.iter())),
+        };
+        let listpack = listpack.into_iter().flatten();
+        let skiplist = skiplist.into_iter().flatten();
+        listpack
+            .map(|(s, m)| (&**m, s.0))
+            .chain(skiplist.map(|(s, m)| (m.as_slice(), s.0)))
+    }
+}
+
+/// Parse a score. Like in redis, `inf` and `-inf` are valid, but not
+/// `nan`.
+pub fn parse_score(s: &str) -> Option<f64> {
+    s.parse::<f64>().ok().filter(|f| !f.is_nan())
+}
+
+/// Format a score like redis replies with it.
+pub fn format_score(score: f64) -> String {
+    match score {
+        f64::INFINITY => "inf".to_string(),
+        f64::NEG_INFINITY => "-inf".to_string(),
+        _ => score.to_string(),
+    }
+}
+
+/// Call `f` on the sorted set at `key`. Returns `Ok(None)` if there is no
+/// such key, and a `WRONGTYPE` error if the key holds another type.
+pub(crate) fn read<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    f: impl FnOnce(&ZSet) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    match db.get(key, lfu) {
+        None => Ok(None),
+        Some(e) => match &e.value {
+            Value::ZSet(z) => Ok(Some(f(z))),
+            _ => Err(Frame::Error(WRONG_TYPE)),
+        },
+    }
+}
+
+/// Modify the sorted set at `key` with `f`, creating an empty one first
+/// if `create` is set. Returns `Ok(None)` if there is no such key, and a
+/// `WRONGTYPE` error if the key holds another type.
+///
+/// The set switches encodings according to the current `limits`, and the
+/// key is removed if the set ends up empty.
+pub(crate) fn update<R>(
+    db: &Db,
+    key: &str,
+    lfu: LfuParams,
+    limits: ListpackLimits,
+    create: bool,
+    f: impl FnOnce(&mut ZSet) -> R,
+) -> Result<Option<R>, Frame<'static>> {
+    let new = || create.then(|| Value::ZSet(ZSet::new(limits)));
+    let res = db.update(key, lfu, new, |v| match v {
+        Value::ZSet(z) => {
+            z.set_limits(limits);
+            Some((f(z), z.is_empty()))
+        }
+        _ => None,
+    });
+    match res {
+        None => Ok(None),
+        Some(None) => Err(Frame::Error(WRONG_TYPE)),
+        Some(Some((r, is_empty))) => {
+            if is_empty {
+                db.remove(key);
+            }
+            Ok(Some(r))
+        }
+    }
+}
+
+/// Execute a sorted set command on the selected database `db_index`.
+/// `args` includes the command name, and `raw` are the same arguments as
+/// bytes, for the members.
+pub fn exec<'are>(
+    st: &State,
+    db_index: usize,
+    cmd: &str,
+    args: &[&'are str],
+    raw: &[&'are [u8]],
+    arena: &'are bumpalo::Bump,
+) -> Frame<'are> {
+    let db = &*st.db(db_index);
+    let (lfu, limits) = {
+        let config = st.config();
+        (config.lfu_params(), config.zset_listpack_limits())
+    };
+    let score_reply =
+        |score: f64| Frame::String(arena.alloc_str(&format_score(score)));
+    match (cmd, &args[1..]) {
+        ("zadd", [k, rest @ ..]) => zadd(st, db_index, k, rest, &raw[2..]),
+        ("zscore", &[k, _]) => match read(db, k, lfu, |z| z.score(raw[2])) {
+            Ok(Some(Some(score))) => score_reply(score),
+            Ok(_) => Frame::Null,
+            Err(e) => e,
+        },
+        ("zcard", &[k]) => match read(db, k, lfu, |z| z.len()) {
+            Ok(n) => Frame::Int(n.unwrap_or(0) as isize),
+            Err(e) => e,
+        },
+        ("zrem", [k, ..]) => {
+            let res = update(db, k, lfu, limits, false, |z| {
+                raw[2..].iter().filter(|m| z.remove(m).is_some()).count()
+            });
+            match res {
+                Ok(Some(n)) if n > 0 => {
+                    st.notify(notify::ZSET, "zrem", k, db_index);
+                    if db.peek(k).is_none() {
+                        st.notify(notify::GENERIC, "del", k, db_index);
+                    }
+                    Frame::Int(n as isize)
+                }
+                Ok(_) => Frame::Int(0),
+                Err(e) => e,
+            }
+        }
+        ("zrange", [k, start, stop, opts @ ..]) => {
+            let with_scores = match opts {
+                [] => false,
+                [o] if o.eq_ignore_ascii_case("withscores") => true,
+                _ => return Frame::Error("ERR syntax error"),
+            };
+            let (Ok(start), Ok(stop)) =
+                (start.parse::<i64>(), stop.parse::<i64>())
+            else {
+                return Frame::Error(
+                    "ERR value is not an integer or out of range",
+                );
+            };
+            let res = read(db, k, lfu, |z| {
+                let len = z.len() as i64;
+                let start = if start < 0 { len + start } else { start };
+                let stop = if stop < 0 { len + stop } else { stop };
+                let (start, stop) = (start.max(0), stop.min(len - 1));
+                let mut items = vec![];
+                if start <= stop {
+                    let n = (stop - start + 1) as usize;
+                    for (m, score) in z.iter().skip(start as usize).take(n) {
+                        items.push(Frame::Bytes(arena.alloc_slice_copy(m)));
+                        if with_scores {
+                            items.push(score_reply(score));
+                        }
+                    }
+                }
+                items
+            });
+            match res {
+                Ok(items) => Frame::Bulk(
+                    arena.alloc_slice_copy(&items.unwrap_or_default()),
+                ),
+                Err(e) => e,
+            }
+        }
+        _ => Frame::Error(arena.alloc_str(&wrong_arity(cmd))),
+    }
+}
+
+/// `ZADD key [NX|XX] [GT|LT] [CH] score member [score member ...]`.
+/// `raw` are the same arguments as `args`, as bytes.
+fn zadd<'are>(
+    st: &State,
+    db_index: usize,
+    key: &str,
+    args: &[&str],
+    raw: &[&[u8]],
+) -> Frame<'are> {
+    let (mut nx, mut xx, mut gt, mut lt, mut ch) =
+        (false, false, false, false, false);
+    let mut args = args;
+    while let [opt, rest @ ..] = args {
+        match &*opt.to_ascii_lowercase() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "gt" => gt = true,
+            "lt" => lt = true,
+            "ch" => ch = true,
+            _ => break,
+        }
+        args = rest;
+    }
+    if nx && xx {
+        return Frame::Error(
+            "ERR XX and NX options at the same time are not compatible",
+        );
+    }
+    if (gt && lt) || ((gt || lt) && nx) {
+        return Frame::Error(
+            "ERR GT, LT, and/or NX options at the same time are not \
+            compatible",
+        );
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Frame::Error("ERR syntax error");
+    }
+    let raw = &raw[raw.len() - args.len()..];
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    for (pair, raw) in args.chunks(2).zip(raw.chunks(2)) {
+        let Some(score) = parse_score(pair[0]) else {
+            return Frame::Error("ERR value is not a valid float");
+        };
+        pairs.push((score, raw[1]));
+    }
+
+    let db = st.db(db_index);
+    let is_new = db.peek(key).is_none();
+    let (lfu, limits) = {
+        let config = st.config();
+        (config.lfu_params(), config.zset_listpack_limits())
+    };
+    let res = update(&db, key, lfu, limits, !xx, |z| {
+        let (mut added, mut changed) = (0, 0);
+        for (score, member) in pairs {
+            match z.score(member) {
+                None if !xx => {
+                    z.insert(member, score);
+                    added += 1;
+                }
+                Some(old)
+                    if !(nx
+                        || old == score
+                        || (gt && score < old)
+                        || (lt && score > old)) =>
+                {
+                    z.insert(member, score);
+                    changed += 1;
+                }
+                _ => (),
+            }
+        }
+        (added, changed)
+    });
+    match res {
+        Ok(Some((added, changed))) => {
+            if is_new && added > 0 {
+                st.notify(notify::NEW, "new", key, db_index);
+            }
+            if added + changed > 0 {
+                st.notify(notify::ZSET, "zadd", key, db_index);
+            }
+            Frame::Int(if ch { added + changed } else { added })
+        }
+        Ok(None) => Frame::Int(0),
+        Err(e) => e,
+    }
+}
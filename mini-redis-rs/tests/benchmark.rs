@@ -0,0 +1,62 @@
+//! Run `mini-redis-benchmark` against an embedded server.
+#![cfg(feature = "cli")]
+
+use std::{net::SocketAddr, process::Command};
+
+use anyhow::Result;
+use mini_redis_rs::Server;
+
+/// Run the benchmark with `args`, and return its output.
+fn benchmark(addr: SocketAddr, args: &[&str]) -> Result<String> {
+    let out = Command::new(env!("CARGO_BIN_EXE_mini-redis-benchmark"))
+        .args(["-p", &addr.port().to_string(), "-n", "500", "-c", "4"])
+        .args(args)
+        .output()?;
+    anyhow::ensure!(out.status.success(), "exited with {}", out.status);
+    Ok(String::from_utf8(out.stdout)?)
+}
+
+#[test]
+fn csv_output() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+    let out = benchmark(addr, &["-t", "set,get", "-P", "4", "--csv"])?;
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3, "{out}");
+    assert!(lines[0].starts_with("\"test\",\"rps\","));
+    assert!(lines[1].starts_with("\"SET\","));
+    assert!(lines[2].starts_with("\"GET\","));
+    assert_eq!(lines[1].split(',').count(), 8);
+    Ok(())
+}
+
+#[test]
+fn json_output_with_mix_and_user_command() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+    let args = [
+        "--json",
+        "-r",
+        "10",
+        "--mix",
+        "get=1,set=1",
+        "set",
+        "k:__rand_int__",
+        "v",
+    ];
+    let out = benchmark(addr, &args)?;
+    assert!(out.starts_with("[\n") && out.ends_with("]\n"), "{out}");
+    assert!(out.contains("{\"test\": \"get=1,set=1\", \"requests\": 500,"));
+    assert!(out.contains(
+        "{\"test\": \"set k:__rand_int__ v\", \"requests\": 500, \
+        \"errors\": 0,"
+    ));
+
+    // the user command ran with random keys
+    let client =
+        mini_redis_rs::client::blocking::Client::connect(addr.to_string())?;
+    let keys = client.keys("k:*")?;
+    assert!(!keys.is_empty() && keys.len() <= 10, "{keys:?}");
+    assert!(keys.iter().all(|k| k.len() == "k:".len() + 12));
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! Drive an embedded server with the blocking client.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::{blocking::Client, Options, ServerError},
+    wire::Value,
+    Server,
+};
+
+#[test]
+fn typed_commands_and_pipeline() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+
+    assert_eq!(client.get("a")?, None);
+    client.set("a", "1")?;
+    client.set_ex("b", "2", Duration::from_secs(100))?;
+    assert_eq!(client.get("a")?, Some("1".to_string()));
+    assert!(client.ttl("b")? > 0);
+    assert_eq!(client.exists(&["a", "b", "c"])?, 2);
+    assert_eq!(client.del(&["a"])?, 1);
+    assert_eq!(client.dbsize()?, 1);
+
+    let e = client.cmd(&["nosuchcommand"]).unwrap_err();
+    assert!(e.is::<ServerError>());
+
+    let cmds: Vec<Vec<String>> = (0..100)
+        .map(|i| vec!["set".into(), format!("k{i}"), i.to_string()])
+        .chain([vec!["dbsize".to_string()]])
+        .collect();
+    let replies = client.pipeline(&cmds)?;
+    assert_eq!(replies.len(), 101);
+    assert_eq!(replies[100], Value::Int(101));
+    Ok(())
+}
+
+#[test]
+fn reconnection_and_subscription() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr().to_string();
+    let opts = Options {
+        db: 1,
+        ..Options::default()
+    };
+    let client = Client::with_options(addr.clone(), opts);
+    client.set("k", "v")?;
+
+    let admin = Client::connect(addr)?;
+    let id = client.client_id()?;
+    assert!(admin.client_kill(id as u64)?);
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(client.get("k")?, Some("v".to_string()));
+    assert_eq!(admin.get("k")?, None);
+
+    let mut sub = client.subscribe()?;
+    sub.subscribe(&["chan"])?;
+    assert_eq!(admin.publish("chan", "hello")?, 1);
+    assert_eq!(sub.next_message()?.payload, b"hello");
+    // messages are binary, channels aren't
+    assert_eq!(admin.publish("chan", b"\xff\x00")?, 1);
+    assert_eq!(sub.next_message()?.payload, b"\xff\x00");
+    let err = admin.cmd(&[&b"publish"[..], b"\xff", b"x"]).unwrap_err();
+    assert!(err.to_string().contains("must be valid UTF-8"), "{err}");
+
+    let pong = admin.cmd(&[&b"ping"[..], b"\xff\x00"])?;
+    assert_eq!(pong, Value::Bytes(b"\xff\x00".to_vec()));
+    Ok(())
+}
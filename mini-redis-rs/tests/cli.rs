@@ -0,0 +1,75 @@
+//! Run `mini-redis-cli` against an embedded server.
+#![cfg(feature = "cli")]
+
+use std::{
+    io::Write,
+    net::SocketAddr,
+    process::{Command, Stdio},
+};
+
+use anyhow::Result;
+use mini_redis_rs::{client::blocking::Client, Server};
+
+/// Run the CLI with `args` and `stdin`, and return its output.
+fn cli(addr: SocketAddr, args: &[&str], stdin: &str) -> Result<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mini-redis-cli"))
+        .args(["-p", &addr.port().to_string()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(stdin.as_bytes())?;
+    let out = child.wait_with_output()?;
+    anyhow::ensure!(out.status.success(), "exited with {}", out.status);
+    Ok(String::from_utf8(out.stdout)?)
+}
+
+#[test]
+fn one_shot_and_formatting() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+    assert_eq!(cli(addr, &["set", "a", "x\ny"], "")?, "OK\n");
+    assert_eq!(cli(addr, &["--no-raw", "get", "a"], "")?, "\"x\\ny\"\n");
+    assert_eq!(cli(addr, &["--no-raw", "get", "b"], "")?, "(nil)\n");
+    assert_eq!(
+        cli(addr, &["--no-raw", "exists", "a", "a"], "")?,
+        "(integer) 2\n"
+    );
+    assert_eq!(
+        cli(addr, &["--no-raw", "command", "info", "get"], "")?,
+        "1) 1) \"get\"\n   \
+            2) (integer) 2\n   \
+            3) 1) \"readonly\"\n      \
+               2) \"fast\"\n   \
+            4) (integer) 1\n   \
+            5) (integer) 1\n   \
+            6) (integer) 1\n"
+    );
+    assert!(cli(addr, &["--no-raw", "nosuch"], "")?.starts_with("(error) "));
+    assert_eq!(cli(addr, &["-n", "3", "dbsize"], "")?, "0\n");
+    Ok(())
+}
+
+#[test]
+fn pipe_and_scan() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+    let out = cli(addr, &["--pipe"], "set a 1\nSET b \"two words\"\n\n")?;
+    assert!(out.ends_with("errors: 0, replies: 2\n"), "{out}");
+    let resp = "*3\r\n$3\r\nset\r\n$1\r\nc\r\n$1\r\n3\r\n";
+    let out = cli(addr, &["--pipe"], resp)?;
+    assert!(out.ends_with("errors: 0, replies: 1\n"), "{out}");
+
+    assert_eq!(cli(addr, &["get", "b"], "")?, "two words\n");
+    // `\x` escapes are bytes
+    let out = cli(addr, &["--pipe"], "set d \"\\xff\\x00\\xc3\\xa9\"\n")?;
+    assert!(out.ends_with("errors: 0, replies: 1\n"), "{out}");
+    let client = Client::connect(addr.to_string())?;
+    assert_eq!(client.get_bytes("d")?, Some(b"\xff\x00\xc3\xa9".to_vec()));
+    client.del(&["d"])?;
+    let out = cli(addr, &["--scan", "--count", "1"], "")?;
+    let mut keys: Vec<&str> = out.lines().collect();
+    keys.sort();
+    assert_eq!(keys, ["a", "b", "c"]);
+    Ok(())
+}
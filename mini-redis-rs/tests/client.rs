@@ -0,0 +1,128 @@
+//! Drive an embedded server with the pooled client.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::{Client, Message, Options, ServerError},
+    wire::Value,
+    Server,
+};
+
+#[test]
+fn client_is_send_and_sync() {
+    fn check<T: Send + Sync + Clone>() {}
+    check::<Client>();
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn typed_commands() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string()).await?;
+
+    assert_eq!(client.get("a").await?, None);
+    client.set("a", "1").await?;
+    client.set_ex("b", "2", Duration::from_secs(100)).await?;
+    assert_eq!(client.get("a").await?, Some("1".to_string()));
+    assert!(client.pttl("b").await? > 0);
+    assert_eq!(client.ttl("a").await?, -1);
+    assert_eq!(client.exists(&["a", "b", "c"]).await?, 2);
+    assert_eq!(client.key_type("a").await?, "string");
+    assert!(client.renamenx("a", "c").await?);
+    let mut keys = client.keys("*").await?;
+    keys.sort();
+    assert_eq!(keys, ["b", "c"]);
+    let (cursor, keys) = client.scan(0, Some("c"), Some(100), None).await?;
+    assert_eq!((cursor, keys), (0, vec!["c".to_string()]));
+    assert!(client.move_key("c", 1).await?);
+    assert_eq!(client.dbsize().await?, 1);
+    assert_eq!(client.del(&["b", "c"]).await?, 1);
+    client.swapdb(0, 1).await?;
+    assert_eq!(client.get("c").await?, Some("1".to_string()));
+    client.flushall().await?;
+    assert_eq!(client.dbsize().await?, 0);
+
+    let cfg = client.config_get("maxmemory-policy").await?;
+    assert_eq!(cfg.len(), 1);
+    assert_eq!(client.publish("chan", "hello").await?, 0);
+
+    // escape hatch, and error replies
+    let v = client.cmd(&["exists", "a", "a"]).await?;
+    assert_eq!(v, Value::Int(0));
+    let e = client.cmd(&["nosuchcommand"]).await.unwrap_err();
+    assert!(e.is::<ServerError>());
+    let v = client
+        .pipeline(&[vec!["set", "x", "1"], vec!["nosuchcommand"]])
+        .await?;
+    assert_eq!(v[0], Value::String("OK".to_string()));
+    assert!(matches!(v[1], Value::Error(_)));
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn pool_options_and_reconnection() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr().to_string();
+    let opts = Options {
+        db: 2,
+        name: Some("pooled".to_string()),
+        pool_size: 2,
+        ..Options::default()
+    };
+    let client = Client::with_options(addr.clone(), opts);
+    client.set("k", "v").await?;
+    assert_eq!(client.client_getname().await?, Some("pooled".into()));
+
+    // kill the pooled connection: the next command reconnects
+    let admin = Client::connect(addr).await?;
+    let id = client.client_id().await?;
+    assert!(admin.client_kill(id as u64).await?);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(client.get("k").await?, Some("v".to_string()));
+    assert_ne!(client.client_id().await?, id);
+
+    // all the connections of the pool select db 2
+    let (a, b) = tokio::join!(client.dbsize(), client.dbsize());
+    assert_eq!((a?, b?), (1, 1));
+    assert_eq!(admin.dbsize().await?, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn subscription() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string()).await?;
+    let mut sub = client.subscribe().await?;
+    sub.subscribe(&["a", "b"]).await?;
+    sub.psubscribe(&["c*"]).await?;
+    assert_eq!(client.pubsub_numpat().await?, 1);
+
+    assert_eq!(client.publish("b", "1").await?, 1);
+    assert_eq!(client.publish("cc", "2").await?, 1);
+    assert_eq!(
+        sub.next_message().await?,
+        Message {
+            pattern: None,
+            channel: "b".to_string(),
+            payload: b"1".to_vec()
+        }
+    );
+    let msg = sub.next_message().await?;
+    assert_eq!(msg.pattern.as_deref(), Some("c*"));
+
+    sub.unsubscribe(&[]).await?;
+    assert_eq!(client.pubsub_channels(None).await?.len(), 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn connection_failure() {
+    let opts = Options {
+        retries: 2,
+        backoff: Duration::from_millis(1),
+        ..Options::default()
+    };
+    // nothing listens on port 1
+    let client = Client::with_options("127.0.0.1:1", opts);
+    assert!(client.get("a").await.is_err());
+}
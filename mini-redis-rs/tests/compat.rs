@@ -0,0 +1,906 @@
+//! Compatibility with redis, for the commands this server supports.
+//!
+//! Every command is sent to two servers with the same history, one with
+//! our [`Client`] and the other with the official `redis` crate, and both
+//! must get the reply (or error message) that redis would send.
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::{Client, Connection, Options, ServerError},
+    wire::Value,
+    Server,
+};
+
+/// Start a server with the default configuration. It runs on its own
+/// thread, so that the blocking `redis` connection can't block it.
+fn start_server() -> Result<Server> {
+    Server::builder().start()
+}
+
+/// Two servers, driven by the two clients.
+struct Compat {
+    ours: Client,
+    theirs: redis::Connection,
+    /// Address of the server of `theirs`.
+    addr: String,
+    _servers: [Server; 2],
+}
+
+impl Compat {
+    async fn new() -> Result<Self> {
+        let servers = [start_server()?, start_server()?];
+        let ours = Client::connect(servers[0].addr().to_string()).await?;
+        let addr = servers[1].addr().to_string();
+        let theirs = redis::Client::open(format!("redis://{addr}/"))?
+            .get_connection()?;
+        Ok(Self {
+            ours,
+            theirs,
+            addr,
+            _servers: servers,
+        })
+    }
+
+    /// Send `args` with both clients, and return both replies.
+    async fn run(&mut self, args: &[&str]) -> (Value, Value) {
+        let ours = match self.ours.cmd(args).await {
+            Ok(v) => v,
+            Err(e) => match e.downcast::<ServerError>() {
+                Ok(e) => Value::Error(e.0),
+                Err(e) => panic!("{args:?}: {e:#}"),
+            },
+        };
+        let theirs = match redis::cmd(args[0])
+            .arg(&args[1..])
+            .query::<redis::Value>(&mut self.theirs)
+        {
+            Ok(v) => from_redis(v),
+            Err(e) => match (e.code(), e.detail()) {
+                (Some(code), Some(detail)) => {
+                    Value::Error(format!("{code} {detail}"))
+                }
+                (Some(code), None) => Value::Error(code.to_string()),
+                _ => panic!("{args:?}: {e}"),
+            },
+        };
+        (ours, theirs)
+    }
+
+    /// Check that both clients get the `expected` reply to `args`.
+    async fn check(&mut self, args: &[&str], expected: Value) {
+        let (ours, theirs) = self.run(args).await;
+        assert_eq!(ours, expected, "{args:?} with our client");
+        assert_eq!(theirs, expected, "{args:?} with the redis crate");
+    }
+
+    /// Check that both clients get an error reply starting with `prefix`.
+    async fn check_err(&mut self, args: &[&str], prefix: &str) {
+        let (ours, theirs) = self.run(args).await;
+        for v in [ours, theirs] {
+            match v {
+                Value::Error(e) if e.starts_with(prefix) => (),
+                v => panic!("{args:?}: expected {prefix:?}, got {v:?}"),
+            }
+        }
+    }
+
+    /// Open a new connection to each server, for commands after which the
+    /// server pushes messages, such as `SUBSCRIBE` or `MONITOR`.
+    async fn connect(&self) -> Result<Pushed> {
+        let ours =
+            Connection::connect(self.ours.addr(), &Options::default()).await?;
+        let theirs = redis::Client::open(format!("redis://{}/", self.addr))?
+            .get_connection()?;
+        Ok(Pushed { ours, theirs })
+    }
+
+    /// Check that both clients get the same reply, whatever it is.
+    async fn check_same(&mut self, args: &[&str]) -> Value {
+        let (ours, theirs) = self.run(args).await;
+        assert_eq!(ours, theirs, "{args:?}");
+        ours
+    }
+}
+
+/// A connection to each server, that receives pushed messages.
+struct Pushed {
+    ours: Connection,
+    theirs: redis::Connection,
+}
+
+impl Pushed {
+    /// Send `args` on both connections, without waiting for replies.
+    async fn send(&mut self, args: &[&str]) -> Result<()> {
+        self.ours.send(args).await?;
+        let cmd = redis::cmd(args[0]).arg(&args[1..]).get_packed_command();
+        self.theirs.send_packed_command(&cmd)?;
+        Ok(())
+    }
+
+    /// The next value received on both connections.
+    async fn recv(&mut self) -> Result<(Value, Value)> {
+        let ours = self.ours.recv().await?;
+        let theirs = from_redis(self.theirs.recv_response()?);
+        Ok((ours, theirs))
+    }
+
+    /// Send `args`, and check that both connections then receive the
+    /// `expected` replies or messages.
+    async fn check(&mut self, args: &[&str], expected: &[Value]) -> Result<()> {
+        if !args.is_empty() {
+            self.send(args).await?;
+        }
+        for e in expected {
+            let (ours, theirs) = self.recv().await?;
+            assert_eq!(&ours, e, "{args:?} with our client");
+            assert_eq!(&theirs, e, "{args:?} with the redis crate");
+        }
+        Ok(())
+    }
+}
+
+fn from_redis(v: redis::Value) -> Value {
+    match v {
+        redis::Value::Nil => Value::Null,
+        redis::Value::Int(i) => Value::Int(i),
+        redis::Value::Data(d) => match String::from_utf8(d) {
+            Ok(s) => Value::String(s),
+            Err(e) => Value::Bytes(e.into_bytes()),
+        },
+        redis::Value::Bulk(v) => {
+            Value::Bulk(v.into_iter().map(from_redis).collect())
+        }
+        redis::Value::Status(s) => Value::String(s),
+        redis::Value::Okay => ok(),
+    }
+}
+
+fn ok() -> Value {
+    s("OK")
+}
+
+fn s(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn int(i: i64) -> Value {
+    Value::Int(i)
+}
+
+fn err(e: &str) -> Value {
+    Value::Error(e.to_string())
+}
+
+fn strs(items: &[&str]) -> Value {
+    Value::Bulk(items.iter().map(|i| s(i)).collect())
+}
+
+fn arr<const N: usize>(items: [Value; N]) -> Value {
+    Value::Bulk(items.into())
+}
+
+const NOT_INT: &str = "ERR value is not an integer or out of range";
+const SYNTAX: &str = "ERR syntax error";
+const WRONG_TYPE: &str =
+    "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+#[tokio::test(flavor = "current_thread")]
+async fn strings() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["get", "k"], Value::Null).await;
+    c.check(&["set", "k", "v"], ok()).await;
+    c.check(&["get", "k"], s("v")).await;
+    c.check(&["set", "k", "v2", "ex", "100"], ok()).await;
+    c.check(&["set", "k", "v2", "px", "100000"], ok()).await;
+    c.check(
+        &["set", "k", "v", "ex", "0"],
+        err("ERR invalid expire time in 'set' command"),
+    )
+    .await;
+    c.check(&["set", "k", "v", "ex", "x"], err(NOT_INT)).await;
+    c.check(&["set", "k", "v", "nope"], err(SYNTAX)).await;
+    c.check(&["get", "k"], s("v2")).await;
+    c.check(
+        &["get"],
+        err("ERR wrong number of arguments for 'get' command"),
+    )
+    .await;
+    c.check(
+        &["get", "a", "b"],
+        err("ERR wrong number of arguments for 'get' command"),
+    )
+    .await;
+    c.check(&["nosuchcommand", "a"], err(
+        "ERR unknown command 'nosuchcommand', with args beginning with: 'a'",
+    ))
+    .await;
+    // line breaks in error messages must not inject replies
+    c.check(
+        &["nosuchcommand", "a\r\n:42\r\nb"],
+        err(
+            "ERR unknown command 'nosuchcommand', with args beginning with: \
+         'a  :42  b'",
+        ),
+    )
+    .await;
+    c.check(&["ping"], s("PONG")).await;
+    c.check(&["ping", "hello"], s("hello")).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn expiration() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["ttl", "k"], int(-2)).await;
+    c.check(&["pttl", "k"], int(-2)).await;
+    c.check(&["expire", "k", "10"], int(0)).await;
+    c.check(&["set", "k", "v"], ok()).await;
+    c.check(&["ttl", "k"], int(-1)).await;
+    c.check(&["persist", "k"], int(0)).await;
+    c.check(&["expire", "k", "100"], int(1)).await;
+    c.check(&["ttl", "k"], int(100)).await;
+    c.check(&["pexpire", "k", "50000"], int(1)).await;
+    c.check(&["ttl", "k"], int(50)).await;
+    c.check(&["persist", "k"], int(1)).await;
+    c.check(&["pttl", "k"], int(-1)).await;
+    c.check(&["expire", "k", "x"], err(NOT_INT)).await;
+    c.check(&["expire", "k", "-1"], int(1)).await;
+    c.check(&["get", "k"], Value::Null).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn keyspace() -> Result<()> {
+    let mut c = Compat::new().await?;
+    for k in ["a", "b", "c"] {
+        c.check(&["set", k, "1"], ok()).await;
+    }
+    c.check(&["exists", "a", "a", "x"], int(2)).await;
+    c.check(&["type", "a"], s("string")).await;
+    c.check(&["type", "x"], s("none")).await;
+    c.check(&["keys", "[ax]"], strs(&["a"])).await;
+    c.check(&["keys", "x*"], Value::Bulk(vec![])).await;
+    c.check(
+        &["scan", "0", "match", "b", "count", "100"],
+        arr([s("0"), strs(&["b"])]),
+    )
+    .await;
+    c.check(&["scan", "x"], err("ERR invalid cursor")).await;
+    c.check(&["dbsize"], int(3)).await;
+    c.check(&["object", "encoding", "a"], s("int")).await;
+    c.check(&["object", "encoding", "x"], Value::Null).await;
+    c.check(
+        &["object", "encoding"],
+        err("ERR wrong number of arguments for 'object|encoding' command"),
+    )
+    .await;
+    c.check_err(&["object", "nope", "a"], "ERR unknown subcommand 'nope'")
+        .await;
+
+    c.check(&["rename", "x", "y"], err("ERR no such key")).await;
+    c.check(&["rename", "a", "d"], ok()).await;
+    c.check(&["renamenx", "d", "b"], int(0)).await;
+    c.check(&["renamenx", "d", "a"], int(1)).await;
+    c.check(&["move", "a", "1"], int(1)).await;
+    c.check(&["move", "a", "1"], int(0)).await;
+    c.check(
+        &["move", "b", "0"],
+        err("ERR source and destination objects are the same"),
+    )
+    .await;
+    c.check(&["move", "b", "99"], err("ERR DB index is out of range"))
+        .await;
+    c.check(&["select", "x"], err(NOT_INT)).await;
+    c.check(&["select", "1"], ok()).await;
+    c.check(&["get", "a"], s("1")).await;
+    c.check(&["swapdb", "0", "1"], ok()).await;
+    c.check(&["swapdb", "x", "1"], err("ERR invalid first DB index"))
+        .await;
+    c.check(&["swapdb", "0", "x"], err("ERR invalid second DB index"))
+        .await;
+    c.check(&["dbsize"], int(2)).await;
+    c.check(&["del", "b", "c", "x"], int(2)).await;
+    c.check(&["flushdb"], ok()).await;
+    c.check(&["select", "0"], ok()).await;
+    c.check(&["dbsize"], int(1)).await;
+    c.check(&["flushall"], ok()).await;
+    c.check(&["flushall", "async"], ok()).await;
+    c.check(&["dbsize"], int(0)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn bitmaps() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["setbit", "b", "7", "1"], int(0)).await;
+    c.check(&["setbit", "b", "7", "1"], int(1)).await;
+    c.check(&["setbit", "b", "9", "1"], int(0)).await;
+    c.check(&["getbit", "b", "9"], int(1)).await;
+    c.check(&["getbit", "b", "100"], int(0)).await;
+    c.check(
+        &["setbit", "b", "x", "1"],
+        err("ERR bit offset is not an integer or out of range"),
+    )
+    .await;
+    c.check(
+        &["setbit", "b", "1", "2"],
+        err("ERR bit is not an integer or out of range"),
+    )
+    .await;
+    c.check(&["bitcount", "b"], int(2)).await;
+    c.check(&["bitcount", "b", "1", "-1"], int(1)).await;
+    c.check(&["bitcount", "b", "0", "8", "bit"], int(1)).await;
+    c.check(&["bitcount", "b", "1"], err(SYNTAX)).await;
+    c.check(&["bitcount", "b", "0", "x"], err(NOT_INT)).await;
+    c.check(&["bitcount", "missing"], int(0)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn hyperloglogs() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["pfadd", "h", "a", "b", "c", "d"], int(1)).await;
+    c.check(&["pfadd", "h", "a"], int(0)).await;
+    c.check(&["pfcount", "h"], int(4)).await;
+    c.check(&["pfadd", "h2", "d", "e"], int(1)).await;
+    c.check(&["pfcount", "h", "h2"], int(5)).await;
+    c.check(&["pfmerge", "h3", "h", "h2"], ok()).await;
+    c.check(&["pfcount", "h3"], int(5)).await;
+    c.check(&["pfcount", "missing"], int(0)).await;
+    c.check(&["set", "s", "not an hll"], ok()).await;
+    c.check(
+        &["pfadd", "s", "a"],
+        err("WRONGTYPE Key is not a valid HyperLogLog string value."),
+    )
+    .await;
+    c.check(&["zadd", "z", "1", "a"], int(1)).await;
+    c.check(&["pfcount", "z"], err(WRONG_TYPE)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sorted_sets() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["zadd", "z", "1", "a", "2", "b", "3", "c"], int(3))
+        .await;
+    c.check(&["zadd", "z", "ch", "5", "a", "4", "d"], int(2))
+        .await;
+    c.check(&["zadd", "z", "nx", "0", "a"], int(0)).await;
+    c.check(&["zadd", "z", "xx", "0", "e"], int(0)).await;
+    c.check(&["zadd", "z", "gt", "ch", "0", "a"], int(0)).await;
+    c.check(&["zadd", "z", "lt", "ch", "0", "a"], int(1)).await;
+    c.check(
+        &["zadd", "z", "nx", "xx", "1", "a"],
+        err("ERR XX and NX options at the same time are not compatible"),
+    )
+    .await;
+    c.check(
+        &["zadd", "z", "gt", "lt", "1", "a"],
+        err(
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        ),
+    )
+    .await;
+    c.check(
+        &["zadd", "z", "x", "a"],
+        err("ERR value is not a valid float"),
+    )
+    .await;
+    c.check(&["zadd", "z", "1", "a", "2"], err(SYNTAX)).await;
+    c.check(&["zscore", "z", "b"], s("2")).await;
+    c.check(&["zscore", "z", "x"], Value::Null).await;
+    c.check(&["zadd", "z", "1.5", "x", "-inf", "y"], int(2))
+        .await;
+    c.check(&["zscore", "z", "x"], s("1.5")).await;
+    c.check(&["zscore", "z", "y"], s("-inf")).await;
+    c.check(&["zcard", "z"], int(6)).await;
+    c.check(
+        &["zrange", "z", "0", "-1"],
+        strs(&["y", "a", "x", "b", "c", "d"]),
+    )
+    .await;
+    c.check(
+        &["zrange", "z", "-2", "10", "withscores"],
+        strs(&["c", "3", "d", "4"]),
+    )
+    .await;
+    c.check(&["zrange", "z", "3", "1"], Value::Bulk(vec![]))
+        .await;
+    c.check(&["zrange", "z", "0", "1", "nope"], err(SYNTAX))
+        .await;
+    c.check(&["zrem", "z", "a", "b", "nope"], int(2)).await;
+    c.check(&["zcard", "missing"], int(0)).await;
+    c.check(&["get", "z"], err(WRONG_TYPE)).await;
+    c.check(&["type", "z"], s("zset")).await;
+    c.check(&["set", "s", "v"], ok()).await;
+    c.check(&["zadd", "s", "1", "a"], err(WRONG_TYPE)).await;
+    c.check(&["zrem", "z", "c", "d", "x", "y"], int(4)).await;
+    c.check(&["exists", "z"], int(0)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn hashes() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["hset", "h", "f1", "v1", "f2", "v2"], int(2))
+        .await;
+    c.check(&["hset", "h", "f1", "x"], int(0)).await;
+    c.check(
+        &["hset", "h", "f"],
+        err("ERR wrong number of arguments for 'hset' command"),
+    )
+    .await;
+    c.check(&["hget", "h", "f1"], s("x")).await;
+    c.check(&["hget", "h", "nope"], Value::Null).await;
+    c.check(&["hget", "missing", "f"], Value::Null).await;
+    c.check(&["hlen", "h"], int(2)).await;
+    c.check(&["hexists", "h", "f2"], int(1)).await;
+    c.check(&["hexists", "h", "nope"], int(0)).await;
+    c.check(&["hgetall", "h"], strs(&["f1", "x", "f2", "v2"]))
+        .await;
+    c.check(&["hgetall", "missing"], Value::Bulk(vec![])).await;
+    c.check(&["type", "h"], s("hash")).await;
+    c.check(&["object", "encoding", "h"], s("listpack")).await;
+    c.check(&["hdel", "h", "f1", "nope"], int(1)).await;
+    c.check(
+        &["hdel", "h"],
+        err("ERR wrong number of arguments for 'hdel' command"),
+    )
+    .await;
+    c.check(&["hdel", "h", "f2"], int(1)).await;
+    c.check(&["exists", "h"], int(0)).await;
+    c.check(&["set", "str", "v"], ok()).await;
+    c.check(&["hset", "str", "f", "v"], err(WRONG_TYPE)).await;
+    c.check(&["hget", "str", "f"], err(WRONG_TYPE)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn lists() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["rpush", "l", "a", "b", "c"], int(3)).await;
+    c.check(&["lpush", "l", "z"], int(4)).await;
+    c.check(
+        &["lpush", "l"],
+        err("ERR wrong number of arguments for 'lpush' command"),
+    )
+    .await;
+    c.check(&["lrange", "l", "0", "-1"], strs(&["z", "a", "b", "c"]))
+        .await;
+    c.check(&["lrange", "l", "-2", "10"], strs(&["b", "c"]))
+        .await;
+    c.check(&["lrange", "l", "2", "1"], Value::Bulk(vec![]))
+        .await;
+    c.check(&["lrange", "l", "x", "1"], err(NOT_INT)).await;
+    c.check(&["llen", "l"], int(4)).await;
+    c.check(&["type", "l"], s("list")).await;
+    c.check(&["object", "encoding", "l"], s("listpack")).await;
+    c.check(&["lpop", "l"], s("z")).await;
+    c.check(&["rpop", "l", "2"], strs(&["c", "b"])).await;
+    c.check(
+        &["lpop", "l", "-1"],
+        err("ERR value is out of range, must be positive"),
+    )
+    .await;
+    c.check(&["lpop", "missing"], Value::Null).await;
+    c.check(&["llen", "missing"], int(0)).await;
+    c.check(&["rpop", "l", "5"], strs(&["a"])).await;
+    c.check(&["exists", "l"], int(0)).await;
+    c.check(&["set", "str", "v"], ok()).await;
+    c.check(&["rpush", "str", "a"], err(WRONG_TYPE)).await;
+    c.check(&["llen", "str"], err(WRONG_TYPE)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sets() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["sadd", "s", "3", "1", "2", "2"], int(3)).await;
+    c.check(&["object", "encoding", "s"], s("intset")).await;
+    c.check(&["smembers", "s"], strs(&["1", "2", "3"])).await;
+    c.check(&["sadd", "s", "a"], int(1)).await;
+    c.check(&["object", "encoding", "s"], s("listpack")).await;
+    c.check(
+        &["sadd", "s"],
+        err("ERR wrong number of arguments for 'sadd' command"),
+    )
+    .await;
+    c.check(&["sismember", "s", "a"], int(1)).await;
+    c.check(&["sismember", "s", "9"], int(0)).await;
+    c.check(&["sismember", "missing", "a"], int(0)).await;
+    c.check(&["scard", "s"], int(4)).await;
+    c.check(&["scard", "missing"], int(0)).await;
+    c.check(&["type", "s"], s("set")).await;
+    c.check(&["srem", "s", "1", "a", "nope"], int(2)).await;
+    c.check(&["smembers", "s"], strs(&["2", "3"])).await;
+    c.check(&["smembers", "missing"], Value::Bulk(vec![])).await;
+    c.check(&["srem", "s", "2", "3"], int(2)).await;
+    c.check(&["exists", "s"], int(0)).await;
+    c.check(&["set", "str", "v"], ok()).await;
+    c.check(&["sadd", "str", "a"], err(WRONG_TYPE)).await;
+    c.check(&["sismember", "str", "a"], err(WRONG_TYPE)).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn geo() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(
+        &[
+            "geoadd",
+            "Sicily",
+            "13.361389",
+            "38.115556",
+            "Palermo",
+            "15.087269",
+            "37.502669",
+            "Catania",
+        ],
+        int(2),
+    )
+    .await;
+    c.check(
+        &["geoadd", "Sicily", "200", "0", "x"],
+        err("ERR invalid longitude,latitude pair 200.000000,0.000000"),
+    )
+    .await;
+    c.check(
+        &["geodist", "Sicily", "Palermo", "Catania"],
+        s("166274.1516"),
+    )
+    .await;
+    c.check(
+        &["geodist", "Sicily", "Palermo", "Catania", "km"],
+        s("166.2742"),
+    )
+    .await;
+    c.check(&["geodist", "Sicily", "Palermo", "x"], Value::Null)
+        .await;
+    c.check(
+        &["geodist", "Sicily", "Palermo", "Catania", "yd"],
+        err("ERR unsupported unit provided. please use M, KM, FT, MI"),
+    )
+    .await;
+    c.check(
+        &["geohash", "Sicily", "Palermo", "Catania", "x"],
+        arr([s("sqc8b49rny0"), s("sqdtr74hyu0"), Value::Null]),
+    )
+    .await;
+    c.check(&["geopos", "Sicily", "x"], arr([Value::Null]))
+        .await;
+    c.check(
+        &[
+            "geosearch",
+            "Sicily",
+            "fromlonlat",
+            "15",
+            "37",
+            "byradius",
+            "200",
+            "km",
+            "asc",
+        ],
+        strs(&["Catania", "Palermo"]),
+    )
+    .await;
+    c.check(
+        &[
+            "geosearch",
+            "Sicily",
+            "frommember",
+            "Palermo",
+            "bybox",
+            "400",
+            "400",
+            "km",
+            "desc",
+            "withdist",
+        ],
+        arr([strs(&["Catania", "166.2742"]), strs(&["Palermo", "0.0000"])]),
+    )
+    .await;
+    c.check(
+        &[
+            "geosearch",
+            "Sicily",
+            "frommember",
+            "x",
+            "byradius",
+            "1",
+            "km",
+        ],
+        err("ERR could not decode requested zset member"),
+    )
+    .await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn dump_and_restore() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["dump", "k"], Value::Null).await;
+    c.check(&["set", "k", "v"], ok()).await;
+    c.check(
+        &["restore", "k", "0", "x"],
+        err("BUSYKEY Target key name already exists."),
+    )
+    .await;
+    c.check(
+        &["restore", "k2", "0", "x"],
+        err("ERR DUMP payload version or checksum are wrong"),
+    )
+    .await;
+    c.check(
+        &["restore", "k2", "-1", "x"],
+        err("ERR Invalid TTL value, must be >= 0"),
+    )
+    .await;
+    c.check(&["restore", "k2", "0", "x", "nope"], err(SYNTAX))
+        .await;
+
+    // payloads are binary, and go through both clients
+    let payload = match c.check_same(&["dump", "k"]).await {
+        Value::Bytes(b) => b,
+        v => panic!("unexpected payload {v:?}"),
+    };
+    c.ours.restore("k2", None, &payload, false).await?;
+    redis::cmd("restore")
+        .arg("k2")
+        .arg(0)
+        .arg(&payload)
+        .query::<()>(&mut c.theirs)?;
+    c.check(&["get", "k2"], s("v")).await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn migrate() -> Result<()> {
+    let mut c = Compat::new().await?;
+    let target = start_server()?;
+    let to = Client::connect(target.addr().to_string()).await?;
+    let host = target.addr().ip().to_string();
+    let port = target.addr().port().to_string();
+    let migrate = |key: &'static str, opts: &[&'static str]| {
+        let mut args = vec!["migrate", &host, &port, key, "0", "1000"];
+        args.extend(opts);
+        args
+    };
+
+    c.check(&migrate("k", &[]), s("NOKEY")).await;
+    c.check(&["set", "k", "v"], ok()).await;
+    c.check(&migrate("k", &["copy", "replace"]), ok()).await;
+    c.check(&["get", "k"], s("v")).await;
+    assert_eq!(to.get("k").await?.as_deref(), Some("v"));
+
+    // both servers move their key to the same target
+    c.check(&["set", "k", "v2"], ok()).await;
+    c.check(&migrate("k", &["replace"]), ok()).await;
+    c.check(&["exists", "k"], int(0)).await;
+    assert_eq!(to.get("k").await?.as_deref(), Some("v2"));
+
+    c.check(&["set", "a", "1"], ok()).await;
+    c.check(&["set", "b", "2"], ok()).await;
+    c.check(&migrate("", &["replace", "keys", "a", "b", "x"]), ok())
+        .await;
+    c.check(&["dbsize"], int(0)).await;
+    assert_eq!(to.get("b").await?.as_deref(), Some("2"));
+    c.check(
+        &migrate("a", &["keys", "a"]),
+        err(
+            "ERR When using MIGRATE KEYS option, the key argument must be \
+             set to the empty string",
+        ),
+    )
+    .await;
+    c.check(&migrate("a", &["nope"]), err(SYNTAX)).await;
+    c.check(
+        &["migrate", "h"],
+        err("ERR wrong number of arguments for 'migrate' command"),
+    )
+    .await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn server() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["config", "get", "maxmemory"], strs(&["maxmemory", "0"]))
+        .await;
+    c.check(&["config", "set", "maxmemory", "10mb"], ok()).await;
+    c.check(
+        &["config", "get", "maxmemory"],
+        strs(&["maxmemory", "10485760"]),
+    )
+    .await;
+    c.check_err(
+        &["config", "set", "maxmemory", "x"],
+        "ERR CONFIG SET failed",
+    )
+    .await;
+    c.check(&["config", "get", "nope"], Value::Bulk(vec![]))
+        .await;
+    c.check(&["config", "resetstat"], ok()).await;
+    c.check_err(&["config", "nope"], "ERR unknown subcommand 'nope'")
+        .await;
+    c.check(
+        &["config", "a\r\n:1"],
+        err("ERR unknown subcommand 'a  :1'"),
+    )
+    .await;
+    c.check(&["ping"], s("PONG")).await;
+
+    c.check(&["client", "setname", "conn"], ok()).await;
+    c.check(&["client", "getname"], s("conn")).await;
+    c.check(
+        &["client", "setname", "a b"],
+        err(
+            "ERR Client names cannot contain spaces, newlines or special \
+        characters.",
+        ),
+    )
+    .await;
+    c.check(&["client", "kill", "1.2.3.4:5"], err("ERR No such client"))
+        .await;
+    c.check(&["client", "list", "id", "x"], err("ERR Invalid client ID"))
+        .await;
+    match c.run(&["client", "id"]).await {
+        (Value::Int(_), Value::Int(_)) => (),
+        v => panic!("unexpected client ids {v:?}"),
+    }
+    let list = c.ours.client_list().await?;
+    assert!(list.contains(" name=conn "), "{list}");
+
+    c.check(&["slowlog", "reset"], ok()).await;
+    c.check(&["slowlog", "len"], int(0)).await;
+    c.check(&["memory", "usage", "missing"], Value::Null).await;
+    c.check(&["set", "k", "v"], ok()).await;
+    match c.check_same(&["memory", "usage", "k"]).await {
+        Value::Int(n) => assert!(n > 0),
+        v => panic!("unexpected memory usage {v:?}"),
+    }
+    let info = c.ours.info(Some("keyspace")).await?;
+    assert!(info.contains("db0:keys=1,expires=0"), "{info}");
+    let info: redis::InfoDict = redis::cmd("info").query(&mut c.theirs)?;
+    assert_eq!(info.get::<i64>("connected_clients"), Some(1));
+
+    match c.check_same(&["command", "count"]).await {
+        Value::Int(n) => assert!(n > 50),
+        v => panic!("unexpected command count {v:?}"),
+    }
+    c.check_same(&["command", "info", "get", "nope"]).await;
+    c.check_err(&["shutdown", "nope"], SYNTAX).await;
+
+    let mut monitor = c.connect().await?;
+    monitor.check(&["monitor"], &[ok()]).await?;
+    c.check(&["set", "k", "v"], ok()).await;
+    for line in <[Value; 2]>::from(monitor.recv().await?) {
+        let Value::String(line) = line else {
+            panic!("unexpected MONITOR line {line:?}");
+        };
+        // a timestamp, the db and the client address, then the command
+        let (time, rest) = line.split_once(" [0 127.0.0.1:").unwrap();
+        assert!(time.split_once('.').is_some_and(|(s, us)| {
+            s.parse::<u64>().is_ok() && us.len() == 6
+        }));
+        assert!(rest.ends_with(r#"] "set" "k" "v""#), "{line}");
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn pubsub() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["publish", "chan", "msg"], int(0)).await;
+    c.check(&["pubsub", "numpat"], int(0)).await;
+    c.check(&["pubsub", "channels"], Value::Bulk(vec![])).await;
+    c.check(&["pubsub", "numsub", "chan"], arr([s("chan"), int(0)]))
+        .await;
+    for cmd in ["subscribe", "psubscribe"] {
+        c.check(
+            &[cmd],
+            err(&format!(
+                "ERR wrong number of arguments for '{cmd}' command"
+            )),
+        )
+        .await;
+    }
+
+    let mut sub = c.connect().await?;
+    let reply =
+        |kind: &str, channel: &str, n: i64| arr([s(kind), s(channel), int(n)]);
+    sub.check(
+        &["subscribe", "a", "b"],
+        &[reply("subscribe", "a", 1), reply("subscribe", "b", 2)],
+    )
+    .await?;
+    sub.check(&["psubscribe", "p*"], &[reply("psubscribe", "p*", 3)])
+        .await?;
+    c.check(&["publish", "b", "hi"], int(1)).await;
+    sub.check(&[], &[arr([s("message"), s("b"), s("hi")])])
+        .await?;
+    c.check(&["publish", "pa", "yo"], int(1)).await;
+    sub.check(&[], &[arr([s("pmessage"), s("p*"), s("pa"), s("yo")])])
+        .await?;
+    sub.check(&["unsubscribe", "a"], &[reply("unsubscribe", "a", 2)])
+        .await?;
+    sub.check(&["punsubscribe"], &[reply("punsubscribe", "p*", 1)])
+        .await?;
+    sub.check(&["unsubscribe"], &[reply("unsubscribe", "b", 0)])
+        .await?;
+    // back to normal
+    sub.check(&["set", "k", "v"], &[ok()]).await?;
+
+    // the redis crate's own pub/sub support
+    let client = redis::Client::open(format!("redis://{}/", c.addr))?;
+    let mut conn = client.get_connection()?;
+    let mut sub = conn.as_pubsub();
+    sub.subscribe("chan")?;
+    sub.psubscribe("ch*")?;
+    let published: i64 = redis::cmd("publish")
+        .arg("chan")
+        .arg("hello")
+        .query(&mut c.theirs)?;
+    assert_eq!(published, 2);
+    let msg = sub.get_message()?;
+    assert_eq!(msg.get_channel_name(), "chan");
+    assert_eq!(msg.get_payload::<String>()?, "hello");
+    let msg = sub.get_message()?;
+    assert_eq!(msg.get_pattern::<String>()?, "ch*");
+    assert_eq!(msg.get_payload::<String>()?, "hello");
+    sub.unsubscribe("chan")?;
+    sub.punsubscribe("ch*")?;
+    drop(sub);
+    let pong: String = redis::cmd("ping").query(&mut conn)?;
+    assert_eq!(pong, "PONG");
+    Ok(())
+}
+
+#[cfg(feature = "lua")]
+#[tokio::test(flavor = "current_thread")]
+async fn scripting() -> Result<()> {
+    let mut c = Compat::new().await?;
+    c.check(&["eval", "return 1", "0"], int(1)).await;
+    c.check(
+        &["eval", "return {KEYS[1], ARGV[1]}", "1", "k", "a"],
+        strs(&["k", "a"]),
+    )
+    .await;
+    let set = "redis.call('set', KEYS[1], 'v')";
+    c.check(&["eval", set, "1", "k"], Value::Null).await;
+    c.check(&["get", "k"], s("v")).await;
+    c.check(
+        &["eval", "return 1", "x"],
+        err("ERR value is not an integer or out of range"),
+    )
+    .await;
+    c.check_err(
+        &["evalsha", "0000000000000000000000000000000000000000", "0"],
+        "NOSCRIPT",
+    )
+    .await;
+    // SHA1 of the script
+    let sha = "e0e1f9fabfc9d4800c877a703b823ac0578ff8db";
+    c.check(&["script", "load", "return 1"], s(sha)).await;
+    c.check(&["script", "exists", sha, "x"], arr([int(1), int(0)]))
+        .await;
+    c.check(&["evalsha", sha, "0"], int(1)).await;
+    c.check(&["script", "flush"], ok()).await;
+    c.check(&["script", "exists", sha], arr([int(0)])).await;
+    // line breaks in error messages must not inject replies
+    c.check(&["eval", "return {err='x\\r\\n:1'}", "0"], err("x  :1"))
+        .await;
+    c.check(
+        &["script", "a\r\n:1"],
+        err("ERR unknown subcommand 'a  :1'"),
+    )
+    .await;
+    c.check(&["ping"], s("PONG")).await;
+
+    // the redis crate's `Script` uses EVALSHA, then EVAL if needed
+    let script = redis::Script::new("return tonumber(ARGV[1]) + 1");
+    let n: i64 = script.arg(41).invoke(&mut c.theirs)?;
+    assert_eq!(n, 42);
+    let n: i64 = script.arg(1).invoke(&mut c.theirs)?;
+    assert_eq!(n, 2);
+    Ok(())
+}
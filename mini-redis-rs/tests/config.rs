@@ -0,0 +1,245 @@
+//! Configuration files: parsing, command line overrides and rewriting.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::blocking::Client,
+    config::{parse_memory, split_args, split_str_args, Config},
+    Server,
+};
+
+/// An empty directory for the test `name`.
+fn temp_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir()
+        .join(format!("mini-redis-config-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn split(line: &str) -> Vec<Vec<u8>> {
+    split_args(line).unwrap()
+}
+
+#[test]
+fn split_plain_and_quoted() {
+    assert_eq!(split(""), Vec::<Vec<u8>>::new());
+    assert_eq!(split("  \t "), Vec::<Vec<u8>>::new());
+    assert_eq!(split("port 6379"), [&b"port"[..], b"6379"]);
+    assert_eq!(split("  save 900   1  "), [&b"save"[..], b"900", b"1"]);
+    assert_eq!(split(r#"dir "/a  b""#), [&b"dir"[..], b"/a  b"]);
+    assert_eq!(split("dir '/a  b'"), [&b"dir"[..], b"/a  b"]);
+    assert_eq!(split(r#"a "" ''"#), [&b"a"[..], b"", b""]);
+    // unquoted arguments are verbatim
+    assert_eq!(split(r#"a\nb c\"d"#), [&br"a\nb"[..], br#"c\"d"#]);
+    assert_eq!(split("é"), ["é".as_bytes()]);
+}
+
+#[test]
+fn split_comments() {
+    assert_eq!(split("# port 6379"), Vec::<Vec<u8>>::new());
+    assert_eq!(split("   #"), Vec::<Vec<u8>>::new());
+    assert_eq!(split("port 6379 # the port"), [&b"port"[..], b"6379"]);
+    // only at the beginning of an argument
+    assert_eq!(split("a#b"), [b"a#b"]);
+    assert_eq!(split(r##"a "#b""##), [&b"a"[..], b"#b"]);
+}
+
+#[test]
+fn split_escapes() {
+    assert_eq!(
+        split(r#""a\nb\rc\td\be\af\"g\\h\'i""#),
+        [b"a\nb\rc\td\x08e\x07f\"g\\h'i"]
+    );
+    // `\xHH` is a byte, not a character
+    assert_eq!(split(r#""\x00\x41\xff\xFe""#), [b"\x00A\xff\xfe"]);
+    assert_eq!(split_str_args(r#""caf\xc3\xa9""#).unwrap(), ["café"]);
+    assert!(split_str_args(r#""\xff""#).is_err());
+    // not an escape without two hex digits
+    assert_eq!(split(r#""\xg1" "\x1" "\x""#), [&b"xg1"[..], b"x1", b"x"]);
+    // single quotes only escape themselves
+    assert_eq!(split(r"'a\'b\nc\\d'"), [br"a'b\nc\\d"]);
+}
+
+#[test]
+fn split_errors() {
+    for line in [
+        r#"dir "/a"#,
+        "dir '/a",
+        r#"dir "/a\"#,
+        r#"a "b"c"#,
+        "a 'b'c",
+        r#"a "b"'c'"#,
+    ] {
+        assert!(split_args(line).is_err(), "{line}");
+    }
+}
+
+#[test]
+fn memory_units() -> Result<()> {
+    assert_eq!(parse_memory("0")?, 0);
+    assert_eq!(parse_memory("100")?, 100);
+    assert_eq!(parse_memory("100b")?, 100);
+    assert_eq!(parse_memory("1k")?, 1000);
+    assert_eq!(parse_memory("1kb")?, 1024);
+    assert_eq!(parse_memory("2M")?, 2_000_000);
+    assert_eq!(parse_memory("2mb")?, 2 << 20);
+    assert_eq!(parse_memory("3g")?, 3_000_000_000);
+    assert_eq!(parse_memory("3GB")?, 3 << 30);
+    for s in ["", "k", "1t", "-1", "1.5mb", "99999999999gb"] {
+        assert!(parse_memory(s).is_err(), "{s}");
+    }
+    Ok(())
+}
+
+#[test]
+fn load() -> Result<()> {
+    let mut config = Config::default();
+    config.load_str(
+        "# a comment\n\
+        \n\
+        PORT 7000\n\
+        bind 10.0.0.1   ::1\n\
+        maxmemory 10mb\n\
+        save 900 1 300 10\n\
+        dir \"/var/lib/my  redis\"\n\
+        logfile ''\n\
+        port 7001\n",
+    )?;
+    assert_eq!(config.port, 7001);
+    assert_eq!(config.bind, ["10.0.0.1", "::1"]);
+    assert_eq!(config.maxmemory, 10 << 20);
+    assert_eq!(config.save, [(900, 1), (300, 10)]);
+    assert_eq!(config.get("save").unwrap(), "900 1 300 10");
+    assert_eq!(config.dir, PathBuf::from("/var/lib/my  redis"));
+    assert_eq!(config.logfile, "");
+
+    // errors tell where they are
+    let err = Config::default().load_str("port 1\nport x\n").unwrap_err();
+    assert!(format!("{err:#}").contains("line 2"), "{err:#}");
+    let err = Config::default().load_str("nope 1").unwrap_err();
+    assert!(format!("{err:#}").contains("unknown parameter"), "{err:#}");
+    assert!(Config::default().load_str("save 900").is_err());
+    assert!(Config::default().load_str("dbfilename a/b").is_err());
+    assert!(Config::default().load_str("dir \"a").is_err());
+    Ok(())
+}
+
+#[test]
+fn from_args() -> Result<()> {
+    let dir = temp_dir("args")?;
+    let path = dir.join("redis.conf");
+    std::fs::write(&path, "port 7000\nmaxclients 10\n")?;
+
+    let args = [
+        path.to_str().unwrap(),
+        "--maxclients",
+        "20",
+        "--save",
+        "60",
+        "1000",
+        "--dir",
+        "/a  b",
+    ];
+    let (config, file) = Config::from_args(args.iter().map(|a| a.to_string()))?;
+    assert_eq!(file.as_deref(), Some(path.as_path()));
+    assert_eq!(config.port, 7000);
+    assert_eq!(config.maxclients, 20);
+    assert_eq!(config.save, [(60, 1000)]);
+    assert_eq!(config.dir, PathBuf::from("/a  b"));
+
+    let (config, file) = Config::from_args(["--port", "1"].map(String::from))?;
+    assert_eq!(file, None);
+    assert_eq!(config.port, 1);
+    assert!(Config::from_args(["--port"].map(String::from)).is_err());
+    assert!(Config::from_args(["a", "b"].map(String::from)).is_err());
+    Ok(())
+}
+
+#[test]
+fn rewrite_round_trip() -> Result<()> {
+    let dir = temp_dir("rewrite")?;
+    let path = dir.join("redis.conf");
+    std::fs::write(
+        &path,
+        "# my config\n\
+        port 7000 # the port\n\
+        \n\
+        maxclients 10\n\
+        maxclients 11\n\
+        # the end\n",
+    )?;
+
+    let mut config = Config::default();
+    config.load_file(&path)?;
+    config.set("maxclients", "50")?;
+    config.set("dir", "/a  b")?;
+    config.set("logfile", "# \"quoted\" \\ \ttab\n")?;
+    config.set("save", "")?;
+    config.set("bind", "127.0.0.1 ::1")?;
+    config.set("client-output-buffer-limit", "pubsub 1mb 512kb 30")?;
+    config.set("notify-keyspace-events", "KEA")?;
+    config.rewrite(&path)?;
+
+    let content = std::fs::read_to_string(&path)?;
+    let lines: Vec<_> = content.lines().collect();
+    // known directives are updated in place, duplicates removed, other
+    // lines kept, and changed parameters appended
+    assert_eq!(
+        lines[..5],
+        ["# my config", "port 7000", "", "maxclients 50", "# the end"]
+    );
+    assert!(lines.contains(&r#"dir "/a  b""#), "{content}");
+    assert!(lines.contains(&r#"save """#), "{content}");
+    assert!(lines.contains(&"bind 127.0.0.1 ::1"), "{content}");
+    assert!(!content.contains("maxmemory"), "{content}");
+
+    // everything reads back the same, except for the unknown directive
+    let content = content.replace("# the end\n", "");
+    let mut reloaded = Config::default();
+    reloaded.load_str(&content)?;
+    for name in Config::param_names() {
+        assert_eq!(reloaded.get(name), config.get(name), "{name}");
+    }
+
+    // rewriting again changes nothing
+    let before = std::fs::read_to_string(&path)?;
+    config.rewrite(&path)?;
+    assert_eq!(std::fs::read_to_string(&path)?, before);
+    Ok(())
+}
+
+#[test]
+fn config_rewrite() -> Result<()> {
+    let dir = temp_dir("command")?;
+    let path = dir.join("redis.conf");
+    std::fs::write(&path, "# comment\nmaxclients 10\n")?;
+
+    let server = Server::builder().config_file(&path)?.start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    assert_eq!(
+        client.config_get("maxclients")?,
+        [("maxclients".to_string(), "10".to_string())]
+    );
+    client.config_set("maxclients", "20")?;
+    client.config_set("dbfilename", "my dump.rdb")?;
+    client.config_rewrite()?;
+
+    let content = std::fs::read_to_string(&path)?;
+    assert!(
+        content.starts_with("# comment\nmaxclients 20\n"),
+        "{content}"
+    );
+    let mut config = Config::default();
+    config.load_file(&path)?;
+    assert_eq!(config.maxclients, 20);
+    assert_eq!(config.dbfilename, "my dump.rdb");
+
+    // without a file
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    let err = client.config_rewrite().unwrap_err();
+    assert!(err.to_string().contains("without a config file"), "{err}");
+    Ok(())
+}
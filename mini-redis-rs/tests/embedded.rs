@@ -0,0 +1,58 @@
+//! Servers embedded in the tests with [`Server::builder`].
+
+use std::{net::TcpStream, time::Duration};
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::{self, blocking},
+    db::{Entry, Value},
+    Server,
+};
+
+#[test]
+fn seed_and_inspect() -> Result<()> {
+    let server = Server::builder().set("databases", "2")?.start()?;
+    assert_ne!(server.addr().port(), 0);
+    let st = server.state();
+    assert_eq!(st.n_dbs(), 2);
+    st.db(1)
+        .insert("seeded".to_string(), Entry::new("v".into(), None));
+
+    let client = blocking::Client::connect(server.addr().to_string())?;
+    client.cmd(&["select", "1"])?;
+    assert_eq!(client.get("seeded")?.as_deref(), Some("v"));
+    client.set("written", "w")?;
+    let e = st.db(1).peek("written").map(|e| e.value.clone());
+    assert!(matches!(e, Some(Value::String(s)) if s == b"w"));
+    assert_eq!(st.n_clients(), 1);
+
+    let addr = server.addr();
+    server.shutdown()?;
+    assert!(TcpStream::connect(addr).is_err());
+    Ok(())
+}
+
+#[test]
+fn errors_and_drop() -> Result<()> {
+    let server = Server::builder().start()?;
+    let port = server.addr().port();
+    let e = Server::builder().port(port).start().err().unwrap();
+    assert!(format!("{e:#}").contains("binding socket"), "{e:#}");
+    assert!(Server::builder().set("nope", "1").is_err());
+
+    // dropping the handle stops the server too
+    drop(server);
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn async_client() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = client::Client::connect(server.addr().to_string()).await?;
+    client.set_ex("k", "v", Duration::from_secs(100)).await?;
+    let ttl = server.state().db(0).peek("k").and_then(|e| e.expires_at);
+    assert!(ttl.is_some());
+    assert_eq!(server.state().db(0).len(), 1);
+    Ok(())
+}
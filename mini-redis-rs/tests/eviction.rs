@@ -0,0 +1,300 @@
+//! Eviction of keys past `maxmemory`, under each policy.
+
+use std::{collections::HashSet, thread, time::Duration};
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::{blocking::Client, Options},
+    db::{now_ms, Db, Entry, LfuParams, Value},
+    Server,
+};
+
+/// Number of keys that fit in `maxmemory` in the tests below.
+const CAPACITY: usize = 100;
+
+/// Start a server with `policy`, and room for at least [`CAPACITY`] keys
+/// written by [`fill`]. Returns the size of the largest such key.
+fn start_server(policy: &str) -> Result<(Server, Client, u64)> {
+    let server = Server::builder().set("maxmemory-policy", policy)?.start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    // as long as the longest key in the tests
+    let probe = "persistent:probe";
+    client.set(probe, &value())?;
+    let size = client.memory_usage(probe)?.unwrap() as u64;
+    client.del(&[probe])?;
+    let maxmemory = size * CAPACITY as u64;
+    client.config_set("maxmemory", &maxmemory.to_string())?;
+    Ok((server, client, size))
+}
+
+fn value() -> String {
+    "x".repeat(100)
+}
+
+/// Write keys `{prefix}:0` to `{prefix}:{n-1}`, expiring after `ttl` plus
+/// `i` seconds if given.
+fn fill(
+    client: &Client,
+    prefix: &str,
+    n: usize,
+    ttl: Option<u64>,
+) -> Result<()> {
+    for i in 0..n {
+        let key = format!("{prefix}:{i:03}");
+        match ttl {
+            Some(ttl) => {
+                let ttl = Duration::from_secs(ttl + i as u64);
+                client.set_ex(&key, &value(), ttl)?
+            }
+            None => client.set(&key, &value())?,
+        }
+    }
+    Ok(())
+}
+
+/// Number of keys among `{prefix}:0` to `{prefix}:{n-1}` still present.
+fn count(client: &Client, prefix: &str, n: usize) -> Result<usize> {
+    let keys: Vec<_> = (0..n).map(|i| format!("{prefix}:{i:03}")).collect();
+    let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+    Ok(client.exists(&keys)? as usize)
+}
+
+fn info_field(client: &Client, name: &str) -> Result<u64> {
+    let info = client.info(None)?;
+    let value = info
+        .lines()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+        .unwrap_or_else(|| panic!("no {name} in INFO"));
+    Ok(value.parse()?)
+}
+
+/// Check that memory stays below `maxmemory`, give or take the last key
+/// written (memory is freed before running a command, not after).
+fn check_memory(client: &Client, key_size: u64) -> Result<()> {
+    let used = info_field(client, "used_memory")?;
+    let maxmemory = info_field(client, "maxmemory")?;
+    assert_eq!(maxmemory, key_size * CAPACITY as u64);
+    assert!(used <= maxmemory + key_size, "{used} > {maxmemory}");
+    Ok(())
+}
+
+#[test]
+fn memory_usage() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    assert_eq!(info_field(&client, "used_memory")?, 0);
+    assert_eq!(client.memory_usage("missing")?, None);
+
+    client.set("a", "abc")?;
+    client.set("b", &value())?;
+    let a = client.memory_usage("a")?.unwrap() as u64;
+    let b = client.memory_usage("b")?.unwrap() as u64;
+    assert_eq!(b - a, 97);
+    assert_eq!(info_field(&client, "used_memory")?, a + b);
+    let usage = client.cmd(&["memory", "usage", "a", "samples", "5"])?;
+    assert_eq!(usage.into_int()? as u64, a);
+
+    client.del(&["b"])?;
+    assert_eq!(info_field(&client, "used_memory")?, a);
+    client.flushall()?;
+    assert_eq!(info_field(&client, "used_memory")?, 0);
+    Ok(())
+}
+
+#[test]
+fn noeviction() -> Result<()> {
+    let (_server, client, size) = start_server("noeviction")?;
+    let err = fill(&client, "key", 2 * CAPACITY, None).unwrap_err();
+    assert!(
+        err.to_string().starts_with("OOM command not allowed"),
+        "{err}"
+    );
+    assert_eq!(info_field(&client, "evicted_keys")?, 0);
+    check_memory(&client, size)?;
+
+    // reads and deletions are still allowed
+    let n = client.dbsize()? as usize;
+    assert!((CAPACITY..2 * CAPACITY).contains(&n), "{n}");
+    assert!(client.get("key:000")?.is_some());
+    client.del(&["key:000", "key:001"])?;
+    client.set("key:000", "abc")?;
+    Ok(())
+}
+
+#[test]
+fn allkeys_random() -> Result<()> {
+    let (_server, client, size) = start_server("allkeys-random")?;
+    fill(&client, "key", 3 * CAPACITY, None)?;
+    check_memory(&client, size)?;
+    let n = client.dbsize()? as u64;
+    assert_eq!(
+        info_field(&client, "evicted_keys")?,
+        3 * CAPACITY as u64 - n
+    );
+    // the last key is always there, it was written after evicting
+    assert!(client
+        .get(&format!("key:{:03}", 3 * CAPACITY - 1))?
+        .is_some());
+    Ok(())
+}
+
+#[test]
+fn allkeys_lru() -> Result<()> {
+    let (_server, client, size) = start_server("allkeys-lru")?;
+    fill(&client, "cold", CAPACITY / 2, None)?;
+    fill(&client, "hot", 10, None)?;
+    // idle times are in seconds
+    thread::sleep(Duration::from_millis(2100));
+    for i in 0..10 {
+        client.get(&format!("hot:{i:03}"))?;
+    }
+
+    fill(&client, "new", CAPACITY / 2, None)?;
+    check_memory(&client, size)?;
+    assert!(info_field(&client, "evicted_keys")? > 0);
+    // only the least recently used keys were evicted
+    assert_eq!(count(&client, "hot", 10)?, 10);
+    assert_eq!(count(&client, "new", CAPACITY / 2)?, CAPACITY / 2);
+    assert!(count(&client, "cold", CAPACITY / 2)? < CAPACITY / 2);
+    Ok(())
+}
+
+#[test]
+fn allkeys_lfu() -> Result<()> {
+    let (_server, client, size) = start_server("allkeys-lfu")?;
+    fill(&client, "cold", CAPACITY / 2, None)?;
+    fill(&client, "hot", 10, None)?;
+    for _ in 0..20 {
+        for i in 0..10 {
+            client.get(&format!("hot:{i:03}"))?;
+        }
+    }
+
+    fill(&client, "new", CAPACITY, None)?;
+    check_memory(&client, size)?;
+    assert!(info_field(&client, "evicted_keys")? > 0);
+    // keys accessed more often than the others are kept
+    assert_eq!(count(&client, "hot", 10)?, 10);
+    Ok(())
+}
+
+#[test]
+fn volatile_ttl() -> Result<()> {
+    let (_server, client, size) = start_server("volatile-ttl")?;
+    fill(&client, "persistent", CAPACITY / 2, None)?;
+    fill(&client, "volatile", CAPACITY / 2, Some(1000))?;
+
+    fill(&client, "new", 10, None)?;
+    check_memory(&client, size)?;
+    let evicted = info_field(&client, "evicted_keys")? as usize;
+    assert!(evicted > 0);
+    // only keys with an expiration time are evicted
+    assert_eq!(count(&client, "persistent", CAPACITY / 2)?, CAPACITY / 2);
+    assert_eq!(count(&client, "new", 10)?, 10);
+    let volatile = count(&client, "volatile", CAPACITY / 2)?;
+    assert_eq!(volatile, CAPACITY / 2 - evicted);
+    // the last one to expire is the last one evicted
+    let last = format!("volatile:{:03}", CAPACITY / 2 - 1);
+    assert!(client.get(&last)?.is_some());
+
+    // nothing left to evict
+    let err = fill(&client, "more", CAPACITY, None).unwrap_err();
+    assert!(err.to_string().starts_with("OOM"), "{err}");
+    assert_eq!(count(&client, "volatile", CAPACITY / 2)?, 0);
+    Ok(())
+}
+
+#[test]
+fn stale_candidates() -> Result<()> {
+    let (server, client, size) = start_server("volatile-ttl")?;
+    // evict some keys, leaving candidates from db 0 in the pool
+    fill(&client, "key", CAPACITY + 20, Some(1000))?;
+    assert!(info_field(&client, "evicted_keys")? > 0);
+    client.swapdb(0, 1)?;
+
+    // the candidates from before SWAPDB are now in db 1, and they expire
+    // sooner than the new keys of db 0, so they're evicted first
+    let opts = Options {
+        db: 1,
+        ..Options::default()
+    };
+    let db1 = Client::with_options(server.addr().to_string(), opts);
+    let before = db1.dbsize()?;
+    fill(&client, "key", 20, Some(100_000))?;
+    check_memory(&client, size)?;
+    assert_eq!(client.dbsize()?, 20);
+    assert!(db1.dbsize()? < before);
+
+    // same after FLUSHALL, with candidates from db 1 left: keys without
+    // an expiration time can't be evicted
+    let evicted = info_field(&client, "evicted_keys")?;
+    client.flushall()?;
+    let err = fill(&db1, "key", 2 * CAPACITY, None).unwrap_err();
+    assert!(err.to_string().starts_with("OOM"), "{err}");
+    assert_eq!(info_field(&client, "evicted_keys")?, evicted);
+    Ok(())
+}
+
+#[test]
+fn lfu_counter() {
+    let params = LfuParams {
+        log_factor: 10,
+        decay_time: 1,
+    };
+    let e = Entry::new(Value::string(b"abc".to_vec()), None);
+    let now = now_ms();
+    assert_eq!(e.lfu_counter(now, params), 5);
+
+    // the first access always counts, the next ones less and less often
+    e.touch(now, params);
+    assert_eq!(e.lfu_counter(now, params), 6);
+    for _ in 0..1000 {
+        e.touch(now, params);
+    }
+    let counter = e.lfu_counter(now, params);
+    assert!(counter > 6 && counter < 50, "{counter}");
+    let log_factor_0 = LfuParams {
+        log_factor: 0,
+        ..params
+    };
+    for _ in 0..1000 {
+        e.touch(now, log_factor_0);
+    }
+    assert_eq!(e.lfu_counter(now, params), 255);
+
+    // decremented once per decay_time idle minutes
+    let min = 60_000;
+    assert_eq!(e.lfu_counter(now + 3 * min, params), 252);
+    let decay_2 = LfuParams {
+        decay_time: 2,
+        ..params
+    };
+    assert_eq!(e.lfu_counter(now + 3 * min, decay_2), 254);
+    let no_decay = LfuParams {
+        decay_time: 0,
+        ..params
+    };
+    assert_eq!(e.lfu_counter(now + 1000 * min, no_decay), 255);
+    assert_eq!(e.lfu_counter(now + 1000 * min, params), 0);
+}
+
+#[test]
+fn sampling() {
+    let db = Db::default();
+    for i in 0..100 {
+        let expires_at = (i % 10 == 0).then_some(u64::MAX);
+        let e = Entry::new(Value::string(b"abc".to_vec()), expires_at);
+        db.insert(format!("key:{i:03}"), e);
+    }
+    let key = |k: &str, _: &Entry| k.to_string();
+    assert_eq!(db.sample(5, |_| true, key).len(), 5);
+    let volatile = db.sample(5, |e| e.expires_at.is_some(), key);
+    assert!(volatile.iter().all(|k| k.ends_with('0')), "{volatile:?}");
+
+    // every key can be picked
+    let mut seen = HashSet::new();
+    for _ in 0..2000 {
+        seen.extend(db.sample(1, |_| true, key));
+    }
+    assert_eq!(seen.len(), 100);
+}
@@ -0,0 +1,120 @@
+//! Glob-style patterns, checked against the behavior of redis'
+//! `stringmatchlen`, corner cases included.
+
+use mini_redis_rs::glob::string_match;
+
+fn m(pattern: &str, string: &str) -> bool {
+    string_match(pattern.as_bytes(), string.as_bytes(), false)
+}
+
+fn m_nocase(pattern: &str, string: &str) -> bool {
+    string_match(pattern.as_bytes(), string.as_bytes(), true)
+}
+
+#[test]
+fn literals() {
+    assert!(m("hello", "hello"));
+    assert!(!m("hello", "hell"));
+    assert!(!m("hell", "hello"));
+    assert!(!m("hello", "Hello"));
+    assert!(m_nocase("hello", "HeLLo"));
+    assert!(m("", ""));
+    assert!(!m("", "a"));
+}
+
+#[test]
+fn star() {
+    assert!(m("*", "hello"));
+    assert!(m("h*", "h"));
+    assert!(m("h*llo", "hllo"));
+    assert!(m("h*llo", "heeeello"));
+    assert!(!m("h*llo", "hellx"));
+    assert!(m("*a*b", "xaxb"));
+    assert!(m("**b**", "abc"));
+    assert!(!m("*a*b", "xbxa"));
+    // unlike shells, but like redis: `*` alone doesn't match an empty
+    // string
+    assert!(!m("*", ""));
+
+    // backtracking is cut short when the rest can't match anyway
+    let pattern = "a*".repeat(30) + "b";
+    assert!(!m(&pattern, &"a".repeat(100)));
+    assert!(m(&pattern, &("a".repeat(100) + "b")));
+}
+
+#[test]
+fn question_mark() {
+    assert!(m("h?llo", "hello"));
+    assert!(m("h?llo", "hallo"));
+    assert!(!m("h?llo", "hllo"));
+    assert!(m("??", "ab"));
+    assert!(!m("?", ""));
+}
+
+#[test]
+fn classes() {
+    assert!(m("h[ae]llo", "hello"));
+    assert!(m("h[ae]llo", "hallo"));
+    assert!(!m("h[ae]llo", "hillo"));
+    assert!(!m("h[ae]llo", "hllo"));
+
+    assert!(m("h[^e]llo", "hallo"));
+    assert!(!m("h[^e]llo", "hello"));
+    assert!(!m("h[^e]llo", "hllo"));
+
+    assert!(m("h[a-c]llo", "hbllo"));
+    assert!(m("h[a-c]llo", "hcllo"));
+    assert!(!m("h[a-c]llo", "hdllo"));
+    assert!(m("h[^a-c]llo", "hdllo"));
+    // reversed ranges are swapped
+    assert!(m("h[c-a]llo", "hallo"));
+    assert!(m("[0-9a-f]", "e"));
+    assert!(!m("[0-9a-f]", "g"));
+
+    assert!(!m("[A-Z]", "q"));
+    assert!(m_nocase("[A-Z]", "q"));
+    assert!(m_nocase("[abc]", "B"));
+    assert!(m_nocase("[^abc]", "D"));
+    assert!(!m_nocase("[^abc]", "B"));
+
+    // `]` first is not special: the class is empty
+    assert!(!m("[]a]", "a"));
+    // `-` at the end of a class makes a range with `]`, which then
+    // doesn't close the class
+    assert!(m("[a-]", "_"));
+    assert!(!m("[a-]", "-"));
+}
+
+#[test]
+fn unterminated_class() {
+    // the class extends to the end of the pattern
+    assert!(m("h[ae", "ha"));
+    assert!(m("h[ae", "he"));
+    assert!(!m("h[ae", "hx"));
+    assert!(!m("h[ae", "hae"));
+    assert!(!m("h[", "h"));
+    assert!(!m("h[", "hx"));
+    assert!(m("[^", "x"));
+}
+
+#[test]
+fn escapes() {
+    assert!(m(r"h\*llo", "h*llo"));
+    assert!(!m(r"h\*llo", "hello"));
+    assert!(m(r"h\?llo", "h?llo"));
+    assert!(!m(r"h\?llo", "hallo"));
+    assert!(m(r"\[a]", "[a]"));
+    assert!(!m(r"\[a]", "a"));
+    assert!(m(r"\h", "h"));
+    assert!(m(r"a\\b", r"a\b"));
+
+    // in classes
+    assert!(m(r"[\]]", "]"));
+    assert!(m(r"[\^a]", "^"));
+    assert!(m(r"[a\-z]", "-"));
+    assert!(!m(r"[a\-z]", "b"));
+
+    // a trailing backslash is literal
+    assert!(m(r"a\", r"a\"));
+    assert!(!m(r"a\", "a"));
+}
@@ -0,0 +1,186 @@
+//! Output buffer limits and idle timeouts.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use mini_redis_rs::{client::blocking::Client, wire, Server};
+
+/// Start a server with `params` on top of the default configuration, and
+/// return it with its address.
+fn start_server(params: &[(&str, &str)]) -> Result<(Server, String)> {
+    let mut builder = Server::builder();
+    for (name, value) in params {
+        builder = builder.set(name, value)?;
+    }
+    let server = builder.start()?;
+    let addr = server.addr().to_string();
+    Ok((server, addr))
+}
+
+/// Value of field `name` in `INFO stats`.
+fn stat(client: &Client, name: &str) -> Result<u64> {
+    let info = client.info(Some("stats"))?;
+    let value = info
+        .lines()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+        .ok_or_else(|| anyhow::anyhow!("no {name} in INFO"))?;
+    Ok(value.parse()?)
+}
+
+/// Open a connection and subscribe to `channel`, without ever reading
+/// from it.
+fn slow_subscriber(addr: &str, channel: &str) -> Result<TcpStream> {
+    let mut sock = TcpStream::connect(addr)?;
+    let mut buf = vec![];
+    wire::encode_command(&["subscribe", channel], &mut buf);
+    sock.write_all(&buf)?;
+    Ok(sock)
+}
+
+/// Wait until `cond` holds, for at most 5 seconds.
+fn wait_for(mut cond: impl FnMut() -> Result<bool>) -> Result<()> {
+    let start = Instant::now();
+    while !cond()? {
+        anyhow::ensure!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+#[test]
+fn pubsub_hard_limit() -> Result<()> {
+    let (_server, addr) =
+        start_server(&[("client-output-buffer-limit", "pubsub 256kb 0 0")])?;
+    let client = Client::connect(&addr)?;
+    let _sub = slow_subscriber(&addr, "news")?;
+    wait_for(|| Ok(client.pubsub_numsub(&["news"])?[0].1 == 1))?;
+
+    // the subscriber's socket buffers fill up, then its output buffer
+    let payload = "x".repeat(64 * 1024);
+    let mut published = 0;
+    while client.publish("news", &payload)? == 1 {
+        published += 1;
+        assert!(published < 10_000, "subscriber never disconnected");
+    }
+    wait_for(|| Ok(client.pubsub_numsub(&["news"])?[0].1 == 0))?;
+    assert_eq!(
+        stat(&client, "client_output_buffer_limit_disconnections")?,
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn pubsub_soft_limit() -> Result<()> {
+    let (_server, addr) =
+        start_server(&[("client-output-buffer-limit", "pubsub 0 128kb 1")])?;
+    let client = Client::connect(&addr)?;
+    let _sub = slow_subscriber(&addr, "news")?;
+    wait_for(|| Ok(client.pubsub_numsub(&["news"])?[0].1 == 1))?;
+
+    // publish until messages pile up in the output buffer, then stop: the
+    // subscriber is closed once it stayed over the soft limit for 1s
+    let payload = "x".repeat(64 * 1024);
+    let over_soft_limit = || -> Result<bool> {
+        let list = client.client_list()?;
+        Ok(list.split_whitespace().any(|f| {
+            f.strip_prefix("omem=")
+                .is_some_and(|m| m.parse::<usize>().unwrap() > 128 * 1024)
+        }))
+    };
+    while !over_soft_limit()? {
+        client.publish("news", &payload)?;
+    }
+    let over_since = Instant::now();
+    wait_for(|| Ok(client.pubsub_numsub(&["news"])?[0].1 == 0))?;
+    assert!(over_since.elapsed() >= Duration::from_millis(900));
+    assert_eq!(
+        stat(&client, "client_output_buffer_limit_disconnections")?,
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn normal_hard_limit() -> Result<()> {
+    let (_server, addr) =
+        start_server(&[("client-output-buffer-limit", "normal 1mb 0 0")])?;
+    let client = Client::connect(&addr)?;
+    client.set("small", "x")?;
+    client.set("big", &"x".repeat(2 * 1024 * 1024))?;
+    assert_eq!(client.get("small")?.as_deref(), Some("x"));
+
+    // the reply is larger than the hard limit: the connection is closed
+    // without it
+    let mut sock = TcpStream::connect(&addr)?;
+    let mut buf = vec![];
+    wire::encode_command(&["get", "big"], &mut buf);
+    sock.write_all(&buf)?;
+    let mut out = vec![];
+    sock.read_to_end(&mut out)?;
+    assert!(out.is_empty());
+    assert_eq!(
+        stat(&client, "client_output_buffer_limit_disconnections")?,
+        1
+    );
+
+    // limits can be changed at runtime
+    client.config_set("client-output-buffer-limit", "normal 0 0 0")?;
+    assert_eq!(client.get("big")?.map(|v| v.len()), Some(2 * 1024 * 1024));
+    Ok(())
+}
+
+#[test]
+fn idle_timeout() -> Result<()> {
+    let (_server, addr) = start_server(&[("timeout", "1")])?;
+    let mut idle = TcpStream::connect(&addr)?;
+    let start = Instant::now();
+    let mut out = vec![];
+    idle.read_to_end(&mut out)?;
+    assert!(start.elapsed() >= Duration::from_millis(900));
+    let client = Client::connect(&addr)?;
+    assert_eq!(stat(&client, "client_idle_timeout_disconnections")?, 1);
+
+    // subscribers are never idle
+    let mut sub = slow_subscriber(&addr, "news")?;
+    sub.set_read_timeout(Some(Duration::from_millis(1500)))?;
+    let mut buf = [0; 1024];
+    let n = sub.read(&mut buf)?;
+    assert!(n > 0, "subscription was not confirmed");
+    let err = sub.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    Ok(())
+}
+
+#[test]
+fn config() -> Result<()> {
+    let (_server, addr) = start_server(&[("tcp-keepalive", "60")])?;
+    let client = Client::connect(&addr)?;
+    let get = |name: &str| -> Result<String> {
+        Ok(client.config_get(name)?.remove(0).1)
+    };
+    assert_eq!(get("tcp-keepalive")?, "60");
+    assert_eq!(
+        get("client-output-buffer-limit")?,
+        "normal 0 0 0 slave 268435456 67108864 60 \
+        pubsub 33554432 8388608 60"
+    );
+
+    // classes that are not given are left alone
+    client.config_set("client-output-buffer-limit", "replica 1mb 1kb 5")?;
+    client.config_set("client-output-buffer-limit", "pubsub 2mb 1mb 10")?;
+    assert_eq!(
+        get("client-output-buffer-limit")?,
+        "normal 0 0 0 slave 1048576 1024 5 pubsub 2097152 1048576 10"
+    );
+    for bad in ["pubsub 1mb 1mb", "other 0 0 0", "normal x 0 0"] {
+        assert!(client
+            .config_set("client-output-buffer-limit", bad)
+            .is_err());
+    }
+    Ok(())
+}
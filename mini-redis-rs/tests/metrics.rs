@@ -0,0 +1,54 @@
+//! Scrape the Prometheus endpoint of an embedded server.
+
+use anyhow::Result;
+use mini_redis_rs::{metrics, Client, Server};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Send a `GET` request for `path` and return the raw response.
+async fn http_get(addr: std::net::SocketAddr, path: &str) -> Result<String> {
+    let mut sock = TcpStream::connect(addr).await?;
+    let req = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    sock.write_all(req.as_bytes()).await?;
+    let mut resp = String::new();
+    sock.read_to_string(&mut resp).await?;
+    Ok(resp)
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn scrape_metrics() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+    let metrics_listen = TcpListener::bind("127.0.0.1:0").await?;
+    let metrics_addr = metrics_listen.local_addr()?;
+    tokio::spawn(metrics::serve(metrics_listen, server.state().clone()));
+
+    let client = Client::connect(addr.to_string()).await?;
+    client.set("a", "1").await?;
+    client.set("b", "2").await?;
+    assert_eq!(client.get("a").await?, Some("1".to_string()));
+
+    let resp = http_get(metrics_addr, "/metrics").await?;
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{resp}");
+    for line in [
+        "mini_redis_connected_clients 1",
+        "mini_redis_keys{db=\"0\"} 2",
+        "mini_redis_commands_total{cmd=\"set\"} 2",
+        "mini_redis_commands_total{cmd=\"get\"} 1",
+        "mini_redis_keyspace_hits_total 1",
+        "mini_redis_command_duration_seconds_bucket\
+        {cmd=\"set\",le=\"+Inf\"} 2",
+        "mini_redis_command_duration_seconds_count{cmd=\"get\"} 1",
+    ] {
+        assert!(
+            resp.lines().any(|l| l == line),
+            "missing {line:?} in:\n{resp}"
+        );
+    }
+
+    let resp = http_get(metrics_addr, "/nope").await?;
+    assert!(resp.starts_with("HTTP/1.1 404"), "{resp}");
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! `DUMP`, `RESTORE` and `MIGRATE`.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use mini_redis_rs::{client::blocking::Client, rdb, Server};
+
+/// Start a server with the default configuration, and connect to it.
+fn start_server() -> Result<(Server, Client)> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    Ok((server, client))
+}
+
+fn error(res: Result<impl std::fmt::Debug>) -> String {
+    res.unwrap_err().to_string()
+}
+
+#[test]
+fn dump_and_restore() -> Result<()> {
+    let (_server, client) = start_server()?;
+    assert_eq!(client.dump("missing")?, None);
+
+    // type, value, RDB version and checksum
+    client.set("foo", "bar")?;
+    let payload = client.dump("foo")?.unwrap();
+    assert_eq!(&payload[..7], b"\x00\x03bar\x09\x00");
+    let crc = rdb::crc64(0, &payload[..7]);
+    assert_eq!(payload[7..], crc.to_le_bytes());
+
+    client.restore("copy", Some(Duration::from_secs(100)), &payload, false)?;
+    assert_eq!(client.get("copy")?.as_deref(), Some("bar"));
+    assert!((99_000..=100_000).contains(&client.pttl("copy")?));
+    assert_eq!(
+        error(client.restore("copy", None, &payload, false)),
+        "BUSYKEY Target key name already exists."
+    );
+    client.set("copy", "other")?;
+    client.restore("copy", None, &payload, true)?;
+    assert_eq!(client.get("copy")?.as_deref(), Some("bar"));
+    assert_eq!(client.ttl("copy")?, -1);
+
+    let mut corrupted = payload.clone();
+    corrupted[2] = b'c';
+    assert_eq!(
+        error(client.restore("new", None, &corrupted, false)),
+        "ERR DUMP payload version or checksum are wrong"
+    );
+    let mut future = payload.clone();
+    future[5] = 99;
+    let crc = rdb::crc64(0, &future[..7]);
+    future[7..].copy_from_slice(&crc.to_le_bytes());
+    assert_eq!(
+        error(client.restore("new", None, &future, false)),
+        "ERR DUMP payload version or checksum are wrong"
+    );
+    let mut truncated = b"\x00\x05bar".to_vec();
+    truncated.extend_from_slice(&payload[5..7]);
+    let crc = rdb::crc64(0, &truncated);
+    truncated.extend_from_slice(&crc.to_le_bytes());
+    assert_eq!(
+        error(client.restore("new", None, &truncated, false)),
+        "ERR Bad data format"
+    );
+    assert_eq!(client.exists(&["new"])?, 0);
+
+    // an absolute TTL in the past only deletes the key
+    let past = ["restore", "copy", "1000", "", "replace", "absttl"];
+    let mut args: Vec<&[u8]> = past.iter().map(|a| a.as_bytes()).collect();
+    args[3] = &payload;
+    client.cmd(&args)?.into_ok()?;
+    assert_eq!(client.exists(&["copy"])?, 0);
+    args[1] = b"abs";
+    args[2] = b"99999999999999";
+    client.cmd(&args)?.into_ok()?;
+    assert!(client.ttl("abs")? > 1_000_000);
+
+    // sorted sets, and binary values
+    client.zadd("z", &[(1.5, "a"), (f64::INFINITY, "b"), (-2.0, "c")])?;
+    let payload = client.dump("z")?.unwrap();
+    client.restore("z2", None, &payload, false)?;
+    assert_eq!(client.zscore("z2", "b")?, Some(f64::INFINITY));
+    assert_eq!(
+        client.cmd(&["zrange", "z2", "0", "-1"])?.into_strings()?,
+        ["c", "a", "b"]
+    );
+    client.setbit("bits", 0, true)?;
+    let payload = client.dump("bits")?.unwrap();
+    client.restore("bits2", None, &payload, false)?;
+    assert_eq!(client.get_bytes("bits2")?, Some(vec![0x80]));
+
+    // values can be binary, keys can't
+    client.cmd(&[&b"set"[..], b"bin", b"\xff\x00"])?.into_ok()?;
+    assert_eq!(client.get_bytes("bin")?, Some(vec![0xff, 0]));
+    assert_eq!(
+        error(client.cmd(&[&b"set"[..], b"\xff", b"v"])),
+        "ERR invalid argument: must be valid UTF-8"
+    );
+    Ok(())
+}
+
+#[test]
+fn migrate() -> Result<()> {
+    let (_src_server, src) = start_server()?;
+    let (dst_server, dst) = start_server()?;
+    let port = dst_server.addr().port();
+    let timeout = Duration::from_secs(5);
+    let migrate = |keys: &[&str], replace| {
+        src.migrate("127.0.0.1", port, 1, keys, timeout, replace)
+    };
+
+    assert!(!migrate(&["missing"], false)?);
+    src.set_ex("a", "1", Duration::from_secs(100))?;
+    src.set("b", "2")?;
+    src.zadd("z", &[(1.0, "x")])?;
+    assert!(migrate(&["a", "b", "z", "missing"], false)?);
+    assert_eq!(src.exists(&["a", "b", "z"])?, 0);
+    assert_eq!(dst.dbsize()?, 0);
+    dst.cmd(&["select", "1"])?;
+    assert_eq!(dst.get("a")?.as_deref(), Some("1"));
+    assert!((99_000..=100_000).contains(&dst.pttl("a")?));
+    assert_eq!(dst.get("b")?.as_deref(), Some("2"));
+    assert_eq!(dst.zscore("z", "x")?, Some(1.0));
+
+    // keys that exist on the target are kept on both sides
+    src.set("a", "new")?;
+    src.set("c", "3")?;
+    assert_eq!(
+        error(migrate(&["a", "c"], false)),
+        "ERR Target instance replied with error: BUSYKEY Target key name \
+        already exists."
+    );
+    assert_eq!(src.get("a")?.as_deref(), Some("new"));
+    assert_eq!(src.exists(&["c"])?, 0);
+    assert_eq!(dst.get("c")?.as_deref(), Some("3"));
+    assert!(migrate(&["a"], true)?);
+    assert_eq!(dst.get("a")?.as_deref(), Some("new"));
+
+    // a single key, and copies
+    src.set("d", "4")?;
+    let port = port.to_string();
+    let args = ["migrate", "127.0.0.1", &port, "d", "1", "1000", "copy"];
+    src.cmd(&args)?.into_ok()?;
+    assert_eq!(src.get("d")?.as_deref(), Some("4"));
+    assert_eq!(dst.get("d")?.as_deref(), Some("4"));
+    let args = ["migrate", "127.0.0.1", &port, "d", "1", "1000", "keys", "d"];
+    assert_eq!(
+        error(src.cmd(&args)),
+        "ERR When using MIGRATE KEYS option, the key argument must be set \
+        to the empty string"
+    );
+
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    assert_eq!(
+        error(src.migrate("127.0.0.1", closed, 0, &["d"], timeout, false)),
+        "IOERR error or timeout connecting to the client"
+    );
+    assert_eq!(src.get("d")?.as_deref(), Some("4"));
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! `MONITOR` and the audit log.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use mini_redis_rs::{client::blocking::Client, Server};
+
+/// Split a line from `MONITOR` into its time, database and source, and
+/// arguments.
+fn parse(line: &str) -> (f64, &str, &str, &str) {
+    let (time, rest) = line.split_once(" [").unwrap();
+    let (db, rest) = rest.split_once(' ').unwrap();
+    let (source, args) = rest.split_once("] ").unwrap();
+    (time.parse().unwrap(), db, source, args)
+}
+
+#[test]
+fn monitor() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    let mut monitor = client.monitor()?;
+    assert!(client.client_list()?.contains(" flags=O "));
+
+    client.set("k", "v")?;
+    let line = monitor.next_line()?;
+    let (time, db, source, args) = parse(&line);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+    assert!((now - 10.0..=now).contains(&time), "{time}");
+    assert_eq!(db, "0");
+    assert!(source.starts_with("127.0.0.1:"), "{source}");
+    assert_eq!(args, r#""set" "k" "v""#);
+
+    // admin commands are not shown
+    client.config_set("timeout", "1")?;
+    client.cmd(&["select", "1"])?;
+    client.cmd(&[&b"set"[..], b"bin", b"a\xff\" \n"])?;
+    client.get("k")?;
+    let mut next = || {
+        let line = monitor.next_line()?;
+        let (_, db, source, args) = parse(&line);
+        let source = if source == "lua" { " lua" } else { "" };
+        anyhow::Ok(format!("{db}{source} {args}"))
+    };
+    assert_eq!(next()?, r#"0 "select" "1""#);
+    assert_eq!(next()?, r#"1 "set" "bin" "a\xff\" \n""#);
+    assert_eq!(next()?, r#"1 "get" "k""#);
+
+    // monitors are never idle, unlike the client, which reconnects to the
+    // default database
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    client.cmd(&["ping"])?;
+    assert_eq!(next()?, r#"0 "ping""#);
+
+    #[cfg(feature = "lua")]
+    {
+        client.eval("return redis.call('set', KEYS[1], 'x')", &["s"], &[])?;
+        assert!(next()?.starts_with(r#"0 "eval" "return"#));
+        assert_eq!(next()?, r#"0 lua "set" "s" "x""#);
+    }
+    Ok(())
+}
+
+/// Lines of the audit log at `path`, or nothing if there is none.
+fn read_log(path: &Path) -> Vec<String> {
+    let content = std::fs::read_to_string(path).unwrap_or_default();
+    content.lines().map(|l| parse(l).3.to_string()).collect()
+}
+
+#[test]
+fn audit_log() -> Result<()> {
+    let dir = std::env::temp_dir()
+        .join(format!("mini-redis-audit-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("audit.log");
+    let rotated = |i: usize| PathBuf::from(format!("{}.{i}", path.display()));
+
+    let server = Server::builder()
+        .set("audit-log", path.to_str().unwrap())?
+        .set("audit-log-max-size", "200")?
+        .set("audit-log-max-files", "2")?
+        .start()?;
+    let client = Client::connect(server.addr().to_string())?;
+
+    // only write commands that succeed
+    client.set("a", "1")?;
+    client.get("a")?;
+    client.setbit("a", 0, true)?;
+    assert!(client.cmd(&["set", "b"]).is_err());
+    assert!(client.zadd("a", &[(1.0, "x")]).is_err());
+    assert_eq!(
+        read_log(&path),
+        [r#""set" "a" "1""#, r#""setbit" "a" "0" "1""#]
+    );
+
+    for i in 0..20 {
+        client.set(&format!("key:{i}"), "value")?;
+    }
+    let (current, old) = (read_log(&path), read_log(&rotated(2)));
+    assert_eq!(current.last().unwrap(), r#""set" "key:19" "value""#);
+    assert!(!old.is_empty());
+    assert!(!rotated(3).exists());
+    for p in [path.clone(), rotated(1), rotated(2)] {
+        assert!(std::fs::metadata(&p)?.len() <= 200);
+    }
+
+    // the file can be changed at runtime
+    let other = dir.join("other.log");
+    client.config_set("audit-log", other.to_str().unwrap())?;
+    client.set("c", "1")?;
+    client.config_set("audit-log", "")?;
+    client.set("d", "1")?;
+    assert_eq!(read_log(&other), [r#""set" "c" "1""#]);
+    assert_eq!(read_log(&path), current);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Subscribe to keyspace notifications of an embedded server.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use mini_redis_rs::{
+    wire::{self, Conn, Frame},
+    Server,
+};
+use tokio::net::TcpStream;
+
+/// Send a command without waiting for the reply.
+async fn send(conn: &mut Conn<'_>, args: &[&str]) -> Result<()> {
+    let args: Vec<Frame> = args.iter().map(|a| Frame::String(a)).collect();
+    wire::write_frame(conn, &Frame::Bulk(&args)).await
+}
+
+/// Read the next reply or message.
+async fn recv<'are>(
+    conn: &mut Conn<'_>,
+    arena: &'are bumpalo::Bump,
+) -> Result<Frame<'are>> {
+    wire::read_frame(conn, arena)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("connection closed"))
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn keyspace_notifications() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+
+    let arena = bumpalo::Bump::new();
+    let mut sub_sock = TcpStream::connect(addr).await?;
+    let mut sub = Conn::new(&mut sub_sock, addr);
+    let mut sock = TcpStream::connect(addr).await?;
+    let mut conn = Conn::new(&mut sock, addr);
+
+    let cmd = ["config", "set", "notify-keyspace-events", "Eg$x"];
+    send(&mut conn, &cmd).await?;
+    assert_eq!(recv(&mut conn, &arena).await?, Frame::String("OK"));
+
+    send(&mut sub, &["psubscribe", "__keyevent@0__:*"]).await?;
+    assert_eq!(
+        recv(&mut sub, &arena).await?,
+        Frame::Bulk(&[
+            Frame::String("psubscribe"),
+            Frame::String("__keyevent@0__:*"),
+            Frame::Int(1)
+        ])
+    );
+
+    for cmd in [
+        &["set", "a", "1", "px", "10"][..],
+        &["set", "b", "2"],
+        &["del", "b"],
+        &["del", "b"],
+    ] {
+        send(&mut conn, cmd).await?;
+        recv(&mut conn, &arena).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    send(&mut conn, &["get", "a"]).await?;
+    assert_eq!(recv(&mut conn, &arena).await?, Frame::Null);
+
+    for (event, key) in
+        [("set", "a"), ("set", "b"), ("del", "b"), ("expired", "a")]
+    {
+        let channel = format!("__keyevent@0__:{event}");
+        assert_eq!(
+            recv(&mut sub, &arena).await?,
+            Frame::Bulk(&[
+                Frame::String("pmessage"),
+                Frame::String("__keyevent@0__:*"),
+                Frame::String(&channel),
+                Frame::String(key),
+            ])
+        );
+    }
+
+    // only subscription commands are allowed while subscribed
+    send(&mut sub, &["get", "a"]).await?;
+    assert!(matches!(recv(&mut sub, &arena).await?, Frame::Error(_)));
+    Ok(())
+}
@@ -0,0 +1,168 @@
+//! Fault injection with the proxy.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::blocking::Client,
+    proxy::{self, Rules},
+    Server,
+};
+
+/// Start a backend server, and a proxy to it applying `rules`.
+fn start_proxy(rules: &str) -> Result<(Server, SocketAddr)> {
+    let server = Server::builder().start()?;
+    let rules = Rules::parse(rules)?;
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")?;
+    listen.set_nonblocking(true)?;
+    let addr = listen.local_addr()?;
+    let backend = server.addr().to_string();
+    std::thread::spawn(move || -> Result<()> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(async {
+            let listen = tokio::net::TcpListener::from_std(listen)?;
+            proxy::run(listen, backend, rules).await;
+            anyhow::Ok(())
+        })
+    });
+    Ok((server, addr))
+}
+
+/// Send raw commands, and read until the proxy closes the connection.
+fn send_raw(addr: SocketAddr, data: &[u8]) -> Result<Vec<u8>> {
+    let mut sock = TcpStream::connect(addr)?;
+    sock.set_read_timeout(Some(Duration::from_secs(5)))?;
+    sock.write_all(data)?;
+    sock.shutdown(std::net::Shutdown::Write)?;
+    let mut buf = vec![];
+    sock.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+#[test]
+fn rules() -> Result<()> {
+    let rules = Rules::parse(
+        "# comment\n\
+        delay 10-20 cmd get,set key user:* prob 0.5\n\
+        \n\
+        error 'ERR nope' cmd set\n\
+        drop prob 0\n",
+    )?;
+    assert_eq!(rules.len(), 3);
+    for (content, error) in [
+        ("drop\nexplode", "at line 2: unknown action \"explode\""),
+        ("delay 20-10", "at line 1: invalid delay range \"20-10\""),
+        ("delay", "at line 1: missing delay"),
+        ("error 'ERR' prob 2", "at line 1: invalid probability \"2\""),
+        ("drop cmd", "at line 1: missing command names"),
+        ("drop when", "at line 1: unknown option \"when\""),
+        ("error \"ERR", "parsing line 1: unbalanced quotes"),
+    ] {
+        let e = Rules::parse(content).unwrap_err();
+        assert_eq!(format!("{e:#}"), error);
+    }
+    Ok(())
+}
+
+#[test]
+fn delays_and_errors() -> Result<()> {
+    let (server, addr) = start_proxy(
+        "delay 200 cmd get key slow:*\n\
+        error \"ERR injected\" cmd set,del key ro:*\n",
+    )?;
+    let client = Client::connect(addr.to_string())?;
+    client.set("a", "1")?;
+    client.set("slow:1", "2")?;
+
+    let start = Instant::now();
+    assert_eq!(client.get("a")?.as_deref(), Some("1"));
+    assert!(start.elapsed() < Duration::from_millis(200));
+    let start = Instant::now();
+    assert_eq!(client.get("slow:1")?.as_deref(), Some("2"));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // failed commands never reach the backend
+    let e = client.set("ro:1", "3").unwrap_err();
+    assert_eq!(e.to_string(), "ERR injected");
+    assert!(server.state().db(0).peek("ro:1").is_none());
+    assert_eq!(client.get("ro:1")?, None);
+    Ok(())
+}
+
+#[test]
+fn drops_and_partial_replies() -> Result<()> {
+    let (server, addr) = start_proxy("drop cmd incr\npartial cmd get\n")?;
+    let client = Client::connect(server.addr().to_string())?;
+    client.set("k", "hello")?;
+
+    let incr = b"*2\r\n$4\r\nincr\r\n$1\r\nn\r\n";
+    assert_eq!(send_raw(addr, incr)?, b"");
+    assert_eq!(client.get("n")?, None);
+
+    let reply = send_raw(addr, b"*2\r\n$3\r\nget\r\n$1\r\nk\r\n")?;
+    let full = b"$5\r\nhello\r\n";
+    assert!(!reply.is_empty() && reply.len() < full.len(), "{reply:?}");
+    assert!(full.starts_with(&reply));
+    Ok(())
+}
+
+#[test]
+fn reordered_replies() -> Result<()> {
+    let (_server, addr) = start_proxy("reorder cmd get\n")?;
+    let client = Client::connect(addr.to_string())?;
+    client.set("a", "1")?;
+    client.set("b", "2")?;
+
+    let replies = send_raw(
+        addr,
+        b"*2\r\n$3\r\nget\r\n$1\r\na\r\n*2\r\n$3\r\nget\r\n$1\r\nb\r\n",
+    )?;
+    assert_eq!(replies, b"$1\r\n2\r\n$1\r\n1\r\n");
+
+    // commands that are not pipelined are not affected
+    assert_eq!(client.get("a")?.as_deref(), Some("1"));
+    assert_eq!(client.get("b")?.as_deref(), Some("2"));
+    Ok(())
+}
+
+#[test]
+fn pushed_messages() -> Result<()> {
+    let (server, addr) = start_proxy("error 'ERR injected' cmd get\n")?;
+    let client = Client::connect(server.addr().to_string())?;
+    let mut sock = TcpStream::connect(addr)?;
+    sock.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let expect = |sock: &mut TcpStream, expected: &[u8]| -> Result<()> {
+        let mut buf = vec![0; expected.len()];
+        sock.read_exact(&mut buf)?;
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            String::from_utf8_lossy(expected)
+        );
+        Ok(())
+    };
+
+    // both confirmations arrive without sending another command
+    sock.write_all(b"*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n$1\r\nb\r\n")?;
+    expect(&mut sock, b"*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n")?;
+    expect(&mut sock, b"*3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n")?;
+
+    // and so do published messages
+    assert_eq!(client.publish("b", "hello")?, 1);
+    expect(
+        &mut sock,
+        b"*3\r\n$7\r\nmessage\r\n$1\r\nb\r\n$5\r\nhello\r\n",
+    )?;
+
+    // commands still go through the rules
+    sock.write_all(b"*2\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n")?;
+    expect(&mut sock, b"*3\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n:1\r\n")?;
+    sock.write_all(b"*2\r\n$3\r\nget\r\n$1\r\nk\r\n")?;
+    expect(&mut sock, b"-ERR injected\r\n")?;
+    Ok(())
+}
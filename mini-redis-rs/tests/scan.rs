@@ -0,0 +1,85 @@
+//! `SCAN` guarantees, while the keyspace changes.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::blocking::Client,
+    db::{Db, Entry, Value},
+    Server,
+};
+
+fn entry() -> Entry {
+    Entry::new(Value::string(b"v".to_vec()), None)
+}
+
+#[test]
+fn keys_are_returned_once() {
+    let db = Db::default();
+    for i in 0..1000 {
+        db.insert(format!("key:{i}"), entry());
+    }
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut cursor = 0;
+    let mut round = 0;
+    loop {
+        let (next, keys) = db.scan(cursor, 10);
+        for k in keys {
+            *seen.entry(k).or_default() += 1;
+        }
+        // keys added or removed during the iteration may or may not be
+        // returned
+        db.insert(format!("new:{round}"), entry());
+        db.remove(&format!("key:{}", 500 + round));
+        round += 1;
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+    }
+    assert!(round > 50, "{round}");
+
+    for i in (0..500).chain(500 + round..1000) {
+        assert_eq!(seen.get(&format!("key:{i}")), Some(&1), "key:{i}");
+    }
+    assert!(seen.values().all(|&n| n == 1));
+    // removed before the iteration reached them, at least for some
+    let removed = (500..500 + round).map(|i| format!("key:{i}"));
+    assert!(removed.filter(|k| seen.contains_key(k)).count() < round);
+}
+
+#[test]
+fn scan_match_and_type() -> Result<()> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    for i in 0..200 {
+        client.set(&format!("user:{i}"), "x")?;
+        client.set(&format!("item:{i}"), "x")?;
+    }
+    client.zadd("user:zset", &[(1., "a")])?;
+
+    let scan = |pattern: Option<&str>, ty: Option<&str>| -> Result<_> {
+        let mut keys = vec![];
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = client.scan(cursor, pattern, Some(7), ty)?;
+            keys.extend(batch);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    };
+    assert_eq!(scan(None, None)?.len(), 401);
+    let users = scan(Some("user:*"), None)?;
+    assert_eq!(users.len(), 201);
+    assert!(users.iter().all(|k| k.starts_with("user:")));
+    assert_eq!(scan(Some("user:1?"), None)?.len(), 10);
+    assert_eq!(scan(Some("*:[0-4]"), None)?.len(), 10);
+    assert_eq!(scan(Some("user:*"), Some("zset"))?, ["user:zset"]);
+    assert_eq!(scan(Some("item:*"), Some("zset"))?.len(), 0);
+    Ok(())
+}
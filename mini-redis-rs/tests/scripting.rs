@@ -0,0 +1,146 @@
+//! Run Lua scripts against an embedded server.
+#![cfg(feature = "lua")]
+
+use anyhow::Result;
+use mini_redis_rs::{
+    wire::{self, Conn, Frame},
+    Server,
+};
+use tokio::net::TcpStream;
+
+/// Send a command and return its reply.
+async fn query<'are, S: AsRef<[u8]>>(
+    conn: &mut Conn<'_>,
+    args: &[S],
+    arena: &'are bumpalo::Bump,
+) -> Result<Frame<'are>> {
+    let args: Vec<Frame> =
+        args.iter().map(|a| Frame::Bytes(a.as_ref())).collect();
+    wire::write_frame(conn, &Frame::Bulk(&args)).await?;
+    wire::read_frame(conn, arena)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("connection closed"))
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn eval_and_evalsha() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+
+    let mut sock = TcpStream::connect(addr).await?;
+    let mut conn = Conn::new(&mut sock, addr);
+    let arena = bumpalo::Bump::new();
+
+    let script = "redis.call('set', KEYS[1], ARGV[1]) \
+        return {redis.call('get', KEYS[1]), \
+        redis.call('get', 'missing'), 42, 1.5}";
+    let reply =
+        query(&mut conn, &["eval", script, "1", "k", "v"], &arena).await?;
+    assert_eq!(
+        reply,
+        Frame::Bulk(&[
+            Frame::String("v"),
+            Frame::Null,
+            Frame::Int(42),
+            Frame::Int(1)
+        ])
+    );
+
+    let sha = "0000000000000000000000000000000000000000";
+    let loaded = query(&mut conn, &["script", "load", script], &arena).await?;
+    let Frame::String(loaded) = loaded else {
+        panic!("unexpected reply {loaded:?}");
+    };
+    let reply =
+        query(&mut conn, &["evalsha", loaded, "1", "k2", "v2"], &arena).await?;
+    assert!(
+        matches!(reply, Frame::Bulk([Frame::String("v2"), ..])),
+        "{reply:?}"
+    );
+    let reply =
+        query(&mut conn, &["script", "exists", loaded, sha], &arena).await?;
+    assert_eq!(reply, Frame::Bulk(&[Frame::Int(1), Frame::Int(0)]));
+
+    let reply = query(&mut conn, &["evalsha", sha, "0"], &arena).await?;
+    assert!(
+        matches!(reply, Frame::Error(e) if e.starts_with("NOSCRIPT")),
+        "{reply:?}"
+    );
+
+    // errors from `redis.call` are raised, `redis.pcall` returns them
+    let reply = query(
+        &mut conn,
+        &["eval", "return redis.call('set', 'a')", "0"],
+        &arena,
+    )
+    .await?;
+    assert_eq!(
+        reply,
+        Frame::Error("ERR wrong number of arguments for 'set' command")
+    );
+    let reply = query(
+        &mut conn,
+        &["eval", "return redis.pcall('set', 'a')['err']", "0"],
+        &arena,
+    )
+    .await?;
+    assert_eq!(
+        reply,
+        Frame::String("ERR wrong number of arguments for 'set' command")
+    );
+    let reply = query(
+        &mut conn,
+        &["eval", "return redis.call('eval', 'return 1', '0')", "0"],
+        &arena,
+    )
+    .await?;
+    assert_eq!(
+        reply,
+        Frame::Error("ERR This Redis command is not allowed from script")
+    );
+
+    let reply = query(&mut conn, &["script", "flush"], &arena).await?;
+    assert_eq!(reply, Frame::String("OK"));
+    let reply = query(&mut conn, &["script", "exists", loaded], &arena).await?;
+    assert_eq!(reply, Frame::Bulk(&[Frame::Int(0)]));
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn binary_arguments() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+
+    let mut sock = TcpStream::connect(addr).await?;
+    let mut conn = Conn::new(&mut sock, addr);
+    let arena = bumpalo::Bump::new();
+
+    // `ARGV` and the arguments of `redis.call` are byte strings
+    let script = b"redis.call('set', KEYS[1], ARGV[1] .. '\\254') \
+        return {ARGV[1], #ARGV[1], redis.call('get', KEYS[1])}";
+    let args = [&b"eval"[..], script, b"1", b"k", b"\xff\x00"];
+    let reply = query(&mut conn, &args, &arena).await?;
+    assert_eq!(
+        reply,
+        Frame::Bulk(&[
+            Frame::Bytes(b"\xff\x00"),
+            Frame::Int(2),
+            Frame::Bytes(b"\xff\x00\xfe"),
+        ])
+    );
+
+    // but keys are strings
+    let args = [&b"eval"[..], b"return 1", b"1", b"\xff"];
+    let reply = query(&mut conn, &args, &arena).await?;
+    assert_eq!(
+        reply,
+        Frame::Error("ERR invalid argument: must be valid UTF-8")
+    );
+    let script = "return redis.call('set', '\\255', 'v')";
+    let reply = query(&mut conn, &["eval", script, "0"], &arena).await?;
+    assert_eq!(
+        reply,
+        Frame::Error("ERR invalid argument: must be valid UTF-8")
+    );
+    Ok(())
+}
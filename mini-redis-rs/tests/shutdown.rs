@@ -0,0 +1,233 @@
+//! Shut down the `mini-redis-server` binary while clients are connected.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    process::{Child, Command},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::{blocking::Client, Options},
+    wire::{self, Value},
+};
+
+/// A server process, with its own directory for the snapshot.
+struct Server {
+    child: Child,
+    port: u16,
+    dir: PathBuf,
+}
+
+impl Server {
+    fn start(name: &str, args: &[&str]) -> Result<Self> {
+        let dir = std::env::temp_dir()
+            .join(format!("mini-redis-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        Self::start_in(dir, args)
+    }
+
+    /// Start a server in `dir`, where it may load a snapshot from.
+    fn start_in(dir: PathBuf, args: &[&str]) -> Result<Self> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_mini-redis-server"))
+            .args(["--port", &port.to_string()])
+            .args(["--dir", dir.to_str().unwrap()])
+            .args(["--loglevel", "nothing"])
+            .args(args)
+            .spawn()?;
+        let server = Self { child, port, dir };
+        let start = Instant::now();
+        while TcpStream::connect(server.addr()).is_err() {
+            anyhow::ensure!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Ok(server)
+    }
+
+    fn addr(&self) -> String {
+        format!("127.0.0.1:{}", self.port)
+    }
+
+    fn dump_file(&self) -> PathBuf {
+        self.dir.join("dump.rdb")
+    }
+
+    /// Wait for the process to exit, and check that it was successful.
+    fn wait(&mut self) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                anyhow::ensure!(status.success(), "exited with {status}");
+                return Ok(());
+            }
+            anyhow::ensure!(
+                start.elapsed() < Duration::from_secs(20),
+                "server did not exit"
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn send(sock: &mut TcpStream, cmds: &[Vec<&str>]) -> Result<()> {
+    let mut buf = vec![];
+    for args in cmds {
+        wire::encode_command(args, &mut buf);
+    }
+    sock.write_all(&buf)?;
+    Ok(())
+}
+
+#[test]
+fn no_reply_is_truncated() -> Result<()> {
+    let mut server = Server::start("truncated", &[])?;
+    let client = Client::connect(server.addr())?;
+    let value = "x".repeat(256 * 1024);
+    client.set("big", &value)?;
+
+    // more replies than the socket buffers can hold, so the server is
+    // still writing them when it's asked to shut down
+    let n = 100;
+    let mut reader = TcpStream::connect(server.addr())?;
+    send(&mut reader, &vec![vec!["get", "big"]; n])?;
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut admin = TcpStream::connect(server.addr())?;
+    send(&mut admin, &[vec!["shutdown"]])?;
+    let mut out = vec![];
+    admin.read_to_end(&mut out)?;
+    assert!(out.is_empty(), "SHUTDOWN got a reply: {out:?}");
+
+    let mut out = vec![];
+    reader.read_to_end(&mut out)?;
+    let mut replies = 0;
+    let mut rest = &out[..];
+    while !rest.is_empty() {
+        let Some((reply, len)) = wire::decode(rest)? else {
+            panic!("truncated reply after {replies} replies");
+        };
+        assert_eq!(reply, Value::String(value.clone()));
+        rest = &rest[len..];
+        replies += 1;
+    }
+    assert!((1..=n).contains(&replies), "{replies} replies");
+
+    server.wait()?;
+    assert!(server.dump_file().exists());
+    Ok(())
+}
+
+#[test]
+fn snapshot_is_reloaded() -> Result<()> {
+    let mut server = Server::start("reload", &[])?;
+    let client = Client::connect(server.addr())?;
+    client.set("a", "1")?;
+    client.set("b", "hello")?;
+    client.set_ex("c", "with ttl", Duration::from_secs(100))?;
+    client.set("gone", "soon")?;
+    client.pexpire("gone", 1)?;
+    let opts = Options {
+        db: 3,
+        ..Default::default()
+    };
+    let client3 = Client::with_options(server.addr(), opts.clone());
+    client3.set("in3", "three")?;
+    std::thread::sleep(Duration::from_millis(10));
+
+    let mut admin = TcpStream::connect(server.addr())?;
+    send(&mut admin, &[vec!["shutdown", "save"]])?;
+    server.wait()?;
+
+    let server = Server::start_in(server.dir.clone(), &[])?;
+    let client = Client::connect(server.addr())?;
+    assert_eq!(client.get("a")?.as_deref(), Some("1"));
+    assert_eq!(client.get("b")?.as_deref(), Some("hello"));
+    assert_eq!(client.get("c")?.as_deref(), Some("with ttl"));
+    assert!((1..=100).contains(&client.ttl("c")?));
+    assert_eq!(client.get("gone")?, None);
+    assert_eq!(client.dbsize()?, 3);
+    let client3 = Client::with_options(server.addr(), opts);
+    assert_eq!(client3.get("in3")?.as_deref(), Some("three"));
+    Ok(())
+}
+
+#[test]
+fn periodic_snapshot() -> Result<()> {
+    let server = Server::start("periodic", &["--save", "1 2"])?;
+    let client = Client::connect(server.addr())?;
+    client.set("a", "1")?;
+    std::thread::sleep(Duration::from_millis(1500));
+    // a single change is not enough
+    assert!(!server.dump_file().exists());
+
+    client.set("b", "2")?;
+    let start = Instant::now();
+    while !server.dump_file().exists() {
+        anyhow::ensure!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let info = client.info(Some("persistence"))?;
+    assert!(info.contains("rdb_changes_since_last_save:0"), "{info}");
+
+    // the snapshot is complete, even though the server was not shut down
+    drop(server);
+    let dir = std::env::temp_dir()
+        .join(format!("mini-redis-periodic-{}", std::process::id()));
+    let server = Server::start_in(dir, &["--save", ""])?;
+    let client = Client::connect(server.addr())?;
+    assert_eq!(client.get("b")?.as_deref(), Some("2"));
+    Ok(())
+}
+
+#[test]
+fn nosave() -> Result<()> {
+    let mut server = Server::start("nosave", &[])?;
+    Client::connect(server.addr())?.set("a", "1")?;
+    let mut admin = TcpStream::connect(server.addr())?;
+    send(&mut admin, &[vec!["shutdown", "nosave"]])?;
+    server.wait()?;
+    assert!(!server.dump_file().exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm() -> Result<()> {
+    let mut server = Server::start("sigterm", &[])?;
+    Client::connect(server.addr())?.set("a", "1")?;
+    // an idle client doesn't delay the shutdown
+    let _idle = TcpStream::connect(server.addr())?;
+
+    let start = Instant::now();
+    let status = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()?;
+    assert!(status.success());
+    server.wait()?;
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(server.dump_file().exists());
+
+    // without `save` rules, nothing is saved by default
+    let mut server = Server::start("sigterm-nosave", &["--save", ""])?;
+    Client::connect(server.addr())?.set("a", "1")?;
+    let status = Command::new("kill")
+        .args(["-TERM", &server.child.id().to_string()])
+        .status()?;
+    assert!(status.success());
+    server.wait()?;
+    assert!(!server.dump_file().exists());
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Client-side caching with `CLIENT TRACKING` against an embedded server.
+
+use anyhow::Result;
+use mini_redis_rs::{
+    wire::{self, Conn, Frame},
+    Server,
+};
+use tokio::net::TcpStream;
+
+/// Send a command without waiting for the reply.
+async fn send(conn: &mut Conn<'_>, args: &[&str]) -> Result<()> {
+    let args: Vec<Frame> = args.iter().map(|a| Frame::String(a)).collect();
+    wire::write_frame(conn, &Frame::Bulk(&args)).await
+}
+
+/// Read the next reply or message.
+async fn recv<'are>(
+    conn: &mut Conn<'_>,
+    arena: &'are bumpalo::Bump,
+) -> Result<Frame<'are>> {
+    wire::read_frame(conn, arena)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("connection closed"))
+}
+
+/// Send a command and return its reply.
+async fn query<'are>(
+    conn: &mut Conn<'_>,
+    args: &[&str],
+    arena: &'are bumpalo::Bump,
+) -> Result<Frame<'are>> {
+    send(conn, args).await?;
+    recv(conn, arena).await
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn invalidation_messages() -> Result<()> {
+    let server = Server::builder().start()?;
+    let addr = server.addr();
+
+    let arena = bumpalo::Bump::new();
+    let mut sub_sock = TcpStream::connect(addr).await?;
+    let mut sub = Conn::new(&mut sub_sock, addr);
+    let mut sock = TcpStream::connect(addr).await?;
+    let mut conn = Conn::new(&mut sock, addr);
+    let mut other_sock = TcpStream::connect(addr).await?;
+    let mut other = Conn::new(&mut other_sock, addr);
+
+    let Frame::Int(sub_id) = query(&mut sub, &["client", "id"], &arena).await?
+    else {
+        panic!("CLIENT ID didn't return an integer");
+    };
+    let sub_id = sub_id.to_string();
+    send(&mut sub, &["subscribe", "__redis__:invalidate"]).await?;
+    recv(&mut sub, &arena).await?;
+
+    let cmd = ["client", "tracking", "on", "redirect", &sub_id];
+    assert_eq!(query(&mut conn, &cmd, &arena).await?, Frame::String("OK"));
+    let cmd = ["client", "getredir"];
+    assert_eq!(
+        query(&mut conn, &cmd, &arena).await?,
+        Frame::Int(sub_id.parse()?)
+    );
+
+    query(&mut other, &["set", "a", "1"], &arena).await?;
+    query(&mut other, &["set", "b", "1"], &arena).await?;
+    query(&mut conn, &["get", "a"], &arena).await?;
+
+    // `b` was never read, so only `a` is invalidated, and only once
+    for key in ["b", "a", "a"] {
+        query(&mut other, &["set", key, "2"], &arena).await?;
+    }
+    query(&mut conn, &["get", "a"], &arena).await?;
+    query(&mut other, &["del", "a"], &arena).await?;
+
+    for _ in 0..2 {
+        assert_eq!(
+            recv(&mut sub, &arena).await?,
+            Frame::Bulk(&[
+                Frame::String("message"),
+                Frame::String("__redis__:invalidate"),
+                Frame::Bulk(&[Frame::String("a")]),
+            ])
+        );
+    }
+
+    // flushing invalidates everything
+    query(&mut other, &["flushall"], &arena).await?;
+    assert_eq!(
+        recv(&mut sub, &arena).await?,
+        Frame::Bulk(&[
+            Frame::String("message"),
+            Frame::String("__redis__:invalidate"),
+            Frame::Null,
+        ])
+    );
+
+    let cmd = ["client", "tracking", "on", "prefix", "a"];
+    assert!(matches!(
+        query(&mut conn, &cmd, &arena).await?,
+        Frame::Error(_)
+    ));
+    Ok(())
+}
@@ -0,0 +1,673 @@
+//! Bitmaps, HyperLogLogs, hashes, lists, sets, sorted sets and geospatial
+//! indexes.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use mini_redis_rs::{
+    client::blocking::Client,
+    db::{Db, Entry, Value as DbValue},
+    hash::Hash,
+    list::List,
+    rdb,
+    set::Set,
+    wire::Value,
+    zset::ZSet,
+    Server,
+};
+
+/// Start a server with the default configuration, and connect to it.
+fn start_server() -> Result<(Server, Client)> {
+    let server = Server::builder().start()?;
+    let client = Client::connect(server.addr().to_string())?;
+    Ok((server, client))
+}
+
+fn strings(v: Value) -> Vec<String> {
+    v.into_strings().unwrap()
+}
+
+#[test]
+fn bitmaps() -> Result<()> {
+    let (_server, client) = start_server()?;
+    assert!(!client.setbit("flags", 7, true)?);
+    assert!(client.setbit("flags", 7, true)?);
+    client.setbit("flags", 9, true)?;
+    client.setbit("flags", 23, true)?;
+    assert_eq!(client.get_bytes("flags")?, Some(vec![0x01, 0x40, 0x01]));
+    assert!(client.getbit("flags", 9)?);
+    assert!(!client.getbit("flags", 10)?);
+    assert!(!client.getbit("flags", 1000)?);
+    assert!(!client.getbit("missing", 0)?);
+
+    assert_eq!(client.bitcount("flags")?, 3);
+    let count = |args: &[&str]| -> Result<i64> {
+        let mut cmd = vec!["bitcount", "flags"];
+        cmd.extend(args);
+        client.cmd(&cmd)?.into_int()
+    };
+    assert_eq!(count(&["1", "-1"])?, 2);
+    assert_eq!(count(&["-1", "-1"])?, 1);
+    assert_eq!(count(&["5", "10"])?, 0);
+    assert_eq!(count(&["7", "9", "bit"])?, 2);
+    assert_eq!(count(&["8", "22", "bit"])?, 1);
+    assert_eq!(count(&["-1", "-1", "bit"])?, 1);
+    assert!(count(&["1"]).is_err());
+
+    // bits are cleared too, and the string only grows
+    assert!(!client.setbit("flags", 100, false)?);
+    assert_eq!(client.get_bytes("flags")?.unwrap().len(), 13);
+    assert!(client.setbit("flags", 7, false)?);
+    assert_eq!(client.bitcount("flags")?, 2);
+
+    client.set("text", "a")?;
+    assert_eq!(client.bitcount("text")?, 3);
+    assert!(client.setbit("text", 8 * 512 * 1024 * 1024, true).is_err());
+    Ok(())
+}
+
+#[test]
+fn hyperloglog() -> Result<()> {
+    let (_server, client) = start_server()?;
+
+    // like redis, an empty HyperLogLog is sparse, with a valid cache
+    assert!(client.pfadd("empty", &[])?);
+    assert!(!client.pfadd("empty", &[])?);
+    let mut empty = b"HYLL\x01\0\0\0".to_vec();
+    empty.extend_from_slice(&[0; 8]);
+    empty.extend_from_slice(b"\x7f\xff");
+    assert_eq!(client.get_bytes("empty")?, Some(empty));
+    assert_eq!(client.pfcount(&["empty"])?, 0);
+
+    // examples from the redis documentation
+    assert!(client.pfadd("hll", &["a", "b", "c", "d", "e", "f", "g"])?);
+    assert!(!client.pfadd("hll", &["a", "b"])?);
+    assert_eq!(client.pfcount(&["hll"])?, 7);
+    client.pfadd("hll1", &["foo", "bar", "zap", "a"])?;
+    client.pfadd("hll2", &["a", "b", "c", "foo"])?;
+    client.pfmerge("hll3", &["hll1", "hll2"])?;
+    assert_eq!(client.pfcount(&["hll3"])?, 6);
+    assert_eq!(client.pfcount(&["hll1", "hll2", "missing"])?, 6);
+
+    // the count is cached in the header until the next change
+    let cache = |key| -> Result<Vec<u8>> {
+        Ok(client.get_bytes(key)?.unwrap()[8..16].to_vec())
+    };
+    assert_eq!(cache("hll")?, [7, 0, 0, 0, 0, 0, 0, 0]);
+    client.pfadd("hll", &["h"])?;
+    assert_eq!(cache("hll")?[7], 0x80);
+    assert_eq!(client.pfcount(&["hll"])?, 8);
+
+    // large sets switch to the dense encoding
+    let mut added = 0;
+    for batch in 0..20 {
+        let elements: Vec<String> =
+            (0..1000).map(|i| format!("visitor:{batch}:{i}")).collect();
+        let elements: Vec<&str> = elements.iter().map(|e| &**e).collect();
+        client.pfadd("visitors", &elements)?;
+        added += elements.len() as i64;
+    }
+    let hll = client.get_bytes("visitors")?.unwrap();
+    assert_eq!((hll[4], hll.len()), (0, 16 + 12288));
+    let count = client.pfcount(&["visitors"])?;
+    assert!((count - added).abs() < added / 50, "{count} != {added}");
+
+    client.set("text", "not a HyperLogLog")?;
+    let err = client.pfadd("text", &["a"]).unwrap_err().to_string();
+    assert!(err.starts_with("WRONGTYPE"), "{err}");
+    assert!(client.pfcount(&["text"]).is_err());
+    Ok(())
+}
+
+/// Elements `{prefix}:0` to `{prefix}:{n-1}`.
+fn elements(prefix: &str, n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("{prefix}:{i}")).collect()
+}
+
+#[test]
+fn hyperloglog_redis_format() -> Result<()> {
+    let (_server, client) = start_server()?;
+    let set = |key: &str, value: &[u8]| -> Result<()> {
+        client.cmd(&[&b"set"[..], key.as_bytes(), value])?.into_ok()
+    };
+
+    // HyperLogLogs of `sparse:0..1000` and `dense:0..10000`, added 100 and
+    // 1000 at a time with the algorithm of redis' `PFADD`: the sparse one
+    // is updated opcode by opcode, and the dense one promoted past 3000
+    // bytes
+    let fixtures: [(&str, &[u8], usize); 2] = [
+        ("sparse", include_bytes!("fixtures/hll-sparse.bin"), 1000),
+        ("dense", include_bytes!("fixtures/hll-dense.bin"), 10000),
+    ];
+    for (name, fixture, n) in fixtures {
+        assert_eq!(fixture[4], (name == "sparse") as u8);
+        // the same registers as when adding the elements here
+        let elements = elements(name, n);
+        let elements: Vec<&str> = elements.iter().map(|e| &**e).collect();
+        let ours = format!("{name}:ours");
+        client.pfadd(&ours, &elements)?;
+        set(name, fixture)?;
+        let count = client.pfcount(&[name])?;
+        assert_eq!(count, client.pfcount(&[&ours])?, "{name}");
+        assert!((count - n as i64).abs() < n as i64 / 50, "{count} != {n}");
+        // the count is now cached, like redis would
+        let cached = client.get_bytes(name)?.unwrap();
+        assert_eq!(cached[8..16], (count as u64).to_le_bytes());
+        assert_eq!(cached[16..], fixture[16..]);
+
+        let merged = format!("{name}:merged");
+        client.pfmerge(&merged, &[name])?;
+        client.pfmerge(&ours, &[])?;
+        assert_eq!(client.get_bytes(&merged)?, client.get_bytes(&ours)?);
+        // adding them again changes nothing
+        assert!(!client.pfadd(name, &elements)?);
+    }
+    let count = client.pfcount(&["sparse", "dense"])?;
+    client.pfmerge("both", &["sparse", "dense"])?;
+    assert_eq!(client.pfcount(&["both"])?, count);
+    assert!((count - 11000).abs() < 220, "{count}");
+
+    // redis only merges adjacent values of the same run when it can, so
+    // its sparse encoding isn't always the shortest: here registers 0 to
+    // 3 are 1, in two opcodes
+    let mut split = b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\x80".to_vec();
+    split.extend_from_slice(&[0x80, 0x82, 0x7f, 0xfb]);
+    set("split", &split)?;
+    assert_eq!(client.pfcount(&["split"])?, 4);
+    client.pfmerge("split", &[])?;
+    assert_eq!(
+        client.get_bytes("split")?.unwrap()[16..],
+        [0x83, 0x7f, 0xfb]
+    );
+
+    // like redis' `hll-sparse-max-bytes`, the limit of 3000 bytes includes
+    // the header
+    let mut longest = 0;
+    for e in elements("limit", 2000) {
+        client.pfadd("limit", &[&e])?;
+        let hll = client.get_bytes("limit")?.unwrap();
+        if hll[4] == 0 {
+            break;
+        }
+        longest = longest.max(hll.len());
+    }
+    assert!((2990..=3000).contains(&longest), "{longest}");
+    Ok(())
+}
+
+#[test]
+fn sorted_sets() -> Result<()> {
+    let (_server, client) = start_server()?;
+    assert_eq!(client.zadd("z", &[(1.0, "a"), (2.0, "b"), (1.0, "c")])?, 3);
+    assert_eq!(client.zadd("z", &[(0.5, "b")])?, 0);
+    let range = client.cmd(&["zrange", "z", "0", "-1", "withscores"])?;
+    assert_eq!(strings(range), ["b", "0.5", "a", "1", "c", "1"]);
+    let range = client.cmd(&["zrange", "z", "-2", "10"])?;
+    assert_eq!(strings(range), ["a", "c"]);
+
+    let zadd = |args: &[&str]| -> Result<i64> {
+        let mut cmd = vec!["zadd", "z"];
+        cmd.extend(args);
+        client.cmd(&cmd)?.into_int()
+    };
+    assert_eq!(zadd(&["nx", "5", "a", "5", "d"])?, 1);
+    assert_eq!(zadd(&["xx", "ch", "5", "a", "5", "e"])?, 1);
+    assert_eq!(zadd(&["gt", "ch", "1", "a", "-inf", "c"])?, 0);
+    assert_eq!(client.zscore("z", "a")?, Some(5.0));
+    assert_eq!(client.zscore("z", "e")?, None);
+    assert!(zadd(&["nx", "xx", "1", "a"]).is_err());
+    assert!(zadd(&["nan", "a"]).is_err());
+
+    assert_eq!(client.zcard("z")?, 4);
+    assert_eq!(client.zrem("z", &["a", "x"])?, 1);
+    assert_eq!(client.key_type("z")?, "zset");
+    assert!(client.get("z").is_err());
+    assert_eq!(client.zrem("z", &["b", "c", "d"])?, 3);
+    assert_eq!(client.exists(&["z"])?, 0);
+    Ok(())
+}
+
+#[test]
+fn hashes() -> Result<()> {
+    let (_server, client) = start_server()?;
+    assert_eq!(client.hset("h", &[("a", "1"), ("b", "2")])?, 2);
+    assert_eq!(client.hset("h", &[("a", "3"), ("c", "4")])?, 1);
+    assert_eq!(client.hget("h", "a")?.as_deref(), Some("3"));
+    assert_eq!(client.hget("h", "x")?, None);
+    assert_eq!(client.hget("missing", "a")?, None);
+    assert_eq!(client.hlen("h")?, 3);
+    assert!(client.cmd(&["hexists", "h", "b"])?.into_bool()?);
+    assert!(!client.cmd(&["hexists", "h", "x"])?.into_bool()?);
+    let mut all = client.hgetall("h")?;
+    all.sort();
+    let pairs = |v: &[(&str, &str)]| -> Vec<(String, String)> {
+        v.iter()
+            .map(|(f, v)| (f.to_string(), v.to_string()))
+            .collect()
+    };
+    assert_eq!(all, pairs(&[("a", "3"), ("b", "2"), ("c", "4")]));
+    assert_eq!(client.hgetall("missing")?, []);
+    assert!(client.cmd(&["hset", "h", "a"]).is_err());
+    assert!(client.cmd(&["hset", "h", "a", "1", "b"]).is_err());
+
+    assert_eq!(client.key_type("h")?, "hash");
+    assert!(client.get("h").is_err());
+    let err = client.zcard("h").unwrap_err().to_string();
+    assert!(err.starts_with("WRONGTYPE"), "{err}");
+    assert_eq!(client.hdel("h", &["a", "x"])?, 1);
+    assert_eq!(client.hdel("h", &["b", "c"])?, 2);
+    assert_eq!(client.exists(&["h"])?, 0);
+    Ok(())
+}
+
+#[test]
+fn lists() -> Result<()> {
+    let (_server, client) = start_server()?;
+    assert_eq!(client.rpush("l", &["b", "c"])?, 2);
+    assert_eq!(client.lpush("l", &["a", "z"])?, 4);
+    assert_eq!(client.lrange("l", 0, -1)?, ["z", "a", "b", "c"]);
+    assert_eq!(client.lrange("l", -2, 10)?, ["b", "c"]);
+    assert_eq!(client.lrange("l", 3, 1)?, Vec::<String>::new());
+    assert_eq!(client.llen("l")?, 4);
+    assert_eq!(client.lpop("l")?.as_deref(), Some("z"));
+    assert_eq!(client.rpop("l")?.as_deref(), Some("c"));
+    assert_eq!(client.lpop("missing")?, None);
+    assert_eq!(client.llen("missing")?, 0);
+
+    let popped = client.cmd(&["rpop", "l", "5"])?;
+    assert_eq!(strings(popped), ["b", "a"]);
+    assert_eq!(client.exists(&["l"])?, 0);
+    assert_eq!(client.cmd(&["lpop", "l", "1"])?, Value::Null);
+    let err = client.cmd(&["lpop", "l", "-1"]).unwrap_err().to_string();
+    assert!(err.contains("must be positive"), "{err}");
+
+    client.rpush("l", &["a"])?;
+    assert_eq!(client.key_type("l")?, "list");
+    let err = client.sadd("l", &["a"]).unwrap_err().to_string();
+    assert!(err.starts_with("WRONGTYPE"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn sets() -> Result<()> {
+    let (_server, client) = start_server()?;
+    assert_eq!(client.sadd("s", &["1", "2", "2", "a"])?, 3);
+    assert_eq!(client.sadd("s", &["a", "b"])?, 1);
+    assert!(client.sismember("s", "2")?);
+    assert!(client.sismember("s", "b")?);
+    assert!(!client.sismember("s", "3")?);
+    assert!(!client.sismember("missing", "a")?);
+    assert_eq!(client.scard("s")?, 4);
+    let mut members = client.smembers("s")?;
+    members.sort();
+    assert_eq!(members, ["1", "2", "a", "b"]);
+    assert_eq!(client.smembers("missing")?, Vec::<String>::new());
+
+    // only canonical integers are stored as such
+    client.sadd("ints", &["10", "-3"])?;
+    assert!(!client.sismember("ints", "010")?);
+    assert!(!client.sismember("ints", "+10")?);
+    assert_eq!(client.sadd("ints", &["010"])?, 1);
+    assert_eq!(client.scard("ints")?, 3);
+
+    assert_eq!(client.key_type("s")?, "set");
+    let err = client.hlen("s").unwrap_err().to_string();
+    assert!(err.starts_with("WRONGTYPE"), "{err}");
+    assert_eq!(client.srem("s", &["1", "a", "x"])?, 2);
+    assert_eq!(client.srem("s", &["2", "b"])?, 2);
+    assert_eq!(client.exists(&["s"])?, 0);
+
+    // SCAN filters on the new types too
+    client.hset("h", &[("a", "1")])?;
+    client.rpush("l", &["a"])?;
+    let (_, keys) = client.scan(0, None, None, Some("set"))?;
+    assert_eq!(keys, ["ints"]);
+    let (_, keys) = client.scan(0, None, None, Some("list"))?;
+    assert_eq!(keys, ["l"]);
+    Ok(())
+}
+
+#[test]
+fn encodings() -> Result<()> {
+    let (_server, client) = start_server()?;
+    let encoding = |key| client.object_encoding(key);
+    let long = "x".repeat(45);
+    for (value, expected) in [
+        ("12345", "int"),
+        ("-7", "int"),
+        ("9223372036854775807", "int"),
+        ("9223372036854775808", "embstr"),
+        ("007", "embstr"),
+        ("+1", "embstr"),
+        ("1.5", "embstr"),
+        (&long[1..], "embstr"),
+        (&long, "raw"),
+    ] {
+        client.set("s", value)?;
+        assert_eq!(encoding("s")?.as_deref(), Some(expected), "{value}");
+        assert_eq!(client.get("s")?.as_deref(), Some(value));
+    }
+    assert_eq!(encoding("missing")?, None);
+    assert!(client.cmd(&["object", "freq", "s"]).is_err());
+    assert!(client.cmd(&["object", "encoding"]).is_err());
+
+    // integers are still strings
+    client.set("n", "1")?;
+    assert_eq!(client.bitcount("n")?, 3);
+    assert!(!client.setbit("n", 6, true)?);
+    assert_eq!(client.get("n")?.as_deref(), Some("3"));
+    assert_eq!(encoding("n")?.as_deref(), Some("embstr"));
+    client.set("n", "1")?;
+    let err = client.pfadd("n", &["a"]).unwrap_err().to_string();
+    assert!(err.starts_with("WRONGTYPE"), "{err}");
+    let payload = client.dump("n")?.unwrap();
+    client.restore("n2", None, &payload, false)?;
+    assert_eq!(encoding("n2")?.as_deref(), Some("int"));
+
+    // small sorted sets are listpacks, until they have too many members
+    client.config_set("zset-max-listpack-entries", "4")?;
+    client.zadd("z", &[(3.0, "c"), (1.0, "a"), (2.0, "b"), (0.0, "d")])?;
+    assert_eq!(encoding("z")?.as_deref(), Some("listpack"));
+    let payload = client.dump("z")?.unwrap();
+    client.zadd("z", &[(2.5, "e")])?;
+    assert_eq!(encoding("z")?.as_deref(), Some("skiplist"));
+    let range = client.cmd(&["zrange", "z", "0", "-1"])?;
+    assert_eq!(strings(range), ["d", "a", "b", "e", "c"]);
+    // ... and never go back
+    client.zrem("z", &["a", "b", "c"])?;
+    assert_eq!(encoding("z")?.as_deref(), Some("skiplist"));
+
+    // or members that are too long
+    client.zadd("long", &[(1.0, "a")])?;
+    assert_eq!(encoding("long")?.as_deref(), Some("listpack"));
+    client.zadd("long", &[(2.0, &"m".repeat(65))])?;
+    assert_eq!(encoding("long")?.as_deref(), Some("skiplist"));
+
+    // restored values follow the current limits
+    client.restore("z2", None, &payload, false)?;
+    assert_eq!(encoding("z2")?.as_deref(), Some("listpack"));
+    client.config_set("zset-max-listpack-entries", "2")?;
+    client.restore("z3", None, &payload, false)?;
+    assert_eq!(encoding("z3")?.as_deref(), Some("skiplist"));
+    assert_eq!(client.zcard("z3")?, 4);
+
+    // sets of integers are intsets, until another member is added...
+    client.sadd("set", &["3", "1", "2"])?;
+    assert_eq!(encoding("set")?.as_deref(), Some("intset"));
+    client.sadd("set", &["a"])?;
+    assert_eq!(encoding("set")?.as_deref(), Some("listpack"));
+    // ... and listpacks until they have too many or too long members
+    client.sadd("set", &[&"m".repeat(65)])?;
+    assert_eq!(encoding("set")?.as_deref(), Some("hashtable"));
+    client.srem("set", &["a"])?;
+    assert_eq!(encoding("set")?.as_deref(), Some("hashtable"));
+    client.config_set("set-max-intset-entries", "3")?;
+    client.sadd("ints", &["1", "2", "3"])?;
+    assert_eq!(encoding("ints")?.as_deref(), Some("intset"));
+    client.sadd("ints", &["4"])?;
+    assert_eq!(encoding("ints")?.as_deref(), Some("hashtable"));
+    client.config_set("set-max-listpack-entries", "3")?;
+    client.sadd("big", &["1", "2", "3"])?;
+    client.sadd("big", &["a"])?;
+    assert_eq!(encoding("big")?.as_deref(), Some("hashtable"));
+    assert_eq!(client.scard("big")?, 4);
+
+    // hashes are listpacks until they have too many or too long fields
+    client.config_set("hash-max-listpack-entries", "2")?;
+    client.hset("hash", &[("a", "1"), ("b", "2")])?;
+    assert_eq!(encoding("hash")?.as_deref(), Some("listpack"));
+    let payload = client.dump("hash")?.unwrap();
+    client.hset("hash", &[("c", "3")])?;
+    assert_eq!(encoding("hash")?.as_deref(), Some("hashtable"));
+    client.hset("hash2", &[("a", &"v".repeat(65))])?;
+    assert_eq!(encoding("hash2")?.as_deref(), Some("hashtable"));
+    client.config_set("hash-max-listpack-entries", "1")?;
+    client.restore("hash3", None, &payload, false)?;
+    assert_eq!(encoding("hash3")?.as_deref(), Some("hashtable"));
+    assert_eq!(client.hget("hash3", "b")?.as_deref(), Some("2"));
+
+    // lists are listpacks until they are too big, and go back to being
+    // listpacks when they shrink to half of that
+    client.config_set("list-max-listpack-size", "4")?;
+    client.rpush("list", &["a", "b", "c", "d"])?;
+    assert_eq!(encoding("list")?.as_deref(), Some("listpack"));
+    client.rpush("list", &["e"])?;
+    assert_eq!(encoding("list")?.as_deref(), Some("quicklist"));
+    client.lpop("list")?;
+    client.lpop("list")?;
+    assert_eq!(encoding("list")?.as_deref(), Some("quicklist"));
+    client.lpop("list")?;
+    assert_eq!(encoding("list")?.as_deref(), Some("listpack"));
+    assert_eq!(client.lrange("list", 0, -1)?, ["d", "e"]);
+    // negative sizes are in bytes: -1 is 4 kB
+    client.config_set("list-max-listpack-size", "-1")?;
+    client.rpush("bytes", &[&"x".repeat(4000)])?;
+    assert_eq!(encoding("bytes")?.as_deref(), Some("listpack"));
+    client.rpush("bytes", &[&"x".repeat(100)])?;
+    assert_eq!(encoding("bytes")?.as_deref(), Some("quicklist"));
+    Ok(())
+}
+
+#[test]
+fn geo() -> Result<()> {
+    let (_server, client) = start_server()?;
+    let sicily = [
+        (13.361389, 38.115556, "Palermo"),
+        (15.087269, 37.502669, "Catania"),
+    ];
+    assert_eq!(client.geoadd("Sicily", &sicily)?, 2);
+    assert_eq!(
+        client.zscore("Sicily", "Palermo")?,
+        Some(3479099956230698.0)
+    );
+    assert_eq!(
+        client.zscore("Sicily", "Catania")?,
+        Some(3479447370796909.0)
+    );
+
+    let dist = client.geodist("Sicily", "Palermo", "Catania")?.unwrap();
+    assert_eq!(dist, 166274.1516);
+    let km = client.cmd(&["geodist", "Sicily", "Palermo", "Catania", "km"])?;
+    assert_eq!(km.into_string()?, "166.2742");
+    assert_eq!(client.geodist("Sicily", "Palermo", "Rome")?, None);
+
+    let hashes = client.cmd(&["geohash", "Sicily", "Palermo", "Catania"])?;
+    assert_eq!(strings(hashes), ["sqc8b49rny0", "sqdtr74hyu0"]);
+    let pos = client.cmd(&["geopos", "Sicily", "Palermo", "Rome"])?;
+    let Value::Bulk(pos) = pos else {
+        panic!("unexpected reply {pos:?}");
+    };
+    let palermo: Vec<f64> = strings(pos[0].clone())
+        .iter()
+        .map(|c| c.parse().unwrap())
+        .collect();
+    assert!((palermo[0] - 13.361389).abs() < 1e-5);
+    assert!((palermo[1] - 38.115556).abs() < 1e-5);
+    assert_eq!(pos[1], Value::Null);
+
+    client.geoadd(
+        "Sicily",
+        &[
+            (12.758489, 38.788135, "edge1"),
+            (17.241510, 38.788135, "edge2"),
+        ],
+    )?;
+    let search = |args: &[&str]| -> Result<Value> {
+        let mut cmd = vec!["geosearch", "Sicily"];
+        cmd.extend(args);
+        client.cmd(&cmd)
+    };
+    let found = search(&["fromlonlat", "15", "37", "byradius", "200", "km"])?;
+    let mut found = strings(found);
+    found.sort();
+    assert_eq!(found, ["Catania", "Palermo"]);
+    let found = search(&[
+        "fromlonlat",
+        "15",
+        "37",
+        "bybox",
+        "400",
+        "400",
+        "km",
+        "asc",
+        "withdist",
+    ])?;
+    let Value::Bulk(found) = found else {
+        panic!("unexpected reply {found:?}");
+    };
+    let found: Vec<Vec<String>> = found.into_iter().map(strings).collect();
+    assert_eq!(
+        found,
+        [
+            ["Catania", "56.4413"],
+            ["Palermo", "190.4424"],
+            ["edge2", "279.7403"],
+            ["edge1", "279.7405"],
+        ]
+    );
+    let found = search(&[
+        "frommember",
+        "Palermo",
+        "byradius",
+        "200",
+        "km",
+        "count",
+        "1",
+        "desc",
+    ])?;
+    assert_eq!(strings(found), ["Catania"]);
+    let found = search(&["frommember", "Rome", "byradius", "1", "m"]);
+    assert!(found.is_err());
+    let found = search(&["fromlonlat", "15", "37", "byradius", "1", "m"])?;
+    assert_eq!(found, Value::Bulk(vec![]));
+    let found = client.cmd(&[
+        "geosearch",
+        "missing",
+        "fromlonlat",
+        "15",
+        "37",
+        "byradius",
+        "1",
+        "m",
+    ])?;
+    assert_eq!(found, Value::Bulk(vec![]));
+
+    let err = client.geoadd("Sicily", &[(200.0, 100.0, "nowhere")]);
+    let err = err.unwrap_err().to_string();
+    assert!(err.contains("invalid longitude,latitude pair"), "{err}");
+    // members are removed like in any sorted set
+    assert_eq!(client.zrem("Sicily", &["edge1", "edge2"])?, 2);
+    assert_eq!(client.zcard("Sicily")?, 2);
+    Ok(())
+}
+
+#[test]
+fn binary_members() -> Result<()> {
+    let (_server, client) = start_server()?;
+    let cmd = |args: &[&[u8]]| client.cmd(args);
+    let bin = || Value::Bytes(b"\xff\x00".to_vec());
+
+    // sorted sets
+    cmd(&[b"zadd", b"z", b"1", b"\xff\x00", b"2", b"a"])?;
+    let members = cmd(&[b"zrange", b"z", b"0", b"-1"])?;
+    assert_eq!(members, Value::Bulk(vec![bin(), Value::String("a".into())]));
+    assert_eq!(client.zscore("z", "\u{fffd}\0")?, None);
+    let score = cmd(&[b"zscore", b"z", b"\xff\x00"])?;
+    assert_eq!(score, Value::String("1".into()));
+    assert_eq!(cmd(&[b"zrem", b"z", b"\xff\x00"])?, Value::Int(1));
+    assert_eq!(client.zcard("z")?, 1);
+
+    // hashes, lists and sets
+    cmd(&[b"hset", b"hash", b"\xff\x00", b"\xff\x00"])?;
+    assert_eq!(cmd(&[b"hget", b"hash", b"\xff\x00"])?, bin());
+    assert_eq!(
+        cmd(&[b"hgetall", b"hash"])?,
+        Value::Bulk(vec![bin(), bin()])
+    );
+    cmd(&[b"rpush", b"list", b"\xff\x00"])?;
+    assert_eq!(cmd(&[b"lpop", b"list"])?, bin());
+    cmd(&[b"sadd", b"set", b"\xff\x00"])?;
+    assert_eq!(cmd(&[b"sismember", b"set", b"\xff\x00"])?, Value::Int(1));
+    assert_eq!(cmd(&[b"smembers", b"set"])?, Value::Bulk(vec![bin()]));
+
+    // HyperLogLogs hash the bytes
+    assert_eq!(cmd(&[b"pfadd", b"h", b"\xff", b"\xfe"])?, Value::Int(1));
+    assert_eq!(cmd(&[b"pfadd", b"h", b"\xfe"])?, Value::Int(0));
+    assert_eq!(client.pfcount(&["h"])?, 2);
+
+    // geospatial indexes
+    cmd(&[b"geoadd", b"g", b"13.361389", b"38.115556", b"\xff\x00"])?;
+    let Value::Bulk(pos) = cmd(&[b"geopos", b"g", b"\xff\x00", b"a"])? else {
+        panic!("unexpected reply");
+    };
+    assert!(matches!(pos[..], [Value::Bulk(_), Value::Null]), "{pos:?}");
+    let found = cmd(&[
+        b"geosearch",
+        b"g",
+        b"frommember",
+        b"\xff\x00",
+        b"byradius",
+        b"1",
+        b"km",
+    ])?;
+    assert_eq!(found, Value::Bulk(vec![bin()]));
+
+    // keys are strings
+    let err = cmd(&[b"zadd", b"\xff", b"1", b"a"]).unwrap_err();
+    assert!(err.to_string().contains("must be valid UTF-8"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn snapshots() -> Result<()> {
+    let db = Arc::new(Db::default());
+    let mut z = ZSet::default();
+    z.insert(b"a", 1.5);
+    z.insert(b"\xff", f64::NEG_INFINITY);
+    db.insert("z".into(), Entry::new(DbValue::ZSet(z), None));
+    let bitmap = DbValue::String(vec![0xff, 0x00, 0x80]);
+    db.insert("bits".into(), Entry::new(bitmap, None));
+    db.insert("n".into(), Entry::new(DbValue::from("42"), None));
+    let mut h = Hash::default();
+    h.insert(b"f", b"\xff");
+    db.insert("h".into(), Entry::new(DbValue::Hash(h), None));
+    let mut l = List::default();
+    l.push_back(b"b");
+    l.push_front(b"a");
+    db.insert("l".into(), Entry::new(DbValue::List(l), None));
+    let mut s = Set::default();
+    s.insert(b"7");
+    s.insert(b"x");
+    db.insert("s".into(), Entry::new(DbValue::Set(s), None));
+
+    let mut data = vec![];
+    rdb::encode(&[db], &mut data);
+    let mut loaded = vec![];
+    rdb::decode(&data, |_, k, e| {
+        loaded.push((k, e.value));
+        Ok(())
+    })?;
+    loaded.sort_by(|a, b| a.0.cmp(&b.0));
+    let keys: Vec<_> = loaded.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(keys, ["bits", "h", "l", "n", "s", "z"]);
+    let value = |key| &loaded.iter().find(|(k, _)| k == key).unwrap().1;
+    let (DbValue::String(bits), n) = (value("bits"), value("n")) else {
+        panic!("unexpected values {loaded:?}");
+    };
+    assert_eq!(bits, &[0xff, 0x00, 0x80]);
+    assert!(matches!(n, DbValue::Int(42)), "{n:?}");
+    let (DbValue::Hash(h), DbValue::List(l)) = (value("h"), value("l")) else {
+        panic!("unexpected values {loaded:?}");
+    };
+    assert_eq!(h.iter().collect::<Vec<_>>(), [(&b"f"[..], &b"\xff"[..])]);
+    assert_eq!(l.iter().collect::<Vec<_>>(), [b"a", b"b"]);
+    let (DbValue::Set(s), DbValue::ZSet(z)) = (value("s"), value("z")) else {
+        panic!("unexpected values {loaded:?}");
+    };
+    let mut members: Vec<_> = s.iter().map(|m| m.into_owned()).collect();
+    members.sort();
+    assert_eq!(members, [b"7", b"x"]);
+    let members: Vec<(&[u8], f64)> = z.iter().collect();
+    assert_eq!(members, [(&b"\xff"[..], f64::NEG_INFINITY), (b"a", 1.5)]);
+    Ok(())
+}
@@ -1,14 +1,57 @@
 //! Bank for arrays and slices
 
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
 pub use crate::error::{Error, Result};
 use crate::{bank::Bank, index::Index};
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
-pub struct ArrayIndex {
-    idx: Index,
+/// Index of a slice of `T` in an [`ArrayBank`].
+pub struct ArrayIndex<T> {
+    /// Index in the bank for slices of length `len`.
+    idx: Index<T>,
     len: u32,
 }
 
+// implemented by hand, like for `Index`
+
+impl<T> Clone for ArrayIndex<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ArrayIndex<T> {}
+
+impl<T> PartialEq for ArrayIndex<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        (self.idx, self.len) == (other.idx, other.len)
+    }
+}
+
+impl<T> Eq for ArrayIndex<T> {}
+
+impl<T> Hash for ArrayIndex<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.idx.hash(state);
+        self.len.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for ArrayIndex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArrayIndex")
+            .field("idx", &self.idx)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
 pub struct ArrayBank<T> {
     bank1: Bank<[T; 1]>,
     bank2: Bank<[T; 2]>,
@@ -25,6 +68,15 @@ where
     arr.clone()
 }
 
+impl<T> Default for ArrayBank<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> ArrayBank<T>
 where
     T: Clone,
@@ -44,27 +96,32 @@ where
         self.bank1.len() + self.bank2.len() + self.bank3.len() + self.bank_n.len()
     }
 
-    pub fn get(&self, x: ArrayIndex) -> &[T] {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, x: ArrayIndex<T>) -> &[T] {
         match x.len {
             0 => &[],
-            1 => self.bank1.get(x.idx).as_slice(),
-            2 => self.bank2.get(x.idx).as_slice(),
-            3 => self.bank3.get(x.idx).as_slice(),
-            _ => &*self.bank_n.get(x.idx),
+            1 => self.bank1.get(x.idx.cast()).as_slice(),
+            2 => self.bank2.get(x.idx.cast()).as_slice(),
+            3 => self.bank3.get(x.idx.cast()).as_slice(),
+            _ => self.bank_n.get(x.idx.cast()),
         }
     }
 
-    pub fn try_get(&self, x: ArrayIndex) -> Option<&[T]> {
+    pub fn try_get(&self, x: ArrayIndex<T>) -> Option<&[T]> {
         Some(match x.len {
             0 => &[],
-            1 => self.bank1.try_get(x.idx)?.as_slice(),
-            2 => self.bank2.try_get(x.idx)?.as_slice(),
-            3 => self.bank3.try_get(x.idx)?.as_slice(),
-            _ => &*self.bank_n.try_get(x.idx)?,
+            1 => self.bank1.try_get(x.idx.cast())?.as_slice(),
+            2 => self.bank2.try_get(x.idx.cast())?.as_slice(),
+            3 => self.bank3.try_get(x.idx.cast())?.as_slice(),
+            _ => &**self.bank_n.try_get(x.idx.cast())?,
         })
     }
 
-    pub fn alloc(&mut self, x: &[T]) -> Result<ArrayIndex> {
+    pub fn alloc(&mut self, x: &[T]) -> Result<ArrayIndex<T>> {
         if x.len() > u32::MAX as usize {
             return Err(Error::SliceTooBig);
         }
@@ -72,13 +129,10 @@ where
         let len = x.len();
         let idx = match len {
             0 => Index::from_u32(0),
-            1 => self.bank1.alloc(const_slice_to(x))?,
-            2 => self.bank2.alloc(const_slice_to(x))?,
-            3 => self.bank3.alloc(const_slice_to(x))?,
-            _ => {
-                let v: Vec<_> = x.iter().cloned().collect();
-                self.bank_n.alloc(v.into_boxed_slice())?
-            }
+            1 => self.bank1.alloc(const_slice_to(x))?.cast(),
+            2 => self.bank2.alloc(const_slice_to(x))?.cast(),
+            3 => self.bank3.alloc(const_slice_to(x))?.cast(),
+            _ => self.bank_n.alloc(x.to_vec().into_boxed_slice())?.cast(),
         };
         Ok(ArrayIndex {
             idx,
@@ -86,18 +140,18 @@ where
         })
     }
 
-    pub fn alloc_iter(&mut self, i: impl IntoIterator<Item = T>) -> Result<ArrayIndex> {
+    pub fn alloc_iter(&mut self, i: impl IntoIterator<Item = T>) -> Result<ArrayIndex<T>> {
         let v: Vec<_> = i.into_iter().collect();
         self.alloc(&v)
     }
 
-    pub fn free(&mut self, x: ArrayIndex) -> Result<()> {
+    pub fn free(&mut self, x: ArrayIndex<T>) -> Result<()> {
         match x.len {
             0 => (),
-            1 => self.bank1.free(x.idx)?,
-            2 => self.bank2.free(x.idx)?,
-            3 => self.bank3.free(x.idx)?,
-            _ => self.bank_n.free(x.idx)?,
+            1 => self.bank1.free(x.idx.cast())?,
+            2 => self.bank2.free(x.idx.cast())?,
+            3 => self.bank3.free(x.idx.cast())?,
+            _ => self.bank_n.free(x.idx.cast())?,
         }
         Ok(())
     }
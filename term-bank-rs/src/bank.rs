@@ -1,7 +1,7 @@
 use std::mem::ManuallyDrop;
 
 pub use crate::error::{Error, Result};
-use crate::index::Index;
+use crate::{bits, index::Index};
 use bit_vec::BitVec;
 
 type Generation = u8;
@@ -43,15 +43,20 @@ impl<T> Bank<T> {
     }
 
     #[inline]
-    pub fn get(&self, x: Index) -> &T {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn get(&self, x: Index<T>) -> &T {
         let idx = x.index();
         assert!(self.present[idx]);
-        debug_assert_eq!(self.generation[idx], x.generation());
+        assert_eq!(self.generation[idx], x.generation(), "stale index");
         // SAFETY: entry is full because `present` is true
         unsafe { &self.data[idx].full }
     }
 
-    pub fn try_get(&self, x: Index) -> Option<&T> {
+    pub fn try_get(&self, x: Index<T>) -> Option<&T> {
         let idx = x.index();
         if idx >= self.data.len() {
             return None;
@@ -67,7 +72,37 @@ impl<T> Bank<T> {
         Some(unsafe { &self.data[idx].full })
     }
 
-    pub fn alloc(&mut self, x: T) -> Result<Index> {
+    #[inline]
+    pub fn get_mut(&mut self, x: Index<T>) -> &mut T {
+        let idx = x.index();
+        assert!(self.present[idx]);
+        assert_eq!(self.generation[idx], x.generation(), "stale index");
+        // SAFETY: entry is full because `present` is true
+        unsafe { &mut self.data[idx].full }
+    }
+
+    pub fn try_get_mut(&mut self, x: Index<T>) -> Option<&mut T> {
+        let idx = self.check(x).ok()?;
+        // SAFETY: entry is full because `present` is true
+        Some(unsafe { &mut self.data[idx].full })
+    }
+
+    /// Mutable references to the items at several distinct indices. Fails
+    /// if one of them is invalid, or if an index is given twice.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, xs: [Index<T>; N]) -> Result<[&mut T; N]> {
+        let mut idxs = [0; N];
+        for (i, &x) in xs.iter().enumerate() {
+            idxs[i] = self.check(x)?;
+            if idxs[..i].contains(&idxs[i]) {
+                return Err(Error::Overlapping(idxs[i] as u32));
+            }
+        }
+        let slots = self.data.get_disjoint_mut(idxs).expect("checked above");
+        // SAFETY: entries are full because `present` is true
+        Ok(slots.map(|slot| unsafe { &mut *slot.full }))
+    }
+
+    pub fn alloc(&mut self, x: T) -> Result<Index<T>> {
         let idx;
         let generation;
         if self.available_slots == 0 {
@@ -76,7 +111,7 @@ impl<T> Bank<T> {
             debug_assert_eq!(self.data.len(), self.present.len());
             idx = self.data.len();
 
-            if idx > MAX_INDEX as usize {
+            if idx > MAX_INDEX {
                 return Err(Error::Full);
             }
 
@@ -104,19 +139,34 @@ impl<T> Bank<T> {
             debug_assert!(idx != usize::MAX); // we must have found an index
         };
 
-        let idx_with_gen = (idx << 8) | (generation as usize);
-
-        debug_assert!(idx_with_gen <= (u32::MAX as usize));
-        Ok(Index::from_u32(idx_with_gen as u32))
+        Ok(Index::new(idx, generation))
     }
 
     #[inline]
-    pub fn alloc_with(&mut self, f: impl FnOnce() -> T) -> Result<Index> {
+    pub fn alloc_with(&mut self, f: impl FnOnce() -> T) -> Result<Index<T>> {
         let x = f();
-        return self.alloc(x);
+        self.alloc(x)
+    }
+
+    pub fn free(&mut self, x: Index<T>) -> Result<()> {
+        self.take(x).map(drop)
+    }
+
+    /// Free the item at `x` and return it.
+    pub fn take(&mut self, x: Index<T>) -> Result<T> {
+        let idx = self.check(x)?;
+        Ok(self.remove_at(idx))
+    }
+
+    /// Replace the item at `x` by `v`, keeping its index, and return the
+    /// previous one. Panics if `x` is invalid, like [`Self::get_mut`].
+    #[inline]
+    pub fn replace(&mut self, x: Index<T>, v: T) -> T {
+        std::mem::replace(self.get_mut(x), v)
     }
 
-    pub fn free(&mut self, x: Index) -> Result<()> {
+    /// Slot of the item at `x`, if it's still there.
+    fn check(&self, x: Index<T>) -> Result<usize> {
         let idx = x.index();
         if idx >= self.data.len() {
             return Err(Error::InvalidIndex(idx as u32));
@@ -126,30 +176,129 @@ impl<T> Bank<T> {
             return Err(Error::InvalidIndex(idx as u32));
         }
 
-        let cur_gen = &mut self.generation[idx];
-        if *cur_gen != x.generation() {
+        if self.generation[idx] != x.generation() {
             return Err(Error::WrongGeneration(idx as u32));
         }
+        Ok(idx)
+    }
 
-        {
-            // remove the data, by swapping it with a list element
-            let mut data = std::mem::replace(
-                &mut self.data[idx],
-                OrEmpty {
-                    prev_empty: self.last_empty,
-                },
-            );
-
-            // SAFETY: `present` is true, so this must be full
-            unsafe { ManuallyDrop::drop(&mut data.full) };
-        }
+    /// Remove the item in slot `idx`, which must be present, and return
+    /// it.
+    fn remove_at(&mut self, idx: usize) -> T {
+        debug_assert!(self.present[idx]);
+        // remove the data, by swapping it with a list element
+        let data = std::mem::replace(
+            &mut self.data[idx],
+            OrEmpty {
+                prev_empty: self.last_empty,
+            },
+        );
         self.last_empty = idx as i32;
 
+        let cur_gen = &mut self.generation[idx];
         *cur_gen = cur_gen.wrapping_add(1);
         self.present.set(idx, false);
         self.available_slots += 1;
 
-        Ok(())
+        // SAFETY: `present` was true, so this must be full
+        ManuallyDrop::into_inner(unsafe { data.full })
+    }
+
+    /// Index of the item in slot `idx`.
+    #[inline]
+    fn index_at(&self, idx: usize) -> Index<T> {
+        Index::new(idx, self.generation[idx])
+    }
+
+    /// Indices of all the items, in slot order.
+    pub fn indices(&self) -> impl Iterator<Item = Index<T>> + '_ {
+        bits::iter_set(&self.present).map(|i| self.index_at(i))
+    }
+
+    /// All the items with their index, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (Index<T>, &T)> + '_ {
+        // SAFETY: entry is full because `present` is true
+        self.indices()
+            .map(|x| (x, unsafe { &*self.data[x.index()].full }))
+    }
+
+    /// All the items with their index, in slot order, for modification.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Index<T>, &mut T)> + '_ {
+        let generation = &self.generation;
+        // the part of `data` after the last item returned
+        let mut data = &mut self.data[..];
+        let mut offset = 0;
+        bits::iter_set(&self.present).map(move |i| {
+            let rest = std::mem::take(&mut data);
+            let (slot, rest) = rest[i - offset..].split_first_mut().unwrap();
+            (data, offset) = (rest, i + 1);
+            // SAFETY: entry is full because `present` is true
+            let x = unsafe { &mut *slot.full };
+            (Index::new(i, generation[i]), x)
+        })
+    }
+
+    /// Keep only the items for which `f` returns `true`, and free the
+    /// others.
+    pub fn retain(&mut self, mut f: impl FnMut(Index<T>, &mut T) -> bool) {
+        let mut from = 0;
+        while let Some(i) = bits::next_set(&self.present, from) {
+            let x = self.index_at(i);
+            // SAFETY: entry is full because `present` is true
+            if !f(x, unsafe { &mut self.data[i].full }) {
+                drop(self.remove_at(i));
+            }
+            from = i + 1;
+        }
+    }
+
+    /// Remove all the items, returning them with their index. The items
+    /// that are not consumed are freed when the iterator is dropped.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        Drain {
+            bank: self,
+            from: 0,
+        }
+    }
+
+    /// Free all the items. Their indices stay invalid when the slots are
+    /// reused, like with [`Self::free`].
+    pub fn clear(&mut self) {
+        self.drain().for_each(drop);
+    }
+}
+
+/// Iterator returned by [`Bank::drain`].
+pub struct Drain<'a, T> {
+    bank: &'a mut Bank<T>,
+    /// Where to look for the next item.
+    from: usize,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = (Index<T>, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = bits::next_set(&self.bank.present, self.from)?;
+        self.from = i + 1;
+        let x = self.bank.index_at(i);
+        Some((x, self.bank.remove_at(i)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.bank.len(), Some(self.bank.len()))
+    }
+}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+impl<T> Default for Bank<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -175,7 +324,7 @@ where
 {
     /// Get a copy at given index.
     #[inline]
-    pub fn get_copy(&self, x: Index) -> T {
+    pub fn get_copy(&self, x: Index<T>) -> T {
         *self.get(x)
     }
 }
@@ -236,4 +385,132 @@ mod test {
             assert_eq!(0, bank.len());
         }
     }
+
+    #[test]
+    fn iterates() {
+        let mut bank = Bank::new();
+        let v: Vec<_> = (0..100)
+            .map(|i| bank.alloc(i.to_string()).unwrap())
+            .collect();
+        assert_eq!(bank.indices().collect::<Vec<_>>(), v);
+
+        // free whole blocks of slots, and some in between
+        for &x in v[..40].iter().chain(v.iter().skip(41).step_by(3)) {
+            bank.free(x).unwrap();
+        }
+        let live: Vec<_> = (0..100)
+            .filter(|&i| i == 40 || (i > 40 && (i - 41) % 3 != 0))
+            .collect();
+        let items: Vec<_> = bank.iter().map(|(x, s)| (x, s.clone())).collect();
+        let expected: Vec<_> = live.iter().map(|&i| (v[i], i.to_string())).collect();
+        assert_eq!(items, expected);
+
+        for (_, s) in bank.iter_mut() {
+            s.push('!');
+        }
+        assert_eq!("40!", bank.get(v[40]));
+        assert_eq!(live.len(), bank.iter_mut().count());
+
+        // reused slots have a new generation
+        let x = bank.alloc("new".to_string()).unwrap();
+        assert!(!v.contains(&x));
+        assert!(bank.indices().any(|y| y == x));
+
+        let empty: Bank<String> = Bank::new();
+        assert_eq!(0, empty.iter().count());
+    }
+
+    #[test]
+    fn retains_and_drains() {
+        let item = std::rc::Rc::new(());
+        let mut bank = Bank::new();
+        let v: Vec<_> = (0..70)
+            .map(|i| bank.alloc((i, item.clone())).unwrap())
+            .collect();
+        bank.free(v[4]).unwrap();
+
+        bank.retain(|x, (i, _)| {
+            assert_eq!(x, v[*i]);
+            *i % 2 == 0
+        });
+        assert_eq!(34, bank.len());
+        assert_eq!(35, std::rc::Rc::strong_count(&item));
+        assert!(bank.try_get(v[1]).is_none());
+        assert_eq!(2, bank.get(v[2]).0);
+
+        // the items that are not consumed are dropped too
+        let drained: Vec<_> = bank.drain().take(2).map(|(x, (i, _))| (x, i)).collect();
+        assert_eq!(drained, [(v[0], 0), (v[2], 2)]);
+        assert!(bank.is_empty());
+        assert_eq!(1, std::rc::Rc::strong_count(&item));
+        assert!(bank.try_get(v[0]).is_none());
+
+        let x = bank.alloc((100, item.clone())).unwrap();
+        assert!(!v.contains(&x));
+        bank.clear();
+        assert!(bank.is_empty());
+        assert!(bank.try_get(x).is_none());
+        assert_eq!(1, std::rc::Rc::strong_count(&item));
+    }
+
+    #[test]
+    fn updates_in_place() {
+        let mut bank: Bank<String> = Bank::new();
+        let a = bank.alloc("a".to_string()).unwrap();
+        let b = bank.alloc("b".to_string()).unwrap();
+        let c = bank.alloc("c".to_string()).unwrap();
+
+        bank.get_mut(a).push('!');
+        assert_eq!("a!", bank.get(a));
+        bank.try_get_mut(b).unwrap().push('?');
+        assert_eq!("b?", bank.get(b));
+        assert_eq!("a!", bank.replace(a, "x".to_string()));
+        assert_eq!("x", bank.get(a));
+
+        let [x, y] = bank.get_disjoint_mut([c, a]).unwrap();
+        std::mem::swap(x, y);
+        assert_eq!("x", bank.get(c));
+        assert_eq!("c", bank.get(a));
+        assert!(matches!(
+            bank.get_disjoint_mut([a, b, a]),
+            Err(Error::Overlapping(0))
+        ));
+
+        // taking the item frees it, and gives it back
+        assert_eq!("b?", bank.take(b).unwrap());
+        assert_eq!(2, bank.len());
+        assert!(bank.try_get_mut(b).is_none());
+        assert!(matches!(bank.take(b), Err(Error::InvalidIndex(1))));
+        assert!(matches!(
+            bank.get_disjoint_mut([a, b]),
+            Err(Error::InvalidIndex(1))
+        ));
+        let d = bank.alloc("d".to_string()).unwrap();
+        assert_eq!(b.index(), d.index());
+        assert!(matches!(bank.take(b), Err(Error::WrongGeneration(1))));
+        assert!(bank.try_get_mut(b).is_none());
+        assert_eq!("d", bank.get(d));
+    }
+
+    #[test]
+    #[should_panic(expected = "stale index")]
+    fn replace_stale_index() {
+        let mut bank = Bank::new();
+        let x = bank.alloc("a".to_string()).unwrap();
+        bank.free(x).unwrap();
+        let y = bank.alloc("new".to_string()).unwrap();
+        assert_eq!(x.index(), y.index());
+        // the slot was reused: must not clobber `y`
+        bank.replace(x, "a".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "stale index")]
+    fn get_stale_index() {
+        let mut bank = Bank::new();
+        let x = bank.alloc(1).unwrap();
+        bank.free(x).unwrap();
+        bank.alloc(2).unwrap();
+        bank.get(x);
+    }
 }
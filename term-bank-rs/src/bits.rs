@@ -0,0 +1,29 @@
+//! Finding the set bits of a `BitVec`, such as the live slots of a bank.
+
+use bit_vec::BitVec;
+
+const BLOCK_BITS: usize = u32::BITS as usize;
+
+/// Position of the first set bit at or after `from`. Blocks with no bit
+/// set are skipped as a whole.
+pub(crate) fn next_set(bits: &BitVec, from: usize) -> Option<usize> {
+    let storage = bits.storage();
+    let mut block = from / BLOCK_BITS;
+    let mut word = *storage.get(block)? & (!0 << (from % BLOCK_BITS));
+    while word == 0 {
+        block += 1;
+        word = *storage.get(block)?;
+    }
+    let i = block * BLOCK_BITS + word.trailing_zeros() as usize;
+    (i < bits.len()).then_some(i)
+}
+
+/// Positions of the set bits, in increasing order.
+pub(crate) fn iter_set(bits: &BitVec) -> impl Iterator<Item = usize> + '_ {
+    let mut from = 0;
+    std::iter::from_fn(move || {
+        let i = next_set(bits, from)?;
+        from = i + 1;
+        Some(i)
+    })
+}
@@ -0,0 +1,186 @@
+//! Banks with branded indices, that can only be used with the bank that
+//! allocated them.
+//!
+//! A branded bank only exists inside the closure passed to [`scope`],
+//! which gives it a unique lifetime `'id`, its brand. Its indices carry the
+//! same brand, so mixing up two banks is a compile error even when they
+//! hold the same type:
+//!
+//! ```compile_fail
+//! use term_bank_rs::branded;
+//!
+//! branded::scope(|mut bank1: branded::Bank<u32>| {
+//!     branded::scope(|bank2: branded::Bank<u32>| {
+//!         let idx = bank1.alloc(42).unwrap();
+//!         bank2.get(idx); // lifetime mismatch
+//!     })
+//! });
+//! ```
+//!
+//! Indices can't escape the closure either. A freed index still has the
+//! brand of its bank though, so the generation is checked like in
+//! [`crate::bank::Bank`].
+
+use std::{fmt, hash, marker::PhantomData};
+
+use crate::{bank, error::Result, index};
+
+/// An invariant lifetime, so that two brands are never unified.
+#[derive(Clone, Copy)]
+struct Brand<'id>(PhantomData<fn(&'id ()) -> &'id ()>);
+
+impl Brand<'_> {
+    fn new() -> Self {
+        Brand(PhantomData)
+    }
+}
+
+/// A [`bank::Bank`] with its own brand `'id`.
+pub struct Bank<'id, T> {
+    inner: bank::Bank<T>,
+    _brand: Brand<'id>,
+}
+
+/// Index of a `T` in the bank with brand `'id`.
+pub struct Index<'id, T> {
+    idx: index::Index<T>,
+    _brand: Brand<'id>,
+}
+
+/// Call `f` with a new, empty bank with a unique brand.
+///
+/// ```
+/// use term_bank_rs::branded;
+///
+/// let len = branded::scope(|mut bank| {
+///     let idx = bank.alloc("abc".to_string()).unwrap();
+///     assert_eq!("abc", bank.get(idx));
+///     bank.len()
+/// });
+/// assert_eq!(1, len);
+/// ```
+pub fn scope<T, R>(f: impl for<'id> FnOnce(Bank<'id, T>) -> R) -> R {
+    f(Bank {
+        inner: bank::Bank::new(),
+        _brand: Brand::new(),
+    })
+}
+
+impl<'id, T> Bank<'id, T> {
+    /// Number of items in the bank.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, x: Index<'id, T>) -> &T {
+        self.inner.get(x.idx)
+    }
+
+    #[inline]
+    pub fn try_get(&self, x: Index<'id, T>) -> Option<&T> {
+        self.inner.try_get(x.idx)
+    }
+
+    pub fn alloc(&mut self, x: T) -> Result<Index<'id, T>> {
+        let idx = self.inner.alloc(x)?;
+        Ok(Index {
+            idx,
+            _brand: Brand::new(),
+        })
+    }
+
+    #[inline]
+    pub fn alloc_with(&mut self, f: impl FnOnce() -> T) -> Result<Index<'id, T>> {
+        self.alloc(f())
+    }
+
+    pub fn free(&mut self, x: Index<'id, T>) -> Result<()> {
+        self.inner.free(x.idx)
+    }
+}
+
+impl<T> Index<'_, T> {
+    #[inline]
+    pub fn index(self) -> usize {
+        self.idx.index()
+    }
+
+    #[inline]
+    pub fn generation(self) -> u8 {
+        self.idx.generation()
+    }
+}
+
+// implemented by hand, like for `index::Index`
+
+impl<T> Clone for Index<'_, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Index<'_, T> {}
+
+impl<T> PartialEq for Index<'_, T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+
+impl<T> Eq for Index<'_, T> {}
+
+impl<T> hash::Hash for Index<'_, T> {
+    #[inline]
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.idx.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for Index<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.idx.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(std::mem::size_of::<Index<'static, String>>(), 4);
+    }
+
+    #[test]
+    fn it_allocates() {
+        scope(|mut bank1| {
+            scope(|mut bank2| {
+                let str1 = bank1.alloc("abc".to_string()).unwrap();
+                let str2 = bank2.alloc("hello".to_string()).unwrap();
+
+                // same slot in both banks
+                assert_eq!(str1.index(), str2.index());
+                assert_eq!("abc", bank1.get(str1));
+                assert_eq!("hello", bank2.get(str2));
+
+                bank1.free(str1).unwrap();
+                assert!(bank1.is_empty());
+                assert_eq!(None, bank1.try_get(str1));
+                assert!(bank1.free(str1).is_err());
+
+                let str3 = bank1.alloc_with(|| "wowza".to_string()).unwrap();
+                assert_ne!(str1, str3);
+                assert_eq!("wowza", bank1.get(str3));
+            })
+        });
+    }
+}
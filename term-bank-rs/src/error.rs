@@ -7,6 +7,8 @@ pub enum Error {
 
     #[error("wrong generation for index {0}")]
     WrongGeneration(u32),
+    #[error("index {0} is given more than once")]
+    Overlapping(u32),
     #[error("slice is too big")]
     SliceTooBig,
 }
@@ -1,9 +1,29 @@
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub struct Index {
+use std::{fmt, hash, marker::PhantomData};
+
+/// Index of a `T` in a bank.
+///
+/// The type parameter is only a marker, so that an index obtained from a
+/// `Bank<String>` can't be passed to a `Bank<u64>`:
+///
+/// ```compile_fail
+/// use term_bank_rs::bank::Bank;
+///
+/// let mut strings: Bank<String> = Bank::new();
+/// let numbers: Bank<u64> = Bank::new();
+/// let idx = strings.alloc("abc".to_string()).unwrap();
+/// numbers.get(idx); // expected `Index<u64>`, found `Index<String>`
+/// ```
+///
+/// Indices of two banks of the same type are still interchangeable, see
+/// [`crate::branded`] for indices tied to a single bank.
+pub struct Index<T> {
     idx: u32,
+    /// `fn() -> T` so that the index is `Send`, `Sync` and `Copy`
+    /// whatever `T` is.
+    _marker: PhantomData<fn() -> T>,
 }
 
-impl Index {
+impl<T> Index<T> {
     #[inline]
     pub fn index(self) -> usize {
         (self.idx >> 8) as usize
@@ -14,9 +34,63 @@ impl Index {
         (self.idx & 0b1111_1111) as u8
     }
 
+    /// Index of slot `idx` with the given generation.
+    #[inline]
+    pub(crate) fn new(idx: usize, generation: u8) -> Self {
+        let idx_with_gen = (idx << 8) | (generation as usize);
+        debug_assert!(idx_with_gen <= (u32::MAX as usize));
+        Self::from_u32(idx_with_gen as u32)
+    }
+
     #[inline]
     pub(crate) fn from_u32(i: u32) -> Self {
-        Self { idx: i }
+        Self {
+            idx: i,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Same index, for another type. Used by banks made of several banks.
+    #[inline]
+    pub(crate) fn cast<U>(self) -> Index<U> {
+        Index::from_u32(self.idx)
+    }
+}
+
+// implemented by hand, since deriving would require `T` to implement the
+// traits too
+
+impl<T> Clone for Index<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Index<T> {}
+
+impl<T> PartialEq for Index<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.idx == other.idx
+    }
+}
+
+impl<T> Eq for Index<T> {}
+
+impl<T> hash::Hash for Index<T> {
+    #[inline]
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.idx.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for Index<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Index")
+            .field("index", &self.index())
+            .field("generation", &self.generation())
+            .finish()
     }
 }
 
@@ -26,6 +100,7 @@ mod test {
 
     #[test]
     fn test_size() {
-        assert_eq!(std::mem::size_of::<Index>(), 4);
+        assert_eq!(std::mem::size_of::<Index<String>>(), 4);
+        assert_eq!(std::mem::size_of::<Index<[u64; 3]>>(), 4);
     }
 }
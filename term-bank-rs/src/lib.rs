@@ -1,5 +1,7 @@
 pub mod array_bank;
 pub mod bank;
+mod bits;
+pub mod branded;
 pub mod copy_bank;
 pub mod error;
 pub mod index;